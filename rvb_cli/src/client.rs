@@ -0,0 +1,23 @@
+use crate::keys;
+use rvb_client::Client;
+use std::path::PathBuf;
+
+/// Options of commands talking to a node.
+#[derive(clap::Args)]
+pub struct NodeArgs {
+    /// Address of the node to talk to.
+    #[arg(long, env = "RVB_NODE", default_value = "127.0.0.1:7070")]
+    pub node: String,
+    /// Private key to sign with.
+    #[arg(long, env = "RVB_KEY")]
+    key: PathBuf,
+}
+
+impl NodeArgs {
+    pub async fn connect(&self) -> Result<Client, String> {
+        let keypair = keys::read_keypair(&self.key)?;
+        Client::connect(&self.node, keypair)
+            .await
+            .map_err(|e| format!("failed to connect to {}: {e:?}", self.node))
+    }
+}
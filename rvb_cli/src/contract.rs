@@ -0,0 +1,98 @@
+use crate::client::NodeArgs;
+use crate::io;
+use clap::Subcommand;
+use rvb_common::contract::{ContractCompiler, ContractMetadata};
+use rvb_common::crypto::b64_encode;
+use rvb_common::schema::DbValue;
+use rvb_contract::wasmtime::{WasmtimeConfig, WasmtimeContractCompiler};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum ContractCommand {
+    /// Check a WebAssembly contract locally, then deploy it to a node and
+    /// print its id.
+    Deploy {
+        wasm: PathBuf,
+        #[arg(long)]
+        namespace: String,
+        /// Tags to find the contract by, comma separated or repeated.
+        #[arg(long = "tag", value_delimiter = ',')]
+        tags: Vec<String>,
+        /// Contract param as `name=value`, the value being JSON.
+        #[arg(long = "param", value_name = "NAME=JSON")]
+        params: Vec<String>,
+        #[command(flatten)]
+        node: NodeArgs,
+    },
+}
+
+/// Parses `name=json` params. Values that are not valid JSON are taken as
+/// strings, so `--param owner=alice` works without quoting.
+pub fn parse_params(params: &[String]) -> Result<HashMap<String, DbValue>, String> {
+    params
+        .iter()
+        .map(|param| {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| format!("param {param:?} is not of the form name=value"))?;
+            let value = serde_json::from_str::<serde_json::Value>(value)
+                .map_or_else(|_| DbValue::String(value.to_string()), DbValue::from);
+            Ok((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Compiles `bytecode` as the node would and checks that every param its
+/// metadata requires is given.
+pub fn validate(
+    bytecode: &[u8],
+    params: &HashMap<String, DbValue>,
+) -> Result<Option<ContractMetadata>, String> {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        pooled_instances: None,
+        ..WasmtimeConfig::default()
+    })
+    .map_err(|e| format!("failed to start wasmtime: {e:?}"))?;
+    let metadata = compiler
+        .create_contract(bytecode)
+        .and_then(|mut contract| contract.metadata())
+        .map_err(|e| format!("invalid contract: {e:?}"))?;
+
+    let missing: Vec<_> = metadata
+        .iter()
+        .flat_map(|metadata| &metadata.required_params)
+        .filter(|param| !params.contains_key(*param))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("missing required params: {missing:?}"));
+    }
+    Ok(metadata)
+}
+
+pub async fn run(command: ContractCommand) -> Result<(), String> {
+    match command {
+        ContractCommand::Deploy {
+            wasm,
+            namespace,
+            tags,
+            params,
+            node,
+        } => {
+            let bytecode = io::read(&wasm)?;
+            let params = parse_params(&params)?;
+            if let Some(metadata) = validate(&bytecode, &params)? {
+                eprintln!("Deploying {} {}", metadata.name, metadata.version);
+            }
+
+            let id = node
+                .connect()
+                .await?
+                .deploy_contract(bytecode, namespace, params, tags)
+                .await
+                .map_err(|e| format!("failed to deploy: {e:?}"))?;
+            println!("{}", b64_encode(&id));
+            Ok(())
+        }
+    }
+}
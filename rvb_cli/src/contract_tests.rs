@@ -0,0 +1,25 @@
+use crate::contract::*;
+use rvb_common::schema::DbValue;
+
+#[test]
+fn test_parse_params() {
+    let params = parse_params(&[
+        "limit=10".to_string(),
+        "owner=alice".to_string(),
+        "tags=[\"a\"]".to_string(),
+    ])
+    .unwrap();
+
+    assert_eq!(params["limit"], DbValue::Number(10));
+    assert_eq!(params["owner"], DbValue::String("alice".into()));
+    assert_eq!(
+        params["tags"],
+        DbValue::Array(vec![Box::new(DbValue::String("a".into()))])
+    );
+    assert!(parse_params(&["limit".to_string()]).is_err());
+}
+
+#[test]
+fn test_validate_rejects_invalid_modules() {
+    assert!(validate(b"not wasm", &Default::default()).is_err());
+}
@@ -0,0 +1,49 @@
+//! Backups and seeding through line-delimited JSON dumps, one record per key
+//! with its state counter. Dumps are read from and written to a node's
+//! database directly, so the node has to be stopped: sled only lets one
+//! process open a database.
+
+use crate::io;
+use rvb_node::storage::DataStore;
+use rvb_node::storage::dump::{self, DumpError};
+use std::path::Path;
+
+/// Opens the database in `storage` without a background flusher, so it is
+/// closed, and can be opened again, as soon as the store is dropped.
+pub fn open(storage: &Path) -> Result<DataStore, String> {
+    sled::Config::new()
+        .path(storage)
+        .flush_every_ms(None)
+        .open()
+        .map(DataStore::new)
+        .map_err(|e| format!("failed to open {}: {e}", storage.display()))
+}
+
+fn dump_error(e: DumpError) -> String {
+    match e {
+        DumpError::IoError(e) => e.to_string(),
+        DumpError::InvalidRecord { line, error } => {
+            format!("invalid record on line {line}: {error}")
+        }
+        DumpError::StorageError(e) => format!("storage error: {e:?}"),
+    }
+}
+
+/// Writes the values stored in `namespace` to `output`, which may be `-`.
+pub fn export(storage: &Path, namespace: &str, output: &Path) -> Result<(), String> {
+    let store = open(storage)?;
+    let mut data = Vec::new();
+    let count = dump::export(&store, namespace, &mut data).map_err(dump_error)?;
+    io::write(Some(output), &data)?;
+    eprintln!("Exported {count} records");
+    Ok(())
+}
+
+/// Merges the records of the dump at `input` into the database.
+pub fn import(storage: &Path, input: &Path) -> Result<(), String> {
+    let store = open(storage)?;
+    let data = io::read(input)?;
+    let count = dump::import(&store, data.as_slice()).map_err(dump_error)?;
+    eprintln!("Imported {count} records");
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use crate::data::*;
+use rvb_common::schema::DbValue;
+use std::fs;
+
+#[test]
+fn test_export_import_round_trip() {
+    let dir = std::env::temp_dir().join(format!("rvb-data-{}", std::process::id()));
+    let (source, target, dump) = (
+        dir.join("source"),
+        dir.join("target"),
+        dir.join("dump.jsonl"),
+    );
+
+    let store = open(&source).unwrap();
+    store
+        .insert("ns", "space", "a", DbValue::String("one".into()), 3)
+        .unwrap();
+    store
+        .insert("ns", "space", "b", DbValue::Number(2), 1)
+        .unwrap();
+    store
+        .insert("other", "space", "c", DbValue::None, 1)
+        .unwrap();
+    drop(store);
+
+    export(&source, "ns", &dump).unwrap();
+    assert_eq!(fs::read_to_string(&dump).unwrap().lines().count(), 2);
+    import(&target, &dump).unwrap();
+
+    let source = open(&source).unwrap();
+    let target = open(&target).unwrap();
+    assert_eq!(target.entries("ns").unwrap(), source.entries("ns").unwrap());
+    assert!(target.entries("other").unwrap().is_empty());
+
+    drop((source, target));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,37 @@
+//! Reading inputs and writing outputs that may be files or the standard
+//! streams, given as `-`.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    if path == Path::new("-") {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .map_err(|e| format!("failed to read standard input: {e}"))?;
+        return Ok(data);
+    }
+    std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))
+}
+
+/// Writes `data` to `path`, or to standard output without one. Files are
+/// only readable by their owner, since they usually hold private keys.
+pub fn write(path: Option<&Path>, data: &[u8]) -> Result<(), String> {
+    let Some(path) = path.filter(|path| *path != Path::new("-")) else {
+        let mut stdout = std::io::stdout();
+        return stdout
+            .write_all(data)
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("failed to write standard output: {e}"));
+    };
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
@@ -0,0 +1,214 @@
+use crate::io;
+use clap::{Subcommand, ValueEnum};
+use rvb_common::crypto::{
+    KeyPair, Keystore, RotationCertificate, SignatureScheme, b64_decode, b64_encode,
+};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Scheme {
+    Ed25519,
+    Secp256k1,
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Generate a keypair and write its armored private key.
+    Generate {
+        #[arg(long, value_enum, default_value = "ed25519")]
+        scheme: Scheme,
+        /// Output file; standard output if omitted.
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Print the armored public key of a private key.
+    Public { key: PathBuf },
+    /// Convert a binary key into its armored form.
+    Armor {
+        input: PathBuf,
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Convert an armored private key into its binary form.
+    Dearmor {
+        input: PathBuf,
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Generate a key replacing `old` and print the rotation certificate,
+    /// signed by the old key, that hands its identity over.
+    Rotate {
+        old: PathBuf,
+        /// Where to write the new armored private key.
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Seconds since the Unix epoch from which the new key is valid;
+        /// now if omitted.
+        #[arg(long)]
+        not_before: Option<u64>,
+        #[arg(long)]
+        not_after: Option<u64>,
+    },
+    /// Store a private key in an encrypted keystore.
+    Store {
+        name: String,
+        key: PathBuf,
+        #[command(flatten)]
+        keystore: KeystoreArgs,
+    },
+    /// Write a key from a keystore as an armored private key.
+    Load {
+        name: String,
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        keystore: KeystoreArgs,
+    },
+    /// List the identities in a keystore.
+    List {
+        #[command(flatten)]
+        keystore: KeystoreArgs,
+    },
+    /// Delete an identity from a keystore.
+    Remove {
+        name: String,
+        #[command(flatten)]
+        keystore: KeystoreArgs,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct KeystoreArgs {
+    #[arg(long, env = "RVB_KEYSTORE", default_value = "keystore.rvb")]
+    keystore: PathBuf,
+    /// Keystore passphrase; prompted for if unset.
+    #[arg(long, env = "RVB_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+}
+
+impl KeystoreArgs {
+    fn open(&self) -> Result<Keystore, String> {
+        Keystore::open(&self.keystore)
+            .map_err(|e| format!("failed to open {}: {e}", self.keystore.display()))
+    }
+
+    fn passphrase(&self) -> Result<String, String> {
+        match &self.passphrase {
+            Some(passphrase) => Ok(passphrase.clone()),
+            None => rpassword::prompt_password("Keystore passphrase: ")
+                .map_err(|e| format!("failed to read passphrase: {e}")),
+        }
+    }
+}
+
+/// Parses a private key in either its armored or its binary form.
+pub fn parse_keypair(data: &[u8]) -> Result<KeyPair, String> {
+    let armored = std::str::from_utf8(data)
+        .ok()
+        .and_then(|text| b64_decode(text.trim()).ok());
+    KeyPair::import(armored.as_deref().unwrap_or(data)).map_err(|e| format!("invalid key: {e}"))
+}
+
+pub fn read_keypair(path: &Path) -> Result<KeyPair, String> {
+    parse_keypair(&io::read(path)?)
+}
+
+fn line(text: String) -> Vec<u8> {
+    let mut data = text.into_bytes();
+    data.push(b'\n');
+    data
+}
+
+/// Issues the certificate moving `old`'s identity to a freshly generated key.
+pub fn rotate(
+    old: &KeyPair,
+    not_before: u64,
+    not_after: Option<u64>,
+) -> (KeyPair, RotationCertificate) {
+    let new = match old.scheme() {
+        SignatureScheme::Ed25519 => KeyPair::generate(),
+        SignatureScheme::Secp256k1 => KeyPair::generate_secp256k1(),
+    };
+    let certificate = RotationCertificate::issue(old, &new.public(), not_before, not_after);
+    (new, certificate)
+}
+
+pub fn run(command: KeysCommand) -> Result<(), String> {
+    match command {
+        KeysCommand::Generate { scheme, out } => {
+            let keypair = match scheme {
+                Scheme::Ed25519 => KeyPair::generate(),
+                Scheme::Secp256k1 => KeyPair::generate_secp256k1(),
+            };
+            io::write(out.as_deref(), &line(keypair.armor_private()))?;
+            eprintln!("Public key: {}", keypair.armor_public());
+            Ok(())
+        }
+        KeysCommand::Public { key } => io::write(None, &line(read_keypair(&key)?.armor_public())),
+        KeysCommand::Armor { input, out } => {
+            let keypair = read_keypair(&input)?;
+            io::write(out.as_deref(), &line(keypair.armor_private()))
+        }
+        KeysCommand::Dearmor { input, out } => {
+            let keypair = read_keypair(&input)?;
+            io::write(Some(&out), &keypair.export_private())
+        }
+        KeysCommand::Rotate {
+            old,
+            out,
+            not_before,
+            not_after,
+        } => {
+            let old = read_keypair(&old)?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs());
+            let (new, certificate) = rotate(&old, not_before.unwrap_or(now), not_after);
+
+            io::write(Some(&out), &line(new.armor_private()))?;
+            let certificate =
+                rmp_serde::to_vec(&certificate).expect("Failed to encode rotation certificate");
+            io::write(None, &line(b64_encode(&certificate)))
+        }
+        KeysCommand::Store {
+            name,
+            key,
+            keystore,
+        } => {
+            let keypair = read_keypair(&key)?;
+            let mut store = keystore.open()?;
+            store
+                .insert(&name, &keypair, &keystore.passphrase()?)
+                .map_err(|e| format!("failed to store {name}: {e}"))
+        }
+        KeysCommand::Load {
+            name,
+            out,
+            keystore,
+        } => {
+            let keypair = keystore
+                .open()?
+                .load(&name, &keystore.passphrase()?)
+                .map_err(|e| format!("failed to load {name}: {e}"))?;
+            io::write(out.as_deref(), &line(keypair.armor_private()))
+        }
+        KeysCommand::List { keystore } => {
+            for name in keystore.open()?.names() {
+                println!("{name}");
+            }
+            Ok(())
+        }
+        KeysCommand::Remove { name, keystore } => {
+            let removed = keystore
+                .open()?
+                .remove(&name)
+                .map_err(|e| format!("failed to remove {name}: {e}"))?;
+            if removed {
+                Ok(())
+            } else {
+                Err(format!("no identity named {name}"))
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::keys::*;
+use rvb_common::crypto::KeyPair;
+
+#[test]
+fn test_parse_armored_and_binary_keys() {
+    let keypair = KeyPair::generate();
+    let armored = format!("{}\n", keypair.armor_private());
+
+    let from_armored = parse_keypair(armored.as_bytes()).unwrap();
+    let from_binary = parse_keypair(&keypair.export_private()).unwrap();
+    assert_eq!(from_armored.export_private(), keypair.export_private());
+    assert_eq!(from_binary.export_private(), keypair.export_private());
+
+    assert!(parse_keypair(b"not a key").is_err());
+}
+
+#[test]
+fn test_rotate_keeps_scheme() {
+    let old = KeyPair::generate_secp256k1();
+    let (new, certificate) = rotate(&old, 10, None);
+
+    assert_eq!(new.scheme(), old.scheme());
+    assert_eq!(certificate.new_key, new.export_public());
+    assert!(certificate.verify(10).is_ok());
+}
@@ -0,0 +1,95 @@
+//! `rvb`, the operator command line for reverb: key management, contract
+//! deployment, data dumps and an interactive shell.
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+mod client;
+mod contract;
+#[cfg(test)]
+mod contract_tests;
+mod data;
+#[cfg(test)]
+mod data_tests;
+mod io;
+mod keys;
+#[cfg(test)]
+mod keys_tests;
+mod repl;
+#[cfg(test)]
+mod repl_tests;
+
+#[derive(Parser)]
+#[command(name = "rvb", version, about = "Operate reverb nodes and their keys")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate, convert, rotate and store node keys.
+    Keys {
+        #[command(subcommand)]
+        command: keys::KeysCommand,
+    },
+    /// Deploy contracts.
+    Contract {
+        #[command(subcommand)]
+        command: contract::ContractCommand,
+    },
+    /// Write the values of a namespace as line-delimited JSON. The node must
+    /// be stopped.
+    Export {
+        /// Database directory of the node.
+        #[arg(long)]
+        storage: PathBuf,
+        #[arg(long)]
+        namespace: String,
+        /// File to write, or `-` for standard output.
+        #[arg(long, default_value = "-")]
+        output: PathBuf,
+    },
+    /// Merge a line-delimited JSON dump into a node's database. Values at
+    /// newer states are kept. The node must be stopped.
+    Import {
+        /// Database directory of the node.
+        #[arg(long)]
+        storage: PathBuf,
+        /// Dump to read, or `-` for standard input.
+        #[arg(default_value = "-")]
+        input: PathBuf,
+    },
+    /// Interactive shell for reading, writing and watching data on a node.
+    Repl {
+        /// Contract id, in base64, to start with.
+        #[arg(long)]
+        contract: Option<String>,
+        #[command(flatten)]
+        node: client::NodeArgs,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Keys { command } => keys::run(command),
+        Command::Contract { command } => contract::run(command).await,
+        Command::Export {
+            storage,
+            namespace,
+            output,
+        } => data::export(&storage, &namespace, &output),
+        Command::Import { storage, input } => data::import(&storage, &input),
+        Command::Repl { contract, node } => repl::run(node, contract).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("rvb: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
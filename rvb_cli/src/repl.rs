@@ -0,0 +1,318 @@
+//! Interactive shell over a client connection, for poking at live meshes.
+
+use crate::client::NodeArgs;
+use crate::contract::parse_params;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use rvb_client::Client;
+use rvb_common::crypto::{b64_decode, b64_encode};
+use rvb_common::protocol::{ContractEvent, Location};
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HELP: &str = "\
+use <contract>                     set the contract of later commands
+get <ns>/<space>/<key> [field.path ...]
+                                   read a value, optionally narrowed to fields
+insert <ns>/<space>/<key> <json> [state] [name=json ...]
+                                   write a value; state defaults to the time
+delete <ns>/<space>/<key> [state]  remove a value; state defaults to the time
+watch <namespace> [topic]          print events emitted in a namespace
+unwatch <namespace> [topic]
+peers                              show the node this shell is connected to
+help
+quit";
+
+#[derive(Debug, PartialEq)]
+pub enum ReplCommand {
+    Use(Vec<u8>),
+    Get {
+        namespace: String,
+        contract_space: String,
+        key: String,
+        select: Vec<Vec<String>>,
+    },
+    Insert {
+        namespace: String,
+        contract_space: String,
+        key: String,
+        value: DbValue,
+        state: Option<u64>,
+        metadata: HashMap<String, DbValue>,
+    },
+    Delete {
+        namespace: String,
+        contract_space: String,
+        key: String,
+        state: Option<u64>,
+    },
+    Watch {
+        namespace: String,
+        topic: Option<String>,
+    },
+    Unwatch {
+        namespace: String,
+        topic: Option<String>,
+    },
+    Peers,
+    Help,
+    Quit,
+}
+
+fn split_location(path: &str) -> Result<(String, String, String), String> {
+    let mut parts = path.splitn(3, '/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(ns), Some(space), Some(key)) if !ns.is_empty() && !space.is_empty() => {
+            Ok((ns.to_string(), space.to_string(), key.to_string()))
+        }
+        _ => Err(format!("expected <ns>/<space>/<key>, got {path:?}")),
+    }
+}
+
+/// Parses one line of input. Empty lines parse to `None`.
+pub fn parse(line: &str) -> Result<Option<ReplCommand>, String> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let words: Vec<&str> = words.collect();
+
+    let command = match (command, words.as_slice()) {
+        ("use", [contract]) => ReplCommand::Use(
+            b64_decode(contract).map_err(|_| format!("{contract:?} is not a base64 id"))?,
+        ),
+        ("get", [path, fields @ ..]) => {
+            let (namespace, contract_space, key) = split_location(path)?;
+            ReplCommand::Get {
+                namespace,
+                contract_space,
+                key,
+                select: fields
+                    .iter()
+                    .map(|field| field.split('.').map(str::to_string).collect())
+                    .collect(),
+            }
+        }
+        ("insert", [path, value, rest @ ..]) => {
+            let (namespace, contract_space, key) = split_location(path)?;
+            let value = serde_json::from_str::<serde_json::Value>(value)
+                .map_err(|e| format!("invalid JSON value: {e}"))?
+                .into();
+            let (state, params) = match rest {
+                [state, params @ ..] if !state.contains('=') => (
+                    Some(
+                        state
+                            .parse()
+                            .map_err(|_| format!("invalid state {state:?}"))?,
+                    ),
+                    params,
+                ),
+                params => (None, params),
+            };
+            let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+            ReplCommand::Insert {
+                namespace,
+                contract_space,
+                key,
+                value,
+                state,
+                metadata: parse_params(&params)?,
+            }
+        }
+        ("delete", [path, state @ ..]) if state.len() <= 1 => {
+            let (namespace, contract_space, key) = split_location(path)?;
+            let state = state
+                .first()
+                .map(|state| {
+                    state
+                        .parse()
+                        .map_err(|_| format!("invalid state {state:?}"))
+                })
+                .transpose()?;
+            ReplCommand::Delete {
+                namespace,
+                contract_space,
+                key,
+                state,
+            }
+        }
+        ("watch", [namespace, topic @ ..]) | ("unwatch", [namespace, topic @ ..])
+            if topic.len() <= 1 =>
+        {
+            let namespace = namespace.to_string();
+            let topic = topic.first().map(|t| t.to_string());
+            if command == "watch" {
+                ReplCommand::Watch { namespace, topic }
+            } else {
+                ReplCommand::Unwatch { namespace, topic }
+            }
+        }
+        ("peers", []) => ReplCommand::Peers,
+        ("help", []) => ReplCommand::Help,
+        ("quit" | "exit", []) => ReplCommand::Quit,
+        _ => return Err(format!("invalid command {line:?}, see help")),
+    };
+    Ok(Some(command))
+}
+
+pub fn pretty(value: DbValue) -> String {
+    serde_json::to_string_pretty(&serde_json::Value::from(value))
+        .expect("JSON values always serialize")
+}
+
+fn print_event(event: ContractEvent) {
+    println!(
+        "[{}/{}] {}: {}",
+        event.namespace,
+        event.contract_space,
+        event.topic,
+        pretty(event.payload)
+    );
+}
+
+/// State of writes not given one: the time in milliseconds, so later writes
+/// win over earlier ones.
+fn now_state() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+struct Repl {
+    client: Client,
+    addr: String,
+    contract: Option<Vec<u8>>,
+}
+
+impl Repl {
+    fn location(
+        &self,
+        namespace: String,
+        contract_space: String,
+        key: String,
+    ) -> Result<Location, String> {
+        let contract = self
+            .contract
+            .clone()
+            .ok_or("no contract selected, run `use <contract>` first")?;
+        Ok(Location {
+            namespace,
+            contract_space,
+            contract,
+            key,
+        })
+    }
+
+    async fn execute(&mut self, command: ReplCommand) -> Result<(), String> {
+        match command {
+            ReplCommand::Use(contract) => self.contract = Some(contract),
+            ReplCommand::Get {
+                namespace,
+                contract_space,
+                key,
+                select,
+            } => {
+                let location = self.location(namespace, contract_space, key)?;
+                match self.client.get(location, select).await {
+                    Ok(Some(value)) => println!("{}", pretty(value)),
+                    Ok(None) => println!("(none)"),
+                    Err(e) => return Err(format!("get failed: {e:?}")),
+                }
+            }
+            ReplCommand::Insert {
+                namespace,
+                contract_space,
+                key,
+                value,
+                state,
+                metadata,
+            } => {
+                let location = self.location(namespace, contract_space, key)?;
+                self.client
+                    .insert(location, value, metadata, state.unwrap_or_else(now_state))
+                    .await
+                    .map_err(|e| format!("insert failed: {e:?}"))?;
+            }
+            ReplCommand::Delete {
+                namespace,
+                contract_space,
+                key,
+                state,
+            } => {
+                let location = self.location(namespace, contract_space, key)?;
+                self.client
+                    .delete(location, state.unwrap_or_else(now_state))
+                    .await
+                    .map_err(|e| format!("delete failed: {e:?}"))?;
+            }
+            ReplCommand::Watch { namespace, topic } => {
+                let mut subscription = self
+                    .client
+                    .subscribe(namespace, topic, None)
+                    .await
+                    .map_err(|e| format!("watch failed: {e:?}"))?;
+                tokio::spawn(async move {
+                    while let Some(event) = subscription.next().await {
+                        print_event(event);
+                    }
+                });
+            }
+            ReplCommand::Unwatch { namespace, topic } => self
+                .client
+                .unsubscribe(namespace, topic)
+                .await
+                .map_err(|e| format!("unwatch failed: {e:?}"))?,
+            ReplCommand::Peers => {
+                let identity = self
+                    .client
+                    .node_identity()
+                    .map_or_else(|| "(not seen yet)".to_string(), |key| b64_encode(&key));
+                println!("{} {identity}", self.addr);
+            }
+            ReplCommand::Help => println!("{HELP}"),
+            ReplCommand::Quit => {}
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(node: NodeArgs, contract: Option<String>) -> Result<(), String> {
+    let mut repl = Repl {
+        client: node.connect().await?,
+        addr: node.node.clone(),
+        contract: contract
+            .map(|id| b64_decode(&id).map_err(|_| format!("{id:?} is not a base64 id")))
+            .transpose()?,
+    };
+    let mut editor =
+        DefaultEditor::new().map_err(|e| format!("failed to open the terminal: {e}"))?;
+
+    loop {
+        let (line, returned) = tokio::task::spawn_blocking(move || {
+            let line = editor.readline("rvb> ");
+            (line, editor)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        editor = returned;
+
+        let line = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(format!("failed to read input: {e}")),
+        };
+        let _ = editor.add_history_entry(&line);
+
+        match parse(&line) {
+            Ok(Some(ReplCommand::Quit)) => return Ok(()),
+            Ok(Some(command)) => {
+                if let Err(e) = repl.execute(command).await {
+                    eprintln!("{e}");
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::repl::*;
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+#[test]
+fn test_parse_get() {
+    assert_eq!(
+        parse("get ns/space/users/1 name address.city").unwrap(),
+        Some(ReplCommand::Get {
+            namespace: "ns".into(),
+            contract_space: "space".into(),
+            key: "users/1".into(),
+            select: vec![
+                vec!["name".to_string()],
+                vec!["address".to_string(), "city".to_string()]
+            ],
+        })
+    );
+    assert!(parse("get ns/space").is_err());
+    assert_eq!(parse("   ").unwrap(), None);
+}
+
+#[test]
+fn test_parse_insert() {
+    assert_eq!(
+        parse("insert ns/space/key {\"a\":1} 7 author=\"bob\"").unwrap(),
+        Some(ReplCommand::Insert {
+            namespace: "ns".into(),
+            contract_space: "space".into(),
+            key: "key".into(),
+            value: DbValue::Object(HashMap::from([("a".into(), Box::new(DbValue::Number(1)))])),
+            state: Some(7),
+            metadata: HashMap::from([("author".to_string(), DbValue::String("bob".into()))]),
+        })
+    );
+    assert!(matches!(
+        parse("insert ns/space/key 1").unwrap(),
+        Some(ReplCommand::Insert { state: None, .. })
+    ));
+    assert!(parse("insert ns/space/key {oops").is_err());
+}
+
+#[test]
+fn test_parse_delete() {
+    assert_eq!(
+        parse("delete ns/space/key 7").unwrap(),
+        Some(ReplCommand::Delete {
+            namespace: "ns".into(),
+            contract_space: "space".into(),
+            key: "key".into(),
+            state: Some(7),
+        })
+    );
+    assert!(matches!(
+        parse("delete ns/space/key").unwrap(),
+        Some(ReplCommand::Delete { state: None, .. })
+    ));
+    assert!(parse("delete ns/space/key soon").is_err());
+    assert!(parse("delete ns/space/key 1 2").is_err());
+}
+
+#[test]
+fn test_parse_other_commands() {
+    assert_eq!(
+        parse("watch ns changed").unwrap(),
+        Some(ReplCommand::Watch {
+            namespace: "ns".into(),
+            topic: Some("changed".into())
+        })
+    );
+    assert_eq!(parse("peers").unwrap(), Some(ReplCommand::Peers));
+    assert_eq!(parse("exit").unwrap(), Some(ReplCommand::Quit));
+    assert!(parse("use not*base64").is_err());
+    assert!(parse("frobnicate").is_err());
+}
+
+#[test]
+fn test_pretty_prints_json() {
+    assert_eq!(
+        pretty(DbValue::Array(vec![Box::new(DbValue::Boolean(true))])),
+        "[\n  true\n]"
+    );
+}
@@ -0,0 +1,160 @@
+//! Safe wrappers over the functions nodes provide to contracts. They take care
+//! of passing buffers to the host and decoding what it hands back, so contracts
+//! never call into the host directly. A failure on the host's side panics,
+//! which the host reports as the contract's error.
+
+use rvb_common::schema::DbValue;
+
+#[cfg(test)]
+use crate::tests::stubs::{
+    host_blake3, host_get, host_log, host_panic, host_random_seed, host_sha256, host_state_get,
+    host_state_set, host_verify, write_value,
+};
+
+#[cfg(not(test))]
+#[link(wasm_import_module = "rvb_host")]
+unsafe extern "C" {
+    #[link_name = "get"]
+    unsafe fn host_get(key_ptr: u64, key_len: u64) -> u64;
+    unsafe fn write_value(ptr: u64) -> u64;
+    #[link_name = "state_get"]
+    unsafe fn host_state_get(key_ptr: u64, key_len: u64) -> u64;
+    #[link_name = "state_set"]
+    unsafe fn host_state_set(key_ptr: u64, key_len: u64, value_ptr: u64, value_len: u64);
+    #[link_name = "random_seed"]
+    unsafe fn host_random_seed(out_ptr: u64);
+    #[link_name = "blake3"]
+    unsafe fn host_blake3(ptr: u64, len: u64, out_ptr: u64);
+    #[link_name = "sha256"]
+    unsafe fn host_sha256(ptr: u64, len: u64, out_ptr: u64);
+    #[link_name = "verify"]
+    unsafe fn host_verify(
+        key_ptr: u64,
+        key_len: u64,
+        data_ptr: u64,
+        data_len: u64,
+        sig_ptr: u64,
+        sig_len: u64,
+    ) -> u64;
+    #[link_name = "log"]
+    unsafe fn host_log(ptr: u64, len: u64);
+    #[link_name = "panic"]
+    unsafe fn host_panic(ptr: u64, len: u64);
+}
+
+/// Reads the current value of `key` in the namespace the contract runs in.
+#[must_use]
+pub fn get(key: &str) -> Option<DbValue> {
+    // SAFETY: the host only reads `key.len()` bytes starting at the key pointer
+    let len = unsafe { host_get(key.as_ptr() as u64, key.len() as u64) };
+    read_value(len)
+}
+
+/// Reads `key` from the contract's private state, kept by each node for itself
+/// and never replicated.
+#[must_use]
+pub fn state_get(key: &str) -> Option<DbValue> {
+    // SAFETY: the host only reads `key.len()` bytes starting at the key pointer
+    let len = unsafe { host_state_get(key.as_ptr() as u64, key.len() as u64) };
+    read_value(len)
+}
+
+/// Sets `key` in the contract's private state. The write is dropped if the
+/// contract fails.
+pub fn state_set(key: &str, value: &DbValue) {
+    let value = rmp_serde::to_vec(value).expect("Failed to encode value");
+    // SAFETY: the host only reads the key and value buffers it is given
+    unsafe {
+        host_state_set(
+            key.as_ptr() as u64,
+            key.len() as u64,
+            value.as_ptr() as u64,
+            value.len() as u64,
+        )
+    };
+}
+
+/// Copies out a value the host looked up, `len` being the length it reported.
+fn read_value(len: u64) -> Option<DbValue> {
+    if len == 0 {
+        return None;
+    }
+
+    let buf = vec![0u8; len as usize];
+    // SAFETY: safe, as the host writes exactly the `len` bytes it reported
+    let res = unsafe { write_value(buf.as_ptr() as u64) };
+
+    assert!((res == 0), "Failed to write value, error code {res}");
+
+    Some(rmp_serde::from_slice(&buf).expect("Invalid payload"))
+}
+
+/// 32 bytes of randomness tied to the message being processed. Every node
+/// executing the same message gets the same seed, so use it (or a PRNG seeded
+/// from it) wherever a contract needs random choices.
+#[must_use]
+pub fn random_seed() -> [u8; 32] {
+    let mut out = [0u8; 32];
+    // SAFETY: the host writes exactly 32 bytes into `out`
+    unsafe { host_random_seed(out.as_mut_ptr() as u64) };
+    out
+}
+
+#[must_use]
+pub fn blake3(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    // SAFETY: the host reads `data` and writes exactly 32 bytes into `out`
+    unsafe {
+        host_blake3(
+            data.as_ptr() as u64,
+            data.len() as u64,
+            out.as_mut_ptr() as u64,
+        )
+    };
+    out
+}
+
+#[must_use]
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    // SAFETY: the host reads `data` and writes exactly 32 bytes into `out`
+    unsafe {
+        host_sha256(
+            data.as_ptr() as u64,
+            data.len() as u64,
+            out.as_mut_ptr() as u64,
+        )
+    };
+    out
+}
+
+/// Checks `signature` over `data` against an exported reverb public key, such
+/// as the context's `signed_by`.
+#[must_use]
+pub fn verify(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    // SAFETY: the host only reads the three buffers it is given
+    let res = unsafe {
+        host_verify(
+            public_key.as_ptr() as u64,
+            public_key.len() as u64,
+            data.as_ptr() as u64,
+            data.len() as u64,
+            signature.as_ptr() as u64,
+            signature.len() as u64,
+        )
+    };
+    res == 1
+}
+
+/// Writes `message` to the node's debug log.
+pub fn log(message: &str) {
+    // SAFETY: the host only reads `message.len()` bytes starting at its pointer
+    unsafe { host_log(message.as_ptr() as u64, message.len() as u64) };
+}
+
+/// Reports a panic to the host, which aborts the call and surfaces `message`
+/// as the contract's error.
+pub(crate) fn panic(message: &str) {
+    // SAFETY: the host only reads `message.len()` bytes starting at its pointer
+    unsafe { host_panic(message.as_ptr() as u64, message.len() as u64) };
+}
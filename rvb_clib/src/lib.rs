@@ -1,30 +1,118 @@
-use rvb_common::{contract::ContractContext, schema::DataAction};
+//! Helpers for writing reverb contracts in Rust.
+//!
+//! The crate needs `std`: contexts and responses are encoded with `rmp-serde`,
+//! which has no `no_std` support, and the schema types it re-exports are built
+//! on `std::collections::HashMap`. Building contracts with `no_std + alloc`
+//! would need both replaced first.
+
+use std::sync::Once;
+
+use rvb_common::{
+    contract::{CONTEXT_CODEC, CONTEXT_TOO_LARGE, CONTEXT_WRITTEN, ContractContext},
+    schema::{DataAction, DbValue},
+};
 
 pub use rvb_common::contract;
 pub use rvb_common::crypto;
 pub use rvb_common::schema;
 pub use serde::{Deserialize, Serialize};
 
+pub mod host;
+mod params;
+#[cfg(test)]
+mod params_tests;
+mod response;
+#[cfg(test)]
+mod response_tests;
+#[cfg(test)]
+mod tests;
+
+pub use params::{ContextParams, PARAM_ERROR_CODE, ParamError};
+pub use response::{DEFAULT_RESPONSE_LIMIT, RESPONSE_ERROR_CODE, Response, ResponseError};
+
+#[cfg(test)]
+use tests::stubs::read_context;
+
+#[cfg(not(test))]
 #[link(wasm_import_module = "rvb_host")]
 unsafe extern "C" {
-    unsafe fn get_context_length() -> u64;
-    unsafe fn write_context(ptr: u64) -> u64;
+    unsafe fn read_context(codec: u64, ptr: u64, capacity: u64) -> u64;
 }
 
+/// Size of the buffer [`get_context`] first reads the context into. Larger
+/// contexts are read again into a buffer of the size the host asks for.
+const INITIAL_CONTEXT_CAPACITY: usize = 4096;
+
+/// Largest context [`get_context`] accepts, unless [`try_get_context`] is
+/// given another limit.
+pub const DEFAULT_CONTEXT_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContextError {
+    #[error("Context is {size} bytes, over the limit of {limit}")]
+    TooLarge { size: usize, limit: usize },
+    #[error("Host failed to read the context, status {0}")]
+    Host(u32),
+    #[error("Invalid context: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+}
+
+/// Routes panics to the host, so a failing contract reports its panic message
+/// instead of an opaque trap.
+fn install_panic_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        std::panic::set_hook(Box::new(|info| host::panic(&info.to_string())));
+    });
+}
+
+/// Reads the context of the current call, panicking if it cannot be read or
+/// is over [`DEFAULT_CONTEXT_LIMIT`].
 #[must_use]
 pub fn get_context() -> ContractContext {
-    // SAFETY: always safe since it just returns a number with no side effects
-    let len = unsafe { get_context_length() };
-    let buf = vec![0u8; len as usize];
-    // SAFETY: safe, as the WASM host does not write over len
-    let res = unsafe { write_context(buf.as_ptr() as u64) };
-
-    assert!((res == 0), "Failed to write context, error code {res}");
+    try_get_context(DEFAULT_CONTEXT_LIMIT).expect("Failed to read context")
+}
 
-    rmp_serde::from_slice(&buf).expect("Invalid payload")
+/// Reads the context of the current call, refusing contexts over `limit`
+/// bytes. The host is told the size of every buffer and writes nothing that
+/// does not fit.
+pub fn try_get_context(limit: usize) -> Result<ContractContext, ContextError> {
+    install_panic_hook();
+    let mut buf = vec![0u8; INITIAL_CONTEXT_CAPACITY.min(limit)];
+    loop {
+        // SAFETY: the host writes at most `buf.len()` bytes into `buf`
+        let res = unsafe {
+            read_context(
+                u64::from(CONTEXT_CODEC),
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+            )
+        };
+        let (status, len) = ((res >> 32) as u32, (res & 0xffff_ffff) as usize);
+        match status {
+            CONTEXT_WRITTEN => {
+                buf.truncate(len);
+                return Ok(rmp_serde::from_slice(&buf)?);
+            }
+            CONTEXT_TOO_LARGE if len > limit => {
+                return Err(ContextError::TooLarge { size: len, limit });
+            }
+            CONTEXT_TOO_LARGE if len > buf.len() => buf.resize(len, 0),
+            status => return Err(ContextError::Host(status)),
+        }
+    }
 }
 
 pub fn run_contract(f: impl Fn(ContractContext) -> Result<Vec<DataAction>, u64>) -> (u64, u64) {
+    respond(f)
+}
+
+/// Like [`run_contract`], for queries answering a read with a value.
+pub fn run_query(f: impl Fn(ContractContext) -> Result<DbValue, u64>) -> (u64, u64) {
+    respond(f)
+}
+
+fn respond<T: Serialize>(f: impl Fn(ContractContext) -> Result<T, u64>) -> (u64, u64) {
     let ctx = get_context();
     let res = f(ctx);
 
@@ -33,16 +121,99 @@ pub fn run_contract(f: impl Fn(ContractContext) -> Result<Vec<DataAction>, u64>)
         Ok(acc) => match rmp_serde::to_vec(&acc) {
             Err(_) => (0, 10),
             Ok(v) => {
-                let v = v.leak();
-                (v.len() as u64, v.as_ptr() as u64)
+                let (ptr, len) = hand_over(v);
+                (len as u64, ptr as u64)
             }
         },
     }
 }
 
+/// Gives up ownership of `buffer` to the host, which hands it back to
+/// [`free`] once it has read it.
+fn hand_over(buffer: Vec<u8>) -> (u32, u32) {
+    let buffer = buffer.into_boxed_slice();
+    let len = buffer.len() as u32;
+    (Box::into_raw(buffer).cast::<u8>() as u32, len)
+}
+
+/// Encodes `value` and hands it over to the host, packing its location the way
+/// the host expects exported responses. Returns 0, an empty response, if
+/// encoding fails.
+pub fn export_response<T: Serialize>(value: &T) -> u64 {
+    match rmp_serde::to_vec(value) {
+        Err(_) => 0,
+        Ok(v) => {
+            let (ptr, len) = hand_over(v);
+            (u64::from(ptr) << 32) | u64::from(len)
+        }
+    }
+}
+
+/// Allocates `len` zeroed bytes for the host to write into and returns their
+/// address. Released with [`free`].
+#[must_use]
+pub fn alloc(len: u32) -> u32 {
+    hand_over(vec![0; len as usize]).0
+}
+
+/// Releases a buffer from [`alloc`] or a response the host has read.
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a buffer from [`alloc`] or a response, which
+/// has not been released yet.
+pub unsafe fn free(ptr: u32, len: u32) {
+    let buffer = std::ptr::slice_from_raw_parts_mut(ptr as usize as *mut u8, len as usize);
+    // SAFETY: the buffer came from `hand_over`, as the caller guarantees
+    drop(unsafe { Box::from_raw(buffer) });
+}
+
+/// Exports [`alloc`] and [`free`] for the host. Emitted by [`contract!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! allocator {
+    () => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_alloc(len: u32) -> u32 {
+            $crate::alloc(len)
+        }
+
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn rvb_free(ptr: u32, len: u32) {
+            // SAFETY: the host only frees what the contract handed over
+            unsafe { $crate::free(ptr, len) }
+        }
+    };
+}
+
+/// Exports the entry point along with `rvb_abi_version`, reporting
+/// [`contract::ABI_VERSION`], `rvb_metadata`, reporting
+/// [`package_metadata!`], and the allocator. Other metadata is given before
+/// the entry point:
+///
+/// ```ignore
+/// contract! {
+///     metadata: ContractMetadata {
+///         required_params: vec!["owner".to_string()],
+///         ..package_metadata!()
+///     },
+///     |ctx| { Ok(vec![ctx.action]) }
+/// }
+/// ```
 #[macro_export]
 macro_rules! contract {
     (|$i:ident| $b:block) => {
+        $crate::contract!(metadata: $crate::package_metadata!(), |$i| $b);
+    };
+    (metadata: $metadata:expr, |$i:ident| $b:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_abi_version() -> u32 {
+            $crate::contract::ABI_VERSION
+        }
+
+        $crate::metadata!($metadata);
+        $crate::allocator!();
+
         #[unsafe(no_mangle)]
         pub extern "C" fn rvb_contract() -> u64 {
             let (len, begin) = $crate::run_contract(|$i: $crate::contract::ContractContext| $b);
@@ -50,3 +221,148 @@ macro_rules! contract {
         }
     };
 }
+
+/// Like [`contract!`], with a separate closure per action instead of one entry
+/// point:
+///
+/// ```ignore
+/// contract_handlers! {
+///     on_insert: |ctx| { Ok(vec![ctx.action]) },
+///     on_delete: |ctx| { Err(1) },
+/// }
+/// ```
+///
+/// `on_insert`, `on_get`, `on_delete` and `init` return actions, while
+/// `on_query` answers reads with a value, like [`query!`]. At least one
+/// handler must be given, each at most once. Metadata other than
+/// [`package_metadata!`] is given first, as `metadata: expr,`. Other handlers
+/// are refused when the contract is compiled:
+///
+/// ```compile_fail
+/// rvb_clib::contract_handlers! {
+///     on_update: |ctx| { Err(1) },
+/// }
+/// ```
+#[macro_export]
+macro_rules! contract_handlers {
+    () => {
+        compile_error!("contract_handlers! needs at least one handler");
+    };
+    (metadata: $metadata:expr $(,)?) => {
+        compile_error!("contract_handlers! needs at least one handler");
+    };
+    (metadata: $metadata:expr, $($name:ident: |$i:ident| $b:block),+ $(,)?) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_abi_version() -> u32 {
+            $crate::contract::ABI_VERSION
+        }
+
+        $crate::metadata!($metadata);
+        $crate::allocator!();
+
+        $($crate::handler!($name, $i, $b);)+
+    };
+    ($($name:ident: |$i:ident| $b:block),+ $(,)?) => {
+        $crate::contract_handlers!(
+            metadata: $crate::package_metadata!(),
+            $($name: |$i| $b),+
+        );
+    };
+}
+
+/// Exports one handler of [`contract_handlers!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! handler {
+    (on_insert, $i:ident, $b:block) => {
+        $crate::handler!(@actions rvb_on_insert, $i, $b);
+    };
+    (on_get, $i:ident, $b:block) => {
+        $crate::handler!(@actions rvb_on_get, $i, $b);
+    };
+    (on_delete, $i:ident, $b:block) => {
+        $crate::handler!(@actions rvb_on_delete, $i, $b);
+    };
+    (init, $i:ident, $b:block) => {
+        $crate::handler!(@actions rvb_init, $i, $b);
+    };
+    (on_query, $i:ident, $b:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_query() -> u64 {
+            let (len, begin) = $crate::run_query(|$i: $crate::contract::ContractContext| $b);
+            ((begin as u64) << 32) | (len as u64)
+        }
+    };
+    (@actions $export:ident, $i:ident, $b:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn $export() -> u64 {
+            let (len, begin) = $crate::run_contract(|$i: $crate::contract::ContractContext| $b);
+            ((begin as u64) << 32) | (len as u64)
+        }
+    };
+    ($other:ident, $i:ident, $b:block) => {
+        compile_error!(concat!(
+            "unknown handler `",
+            stringify!($other),
+            "`, expected on_insert, on_get, on_delete, on_query or init"
+        ));
+    };
+}
+
+/// Exports the contract's metadata, read by nodes when the contract is deployed.
+/// Emitted by [`contract!`] and [`contract_handlers!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! metadata {
+    ($metadata:expr) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_metadata() -> u64 {
+            let metadata: $crate::contract::ContractMetadata = $metadata;
+            $crate::export_response(&metadata)
+        }
+    };
+}
+
+/// Metadata naming the contract after the crate it is built from, with its
+/// version, no author and no required params. Exported by [`contract!`]
+/// unless it is given other metadata, whose remaining fields can be filled in
+/// from this with struct update syntax.
+#[macro_export]
+macro_rules! package_metadata {
+    () => {
+        $crate::contract::ContractMetadata {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            author: Vec::new(),
+            required_params: Vec::new(),
+        }
+    };
+}
+
+/// Exports a read-only query, run by nodes for `Get` requests instead of
+/// serving the stored value. It returns the value to answer with.
+#[macro_export]
+macro_rules! query {
+    (|$i:ident| $b:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_query() -> u64 {
+            let (len, begin) = $crate::run_query(|$i: $crate::contract::ContractContext| $b);
+            ((begin as u64) << 32) | (len as u64)
+        }
+    };
+}
+
+/// Exports a migration hook, run by nodes over every stored value after the
+/// contract is upgraded to this code. It receives the value as an insert of its
+/// current contents and returns the actions to apply instead, like
+/// [`contract!`].
+#[macro_export]
+macro_rules! migrate {
+    (|$i:ident| $b:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn rvb_migrate() -> u64 {
+            let (len, begin) = $crate::run_contract(|$i: $crate::contract::ContractContext| $b);
+            ((begin as u64) << 32) | (len as u64)
+        }
+    };
+}
@@ -0,0 +1,40 @@
+use rvb_common::{
+    contract::ContractContext,
+    schema::{SchemaError, into_schema},
+};
+use serde::de::DeserializeOwned;
+
+/// Error code a contract fails with when it returns a [`ParamError`] through
+/// `?`.
+pub const PARAM_ERROR_CODE: u64 = 11;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParamError {
+    #[error("Missing contract param {0}")]
+    Missing(String),
+    #[error("Contract param {0} has the wrong type: {1}")]
+    Invalid(String, SchemaError),
+}
+
+impl From<ParamError> for u64 {
+    fn from(_: ParamError) -> Self {
+        PARAM_ERROR_CODE
+    }
+}
+
+/// Typed access to the params a contract was deployed with.
+pub trait ContextParams {
+    /// Converts param `name` into `T` the way [`into_schema`] converts values.
+    fn param<T: DeserializeOwned>(&self, name: &str) -> Result<T, ParamError>;
+}
+
+impl ContextParams for ContractContext {
+    fn param<T: DeserializeOwned>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .contract_params
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+        into_schema(value.clone()).map_err(|e| ParamError::Invalid(name.to_string(), e))
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use rvb_common::{
+    contract::ContractContext,
+    schema::{DataAction, DbValue},
+};
+
+use crate::{ContextParams, PARAM_ERROR_CODE, ParamError};
+
+fn context(params: &[(&str, DbValue)]) -> ContractContext {
+    ContractContext {
+        action: DataAction::Get {
+            key: "key".to_string(),
+        },
+        namespace: "namespace".to_string(),
+        contract_space: "contract".to_string(),
+        signed_by: Vec::new(),
+        contract_params: params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<HashMap<_, _>>(),
+        message_id: Vec::new(),
+        signed_at: 0,
+        publisher: String::new(),
+        state: 0,
+    }
+}
+
+#[test]
+fn test_param_converts_values() {
+    let ctx = context(&[
+        ("owner", DbValue::String("alice".to_string())),
+        ("limit", DbValue::Number(10)),
+        ("open", DbValue::Boolean(true)),
+    ]);
+
+    assert_eq!(ctx.param::<String>("owner").unwrap(), "alice");
+    assert_eq!(ctx.param::<u32>("limit").unwrap(), 10);
+    assert!(ctx.param::<bool>("open").unwrap());
+}
+
+#[test]
+fn test_missing_param() {
+    let ctx = context(&[]);
+
+    assert!(matches!(
+        ctx.param::<String>("owner"),
+        Err(ParamError::Missing(name)) if name == "owner"
+    ));
+}
+
+#[test]
+fn test_param_of_the_wrong_type() {
+    let ctx = context(&[
+        ("owner", DbValue::Number(1)),
+        ("limit", DbValue::Number(-1)),
+    ]);
+
+    assert!(matches!(
+        ctx.param::<String>("owner"),
+        Err(ParamError::Invalid(name, _)) if name == "owner"
+    ));
+    // Out of range for the type asked for
+    let err = ctx.param::<u32>("limit").unwrap_err();
+    assert!(matches!(&err, ParamError::Invalid(name, _) if name == "limit"));
+    assert_eq!(u64::from(err), PARAM_ERROR_CODE);
+}
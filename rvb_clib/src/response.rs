@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+
+use rvb_common::schema::{DataAction, DbValue, PatchOp};
+
+/// Error code a contract fails with when it returns a [`ResponseError`]
+/// through `?`.
+pub const RESPONSE_ERROR_CODE: u64 = 12;
+
+/// Encoded size responses are held to unless [`Response::limit`] says
+/// otherwise.
+pub const DEFAULT_RESPONSE_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResponseError {
+    #[error("Key {0} is written more than once")]
+    DuplicateKey(String),
+    #[error("Response is {size} bytes, over the limit of {limit}")]
+    TooLarge { size: usize, limit: usize },
+}
+
+impl From<ResponseError> for u64 {
+    fn from(_: ResponseError) -> Self {
+        RESPONSE_ERROR_CODE
+    }
+}
+
+/// Builds the actions a contract responds with, checking them as they are
+/// added:
+///
+/// ```ignore
+/// Response::new()
+///     .insert("counter", DbValue::Number(1))
+///     .emit("counted", DbValue::Boolean(true))
+///     .build()
+/// ```
+///
+/// The first problem found is returned by [`Response::build`].
+#[derive(Debug)]
+pub struct Response {
+    actions: Vec<DataAction>,
+    keys: HashSet<String>,
+    size: usize,
+    limit: usize,
+    error: Option<ResponseError>,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Response {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            actions: Vec::new(),
+            keys: HashSet::new(),
+            size: 0,
+            limit: DEFAULT_RESPONSE_LIMIT,
+            error: None,
+        }
+    }
+
+    /// Sets the largest encoded size, in bytes, the response may reach.
+    #[must_use]
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    #[must_use]
+    pub fn insert(self, key: impl Into<String>, value: DbValue) -> Self {
+        self.push(DataAction::Insert {
+            key: key.into(),
+            incoming_data: value,
+            params: HashMap::new(),
+        })
+    }
+
+    #[must_use]
+    pub fn delete(self, key: impl Into<String>) -> Self {
+        self.push(DataAction::Delete { key: key.into() })
+    }
+
+    #[must_use]
+    pub fn patch(self, key: impl Into<String>, ops: Vec<PatchOp>) -> Self {
+        self.push(DataAction::Patch {
+            key: key.into(),
+            ops,
+        })
+    }
+
+    #[must_use]
+    pub fn emit(self, topic: impl Into<String>, payload: DbValue) -> Self {
+        self.push(DataAction::Emit {
+            topic: topic.into(),
+            payload,
+        })
+    }
+
+    fn push(mut self, action: DataAction) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+
+        if let Some(key) = action.key().filter(|key| self.keys.contains(*key)) {
+            self.error = Some(ResponseError::DuplicateKey(key.to_string()));
+            return self;
+        }
+
+        self.size += rmp_serde::to_vec(&action).map_or(0, |v| v.len());
+        if self.size > self.limit {
+            self.error = Some(ResponseError::TooLarge {
+                size: self.size,
+                limit: self.limit,
+            });
+            return self;
+        }
+
+        if let Some(key) = action.key() {
+            self.keys.insert(key.to_string());
+        }
+        self.actions.push(action);
+        self
+    }
+
+    /// Returns the actions in the order they were added.
+    pub fn build(self) -> Result<Vec<DataAction>, ResponseError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.actions),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use rvb_common::schema::{DataAction, DbValue};
+
+use crate::{RESPONSE_ERROR_CODE, Response, ResponseError};
+
+#[test]
+fn test_actions_in_order() {
+    let actions = Response::new()
+        .insert("counter", DbValue::Number(1))
+        .delete("old")
+        .emit("counted", DbValue::Boolean(true))
+        .emit("counted", DbValue::Boolean(false))
+        .build()
+        .unwrap();
+
+    assert_eq!(actions.len(), 4);
+    assert_eq!(actions[0].key(), Some("counter"));
+    assert_eq!(
+        actions[1],
+        DataAction::Delete {
+            key: "old".to_string()
+        }
+    );
+    assert!(matches!(&actions[3], DataAction::Emit { topic, .. } if topic == "counted"));
+}
+
+#[test]
+fn test_duplicate_keys() {
+    let err = Response::new()
+        .insert("counter", DbValue::Number(1))
+        .emit("counted", DbValue::Boolean(true))
+        .delete("counter")
+        .insert("other", DbValue::Number(2))
+        .build()
+        .unwrap_err();
+
+    assert!(matches!(&err, ResponseError::DuplicateKey(key) if key == "counter"));
+    assert_eq!(u64::from(err), RESPONSE_ERROR_CODE);
+}
+
+#[test]
+fn test_size_limit() {
+    let value = DbValue::String("x".repeat(64));
+    let one = Response::new()
+        .limit(100)
+        .insert("a", value.clone())
+        .build()
+        .unwrap();
+    assert_eq!(one.len(), 1);
+
+    let err = Response::new()
+        .limit(100)
+        .insert("a", value.clone())
+        .insert("b", value)
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, ResponseError::TooLarge { size, limit: 100 } if size > 100));
+}
+
+#[test]
+fn test_first_error_is_kept() {
+    let err = Response::new()
+        .limit(10)
+        .insert("a", DbValue::String("x".repeat(64)))
+        .insert("a", DbValue::None)
+        .build()
+        .unwrap_err();
+
+    assert!(matches!(err, ResponseError::TooLarge { .. }));
+}
@@ -0,0 +1,193 @@
+//! Runs the exports the macros generate and the host wrappers natively,
+//! against stand-ins for the host functions they call.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use rvb_common::{
+    contract::{ABI_VERSION, CONTEXT_TOO_LARGE, CONTEXT_WRITTEN, ContractContext},
+    schema::{DataAction, DbValue},
+};
+
+use crate::host;
+use stubs::DATA;
+
+/// Stand-ins for the host functions, used by [`crate::host`] and
+/// [`crate::try_get_context`] in place of their imports when testing. They
+/// are plain Rust functions rather than exported symbols, so they cannot
+/// clash with symbols of the same name, such as libm's `log`.
+pub(crate) mod stubs {
+    use super::*;
+
+    thread_local! {
+        /// Encoded values `get` finds, by key.
+        pub(super) static DATA: RefCell<HashMap<String, Vec<u8>>> = RefCell::default();
+        /// Encoded values of the contract's private state, by key.
+        static STATE: RefCell<HashMap<String, Vec<u8>>> = RefCell::default();
+        /// Value looked up last, copied out by `write_value`.
+        static FOUND: RefCell<Vec<u8>> = RefCell::default();
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` and `len` must describe a live buffer.
+    unsafe fn bytes<'a>(ptr: u64, len: u64) -> &'a [u8] {
+        // SAFETY: guaranteed by the caller
+        unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }
+    }
+
+    /// Keeps the value of `key` in `values` for `write_value` and returns its
+    /// length, 0 if there is none.
+    fn look_up(
+        values: &'static std::thread::LocalKey<RefCell<HashMap<String, Vec<u8>>>>,
+        key: &[u8],
+    ) -> u64 {
+        let key = String::from_utf8_lossy(key);
+        let value = values.with(|values| values.borrow().get(key.as_ref()).cloned());
+        let len = value.as_ref().map_or(0, Vec::len) as u64;
+        FOUND.with(|found| *found.borrow_mut() = value.unwrap_or_default());
+        len
+    }
+
+    pub(crate) unsafe fn read_context(_codec: u64, ptr: u64, capacity: u64) -> u64 {
+        let context = rmp_serde::to_vec(&ContractContext {
+            action: DataAction::Delete {
+                key: "key".to_string(),
+            },
+            namespace: "namespace".to_string(),
+            contract_space: "contract".to_string(),
+            signed_by: Vec::new(),
+            contract_params: HashMap::new(),
+            message_id: Vec::new(),
+            signed_at: 0,
+            publisher: String::new(),
+            state: 0,
+        })
+        .unwrap();
+        let len = context.len() as u64;
+        if len > capacity {
+            return (u64::from(CONTEXT_TOO_LARGE) << 32) | len;
+        }
+
+        // SAFETY: the buffer has room for `capacity` bytes, as `get_context` says
+        unsafe { std::ptr::copy_nonoverlapping(context.as_ptr(), ptr as *mut u8, context.len()) };
+        (u64::from(CONTEXT_WRITTEN) << 32) | len
+    }
+
+    pub(crate) unsafe fn host_get(key_ptr: u64, key_len: u64) -> u64 {
+        // SAFETY: the wrapper passes its key
+        look_up(&DATA, unsafe { bytes(key_ptr, key_len) })
+    }
+
+    pub(crate) unsafe fn host_state_get(key_ptr: u64, key_len: u64) -> u64 {
+        // SAFETY: the wrapper passes its key
+        look_up(&STATE, unsafe { bytes(key_ptr, key_len) })
+    }
+
+    pub(crate) unsafe fn write_value(ptr: u64) -> u64 {
+        FOUND.with(|found| {
+            let found = found.borrow();
+            // SAFETY: the wrapper allocated the length `get` reported
+            unsafe { std::ptr::copy_nonoverlapping(found.as_ptr(), ptr as *mut u8, found.len()) };
+        });
+        0
+    }
+
+    pub(crate) unsafe fn host_state_set(
+        key_ptr: u64,
+        key_len: u64,
+        value_ptr: u64,
+        value_len: u64,
+    ) {
+        // SAFETY: the wrapper passes its key and encoded value
+        let (key, value) = unsafe { (bytes(key_ptr, key_len), bytes(value_ptr, value_len)) };
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .insert(String::from_utf8_lossy(key).into_owned(), value.to_vec())
+        });
+    }
+
+    pub(crate) unsafe fn host_random_seed(_out_ptr: u64) {
+        unimplemented!("no stand-in for random_seed")
+    }
+
+    pub(crate) unsafe fn host_blake3(_ptr: u64, _len: u64, _out_ptr: u64) {
+        unimplemented!("no stand-in for blake3")
+    }
+
+    pub(crate) unsafe fn host_sha256(_ptr: u64, _len: u64, _out_ptr: u64) {
+        unimplemented!("no stand-in for sha256")
+    }
+
+    pub(crate) unsafe fn host_verify(
+        _key_ptr: u64,
+        _key_len: u64,
+        _data_ptr: u64,
+        _data_len: u64,
+        _sig_ptr: u64,
+        _sig_len: u64,
+    ) -> u64 {
+        unimplemented!("no stand-in for verify")
+    }
+
+    pub(crate) unsafe fn host_log(ptr: u64, len: u64) {
+        // SAFETY: the wrapper passes its message
+        eprintln!("{}", String::from_utf8_lossy(unsafe { bytes(ptr, len) }));
+    }
+
+    pub(crate) unsafe fn host_panic(ptr: u64, len: u64) {
+        // SAFETY: the panic hook passes a live message of `len` bytes
+        eprintln!("{}", String::from_utf8_lossy(unsafe { bytes(ptr, len) }));
+    }
+}
+
+/// Packs a contract's error code the way exports report it.
+fn failed_with(code: u64) -> u64 {
+    code << 32
+}
+
+mod handlers {
+    use rvb_common::schema::DataAction;
+
+    crate::contract_handlers! {
+        on_insert: |_ctx| { Err(1) },
+        on_delete: |ctx| {
+            match ctx.action {
+                DataAction::Delete { key } if key == "key" => Err(2),
+                _ => Err(0),
+            }
+        },
+        on_query: |_ctx| { Err(3) },
+    }
+}
+
+#[test]
+fn test_handlers_dispatch_by_action() {
+    assert_eq!(handlers::rvb_abi_version(), ABI_VERSION);
+    assert_eq!(handlers::rvb_on_insert(), failed_with(1));
+    assert_eq!(handlers::rvb_on_delete(), failed_with(2));
+    assert_eq!(handlers::rvb_query(), failed_with(3));
+}
+
+#[test]
+fn test_host_reads() {
+    DATA.with(|data| {
+        data.borrow_mut().insert(
+            "key".to_string(),
+            rmp_serde::to_vec(&DbValue::Number(1)).unwrap(),
+        )
+    });
+
+    assert_eq!(host::get("key"), Some(DbValue::Number(1)));
+    assert_eq!(host::get("missing"), None);
+}
+
+#[test]
+fn test_host_state() {
+    assert_eq!(host::state_get("count"), None);
+    host::state_set("count", &DbValue::Number(2));
+    assert_eq!(host::state_get("count"), Some(DbValue::Number(2)));
+    // Private state is apart from the namespace's data
+    assert_eq!(host::get("count"), None);
+}
@@ -9,7 +9,9 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 contract! {
     |ctx| {
-        let DataAction::Insert { key, incoming_data, .. } = ctx.action;
+        let DataAction::Insert { key, incoming_data, .. } = ctx.action else {
+            return Err(1);
+        };
 
         Ok(vec![
             DataAction::Insert { key, incoming_data, params: HashMap::new() },
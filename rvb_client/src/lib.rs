@@ -0,0 +1,623 @@
+//! Client for applications talking to a reverb node. A [`Client`] holds one
+//! connection: it introduces itself with `Hello`, signs every request with
+//! its keypair, and routes the node's replies back to the calls waiting for
+//! them.
+//!
+//! Once welcomed, the client encrypts the connection with the session agreed
+//! on through the `session_key`s of its `Hello` and the node's `WhoAreYou`.
+//!
+//! Replies do not carry the id of the request they answer, so they are
+//! matched by what they are about: a `GetResult` goes to the oldest pending
+//! `get` of the same location.
+
+use log::debug;
+use rvb_common::crypto::{
+    CryptoError, GroupKey, IdentityCertificate, KeyPair, PublicKey, SessionState, ThresholdKey,
+    ThresholdSignature, b64_encode, contract_id, hash,
+};
+use rvb_common::delta::{self, DeltaError};
+use rvb_common::protocol::{
+    ContractEvent, Location, Message, ProtocolError, StateProof, TransportMessage,
+    challenge_payload,
+};
+use rvb_common::schema::{DbValue, Filter};
+use rvb_common::transport::{Client as _, TransportError, TransportPeer};
+use rvb_transport::tcp::TcpClient;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, broadcast, oneshot};
+use tokio::task::JoinHandle;
+
+pub mod offline;
+
+#[cfg(test)]
+mod tests;
+
+/// How long a request waits for its reply by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub enum ClientError {
+    TransportError(TransportError),
+    ProtocolError(ProtocolError),
+    SigningError(CryptoError),
+    /// A message could not be sealed or opened with the session key.
+    SessionError(CryptoError),
+    /// The node did not reply within the client's timeout.
+    Timeout,
+    /// The connection closed before the node replied.
+    Closed,
+    /// A group key could not be wrapped for a member, or unwrapped with the
+    /// client's key.
+    GroupKeyError(CryptoError),
+    /// A read asked to be proven came back without a proof, or with one that
+    /// does not hold.
+    InvalidProof(Option<CryptoError>),
+    PatchError(DeltaError),
+    /// Writes could not be kept in or read back from a
+    /// [`WriteStore`](offline::WriteStore).
+    StorageError(std::io::Error),
+}
+
+/// Requests waiting for their reply, by what the reply is about.
+type Waiters<K, V> = Arc<Mutex<HashMap<K, VecDeque<oneshot::Sender<V>>>>>;
+
+/// Reads waiting for the value and proof of a `GetResult`.
+type Gets = Waiters<Location, (Option<DbValue>, Option<StateProof>)>;
+
+/// Requests waiting for the epoch and wrapped key of a `GroupKey` reply.
+type GroupKeys = Waiters<String, (u64, Option<Vec<u8>>)>;
+
+/// State shared between the client and the task reading from the node.
+struct Connection {
+    keypair: KeyPair,
+    identity: Vec<u8>,
+    peer: Box<dyn TransportPeer>,
+    /// Key the node signed its last message with.
+    node: std::sync::Mutex<Option<Vec<u8>>>,
+    session: SessionState,
+    /// Held while a message is sealed and sent, so they reach the node in
+    /// the order they were sealed.
+    sending: Mutex<()>,
+}
+
+impl Connection {
+    async fn send(&self, messages: &[Message]) -> Result<(), ClientError> {
+        let msg = TransportMessage::sign_with(messages, &self.keypair, b64_encode(&self.identity))
+            .await
+            .map_err(ClientError::SigningError)?;
+        self.send_raw(rmp_serde::to_vec(&msg).expect("Failed to encode message"))
+            .await
+    }
+
+    /// Sends an encoded batch, sealed if the session is.
+    async fn send_raw(&self, mut raw: Vec<u8>) -> Result<(), ClientError> {
+        let _sending = self.sending.lock().await;
+        if let Some(sealed) = self.session.seal(&raw).map_err(ClientError::SessionError)? {
+            raw = sealed;
+        }
+        self.peer
+            .send(raw.into())
+            .await
+            .map_err(ClientError::TransportError)
+    }
+
+    async fn recv(&self) -> Result<Vec<Message>, ClientError> {
+        let mut raw = self
+            .peer
+            .recv()
+            .await
+            .map_err(ClientError::TransportError)?;
+        if let Some(opened) = self.session.open(&raw).map_err(ClientError::SessionError)? {
+            raw = opened.into();
+        }
+        let msg: TransportMessage = rmp_serde::from_slice(&raw)
+            .map_err(|e| ClientError::ProtocolError(ProtocolError::Schema(e)))?;
+        let signed_by = msg.signature.signed_by.clone();
+        let messages: Vec<Message> = msg.try_into().map_err(ClientError::ProtocolError)?;
+        // The node's part of the key exchange, taken before its first sealed
+        // message is read
+        for message in &messages {
+            if let Message::WhoAreYou {
+                public_key,
+                session_key,
+                ..
+            } = message
+                && *public_key == signed_by
+            {
+                self.session
+                    .agree(session_key)
+                    .map_err(ClientError::SessionError)?;
+            }
+        }
+        *self.node.lock().unwrap() = Some(signed_by);
+        Ok(messages)
+    }
+}
+
+pub struct Client {
+    connection: Arc<Connection>,
+    gets: Gets,
+    group_keys: GroupKeys,
+    events: broadcast::Sender<ContractEvent>,
+    reader: JoinHandle<()>,
+    timeout: Duration,
+}
+
+/// Events from one [`Client::subscribe`] call.
+pub struct Subscription {
+    namespace: String,
+    topic: Option<String>,
+    filter: Option<Filter>,
+    events: broadcast::Receiver<ContractEvent>,
+}
+
+impl Subscription {
+    /// Waits for the next event in the subscribed namespace and topic that
+    /// matches the filter. `None` once the connection is closed.
+    pub async fn next(&mut self) -> Option<ContractEvent> {
+        loop {
+            match self.events.recv().await {
+                Ok(event)
+                    if event.namespace == self.namespace
+                        && self.topic.as_ref().is_none_or(|t| *t == event.topic)
+                        && self
+                            .filter
+                            .as_ref()
+                            .is_none_or(|f| f.matches(&event.payload)) =>
+                {
+                    return Some(event);
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("Subscription missed {missed} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Connects to the node at `addr` over TCP.
+    pub async fn connect(addr: &str, keypair: KeyPair) -> Result<Self, ClientError> {
+        let peer = TcpClient
+            .connect(addr)
+            .await
+            .map_err(ClientError::TransportError)?;
+        Self::handshake(peer, keypair, &[]).await
+    }
+
+    /// Introduces `keypair` to the node on the other end of `peer`, presenting
+    /// `certificates` to nodes that only admit trusted peers, and waits for
+    /// the node to welcome it after it answered the node's challenge. Nodes
+    /// refuse inserts and reads until then.
+    pub async fn handshake(
+        peer: Box<dyn TransportPeer>,
+        keypair: KeyPair,
+        certificates: &[IdentityCertificate],
+    ) -> Result<Self, ClientError> {
+        let identity = keypair.export_public();
+        let connection = Arc::new(Connection {
+            keypair,
+            identity: identity.clone(),
+            peer,
+            node: std::sync::Mutex::new(None),
+            session: SessionState::new(),
+            sending: Mutex::new(()),
+        });
+        connection
+            .send(&[Message::Hello {
+                public_key: identity,
+                session_key: connection.session.public().to_vec(),
+                certificates: certificates
+                    .iter()
+                    .map(IdentityCertificate::encode)
+                    .collect(),
+            }])
+            .await?;
+
+        let gets = Gets::default();
+        let group_keys = GroupKeys::default();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (welcome_tx, welcome) = oneshot::channel();
+        let reader = tokio::spawn(Self::read(
+            connection.clone(),
+            gets.clone(),
+            group_keys.clone(),
+            events.clone(),
+            welcome_tx,
+        ));
+
+        let client = Self {
+            connection,
+            gets,
+            group_keys,
+            events,
+            reader,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        match tokio::time::timeout(client.timeout, welcome).await {
+            Ok(Ok(())) => Ok(client),
+            Ok(Err(_)) => Err(ClientError::Closed),
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    /// Sets how long requests wait for their reply.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn identity(&self) -> &[u8] {
+        &self.connection.identity
+    }
+
+    /// Public key of the node, known once it sent a signed message.
+    #[must_use]
+    pub fn node_identity(&self) -> Option<Vec<u8>> {
+        self.connection.node.lock().unwrap().clone()
+    }
+
+    async fn read(
+        connection: Arc<Connection>,
+        gets: Gets,
+        group_keys: GroupKeys,
+        events: broadcast::Sender<ContractEvent>,
+        welcome: oneshot::Sender<()>,
+    ) {
+        let mut welcome = Some(welcome);
+        loop {
+            let messages = match connection.recv().await {
+                Ok(messages) => messages,
+                Err(ClientError::TransportError(e)) => {
+                    debug!("Connection closed: {e:?}");
+                    break;
+                }
+                Err(ClientError::SessionError(e)) => {
+                    debug!("Closing connection with a broken session: {e:?}");
+                    break;
+                }
+                Err(e) => {
+                    debug!("Dropping message from node: {e:?}");
+                    continue;
+                }
+            };
+
+            for message in messages {
+                match message {
+                    Message::GetResult {
+                        location,
+                        value,
+                        proof,
+                    } => {
+                        let waiter = gets
+                            .lock()
+                            .await
+                            .get_mut(&location)
+                            .and_then(VecDeque::pop_front);
+                        if let Some(waiter) = waiter {
+                            let _ = waiter.send((value, proof));
+                        }
+                    }
+                    Message::GroupKey {
+                        namespace,
+                        epoch,
+                        key,
+                    } => {
+                        let waiter = group_keys
+                            .lock()
+                            .await
+                            .get_mut(&namespace)
+                            .and_then(VecDeque::pop_front);
+                        if let Some(waiter) = waiter {
+                            let _ = waiter.send((epoch, key));
+                        }
+                    }
+                    Message::Event { event } => {
+                        let _ = events.send(event);
+                    }
+                    Message::WhoAreYou { data, .. } => {
+                        let signature = connection.keypair.sign(&challenge_payload(&data));
+                        if let Err(e) = connection.send(&[Message::ItsMe { signature, data }]).await
+                        {
+                            debug!("Failed to answer WhoAreYou: {e:?}");
+                        }
+                    }
+                    Message::Welcome { .. } => {
+                        connection.session.secure();
+                        if let Some(welcome) = welcome.take() {
+                            let _ = welcome.send(());
+                        }
+                    }
+                    other => debug!("Ignoring message from node: {other:?}"),
+                }
+            }
+        }
+
+        // Wake up every pending request.
+        gets.lock().await.clear();
+        group_keys.lock().await.clear();
+    }
+
+    /// Whether the connection is still open. Once it closed, writes can be
+    /// kept in an [`offline::OfflineQueue`] until a new client connects.
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        !self.reader.is_finished()
+    }
+
+    /// Sends `batch`, an encoded [`TransportMessage`] signed elsewhere, as it
+    /// is.
+    pub(crate) async fn send_signed(&self, batch: Vec<u8>) -> Result<(), ClientError> {
+        self.connection.send_raw(batch).await
+    }
+
+    /// Sends `message` and waits for the reply `waiters` receive under `key`.
+    async fn request<K: Eq + std::hash::Hash, V>(
+        &self,
+        waiters: &Waiters<K, V>,
+        key: K,
+        message: Message,
+    ) -> Result<V, ClientError> {
+        if !self.is_connected() {
+            return Err(ClientError::Closed);
+        }
+        let (tx, rx) = oneshot::channel();
+        waiters.lock().await.entry(key).or_default().push_back(tx);
+
+        self.connection.send(&[message]).await?;
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(_)) => Err(ClientError::Closed),
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    /// Inserts `data` at `location`. The node applies it asynchronously, at
+    /// `state` or later.
+    pub async fn insert(
+        &self,
+        location: Location,
+        data: DbValue,
+        metadata: HashMap<String, DbValue>,
+        state: u64,
+    ) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::Insert {
+                location,
+                incoming_data: data,
+                metadata,
+                state,
+            }])
+            .await
+    }
+
+    /// Deletes the value at `location` as of `state`, if its contract
+    /// approves. Like inserts, the node applies it asynchronously.
+    pub async fn delete(&self, location: Location, state: u64) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::Delete { location, state }])
+            .await
+    }
+
+    /// Reads the value at `location`, narrowed to the `select`ed fields, or
+    /// all of it if `select` is empty.
+    pub async fn get(
+        &self,
+        location: Location,
+        select: Vec<Vec<String>>,
+    ) -> Result<Option<DbValue>, ClientError> {
+        let (value, _) = self
+            .request(
+                &self.gets,
+                location.clone(),
+                Message::Get { location, select },
+            )
+            .await?;
+        Ok(value)
+    }
+
+    /// Reads the whole value at `location` along with the node's proof that
+    /// it stores it, checked before it is returned. Only nodes configured to
+    /// prove their reads send one, and only for contracts without a query.
+    pub async fn get_proven(
+        &self,
+        location: Location,
+    ) -> Result<(Option<DbValue>, StateProof), ClientError> {
+        let (value, proof) = self
+            .request(
+                &self.gets,
+                location.clone(),
+                Message::Get {
+                    location: location.clone(),
+                    select: Vec::new(),
+                },
+            )
+            .await?;
+        let proof = proof.ok_or(ClientError::InvalidProof(None))?;
+        proof
+            .verify(&location, value.as_ref())
+            .map_err(|e| ClientError::InvalidProof(Some(e)))?;
+        Ok((value, proof))
+    }
+
+    /// Limits who may insert into `namespace` to the public keys in
+    /// `writers`, or lets anyone insert again if `None`. Only takes effect if
+    /// the client deployed a contract in the namespace.
+    pub async fn set_writers(
+        &self,
+        namespace: String,
+        writers: Option<Vec<Vec<u8>>>,
+    ) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::SetWriters { namespace, writers }])
+            .await
+    }
+
+    /// Hands control of who writes to `namespace` to `admins`, after which
+    /// its writers change only with [`Client::admin_set_writers`]. Only takes
+    /// effect if the client deployed a contract in the namespace and it has
+    /// no admins yet.
+    pub async fn set_admins(
+        &self,
+        namespace: String,
+        admins: &ThresholdKey,
+    ) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::SetAdmins {
+                namespace,
+                admins: admins.export(),
+            }])
+            .await
+    }
+
+    /// Sets the writers of `namespace` as its admins agreed: `signature`
+    /// holds their signatures over [`admin_payload`](rvb_common::protocol::admin_payload)
+    /// of the same arguments. `sequence` must be above that of every change
+    /// applied before.
+    pub async fn admin_set_writers(
+        &self,
+        namespace: String,
+        writers: Option<Vec<Vec<u8>>>,
+        sequence: u64,
+        signature: ThresholdSignature,
+    ) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::AdminSetWriters {
+                namespace,
+                writers,
+                sequence,
+                shares: signature.shares,
+            }])
+            .await
+    }
+
+    /// Makes `namespace` private to `members`, handing each of them `key`
+    /// wrapped to their public key. The first client to share a group for a
+    /// namespace owns it; later epochs, such as one leaving out a removed
+    /// member, must be shared by the same key.
+    pub async fn share_group_key(
+        &self,
+        namespace: String,
+        key: &GroupKey,
+        members: &[PublicKey],
+    ) -> Result<(), ClientError> {
+        let keys = members
+            .iter()
+            .map(|member| Ok((member.export(), key.wrap(member)?)))
+            .collect::<Result<HashMap<_, _>, CryptoError>>()
+            .map_err(ClientError::GroupKeyError)?;
+        self.connection
+            .send(&[Message::ShareGroupKey {
+                namespace,
+                epoch: key.epoch(),
+                keys,
+            }])
+            .await
+    }
+
+    /// Current key of the group of `namespace`, for sealing values inserted
+    /// into it and opening those read from it. `None` if the namespace is
+    /// not private or the client is not a member.
+    pub async fn group_key(&self, namespace: String) -> Result<Option<GroupKey>, ClientError> {
+        let (epoch, key) = self
+            .request(
+                &self.group_keys,
+                namespace.clone(),
+                Message::GetGroupKey { namespace },
+            )
+            .await?;
+        key.map(|key| GroupKey::unwrap(&self.connection.keypair, epoch, &key))
+            .transpose()
+            .map_err(ClientError::GroupKeyError)
+    }
+
+    /// Deploys a contract and returns its id, the [`contract_id`] of the
+    /// bytecode and the client's key, known without waiting for the node.
+    pub async fn deploy_contract(
+        &self,
+        contract_payload: Vec<u8>,
+        namespace: String,
+        params: HashMap<String, DbValue>,
+        tags: Vec<String>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let id = contract_id(&contract_payload, &self.connection.identity).to_vec();
+        self.connection
+            .send(&[Message::DeployContract {
+                contract_payload,
+                namespace,
+                params,
+                tags,
+                id: Some(id.clone()),
+            }])
+            .await?;
+        Ok(id)
+    }
+
+    /// Upgrades contract `id`, deployed by this client, from `base`, its
+    /// current bytecode, to `bytecode`, sending only a patch between the two.
+    pub async fn patch_contract(
+        &self,
+        id: Vec<u8>,
+        base: &[u8],
+        bytecode: &[u8],
+    ) -> Result<(), ClientError> {
+        let patch = delta::diff(base, bytecode).map_err(ClientError::PatchError)?;
+        self.connection
+            .send(&[Message::PatchContract {
+                contract: id,
+                base: hash::blake3(base).to_vec(),
+                patch,
+                target: hash::blake3(bytecode).to_vec(),
+            }])
+            .await
+    }
+
+    /// Asks the node to forward events emitted in `namespace`, on `topic` or
+    /// on all topics, and with a payload matching `filter` if one is given.
+    /// The node drops the others before sending them.
+    pub async fn subscribe(
+        &self,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<Filter>,
+    ) -> Result<Subscription, ClientError> {
+        let events = self.events.subscribe();
+        self.connection
+            .send(&[Message::Subscribe {
+                namespace: namespace.clone(),
+                topic: topic.clone(),
+                filter: filter.clone(),
+            }])
+            .await?;
+        Ok(Subscription {
+            namespace,
+            topic,
+            filter,
+            events,
+        })
+    }
+
+    pub async fn unsubscribe(
+        &self,
+        namespace: String,
+        topic: Option<String>,
+    ) -> Result<(), ClientError> {
+        self.connection
+            .send(&[Message::Unsubscribe { namespace, topic }])
+            .await
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
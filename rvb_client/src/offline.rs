@@ -0,0 +1,251 @@
+//! Writes made while no node is reachable, kept until a client can send them.
+//! An [`OfflineQueue`] holds inserts in a [`WriteStore`]. Once connected
+//! again, [`OfflineQueue::replay`] sends them in the order they were made,
+//! and [`reconcile`] reads back what the node kept of them.
+//!
+//! Writes are signed as they are queued, so they are the writes of the key
+//! that made them, at the time it made them, whichever client sends them
+//! later. As they may wait long for a connection, their batches never
+//! expire; nodes drop batches they took before, so a write replayed twice is
+//! applied once. Their state still orders them against writes other clients
+//! made in the meantime, so a replayed write does not override a later one.
+
+use crate::{Client, ClientError};
+use rvb_common::crypto::{KeyPair, b64_encode};
+use rvb_common::protocol::{Location, Message, TransportMessage};
+use rvb_common::schema::DbValue;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// An insert waiting to be sent, as passed to [`Client::insert`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueuedWrite {
+    pub location: Location,
+    pub value: DbValue,
+    pub metadata: HashMap<String, DbValue>,
+    pub state: u64,
+    /// The insert as signed when it was queued, an encoded
+    /// [`TransportMessage`] that never expires.
+    pub batch: Vec<u8>,
+}
+
+/// Where an [`OfflineQueue`] keeps its writes. [`FileWriteStore`] keeps them
+/// across restarts; implement it over other storage that does to use that
+/// instead, as [`QueuedWrite`] serializes with serde.
+pub trait WriteStore: Send + Sync {
+    /// Adds `write` after the others, returning its sequence number.
+    fn push(&self, write: QueuedWrite) -> Result<u64, ClientError>;
+    /// Writes not sent yet, oldest first.
+    fn pending(&self) -> Result<Vec<(u64, QueuedWrite)>, ClientError>;
+    /// Forgets the write with `sequence` once it was sent.
+    fn remove(&self, sequence: u64) -> Result<(), ClientError>;
+}
+
+/// Keeps writes in memory, for as long as the process runs.
+#[derive(Default)]
+pub struct MemoryWriteStore {
+    writes: Mutex<(u64, BTreeMap<u64, QueuedWrite>)>,
+}
+
+impl WriteStore for MemoryWriteStore {
+    fn push(&self, write: QueuedWrite) -> Result<u64, ClientError> {
+        let mut writes = self.writes.lock().unwrap();
+        let sequence = writes.0;
+        writes.0 += 1;
+        writes.1.insert(sequence, write);
+        Ok(sequence)
+    }
+
+    fn pending(&self) -> Result<Vec<(u64, QueuedWrite)>, ClientError> {
+        let writes = self.writes.lock().unwrap();
+        Ok(writes
+            .1
+            .iter()
+            .map(|(sequence, write)| (*sequence, write.clone()))
+            .collect())
+    }
+
+    fn remove(&self, sequence: u64) -> Result<(), ClientError> {
+        self.writes.lock().unwrap().1.remove(&sequence);
+        Ok(())
+    }
+}
+
+/// Keeps each write in a file of its own in a directory, so writes outlive
+/// the process. Files are written whole before they are named after their
+/// sequence number, so a crash while queueing loses at most that write.
+pub struct FileWriteStore {
+    dir: PathBuf,
+    next: Mutex<u64>,
+}
+
+const WRITE_EXTENSION: &str = "write";
+
+impl FileWriteStore {
+    /// Store in `dir`, created if missing, taking up the writes left in it.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, ClientError> {
+        let store = Self {
+            dir: dir.into(),
+            next: Mutex::new(0),
+        };
+        std::fs::create_dir_all(&store.dir).map_err(ClientError::StorageError)?;
+        let next = store.sequences()?.last().map_or(0, |last| last + 1);
+        *store.next.lock().unwrap() = next;
+        Ok(store)
+    }
+
+    fn path(&self, sequence: u64) -> PathBuf {
+        self.dir.join(format!("{sequence:020}.{WRITE_EXTENSION}"))
+    }
+
+    /// Sequence numbers of the writes in the directory, in order.
+    fn sequences(&self) -> Result<Vec<u64>, ClientError> {
+        let mut sequences = Vec::new();
+        for entry in std::fs::read_dir(&self.dir).map_err(ClientError::StorageError)? {
+            let path = entry.map_err(ClientError::StorageError)?.path();
+            if path.extension().is_some_and(|ext| ext == WRITE_EXTENSION)
+                && let Some(sequence) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse().ok())
+            {
+                sequences.push(sequence);
+            }
+        }
+        sequences.sort_unstable();
+        Ok(sequences)
+    }
+}
+
+impl WriteStore for FileWriteStore {
+    fn push(&self, write: QueuedWrite) -> Result<u64, ClientError> {
+        let mut next = self.next.lock().unwrap();
+        let sequence = *next;
+        let path = self.path(sequence);
+        let partial = path.with_extension("partial");
+        let raw = rmp_serde::to_vec(&write).expect("Failed to encode write");
+        std::fs::write(&partial, raw).map_err(ClientError::StorageError)?;
+        std::fs::rename(&partial, &path).map_err(ClientError::StorageError)?;
+        *next += 1;
+        Ok(sequence)
+    }
+
+    fn pending(&self) -> Result<Vec<(u64, QueuedWrite)>, ClientError> {
+        let mut pending = Vec::new();
+        for sequence in self.sequences()? {
+            let raw = std::fs::read(self.path(sequence)).map_err(ClientError::StorageError)?;
+            let write = rmp_serde::from_slice(&raw).map_err(|e| {
+                ClientError::StorageError(std::io::Error::new(ErrorKind::InvalidData, e))
+            })?;
+            pending.push((sequence, write));
+        }
+        Ok(pending)
+    }
+
+    fn remove(&self, sequence: u64) -> Result<(), ClientError> {
+        match std::fs::remove_file(self.path(sequence)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ClientError::StorageError(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Inserts held back until a node can take them, signed by the key they are
+/// queued with.
+pub struct OfflineQueue {
+    store: Arc<dyn WriteStore>,
+    keypair: Mutex<KeyPair>,
+}
+
+impl OfflineQueue {
+    #[must_use]
+    pub fn new(store: Arc<dyn WriteStore>, keypair: KeyPair) -> Self {
+        Self {
+            store,
+            keypair: Mutex::new(keypair),
+        }
+    }
+
+    /// Signs and queues an insert of `value` at `location`, returning its
+    /// sequence number.
+    pub fn insert(
+        &self,
+        location: Location,
+        value: DbValue,
+        metadata: HashMap<String, DbValue>,
+        state: u64,
+    ) -> Result<u64, ClientError> {
+        let insert = Message::Insert {
+            location: location.clone(),
+            incoming_data: value.clone(),
+            metadata: metadata.clone(),
+            state,
+        };
+        let batch = {
+            let mut keypair = self.keypair.lock().unwrap();
+            let publisher = b64_encode(&keypair.export_public());
+            TransportMessage::sign_expiring(&[insert], &mut keypair, publisher, 0)
+        };
+        self.store.push(QueuedWrite {
+            location,
+            value,
+            metadata,
+            state,
+            batch: rmp_serde::to_vec(&batch).expect("Failed to encode message"),
+        })
+    }
+
+    /// Writes not sent yet, oldest first.
+    pub fn pending(&self) -> Result<Vec<(u64, QueuedWrite)>, ClientError> {
+        self.store.pending()
+    }
+
+    /// Sends the queued writes through `client`, oldest first and as they
+    /// were signed, forgetting each once sent. Stops at the first that cannot
+    /// be sent, which stays queued with those after it. Returns the writes
+    /// sent.
+    pub async fn replay(&self, client: &Client) -> Result<Vec<QueuedWrite>, ClientError> {
+        let mut sent = Vec::new();
+        for (sequence, write) in self.store.pending()? {
+            client.send_signed(write.batch.clone()).await?;
+            self.store.remove(sequence)?;
+            sent.push(write);
+        }
+        Ok(sent)
+    }
+}
+
+/// What the node holds at the location of a replayed write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciled {
+    pub write: QueuedWrite,
+    /// The value read back, `None` if there is none.
+    pub value: Option<DbValue>,
+}
+
+impl Reconciled {
+    /// Whether the node holds the written value. Not so if a write with a
+    /// later state won, or the contract refused or changed the value. Values
+    /// of contracts answering reads with a query never compare equal.
+    #[must_use]
+    pub fn kept(&self) -> bool {
+        self.value.as_ref() == Some(&self.write.value)
+    }
+}
+
+/// Reads back the locations of `writes` through `client`. A node takes the
+/// inserts and reads of a client for the same location in order, so writes
+/// just replayed through `client` are applied before they are read.
+pub async fn reconcile(
+    client: &Client,
+    writes: Vec<QueuedWrite>,
+) -> Result<Vec<Reconciled>, ClientError> {
+    let mut reconciled = Vec::with_capacity(writes.len());
+    for write in writes {
+        let value = client.get(write.location.clone(), Vec::new()).await?;
+        reconciled.push(Reconciled { write, value });
+    }
+    Ok(reconciled)
+}
@@ -0,0 +1,297 @@
+use super::*;
+use bytes::Bytes;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+
+/// One end of an in-memory connection.
+struct Pipe {
+    tx: Sender<Bytes>,
+    rx: Mutex<Receiver<Bytes>>,
+}
+
+fn pipe() -> (Pipe, Pipe) {
+    let (a_tx, a_rx) = channel(16);
+    let (b_tx, b_rx) = channel(16);
+    (
+        Pipe {
+            tx: a_tx,
+            rx: Mutex::new(b_rx),
+        },
+        Pipe {
+            tx: b_tx,
+            rx: Mutex::new(a_rx),
+        },
+    )
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for Pipe {
+    async fn bye(self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        self.tx
+            .send(msg)
+            .await
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+}
+
+/// The node's end of a connection, speaking the protocol by hand.
+struct FakeNode {
+    connection: Connection,
+}
+
+impl FakeNode {
+    async fn expect(&self) -> Message {
+        self.connection.recv().await.unwrap().remove(0)
+    }
+
+    async fn reply(&self, message: Message) {
+        self.connection.send(&[message]).await.unwrap();
+    }
+
+    /// Takes the client through the handshake, checking it proves the key it
+    /// introduced.
+    async fn greet(&self) {
+        let Message::Hello { public_key, .. } = self.expect().await else {
+            panic!("expected Hello");
+        };
+        self.reply(Message::WhoAreYou {
+            data: b"challenge".to_vec(),
+            public_key: self.connection.identity.clone(),
+            session_key: Vec::new(),
+            certificates: Vec::new(),
+        })
+        .await;
+        let Message::ItsMe { signature, data } = self.expect().await else {
+            panic!("expected ItsMe");
+        };
+        assert_eq!(data, b"challenge");
+        assert!(
+            PublicKey::import(&public_key)
+                .unwrap()
+                .verify(&challenge_payload(&data), &signature)
+        );
+        self.reply(Message::Welcome {
+            dht_ip: String::new(),
+            dht_port: 0,
+            signature: Vec::new(),
+        })
+        .await;
+    }
+}
+
+async fn connect() -> (Client, FakeNode) {
+    let (client, node) = pipe();
+    let keypair = KeyPair::generate();
+    let node = FakeNode {
+        connection: Connection {
+            identity: keypair.export_public(),
+            keypair,
+            peer: Box::new(node),
+            node: std::sync::Mutex::new(None),
+            session: SessionState::new(),
+            sending: Mutex::new(()),
+        },
+    };
+    let (client, ()) = tokio::join!(
+        Client::handshake(Box::new(client), KeyPair::generate(), &[]),
+        node.greet()
+    );
+    (client.unwrap(), node)
+}
+
+fn location(key: &str) -> Location {
+    Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: vec![1],
+        key: key.into(),
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_and_get() {
+    let (client, node) = connect().await;
+
+    let (value, ()) = tokio::join!(client.get(location("a"), Vec::new()), async {
+        let Message::Get { location, .. } = node.expect().await else {
+            panic!("expected Get");
+        };
+        // A reply for another key must not satisfy the request.
+        node.reply(Message::GetResult {
+            location: self::location("b"),
+            value: Some(DbValue::Number(2)),
+            proof: None,
+        })
+        .await;
+        node.reply(Message::GetResult {
+            location,
+            value: Some(DbValue::Number(1)),
+            proof: None,
+        })
+        .await;
+    });
+    assert_eq!(value.unwrap(), Some(DbValue::Number(1)));
+    assert_eq!(
+        client.node_identity(),
+        Some(node.connection.identity.clone())
+    );
+}
+
+#[tokio::test]
+async fn test_get_times_out() {
+    let (client, _node) = connect().await;
+    let client = client.with_timeout(Duration::from_millis(50));
+    assert!(matches!(
+        client.get(location("a"), Vec::new()).await,
+        Err(ClientError::Timeout)
+    ));
+}
+
+#[tokio::test]
+async fn test_subscription_and_deploy() {
+    let (client, node) = connect().await;
+
+    let mut subscription = client
+        .subscribe("ns".into(), Some("changed".into()), None)
+        .await
+        .unwrap();
+    assert!(matches!(node.expect().await, Message::Subscribe { .. }));
+
+    for topic in ["other", "changed"] {
+        node.reply(Message::Event {
+            event: ContractEvent {
+                namespace: "ns".into(),
+                contract_space: "space".into(),
+                topic: topic.into(),
+                payload: DbValue::None,
+            },
+        })
+        .await;
+    }
+    assert_eq!(subscription.next().await.unwrap().topic, "changed");
+
+    let id = client
+        .deploy_contract(vec![0, 1, 2], "ns".into(), HashMap::new(), Vec::new())
+        .await
+        .unwrap();
+    assert_eq!(id, contract_id(&[0, 1, 2], client.identity()));
+    assert!(matches!(
+        node.expect().await,
+        Message::DeployContract { id: Some(sent), .. } if sent == id
+    ));
+}
+
+#[tokio::test]
+async fn test_offline_writes_replay_and_reconcile() {
+    use offline::{MemoryWriteStore, OfflineQueue, reconcile};
+
+    let author = KeyPair::generate();
+    let queue = OfflineQueue::new(Arc::new(MemoryWriteStore::default()), author.clone());
+    for (key, value) in [("a", 1), ("b", 2)] {
+        queue
+            .insert(location(key), DbValue::Number(value), HashMap::new(), 5)
+            .unwrap();
+    }
+    // Signed as they are queued, without an expiry
+    for (_, write) in queue.pending().unwrap() {
+        let batch: TransportMessage = rmp_serde::from_slice(&write.batch).unwrap();
+        assert_eq!(batch.signature.signed_by, author.export_public());
+        assert_eq!(batch.expires_at, 0);
+    }
+
+    // Nothing is lost while the node is gone
+    let (client, node) = connect().await;
+    drop(node);
+    while client.is_connected() {
+        tokio::task::yield_now().await;
+    }
+    assert!(queue.replay(&client).await.is_err());
+    assert_eq!(queue.pending().unwrap().len(), 2);
+
+    let (client, node) = connect().await;
+    let sent = queue.replay(&client).await.unwrap();
+    assert!(queue.pending().unwrap().is_empty());
+    for write in &sent {
+        let Message::Insert {
+            location, state, ..
+        } = node.expect().await
+        else {
+            panic!("expected Insert");
+        };
+        assert_eq!((&location, state), (&write.location, 5));
+        // Sent as the author signed it, not re-signed by the client
+        assert_eq!(
+            node.connection.node.lock().unwrap().as_deref(),
+            Some(author.export_public().as_slice())
+        );
+    }
+
+    let (reconciled, ()) = tokio::join!(reconcile(&client, sent), async {
+        for value in [1, 3] {
+            let Message::Get { location, .. } = node.expect().await else {
+                panic!("expected Get");
+            };
+            node.reply(Message::GetResult {
+                location,
+                value: Some(DbValue::Number(value)),
+                proof: None,
+            })
+            .await;
+        }
+    });
+    let kept: Vec<bool> = reconciled.unwrap().iter().map(|r| r.kept()).collect();
+    assert_eq!(kept, [true, false]);
+}
+
+#[test]
+fn test_file_write_store_outlives_the_process() {
+    use offline::{FileWriteStore, QueuedWrite, WriteStore};
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("rvb-writes-{}-{nanos}", std::process::id()));
+    let write = |key: &str| QueuedWrite {
+        location: location(key),
+        value: DbValue::Number(1),
+        metadata: HashMap::new(),
+        state: 1,
+        batch: key.as_bytes().to_vec(),
+    };
+
+    let store = FileWriteStore::open(&dir).unwrap();
+    assert_eq!(store.push(write("a")).unwrap(), 0);
+    assert_eq!(store.push(write("b")).unwrap(), 1);
+    drop(store);
+
+    let store = FileWriteStore::open(&dir).unwrap();
+    let pending = store.pending().unwrap();
+    assert_eq!(pending, vec![(0, write("a")), (1, write("b"))]);
+    store.remove(0).unwrap();
+    drop(store);
+
+    // Sequence numbers keep growing across restarts
+    let store = FileWriteStore::open(&dir).unwrap();
+    assert_eq!(store.push(write("c")).unwrap(), 2);
+    let keys: Vec<_> = store
+        .pending()
+        .unwrap()
+        .into_iter()
+        .map(|(_, write)| write.location.key)
+        .collect();
+    assert_eq!(keys, ["b", "c"]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
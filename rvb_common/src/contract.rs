@@ -1,9 +1,33 @@
-use std::{collections::HashMap, error::Error};
+use std::{collections::HashMap, error::Error, sync::Arc, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
 use crate::schema::{DataAction, DbValue};
 
+/// Newest host/guest ABI version this build speaks. Contracts report theirs
+/// through an exported `rvb_abi_version`; contracts without that export predate
+/// versioning and are treated as version 1. Version 2 added the message
+/// provenance fields to [`ContractContext`].
+pub const ABI_VERSION: u32 = 2;
+/// Oldest ABI version the runtimes still accept.
+pub const MIN_ABI_VERSION: u32 = 1;
+
+/// Encoding of the context written by the `read_context` host function:
+/// msgpack, shaped by the contract's ABI version. Guests pass the codec they
+/// decode, and hosts refuse codecs they do not know, so the context can change
+/// encoding without guests misreading it.
+pub const CONTEXT_CODEC: u32 = 1;
+/// Status `read_context` returns in its high 32 bits when it wrote the
+/// context. The low 32 bits are then the bytes written.
+pub const CONTEXT_WRITTEN: u32 = 0;
+/// Status of a `read_context` whose buffer lies outside the guest's memory.
+pub const CONTEXT_WRITE_FAILED: u32 = 1;
+/// Status of a `read_context` whose buffer is too small. Nothing was written,
+/// and the low 32 bits are the bytes needed.
+pub const CONTEXT_TOO_LARGE: u32 = 2;
+/// Status of a `read_context` asking for a codec the host does not have.
+pub const CONTEXT_UNSUPPORTED: u32 = 3;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ContractContext {
     pub action: DataAction,
@@ -11,12 +35,23 @@ pub struct ContractContext {
     pub contract_space: String,
     pub signed_by: Vec<u8>,
     pub contract_params: HashMap<String, DbValue>,
+    /// Id of the message that caused the call. Empty for calls the node makes
+    /// on its own, such as migrations.
+    pub message_id: Vec<u8>,
+    /// When the sender signed the message, in seconds since the Unix epoch,
+    /// as stated by the sender. 0 if unknown.
+    pub signed_at: u64,
+    /// Publisher named by the message.
+    pub publisher: String,
+    /// State counter of the key the call concerns, as stored before the call.
+    /// 0 if nothing is stored under it.
+    pub state: u64,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ContractError {
     #[error("Runtime error {0}")]
-    RuntimeError(Box<dyn Error>),
+    RuntimeError(Box<dyn Error + Send + Sync>),
     #[error("Compilation error {0}")]
     CompilationError(String),
     #[error("Contract not implemented")]
@@ -25,12 +60,143 @@ pub enum ContractError {
     InvalidResponse,
     #[error("Contract failed. Code: {0}")]
     ContractFailed(usize),
+    #[error("Contract panicked: {0}")]
+    Panicked(String),
+    #[error("Contract execution timed out")]
+    Timeout,
+    /// The call used up the fuel its runtime meters it with, as opposed to
+    /// running past its wall-clock deadline.
+    #[error("Contract ran out of fuel")]
+    OutOfFuel,
+    #[error(
+        "Contract ABI version {0} is not supported, expected {MIN_ABI_VERSION} to {ABI_VERSION}"
+    )]
+    AbiMismatch(u32),
+    /// The module breaks the contract ABI in a way caught before running it.
+    #[error("Invalid contract module: {0}")]
+    InvalidModule(String),
+}
+
+/// Descriptive information a contract carries about itself, read once when it
+/// is deployed.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ContractMetadata {
+    pub name: String,
+    pub version: String,
+    /// Public key of the contract's author.
+    pub author: Vec<u8>,
+    /// Contract params that must be supplied when deploying the contract.
+    pub required_params: Vec<String>,
+}
+
+/// Node-side services a contract can call back into while it runs.
+pub trait ContractHost: Send + Sync {
+    /// Current value of `key` in the given namespace and contract space.
+    fn get(&self, namespace: &str, contract_space: &str, key: &str) -> Option<DbValue>;
+
+    /// Seed for the contract's randomness. Must be derived from the triggering
+    /// message (its id and signature) so every replica draws the same values.
+    fn random_seed(&self) -> [u8; 32];
+
+    /// Value of `key` in the calling contract's private state. Each node keeps
+    /// private state for itself; it is never replicated like user data.
+    fn state_get(&self, _key: &str) -> Option<DbValue> {
+        None
+    }
+
+    /// Sets `key` in the calling contract's private state. Writes only take
+    /// effect once the call has succeeded.
+    fn state_set(&self, _key: &str, _value: DbValue) {}
+
+    /// Told the fuel a call consumed once it has finished, successfully or
+    /// not. Only runtimes that meter execution report it; units differ
+    /// between runtimes.
+    fn record_fuel(&self, _fuel: u64) {}
+
+    /// Told the size of the contract's linear memory in bytes once a call has
+    /// finished. Memory never shrinks during a call, so this is its peak.
+    fn record_memory(&self, _bytes: u64) {}
+}
+
+/// Resources a contract call used, as reported through [`ContractHost`].
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExecutionReport {
+    /// Fuel consumed, 0 for runtimes that do not meter execution.
+    pub fuel: u64,
+    /// Peak linear memory in bytes, 0 for runtimes without one.
+    pub peak_memory: u64,
+    /// Wall-clock time of the call.
+    pub duration: Duration,
+}
+
+/// Host with no stored data, for running contracts outside a node.
+pub struct NullHost;
+
+impl ContractHost for NullHost {
+    fn get(&self, _namespace: &str, _contract_space: &str, _key: &str) -> Option<DbValue> {
+        None
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        [0; 32]
+    }
+}
+
+pub trait Contract: Send {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError>;
+
+    /// Computes the response to a read of `ctx.action`, a `DataAction::Get`,
+    /// for contracts serving derived views. Queries cannot change any data.
+    /// Returns `None` if the contract has no query, in which case the stored
+    /// value is served as is.
+    fn query(
+        &mut self,
+        _ctx: ContractContext,
+        _host: Arc<dyn ContractHost>,
+    ) -> Result<Option<DbValue>, ContractError> {
+        Ok(None)
+    }
+
+    /// Runs the contract's init hook, once per contract space before the first
+    /// action there, with `ctx` describing that action. Returns `None` if the
+    /// contract has no hook.
+    fn init(
+        &mut self,
+        _ctx: ContractContext,
+        _host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        Ok(None)
+    }
+
+    /// Runs the contract's migration hook on one stored value after the
+    /// contract was upgraded to this code. `ctx.action` inserts the current
+    /// value under its key. Returns `None` if the contract has no hook, in
+    /// which case stored data is kept as is.
+    fn migrate(
+        &mut self,
+        _ctx: ContractContext,
+        _host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        Ok(None)
+    }
+
+    /// Metadata the contract describes itself with, if it provides any.
+    fn metadata(&mut self) -> Result<Option<ContractMetadata>, ContractError> {
+        Ok(None)
+    }
 }
 
-pub trait Contract {
-    fn execute(&mut self, ctx: ContractContext) -> Result<Vec<DataAction>, ContractError>;
+/// Persistent store for compiled contract artifacts, so runtimes can skip
+/// compilation for bytecode they have seen before.
+pub trait ArtifactCache: Send + Sync {
+    fn load(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn store(&self, key: &[u8], artifact: &[u8]);
 }
 
-pub trait ContractCompiler {
+pub trait ContractCompiler: Send + Sync {
     fn create_contract(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError>;
 }
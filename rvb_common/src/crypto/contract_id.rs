@@ -0,0 +1,23 @@
+//! Contract ids. A contract's id is the blake3 hash of the bytecode it was
+//! first deployed with followed by the public key that deployed it, so anyone
+//! can work it out before deploying, and two keys deploying the same bytecode
+//! get contracts of their own. Upgrades keep the id of the first deployment.
+
+use super::hash::{HashAlgorithm, Hasher};
+
+/// Id of the contract `deployer` deploys with `bytecode`.
+#[must_use]
+pub fn contract_id(bytecode: &[u8], deployer: &[u8]) -> [u8; 32] {
+    Hasher::new(HashAlgorithm::Blake3)
+        .update(bytecode)
+        .update(deployer)
+        .finalize()
+}
+
+/// Whether `id` is the id of the contract `deployer` deploys with `bytecode`,
+/// which is not the case if the bytecode was altered after the id was worked
+/// out.
+#[must_use]
+pub fn verify_contract_id(id: &[u8], bytecode: &[u8], deployer: &[u8]) -> bool {
+    id == contract_id(bytecode, deployer)
+}
@@ -0,0 +1,12 @@
+use super::contract_id::*;
+use super::hash::blake3;
+
+#[test]
+fn test_contract_id() {
+    let id = contract_id(b"contract", b"deployer");
+    assert_eq!(id, blake3(b"contractdeployer"));
+    assert_ne!(id, contract_id(b"contract", b"other deployer"));
+    assert!(verify_contract_id(&id, b"contract", b"deployer"));
+    assert!(!verify_contract_id(&id, b"tampered", b"deployer"));
+    assert!(!verify_contract_id(&id[..16], b"contract", b"deployer"));
+}
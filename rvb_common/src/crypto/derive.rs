@@ -0,0 +1,44 @@
+//! Deterministic derivation of keypairs from a master secret, so a fleet can
+//! be provisioned from one seed. Each `/` separated segment of a path keys a
+//! blake3 hash of its parent's chain key, so `fleet/eu/node-1` and
+//! `fleet/eu/node-2` are unrelated to each other and to `fleet/eu`, and the
+//! master cannot be recovered from any of them.
+
+use zeroize::Zeroizing;
+
+use super::{CryptoError, KEY_FORMAT_VERSION, KEY_LENGTH, KeyPair};
+
+/// Shortest master secret accepted by [`KeyPair::derive`].
+pub const MIN_MASTER_LENGTH: usize = 32;
+
+const MASTER_CONTEXT: &str = "reverb 2025-06 key derivation master";
+
+impl KeyPair {
+    /// Derives the ed25519 keypair at `path` under `master`. The same master
+    /// and path always give the same keypair.
+    pub fn derive(master: &[u8], path: &str) -> Result<Self, CryptoError> {
+        if master.len() < MIN_MASTER_LENGTH {
+            return Err(CryptoError::InvalidKeyFormat(format!(
+                "master secret must be at least {MIN_MASTER_LENGTH} bytes"
+            )));
+        }
+
+        let mut chain = Zeroizing::new(blake3::derive_key(MASTER_CONTEXT, master));
+        for segment in path.split('/') {
+            if segment.is_empty() {
+                return Err(CryptoError::InvalidKeyFormat(format!(
+                    "empty segment in derivation path {path:?}"
+                )));
+            }
+            *chain = *blake3::keyed_hash(&chain, segment.as_bytes()).as_bytes();
+        }
+
+        let mut data = Zeroizing::new(vec![0u8; KEY_LENGTH]);
+        data[0] = KEY_FORMAT_VERSION;
+        blake3::Hasher::new_keyed(&chain)
+            .update(b"keypair")
+            .finalize_xof()
+            .fill(&mut data[1..]);
+        Self::import(&data)
+    }
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+const MASTER: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+#[test]
+fn test_derivation_is_deterministic() {
+    let first = KeyPair::derive(MASTER, "fleet/eu/node-1").unwrap();
+    let second = KeyPair::derive(MASTER, "fleet/eu/node-1").unwrap();
+    assert_eq!(first.export_private(), second.export_private());
+
+    let signature = first.sign(b"node-1");
+    assert!(second.public().verify(b"node-1", &signature));
+}
+
+#[test]
+fn test_paths_give_distinct_keys() {
+    let keys = [
+        "fleet",
+        "fleet/eu",
+        "fleet/eu/node-1",
+        "fleet/eu/node-2",
+        "fleet/eunode-1",
+    ]
+    .map(|path| KeyPair::derive(MASTER, path).unwrap().export_public());
+    for (i, key) in keys.iter().enumerate() {
+        assert!(keys[i + 1..].iter().all(|other| other != key));
+    }
+
+    let other_master = KeyPair::derive(b"fedcba9876543210fedcba9876543210", "fleet").unwrap();
+    assert_ne!(other_master.export_public(), keys[0]);
+}
+
+#[test]
+fn test_invalid_derivations() {
+    assert!(KeyPair::derive(b"short", "fleet").is_err());
+    assert!(KeyPair::derive(MASTER, "").is_err());
+    assert!(KeyPair::derive(MASTER, "fleet//node-1").is_err());
+    assert!(KeyPair::derive(MASTER, "fleet/").is_err());
+}
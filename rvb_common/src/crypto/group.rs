@@ -0,0 +1,117 @@
+//! Symmetric keys shared by the members of a private namespace. The key is
+//! handed to each member wrapped to their public key, and values are sealed
+//! with it before they are sent to a node, so nodes and relays that are not
+//! members only ever handle ciphertext.
+//!
+//! Sealed values are strings of the form `sealed:<epoch>:<base64>`, where the
+//! payload is a random 12 byte nonce followed by the ChaCha20-Poly1305
+//! ciphertext of the msgpack encoded value.
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::Aead};
+use rand::{RngCore, rngs::OsRng};
+use zeroize::Zeroizing;
+
+use super::{CryptoError, KeyPair, PublicKey, b64_decode, b64_encode};
+use crate::schema::DbValue;
+
+const SEALED_PREFIX: &str = "sealed:";
+const NONCE_LENGTH: usize = 12;
+
+/// Key of one epoch of a namespace's group. A new epoch, with a new key, is
+/// started whenever a member is removed, so they cannot read later values.
+pub struct GroupKey {
+    epoch: u64,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl GroupKey {
+    #[must_use]
+    pub fn generate(epoch: u64) -> Self {
+        let mut key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut *key);
+        Self { epoch, key }
+    }
+
+    #[must_use]
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The key encrypted to `member`, for [`GroupKey::unwrap`].
+    pub fn wrap(&self, member: &PublicKey) -> Result<Vec<u8>, CryptoError> {
+        member.encrypt(&*self.key)
+    }
+
+    /// Recovers the key of `epoch` wrapped to `keypair`.
+    pub fn unwrap(keypair: &KeyPair, epoch: u64, wrapped: &[u8]) -> Result<Self, CryptoError> {
+        let key = Zeroizing::new(keypair.decrypt(wrapped)?);
+        Ok(Self {
+            epoch,
+            key: Zeroizing::new(
+                key.as_slice()
+                    .try_into()
+                    .map_err(|_| CryptoError::InvalidKey)?,
+            ),
+        })
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&*self.key))
+    }
+
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher()
+                .encrypt(Nonce::from_slice(&nonce), data)
+                .map_err(|_| CryptoError::InvalidKey)?,
+        );
+        Ok(sealed)
+    }
+
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if data.len() < NONCE_LENGTH {
+            return Err(CryptoError::InvalidKey);
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LENGTH);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| CryptoError::InvalidKey)
+    }
+
+    /// `value` sealed into a string only members can open.
+    pub fn seal_value(&self, value: &DbValue) -> Result<DbValue, CryptoError> {
+        let sealed = self.seal(&rmp_serde::to_vec(value).unwrap())?;
+        Ok(DbValue::String(format!(
+            "{SEALED_PREFIX}{}:{}",
+            self.epoch,
+            b64_encode(&sealed)
+        )))
+    }
+
+    /// Opens a value sealed with [`GroupKey::seal_value`] under this key.
+    pub fn open_value(&self, value: &DbValue) -> Result<DbValue, CryptoError> {
+        let (epoch, sealed) = split_sealed(value).ok_or(CryptoError::InvalidKey)?;
+        if epoch != self.epoch {
+            return Err(CryptoError::InvalidKey);
+        }
+        let opened = self.open(&b64_decode(sealed)?)?;
+        rmp_serde::from_slice(&opened).map_err(|_| CryptoError::InvalidKey)
+    }
+}
+
+fn split_sealed(value: &DbValue) -> Option<(u64, &str)> {
+    let DbValue::String(value) = value else {
+        return None;
+    };
+    let (epoch, sealed) = value.strip_prefix(SEALED_PREFIX)?.split_once(':')?;
+    Some((epoch.parse().ok()?, sealed))
+}
+
+/// Epoch of the key `value` was sealed with, or `None` if it is not sealed.
+#[must_use]
+pub fn sealed_epoch(value: &DbValue) -> Option<u64> {
+    split_sealed(value).map(|(epoch, _)| epoch)
+}
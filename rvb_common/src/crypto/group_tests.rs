@@ -0,0 +1,35 @@
+use super::*;
+use crate::schema::DbValue;
+
+#[test]
+fn test_group_key_roundtrip() {
+    let member = KeyPair::generate();
+    let key = GroupKey::generate(3);
+    let wrapped = key.wrap(&member.public()).unwrap();
+
+    let unwrapped = GroupKey::unwrap(&member, 3, &wrapped).unwrap();
+    let value = DbValue::String("secret".into());
+    let sealed = key.seal_value(&value).unwrap();
+    assert_ne!(sealed, value);
+    assert_eq!(sealed_epoch(&sealed), Some(3));
+    assert_eq!(unwrapped.open_value(&sealed).unwrap(), value);
+}
+
+#[test]
+fn test_group_key_rejects_outsiders() {
+    let key = GroupKey::generate(1);
+    let wrapped = key.wrap(&KeyPair::generate().public()).unwrap();
+    assert!(GroupKey::unwrap(&KeyPair::generate(), 1, &wrapped).is_err());
+
+    let sealed = key.seal_value(&DbValue::Number(1)).unwrap();
+    assert!(GroupKey::generate(1).open_value(&sealed).is_err());
+    // Keys of other epochs are not tried
+    assert!(GroupKey::generate(2).open_value(&sealed).is_err());
+}
+
+#[test]
+fn test_sealed_epoch_of_plain_values() {
+    assert_eq!(sealed_epoch(&DbValue::Number(1)), None);
+    assert_eq!(sealed_epoch(&DbValue::String("plain".into())), None);
+    assert_eq!(sealed_epoch(&DbValue::String("sealed:x:AAAA".into())), None);
+}
@@ -0,0 +1,63 @@
+//! Hash functions used across reverb. Contract ids, random seeds and artifact
+//! keys are blake3 digests; sha256 is offered to contracts for interop with
+//! other systems.
+
+use sha2::{Digest, Sha256};
+
+#[must_use]
+pub fn blake3(data: &[u8]) -> [u8; 32] {
+    *::blake3::hash(data).as_bytes()
+}
+
+#[must_use]
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+}
+
+#[derive(Clone)]
+enum State {
+    Blake3(Box<::blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+/// Incremental hasher, for input that arrives in pieces. Feeding it the
+/// pieces of `data` gives the same digest as hashing `data` at once.
+#[derive(Clone)]
+pub struct Hasher {
+    state: State,
+}
+
+impl Hasher {
+    #[must_use]
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        let state = match algorithm {
+            HashAlgorithm::Blake3 => State::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => State::Sha256(Sha256::new()),
+        };
+        Self { state }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> &mut Self {
+        match &mut self.state {
+            State::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            State::Sha256(hasher) => hasher.update(data),
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn finalize(&self) -> [u8; 32] {
+        match &self.state {
+            State::Blake3(hasher) => *hasher.finalize().as_bytes(),
+            State::Sha256(hasher) => hasher.clone().finalize().into(),
+        }
+    }
+}
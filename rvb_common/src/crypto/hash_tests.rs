@@ -0,0 +1,25 @@
+use super::b64_encode;
+use super::hash::*;
+
+#[test]
+fn test_incremental_hashing() {
+    for (algorithm, expected) in [
+        (HashAlgorithm::Blake3, blake3(b"hello world")),
+        (HashAlgorithm::Sha256, sha256(b"hello world")),
+    ] {
+        let digest = Hasher::new(algorithm)
+            .update(b"hello")
+            .update(b" world")
+            .finalize();
+        assert_eq!(digest, expected);
+    }
+}
+
+#[test]
+fn test_known_digests() {
+    assert_eq!(
+        b64_encode(&sha256(b"abc")),
+        "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+    );
+    assert_ne!(blake3(b"abc"), sha256(b"abc"));
+}
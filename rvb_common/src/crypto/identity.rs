@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CryptoError, KeyPair, PublicKey};
+
+/// Prefix of the signed payload, so an identity signature cannot be replayed
+/// as any other kind of signed message.
+const IDENTITY_DOMAIN: &[u8] = b"reverb-identity-v1";
+
+/// Attestation by `issuer` that `subject` is known as `name` and holds
+/// `permissions`. Valid from `not_before` until `not_after`, in seconds since
+/// the Unix epoch, and forever if `not_after` is `None`.
+///
+/// Issuers are either an authority trusted by every node of a mesh or peers
+/// vouching for each other; [`TrustStore`] decides which issuers count and
+/// how many of them a peer needs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdentityCertificate {
+    pub subject: Vec<u8>,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub issuer: Vec<u8>,
+    pub not_before: u64,
+    pub not_after: Option<u64>,
+    pub signature: Vec<u8>,
+}
+
+impl IdentityCertificate {
+    #[must_use]
+    pub fn issue(
+        issuer: &KeyPair,
+        subject: &PublicKey,
+        name: impl Into<String>,
+        permissions: Vec<String>,
+        not_before: u64,
+        not_after: Option<u64>,
+    ) -> Self {
+        let mut certificate = Self {
+            subject: subject.export(),
+            name: name.into(),
+            permissions,
+            issuer: issuer.export_public(),
+            not_before,
+            not_after,
+            signature: Vec::new(),
+        };
+        certificate.signature = issuer.sign(&certificate.signed_payload());
+        certificate
+    }
+
+    fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = IDENTITY_DOMAIN.to_vec();
+        payload.extend(
+            rmp_serde::to_vec(&(
+                &self.subject,
+                &self.name,
+                &self.permissions,
+                &self.issuer,
+                self.not_before,
+                self.not_after,
+            ))
+            .expect("Failed to encode identity certificate"),
+        );
+        payload
+    }
+
+    /// Checks the signature and that `now` falls within the validity window.
+    pub fn verify(&self, now: u64) -> Result<(), CryptoError> {
+        let issuer = PublicKey::import(&self.issuer)?;
+        PublicKey::import(&self.subject)?;
+
+        if !issuer.verify(&self.signed_payload(), &self.signature) {
+            return Err(CryptoError::InvalidCertificate("bad signature".into()));
+        }
+        if now < self.not_before || self.not_after.is_some_and(|end| now >= end) {
+            return Err(CryptoError::InvalidCertificate(
+                "outside its validity window".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Encodes the certificate for the handshake messages.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).expect("Failed to encode identity certificate")
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, CryptoError> {
+        rmp_serde::from_slice(data).map_err(|e| CryptoError::InvalidCertificate(e.to_string()))
+    }
+}
+
+/// Who a peer is, as established by [`TrustStore::check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub key: Vec<u8>,
+    pub name: String,
+    /// Permissions granted by every certificate that vouched for the peer.
+    pub permissions: Vec<String>,
+}
+
+impl Identity {
+    #[must_use]
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Issuers whose certificates a node accepts. A single trusted authority
+/// with `required` set to 1 makes a CA-style mesh; several peer keys with a
+/// higher `required` makes a web of trust, where a newcomer needs that many
+/// existing members to vouch for it.
+#[derive(Clone, Debug)]
+pub struct TrustStore {
+    pub issuers: Vec<Vec<u8>>,
+    pub required: usize,
+}
+
+impl TrustStore {
+    /// Trusts certificates signed by `authority` alone.
+    #[must_use]
+    pub fn authority(authority: Vec<u8>) -> Self {
+        Self {
+            issuers: vec![authority],
+            required: 1,
+        }
+    }
+
+    /// Establishes the identity of `subject` from the certificates it
+    /// presented. Certificates for other keys, from untrusted issuers or no
+    /// longer valid are ignored; the rest must come from at least `required`
+    /// distinct issuers and agree on the name.
+    pub fn check(
+        &self,
+        subject: &[u8],
+        certificates: &[IdentityCertificate],
+        now: u64,
+    ) -> Result<Identity, CryptoError> {
+        let mut issuers: Vec<&[u8]> = Vec::new();
+        let mut identity: Option<Identity> = None;
+
+        for certificate in certificates {
+            if certificate.subject != subject
+                || !self.issuers.contains(&certificate.issuer)
+                || issuers.contains(&certificate.issuer.as_slice())
+                || certificate.verify(now).is_err()
+            {
+                continue;
+            }
+            issuers.push(&certificate.issuer);
+
+            match &mut identity {
+                None => {
+                    identity = Some(Identity {
+                        key: subject.to_vec(),
+                        name: certificate.name.clone(),
+                        permissions: certificate.permissions.clone(),
+                    });
+                }
+                Some(identity) if identity.name != certificate.name => {
+                    return Err(CryptoError::InvalidCertificate(format!(
+                        "certificates disagree on the name: {:?} and {:?}",
+                        identity.name, certificate.name
+                    )));
+                }
+                Some(identity) => identity
+                    .permissions
+                    .retain(|p| certificate.permissions.contains(p)),
+            }
+        }
+
+        match identity {
+            Some(identity) if issuers.len() >= self.required.max(1) => Ok(identity),
+            _ => Err(CryptoError::InvalidCertificate(format!(
+                "{} of {} required trusted certificates",
+                issuers.len(),
+                self.required.max(1)
+            ))),
+        }
+    }
+}
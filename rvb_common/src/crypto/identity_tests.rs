@@ -0,0 +1,116 @@
+use super::*;
+
+fn permissions(list: &[&str]) -> Vec<String> {
+    list.iter().map(|p| p.to_string()).collect()
+}
+
+#[test]
+fn test_identity_certificate() {
+    let authority = KeyPair::generate();
+    let node = KeyPair::generate();
+    let certificate = IdentityCertificate::issue(
+        &authority,
+        &node.public(),
+        "storage-1",
+        permissions(&["deploy"]),
+        100,
+        Some(200),
+    );
+
+    assert!(certificate.verify(150).is_ok());
+    assert!(certificate.verify(200).is_err());
+    assert_eq!(
+        IdentityCertificate::decode(&certificate.encode()).unwrap(),
+        certificate
+    );
+
+    let mut forged = certificate.clone();
+    forged.name = "admin".into();
+    assert!(forged.verify(150).is_err());
+}
+
+#[test]
+fn test_authority_trust() {
+    let authority = KeyPair::generate();
+    let node = KeyPair::generate();
+    let trust = TrustStore::authority(authority.export_public());
+    let subject = node.export_public();
+
+    let certificate = IdentityCertificate::issue(
+        &authority,
+        &node.public(),
+        "storage-1",
+        permissions(&["deploy"]),
+        0,
+        None,
+    );
+    let identity = trust
+        .check(&subject, std::slice::from_ref(&certificate), 10)
+        .unwrap();
+    assert_eq!(identity.name, "storage-1");
+    assert!(identity.has_permission("deploy"));
+
+    // Presented by another key.
+    assert!(
+        trust
+            .check(&KeyPair::generate().export_public(), &[certificate], 10)
+            .is_err()
+    );
+
+    let self_signed =
+        IdentityCertificate::issue(&node, &node.public(), "storage-1", Vec::new(), 0, None);
+    assert!(trust.check(&subject, &[self_signed], 10).is_err());
+    assert!(trust.check(&subject, &[], 10).is_err());
+}
+
+#[test]
+fn test_web_of_trust() {
+    let members = [
+        KeyPair::generate(),
+        KeyPair::generate(),
+        KeyPair::generate(),
+    ];
+    let newcomer = KeyPair::generate();
+    let trust = TrustStore {
+        issuers: members.iter().map(KeyPair::export_public).collect(),
+        required: 2,
+    };
+    let subject = newcomer.export_public();
+    let vouch = |member: &KeyPair, name: &str, granted: &[&str]| {
+        IdentityCertificate::issue(
+            member,
+            &newcomer.public(),
+            name,
+            permissions(granted),
+            0,
+            None,
+        )
+    };
+
+    let one = vouch(&members[0], "edge", &["read", "write"]);
+    assert!(
+        trust
+            .check(&subject, std::slice::from_ref(&one), 10)
+            .is_err()
+    );
+    assert!(
+        trust
+            .check(&subject, &[one.clone(), one.clone()], 10)
+            .is_err()
+    );
+
+    let identity = trust
+        .check(
+            &subject,
+            &[one.clone(), vouch(&members[1], "edge", &["read"])],
+            10,
+        )
+        .unwrap();
+    assert_eq!(identity.permissions, permissions(&["read"]));
+
+    assert!(
+        trust
+            .check(&subject, &[one, vouch(&members[2], "core", &["read"])], 10)
+            .is_err()
+    );
+}
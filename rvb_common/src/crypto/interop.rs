@@ -0,0 +1,126 @@
+//! Conversions between reverb keys and the PKCS#8 and OpenSSH formats, so
+//! existing ed25519 keys can become node identities and identities can be
+//! managed with standard tooling.
+//!
+//! Those formats only hold the signing key. Keys imported from them get the
+//! X25519 key converted from their ed25519 key for encryption, so a reverb
+//! keypair exported and imported again keeps its signing identity but not its
+//! encryption key, unless it was imported from one of these formats to begin
+//! with.
+
+use ed25519_dalek::{
+    SigningKey, VerifyingKey,
+    pkcs8::{
+        DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey,
+        spki::der::pem::LineEnding,
+    },
+};
+use ssh_key::private::{Ed25519Keypair, KeypairData};
+use ssh_key::public::{Ed25519PublicKey, KeyData};
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+use super::{CryptoError, KeyPair, PublicKey, scheme::Signing, scheme::Verifying};
+
+fn format_error(e: impl std::fmt::Display) -> CryptoError {
+    CryptoError::InvalidKeyFormat(e.to_string())
+}
+
+impl KeyPair {
+    fn from_ed25519(signing: SigningKey) -> Self {
+        Self {
+            encrypting_pair: StaticSecret::from(signing.to_scalar_bytes()),
+            signing_pair: Signing::Ed25519(signing),
+        }
+    }
+
+    fn ed25519(&self) -> Result<&SigningKey, CryptoError> {
+        match &self.signing_pair {
+            Signing::Ed25519(key) => Ok(key),
+            #[cfg(feature = "secp256k1")]
+            Signing::Secp256k1(_) => Err(format_error("only ed25519 keys can be exported")),
+        }
+    }
+
+    pub fn from_pkcs8_pem(pem: &str) -> Result<Self, CryptoError> {
+        SigningKey::from_pkcs8_pem(pem)
+            .map(Self::from_ed25519)
+            .map_err(format_error)
+    }
+
+    pub fn to_pkcs8_pem(&self) -> Result<Zeroizing<String>, CryptoError> {
+        self.ed25519()?
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(format_error)
+    }
+
+    /// Imports an unencrypted OpenSSH ed25519 private key.
+    pub fn from_openssh(pem: &str) -> Result<Self, CryptoError> {
+        let key = ssh_key::PrivateKey::from_openssh(pem).map_err(format_error)?;
+        let keypair = key
+            .key_data()
+            .ed25519()
+            .ok_or_else(|| format_error("not an ed25519 key"))?;
+
+        SigningKey::try_from(keypair)
+            .map(Self::from_ed25519)
+            .map_err(format_error)
+    }
+
+    pub fn to_openssh(&self, comment: &str) -> Result<Zeroizing<String>, CryptoError> {
+        let data = KeypairData::Ed25519(Ed25519Keypair::from(self.ed25519()?));
+        ssh_key::PrivateKey::new(data, comment)
+            .and_then(|key| key.to_openssh(ssh_key::LineEnding::LF))
+            .map_err(format_error)
+    }
+}
+
+impl PublicKey {
+    fn from_ed25519(key: VerifyingKey) -> Self {
+        Self {
+            encrypting_key: x25519_dalek::PublicKey::from(key.to_montgomery().to_bytes()),
+            verifying_key: Verifying::Ed25519(key),
+        }
+    }
+
+    fn ed25519(&self) -> Result<&VerifyingKey, CryptoError> {
+        match &self.verifying_key {
+            Verifying::Ed25519(key) => Ok(key),
+            #[cfg(feature = "secp256k1")]
+            Verifying::Secp256k1(_) => Err(format_error("only ed25519 keys can be exported")),
+        }
+    }
+
+    /// Imports an SPKI public key PEM.
+    pub fn from_public_key_pem(pem: &str) -> Result<Self, CryptoError> {
+        VerifyingKey::from_public_key_pem(pem)
+            .map(Self::from_ed25519)
+            .map_err(format_error)
+    }
+
+    pub fn to_public_key_pem(&self) -> Result<String, CryptoError> {
+        self.ed25519()?
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(format_error)
+    }
+
+    /// Imports an `ssh-ed25519 AAAA...` line, as found in `authorized_keys`.
+    pub fn from_openssh(line: &str) -> Result<Self, CryptoError> {
+        let key = ssh_key::PublicKey::from_openssh(line).map_err(format_error)?;
+        let key = key
+            .key_data()
+            .ed25519()
+            .ok_or_else(|| format_error("not an ed25519 key"))?;
+
+        VerifyingKey::try_from(key)
+            .map(Self::from_ed25519)
+            .map_err(format_error)
+    }
+
+    pub fn to_openssh(&self, comment: &str) -> Result<String, CryptoError> {
+        let data = KeyData::Ed25519(Ed25519PublicKey::from(self.ed25519()?));
+        ssh_key::PublicKey::new(data, comment)
+            .to_openssh()
+            .map_err(format_error)
+    }
+}
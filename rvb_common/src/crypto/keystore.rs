@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, aead::Aead};
+use rand::{RngCore, rngs::OsRng};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::{CryptoError, KeyPair};
+
+const KEYSTORE_VERSION: u8 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeystoreError {
+    #[error("Keystore I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Corrupt keystore: {0}")]
+    Corrupt(String),
+    #[error("Unsupported keystore version {0}")]
+    UnsupportedVersion(u8),
+    #[error("No identity named {0}")]
+    UnknownIdentity(String),
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+}
+
+/// A private key encrypted with a key derived from a passphrase.
+#[derive(Serialize, Deserialize)]
+struct SealedKey {
+    salt: [u8; 16],
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u8,
+    identities: BTreeMap<String, SealedKey>,
+}
+
+/// Named keypairs kept in one file, each encrypted with its own passphrase.
+/// Passphrases are stretched with Argon2id and keys sealed with
+/// ChaCha20-Poly1305, so the file is safe to keep next to a node's config.
+///
+/// Every change rewrites the file under a temporary name and renames it into
+/// place.
+pub struct Keystore {
+    path: PathBuf,
+    file: KeystoreFile,
+}
+
+impl Keystore {
+    /// Opens the keystore at `path`. A missing file is an empty keystore,
+    /// created on the first [`Keystore::insert`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, KeystoreError> {
+        let path = path.as_ref().to_path_buf();
+        let file = match fs::read(&path) {
+            Ok(data) => {
+                let file: KeystoreFile = rmp_serde::from_slice(&data)
+                    .map_err(|e| KeystoreError::Corrupt(e.to_string()))?;
+                if file.version != KEYSTORE_VERSION {
+                    return Err(KeystoreError::UnsupportedVersion(file.version));
+                }
+                file
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => KeystoreFile {
+                version: KEYSTORE_VERSION,
+                identities: BTreeMap::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self { path, file })
+    }
+
+    /// Names of the stored identities, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.file.identities.keys().map(String::as_str)
+    }
+
+    /// Stores `keypair` as `name`, replacing any identity of that name.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        keypair: &KeyPair,
+        passphrase: &str,
+    ) -> Result<(), KeystoreError> {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(&nonce.into(), keypair.export_private().as_slice())
+            .map_err(|_| CryptoError::KeyGenerationError("failed to seal key".into()))?;
+
+        self.file.identities.insert(
+            name.to_string(),
+            SealedKey {
+                salt,
+                nonce,
+                ciphertext,
+            },
+        );
+        self.save()
+    }
+
+    /// Decrypts the identity called `name`.
+    pub fn load(&self, name: &str, passphrase: &str) -> Result<KeyPair, KeystoreError> {
+        let sealed = self
+            .file
+            .identities
+            .get(name)
+            .ok_or_else(|| KeystoreError::UnknownIdentity(name.to_string()))?;
+
+        let data = cipher(passphrase, &sealed.salt)?
+            .decrypt(&sealed.nonce.into(), sealed.ciphertext.as_slice())
+            .map(Zeroizing::new)
+            .map_err(|_| KeystoreError::WrongPassphrase)?;
+        Ok(KeyPair::import(&data)?)
+    }
+
+    /// Removes the identity called `name`, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> Result<bool, KeystoreError> {
+        if self.file.identities.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<(), KeystoreError> {
+        let data =
+            rmp_serde::to_vec(&self.file).map_err(|e| KeystoreError::Corrupt(e.to_string()))?;
+
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, data)?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, CryptoError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| CryptoError::KeyGenerationError(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&*key)))
+}
@@ -0,0 +1,38 @@
+use super::*;
+use std::fs;
+
+#[test]
+fn keys_survive_reopening() {
+    let path = std::env::temp_dir().join(format!("rvb-keystore-{}", std::process::id()));
+    let keypair = KeyPair::generate();
+
+    let mut keystore = Keystore::open(&path).unwrap();
+    keystore.insert("node", &keypair, "hunter2").unwrap();
+    keystore
+        .insert("backup", &KeyPair::generate(), "other")
+        .unwrap();
+
+    let mut keystore = Keystore::open(&path).unwrap();
+    assert_eq!(keystore.names().collect::<Vec<_>>(), vec!["backup", "node"]);
+    assert_eq!(
+        keystore.load("node", "hunter2").unwrap().export_private(),
+        keypair.export_private()
+    );
+    assert!(matches!(
+        keystore.load("node", "wrong"),
+        Err(KeystoreError::WrongPassphrase)
+    ));
+    assert!(matches!(
+        keystore.load("missing", "hunter2"),
+        Err(KeystoreError::UnknownIdentity(_))
+    ));
+
+    assert!(keystore.remove("node").unwrap());
+    assert!(!keystore.remove("node").unwrap());
+    assert_eq!(
+        Keystore::open(&path).unwrap().names().collect::<Vec<_>>(),
+        vec!["backup"]
+    );
+
+    fs::remove_file(&path).unwrap();
+}
@@ -1,11 +1,68 @@
+use async_trait::async_trait;
 use base64::Engine;
 #[cfg(feature = "encrypt")]
 use ecies::{decrypt, encrypt};
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey, ed25519::signature::SignerMut};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 #[cfg(feature = "crypto_random")]
 use rand::rngs::OsRng;
+use scheme::{Signing, Verifying};
+use x25519_dalek::StaticSecret;
+use zeroize::{ZeroizeOnDrop, Zeroizing};
 
-#[must_use] pub fn b64_encode(data: &[u8]) -> String {
+#[cfg(feature = "hash")]
+mod contract_id;
+#[cfg(feature = "hash")]
+mod derive;
+#[cfg(feature = "group")]
+mod group;
+#[cfg(feature = "hash")]
+pub mod hash;
+mod identity;
+#[cfg(feature = "interop")]
+mod interop;
+#[cfg(feature = "keystore")]
+mod keystore;
+#[cfg(feature = "keystore")]
+pub use keystore::{Keystore, KeystoreError};
+#[cfg(all(feature = "hash", feature = "protocol"))]
+mod proof;
+mod rotation;
+mod scheme;
+#[cfg(feature = "session")]
+mod session;
+#[cfg(feature = "stream")]
+mod stream;
+mod threshold;
+#[cfg(feature = "hash")]
+pub use contract_id::{contract_id, verify_contract_id};
+#[cfg(feature = "hash")]
+pub use derive::MIN_MASTER_LENGTH;
+#[cfg(feature = "group")]
+pub use group::{GroupKey, sealed_epoch};
+pub use identity::{Identity, IdentityCertificate, TrustStore};
+#[cfg(all(feature = "hash", feature = "protocol"))]
+pub use proof::{leaf_digest, merkle_anchor, value_digest};
+pub use rotation::RotationCertificate;
+pub use scheme::SignatureScheme;
+#[cfg(feature = "session")]
+pub use session::{Handshake, Session, SessionState};
+#[cfg(feature = "stream")]
+pub use stream::{STREAM_CHUNK_SIZE, StreamError, decrypt_stream, encrypt_stream};
+pub use threshold::{ThresholdKey, ThresholdSignature};
+
+/// First byte of exported ed25519 keys. Exported keys start with the tag of
+/// their [`SignatureScheme`], followed by the signing key and a 32 byte X25519
+/// key for encryption. Untagged 64 byte keys from before tags carry a second
+/// ed25519 key instead, which is converted to X25519 on import.
+pub const KEY_FORMAT_VERSION: u8 = 1;
+
+/// Length of exported ed25519 keys.
+pub const KEY_LENGTH: usize = 65;
+
+const LEGACY_KEY_LENGTH: usize = 64;
+
+#[must_use]
+pub fn b64_encode(data: &[u8]) -> String {
     base64::engine::general_purpose::STANDARD.encode(data)
 }
 
@@ -23,27 +80,73 @@ pub enum CryptoError {
     KeyGenerationError(String),
     #[error("Invalid key")]
     InvalidKey,
+    #[error("Invalid certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+}
+
+/// An exported key split into its parts.
+struct SplitKey<'a> {
+    /// `None` for legacy keys.
+    scheme: Option<SignatureScheme>,
+    signing: &'a [u8],
+    encryption: &'a [u8; 32],
+}
+
+fn split_key(data: &[u8]) -> Result<SplitKey<'_>, CryptoError> {
+    let (scheme, signing, encryption) = if data.len() == LEGACY_KEY_LENGTH {
+        (None, &data[..32], &data[32..])
+    } else {
+        let (&tag, rest) = data.split_first().ok_or(CryptoError::InvalidKey)?;
+        let scheme = SignatureScheme::from_tag(tag)?;
+        if rest.len() <= 32 {
+            return Err(CryptoError::InvalidKey);
+        }
+        let (signing, encryption) = rest.split_at(rest.len() - 32);
+        (Some(scheme), signing, encryption)
+    };
+
+    Ok(SplitKey {
+        scheme,
+        signing,
+        encryption: encryption.try_into().map_err(|_| CryptoError::InvalidKey)?,
+    })
 }
 
 #[derive(Clone, Debug)]
 pub struct PublicKey {
-    encrypting_key: VerifyingKey,
-    verifying_key: VerifyingKey,
+    encrypting_key: x25519_dalek::PublicKey,
+    verifying_key: Verifying,
 }
 
 impl PublicKey {
     pub fn import(data: &[u8]) -> Result<PublicKey, CryptoError> {
-        if data.len() != 64 {
-            return Err(CryptoError::InvalidKey);
-        }
+        let SplitKey {
+            scheme,
+            signing,
+            encryption,
+        } = split_key(data)?;
+        let encrypting_key = match scheme {
+            Some(_) => x25519_dalek::PublicKey::from(*encryption),
+            None => {
+                let key =
+                    VerifyingKey::from_bytes(encryption).map_err(|_| CryptoError::InvalidKey)?;
+                x25519_dalek::PublicKey::from(key.to_montgomery().to_bytes())
+            }
+        };
+
         Ok(Self {
-            verifying_key: VerifyingKey::try_from(&data[..32])
-                .map_err(|_| CryptoError::InvalidKey)?,
-            encrypting_key: VerifyingKey::try_from(&data[32..])
-                .map_err(|_| CryptoError::InvalidKey)?,
+            verifying_key: Verifying::import(scheme.unwrap_or(SignatureScheme::Ed25519), signing)?,
+            encrypting_key,
         })
     }
 
+    #[must_use]
+    pub fn scheme(&self) -> SignatureScheme {
+        self.verifying_key.scheme()
+    }
+
     pub fn import_armored(data: &str) -> Result<Self, CryptoError> {
         let data = b64_decode(data)?;
         Self::import(&data)
@@ -56,9 +159,10 @@ impl PublicKey {
 
     #[must_use]
     pub fn export(&self) -> Vec<u8> {
-        let mut v = Vec::with_capacity(64);
+        let mut v = Vec::with_capacity(KEY_LENGTH);
 
-        v.extend_from_slice(self.verifying_key.as_bytes());
+        v.push(self.scheme().tag());
+        v.extend_from_slice(&self.verifying_key.export());
         v.extend_from_slice(self.encrypting_key.as_bytes());
 
         v
@@ -72,17 +176,41 @@ impl PublicKey {
 
     #[must_use]
     pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
-        match Signature::from_slice(signature) {
-            Ok(sign) => self.verifying_key.verify_strict(data, &sign).is_ok(),
-            Err(_) => false,
-        }
+        self.verifying_key.verify(data, signature)
     }
 }
 
+/// Signature made by a [`Signer`], with the exported public key to check it
+/// against.
 #[derive(Clone, Debug)]
+pub struct Signed {
+    pub signature: Vec<u8>,
+    pub public_key: Vec<u8>,
+}
+
+/// Signs with an identity whose private key may live outside the process, such
+/// as on a hardware token or in a KMS. [`KeyPair`] implements it for keys held
+/// in memory.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Exported public key of the identity.
+    fn public_key(&self) -> Vec<u8>;
+
+    async fn sign(&self, data: &[u8]) -> Result<Signed, CryptoError>;
+}
+
+#[derive(Clone)]
 pub struct KeyPair {
-    signing_pair: SigningKey,
-    encrypting_pair: SigningKey,
+    signing_pair: Signing,
+    encrypting_pair: StaticSecret,
+}
+
+impl std::fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public", &self.public())
+            .finish_non_exhaustive()
+    }
 }
 
 impl KeyPair {
@@ -91,35 +219,93 @@ impl KeyPair {
     pub fn generate() -> Self {
         let mut rng = OsRng;
         Self {
-            signing_pair: SigningKey::generate(&mut rng),
-            encrypting_pair: SigningKey::generate(&mut rng),
+            signing_pair: Signing::Ed25519(SigningKey::generate(&mut rng)),
+            encrypting_pair: StaticSecret::random_from_rng(rng),
         }
     }
 
+    /// Like [`KeyPair::generate`], signing with secp256k1 instead of ed25519.
+    #[must_use]
+    #[cfg(all(feature = "crypto_random", feature = "secp256k1"))]
+    pub fn generate_secp256k1() -> Self {
+        let mut rng = OsRng;
+        Self {
+            signing_pair: Signing::Secp256k1(k256::ecdsa::SigningKey::random(&mut rng)),
+            encrypting_pair: StaticSecret::random_from_rng(rng),
+        }
+    }
+
+    /// Generates a keypair along with the 24 word BIP39 phrase it can be
+    /// recovered from through [`KeyPair::from_mnemonic`].
+    #[must_use]
+    #[cfg(all(feature = "crypto_random", feature = "mnemonic"))]
+    pub fn generate_with_mnemonic() -> (Self, String) {
+        use rand::RngCore;
+
+        let mut entropy = [0u8; 32];
+        OsRng.fill_bytes(&mut entropy);
+        let phrase = bip39::Mnemonic::from_entropy(&entropy)
+            .expect("32 bytes is a valid entropy length")
+            .to_string();
+        let keypair = Self::from_mnemonic(&phrase, "").expect("generated phrase is valid");
+        (keypair, phrase)
+    }
+
+    /// Recovers a keypair from a BIP39 phrase. The 64 byte seed derived from
+    /// the phrase and `passphrase` holds the signing key in its first half and
+    /// the encryption key in its second.
+    #[cfg(feature = "mnemonic")]
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, CryptoError> {
+        let mnemonic = bip39::Mnemonic::parse(phrase)
+            .map_err(|e| CryptoError::InvalidKeyFormat(e.to_string()))?;
+        let seed = Zeroizing::new(mnemonic.to_seed(passphrase));
+
+        let mut data = Zeroizing::new(Vec::with_capacity(KEY_LENGTH));
+        data.push(KEY_FORMAT_VERSION);
+        data.extend_from_slice(&*seed);
+        Self::import(&data)
+    }
+
     pub fn import_armored(data: &str) -> Result<Self, CryptoError> {
         let data = b64_decode(data)?;
         Self::import(&data)
     }
 
+    /// Imports a private key in the current or the legacy format. Legacy
+    /// keys get the X25519 key matching their ed25519 encryption key, so peers
+    /// holding the legacy public key can keep encrypting to it.
     pub fn import(data: &[u8]) -> Result<Self, CryptoError> {
-        if data.len() != 64 {
-            return Err(CryptoError::InvalidKey);
-        }
+        let SplitKey {
+            scheme,
+            signing,
+            encryption,
+        } = split_key(data)?;
+        let encrypting_pair = match scheme {
+            Some(_) => StaticSecret::from(*encryption),
+            None => StaticSecret::from(SigningKey::from_bytes(encryption).to_scalar_bytes()),
+        };
+
         Ok(Self {
-            signing_pair: SigningKey::try_from(&data[..32]).map_err(|_| CryptoError::InvalidKey)?,
-            encrypting_pair: SigningKey::try_from(&data[32..])
-                .map_err(|_| CryptoError::InvalidKey)?,
+            signing_pair: Signing::import(scheme.unwrap_or(SignatureScheme::Ed25519), signing)?,
+            encrypting_pair,
         })
     }
 
     #[must_use]
     pub fn public(&self) -> PublicKey {
         PublicKey {
-            encrypting_key: self.encrypting_pair.verifying_key(),
-            verifying_key: self.signing_pair.verifying_key(),
+            encrypting_key: x25519_dalek::PublicKey::from(&self.encrypting_pair),
+            verifying_key: self.signing_pair.verifying(),
         }
     }
 
+    #[must_use]
+    pub fn scheme(&self) -> SignatureScheme {
+        self.signing_pair.verifying().scheme()
+    }
+
+    /// Exports the private key as base64. Unlike [`KeyPair::export_private`],
+    /// the string is not wiped when dropped.
     #[must_use]
     pub fn armor_private(&self) -> String {
         b64_encode(&self.export_private())
@@ -130,11 +316,14 @@ impl KeyPair {
         b64_encode(&self.export_public())
     }
 
+    /// Exports the private key, wiped from memory when the returned buffer
+    /// is dropped.
     #[must_use]
-    pub fn export_private(&self) -> Vec<u8> {
-        let mut v = Vec::with_capacity(64);
+    pub fn export_private(&self) -> Zeroizing<Vec<u8>> {
+        let mut v = Zeroizing::new(Vec::with_capacity(KEY_LENGTH));
 
-        v.extend_from_slice(self.signing_pair.as_bytes());
+        v.push(self.scheme().tag());
+        v.extend_from_slice(&self.signing_pair.export());
         v.extend_from_slice(self.encrypting_pair.as_bytes());
 
         v
@@ -142,31 +331,21 @@ impl KeyPair {
 
     #[must_use]
     pub fn export_public(&self) -> Vec<u8> {
-        let mut v = Vec::with_capacity(64);
-
-        v.extend_from_slice(self.signing_pair.verifying_key().as_bytes());
-        v.extend_from_slice(self.encrypting_pair.verifying_key().as_bytes());
-
-        v
+        self.public().export()
     }
 
-    pub fn sign(&mut self, data: &[u8]) -> Vec<u8> {
-        self.signing_pair.sign(data).to_vec()
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.signing_pair.sign(data)
     }
 
     #[must_use]
     pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
-        match Signature::from_slice(signature) {
-            Ok(sign) => self.signing_pair.verify_strict(data, &sign).is_ok(),
-            Err(_) => false,
-        }
+        self.signing_pair.verifying().verify(data, signature)
     }
 
     #[cfg(feature = "encrypt")]
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        let verkey = self.encrypting_pair.verifying_key();
-        let public_key_bytes = verkey.as_bytes();
-        encrypt(public_key_bytes, data).map_err(|_| CryptoError::InvalidKey)
+        self.public().encrypt(data)
     }
 
     #[cfg(feature = "encrypt")]
@@ -176,5 +355,52 @@ impl KeyPair {
     }
 }
 
+/// Both halves wipe their secrets when dropped.
+impl ZeroizeOnDrop for KeyPair {}
+
+#[async_trait]
+impl Signer for KeyPair {
+    fn public_key(&self) -> Vec<u8> {
+        self.export_public()
+    }
+
+    async fn sign(&self, data: &[u8]) -> Result<Signed, CryptoError> {
+        Ok(Signed {
+            signature: self.signing_pair.sign(data),
+            public_key: self.export_public(),
+        })
+    }
+}
+
 #[cfg(all(test, feature = "encrypt", feature = "crypto_random"))]
 mod tests;
+
+#[cfg(all(test, feature = "hash"))]
+mod contract_id_tests;
+#[cfg(all(test, feature = "hash"))]
+mod derive_tests;
+#[cfg(all(test, feature = "group"))]
+mod group_tests;
+#[cfg(all(test, feature = "hash"))]
+mod hash_tests;
+#[cfg(all(test, feature = "crypto_random"))]
+mod identity_tests;
+#[cfg(all(test, feature = "interop", feature = "crypto_random"))]
+mod interop_tests;
+#[cfg(all(test, feature = "keystore"))]
+mod keystore_tests;
+#[cfg(all(
+    test,
+    feature = "hash",
+    feature = "protocol",
+    feature = "crypto_random"
+))]
+mod proof_tests;
+#[cfg(all(test, feature = "crypto_random"))]
+mod rotation_tests;
+#[cfg(all(test, feature = "session"))]
+mod session_tests;
+#[cfg(all(test, feature = "stream"))]
+mod stream_tests;
+#[cfg(all(test, feature = "crypto_random"))]
+mod threshold_tests;
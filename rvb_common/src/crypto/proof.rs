@@ -0,0 +1,167 @@
+//! Signing and checking of [`StateProof`]s.
+//!
+//! The Merkle tree of a contract space has a leaf for each stored key, in key
+//! order. Leaves are the blake3 digest of a zero byte followed by the msgpack
+//! encoded key, value digest and state; inner nodes that of a one byte
+//! followed by their children. A node left without a sibling moves up a level
+//! unchanged.
+
+use super::{CryptoError, KeyPair, PublicKey, Signer, hash};
+use crate::protocol::{Location, MerkleAnchor, MerkleStep, StateProof};
+use crate::schema::DbValue;
+
+/// Prefix of the signed payload, so a proof signature cannot be replayed as
+/// any other kind of signed message.
+const PROOF_DOMAIN: &[u8] = b"reverb-state-proof-v1";
+
+/// Digest of a stored value, or of `None` for a key that is not stored.
+#[must_use]
+pub fn value_digest(value: Option<&DbValue>) -> [u8; 32] {
+    hash::blake3(&rmp_serde::to_vec(&value).expect("Failed to encode value"))
+}
+
+/// Leaf of `key` in the Merkle tree of its contract space.
+#[must_use]
+pub fn leaf_digest(key: &str, value_digest: &[u8], state: u64) -> [u8; 32] {
+    let mut data = vec![0];
+    data.extend(rmp_serde::to_vec(&(key, value_digest, state)).expect("Failed to encode leaf"));
+    hash::blake3(&data)
+}
+
+fn inner_digest(left: &[u8], right: &[u8]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(1 + left.len() + right.len());
+    data.push(1);
+    data.extend_from_slice(left);
+    data.extend_from_slice(right);
+    hash::blake3(&data)
+}
+
+/// Root of the tree over `leaves`, given in key order, with the path up from
+/// leaf `index`.
+///
+/// # Panics
+///
+/// If `index` is not a position in `leaves`.
+#[must_use]
+pub fn merkle_anchor(leaves: &[[u8; 32]], mut index: usize) -> MerkleAnchor {
+    assert!(index < leaves.len(), "Leaf {index} is not in the tree");
+    let mut level = leaves.to_vec();
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if let Some(digest) = level.get(sibling) {
+            path.push(MerkleStep {
+                sibling: digest.to_vec(),
+                left: sibling < index,
+            });
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => inner_digest(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        index /= 2;
+    }
+
+    MerkleAnchor {
+        root: level[0].to_vec(),
+        path,
+    }
+}
+
+impl StateProof {
+    fn unsigned(
+        signed_by: Vec<u8>,
+        location: Location,
+        value: Option<&DbValue>,
+        state: u64,
+        anchor: Option<MerkleAnchor>,
+    ) -> Self {
+        Self {
+            location,
+            value_digest: value_digest(value).to_vec(),
+            state,
+            anchor,
+            signed_by,
+            signature: Vec::new(),
+        }
+    }
+
+    /// Proves with `keypair` that `value` is stored at `state` for
+    /// `location`.
+    #[must_use]
+    pub fn sign(
+        keypair: &KeyPair,
+        location: Location,
+        value: Option<&DbValue>,
+        state: u64,
+        anchor: Option<MerkleAnchor>,
+    ) -> Self {
+        let mut proof = Self::unsigned(keypair.export_public(), location, value, state, anchor);
+        proof.signature = keypair.sign(&proof.signed_payload());
+        proof
+    }
+
+    /// Like [`StateProof::sign`], signing through `signer`.
+    pub async fn sign_with(
+        signer: &dyn Signer,
+        location: Location,
+        value: Option<&DbValue>,
+        state: u64,
+        anchor: Option<MerkleAnchor>,
+    ) -> Result<Self, CryptoError> {
+        let mut proof = Self::unsigned(signer.public_key(), location, value, state, anchor);
+        proof.signature = signer.sign(&proof.signed_payload()).await?.signature;
+        Ok(proof)
+    }
+
+    fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = PROOF_DOMAIN.to_vec();
+        payload.extend(
+            rmp_serde::to_vec(&(
+                &self.location,
+                &self.value_digest,
+                self.state,
+                self.anchor.as_ref().map(|anchor| &anchor.root),
+                &self.signed_by,
+            ))
+            .expect("Failed to encode state proof"),
+        );
+        payload
+    }
+
+    /// Checks that the proof is about `location` and `value` and signed by
+    /// `signed_by`, and that an anchored key's leaf leads up to the root.
+    /// Whether the signer is a node worth trusting, or its root matches that
+    /// of other nodes, is up to the caller.
+    pub fn verify(&self, location: &Location, value: Option<&DbValue>) -> Result<(), CryptoError> {
+        if self.location != *location {
+            return Err(CryptoError::InvalidProof("about another location".into()));
+        }
+        let digest = value_digest(value);
+        if self.value_digest != digest {
+            return Err(CryptoError::InvalidProof("about another value".into()));
+        }
+        if !PublicKey::import(&self.signed_by)?.verify(&self.signed_payload(), &self.signature) {
+            return Err(CryptoError::InvalidProof("bad signature".into()));
+        }
+
+        if let Some(anchor) = &self.anchor {
+            let leaf = leaf_digest(&location.key, &digest, self.state);
+            let root = anchor.path.iter().fold(leaf, |digest, step| {
+                if step.left {
+                    inner_digest(&step.sibling, &digest)
+                } else {
+                    inner_digest(&digest, &step.sibling)
+                }
+            });
+            if anchor.root != root {
+                return Err(CryptoError::InvalidProof("not under its root".into()));
+            }
+        }
+        Ok(())
+    }
+}
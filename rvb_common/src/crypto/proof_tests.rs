@@ -0,0 +1,64 @@
+use super::*;
+use crate::protocol::{Location, StateProof};
+use crate::schema::DbValue;
+
+fn location(key: &str) -> Location {
+    Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: vec![1],
+        key: key.into(),
+    }
+}
+
+fn leaves(count: usize) -> Vec<[u8; 32]> {
+    (0..count)
+        .map(|i| {
+            let value = DbValue::Number(i as i128);
+            leaf_digest(&i.to_string(), &value_digest(Some(&value)), 1)
+        })
+        .collect()
+}
+
+#[test]
+fn test_state_proof() {
+    let node = KeyPair::generate();
+    let value = DbValue::String("value".into());
+    let proof = StateProof::sign(&node, location("key"), Some(&value), 3, None);
+
+    assert!(proof.verify(&location("key"), Some(&value)).is_ok());
+    assert!(proof.verify(&location("other"), Some(&value)).is_err());
+    assert!(proof.verify(&location("key"), None).is_err());
+
+    let mut forged = proof.clone();
+    forged.state = 4;
+    assert!(forged.verify(&location("key"), Some(&value)).is_err());
+
+    // A proof that nothing is stored
+    let absent = StateProof::sign(&node, location("key"), None, 0, None);
+    assert!(absent.verify(&location("key"), None).is_ok());
+}
+
+#[test]
+fn test_anchored_state_proof() {
+    let node = KeyPair::generate();
+    let leaves = leaves(5);
+    let anchor = merkle_anchor(&leaves, 4);
+    // Every leaf leads to the same root, however far from a sibling it is
+    for index in 0..leaves.len() {
+        assert_eq!(merkle_anchor(&leaves, index).root, anchor.root);
+    }
+    assert_eq!(merkle_anchor(&leaves[..1], 0).root, leaves[0]);
+
+    let value = DbValue::Number(4);
+    let proof = StateProof::sign(&node, location("4"), Some(&value), 1, Some(anchor));
+    assert!(proof.verify(&location("4"), Some(&value)).is_ok());
+
+    let mut moved = proof.clone();
+    moved.anchor = Some(merkle_anchor(&leaves, 3));
+    assert!(moved.verify(&location("4"), Some(&value)).is_err());
+
+    let mut rerooted = proof;
+    rerooted.anchor.as_mut().unwrap().root = merkle_anchor(&leaves[..4], 0).root;
+    assert!(rerooted.verify(&location("4"), Some(&value)).is_err());
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CryptoError, KeyPair, PublicKey};
+
+/// Prefix of the signed payload, so a rotation signature cannot be replayed
+/// as any other kind of signed message.
+const ROTATION_DOMAIN: &[u8] = b"reverb-key-rotation-v1";
+
+/// Statement by an identity that `new_key` takes over from `old_key`, signed
+/// by the old key. It holds from `not_before` until `not_after`, both in
+/// seconds since the Unix epoch, and forever if `not_after` is `None`.
+///
+/// Keys are exported public keys. Chains of certificates carry an identity
+/// across several rotations; see [`RotationCertificate::follow`].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RotationCertificate {
+    pub old_key: Vec<u8>,
+    pub new_key: Vec<u8>,
+    pub not_before: u64,
+    pub not_after: Option<u64>,
+    pub signature: Vec<u8>,
+}
+
+impl RotationCertificate {
+    /// Signs the move from `old` to `new_key` with the old keypair.
+    #[must_use]
+    pub fn issue(
+        old: &KeyPair,
+        new_key: &PublicKey,
+        not_before: u64,
+        not_after: Option<u64>,
+    ) -> Self {
+        let mut certificate = Self {
+            old_key: old.export_public(),
+            new_key: new_key.export(),
+            not_before,
+            not_after,
+            signature: Vec::new(),
+        };
+        certificate.signature = old.sign(&certificate.signed_payload());
+        certificate
+    }
+
+    fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = ROTATION_DOMAIN.to_vec();
+        payload.extend(
+            rmp_serde::to_vec(&(
+                &self.old_key,
+                &self.new_key,
+                self.not_before,
+                self.not_after,
+            ))
+            .expect("Failed to encode rotation certificate"),
+        );
+        payload
+    }
+
+    /// Checks the signature and that `now` falls within the validity window.
+    pub fn verify(&self, now: u64) -> Result<(), CryptoError> {
+        let old_key = PublicKey::import(&self.old_key)?;
+        PublicKey::import(&self.new_key)?;
+
+        if !old_key.verify(&self.signed_payload(), &self.signature) {
+            return Err(CryptoError::InvalidCertificate("bad signature".into()));
+        }
+        if now < self.not_before || self.not_after.is_some_and(|end| now >= end) {
+            return Err(CryptoError::InvalidCertificate(
+                "outside its validity window".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Follows `chain` from `root`, each certificate rotating away from the
+    /// key the previous one moved to, and returns the key the identity uses at
+    /// `now`.
+    pub fn follow(
+        root: &[u8],
+        chain: &[RotationCertificate],
+        now: u64,
+    ) -> Result<Vec<u8>, CryptoError> {
+        let mut current = root.to_vec();
+        for certificate in chain {
+            if certificate.old_key != current {
+                return Err(CryptoError::InvalidCertificate(
+                    "chain does not continue from the previous key".into(),
+                ));
+            }
+            certificate.verify(now)?;
+            current.clone_from(&certificate.new_key);
+        }
+        Ok(current)
+    }
+}
@@ -0,0 +1,37 @@
+use super::*;
+
+#[test]
+fn test_rotation_certificate() {
+    let old = KeyPair::generate();
+    let new = KeyPair::generate();
+    let certificate = RotationCertificate::issue(&old, &new.public(), 100, Some(200));
+
+    assert!(certificate.verify(150).is_ok());
+    assert!(certificate.verify(99).is_err());
+    assert!(certificate.verify(200).is_err());
+
+    let mut forged = certificate.clone();
+    forged.new_key = KeyPair::generate().export_public();
+    assert!(forged.verify(150).is_err());
+}
+
+#[test]
+fn test_rotation_chain() {
+    let first = KeyPair::generate();
+    let second = KeyPair::generate();
+    let third = KeyPair::generate();
+    let chain = [
+        RotationCertificate::issue(&first, &second.public(), 0, None),
+        RotationCertificate::issue(&second, &third.public(), 0, None),
+    ];
+
+    assert_eq!(
+        RotationCertificate::follow(&first.export_public(), &chain, 10).unwrap(),
+        third.export_public()
+    );
+    assert!(RotationCertificate::follow(&second.export_public(), &chain, 10).is_err());
+    assert_eq!(
+        RotationCertificate::follow(&first.export_public(), &[], 10).unwrap(),
+        first.export_public()
+    );
+}
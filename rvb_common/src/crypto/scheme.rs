@@ -0,0 +1,142 @@
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use zeroize::Zeroizing;
+
+use super::{CryptoError, KEY_FORMAT_VERSION};
+
+/// Algorithm a key signs with, tagged in the first byte of its export.
+/// Signatures are not tagged themselves: they are checked with the scheme of
+/// the key they are attributed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureScheme {
+    Ed25519,
+    /// ECDSA over secp256k1 with SHA-256, signatures in their 64 byte compact
+    /// form.
+    #[cfg(feature = "secp256k1")]
+    Secp256k1,
+}
+
+impl SignatureScheme {
+    #[must_use]
+    pub fn tag(self) -> u8 {
+        match self {
+            SignatureScheme::Ed25519 => KEY_FORMAT_VERSION,
+            #[cfg(feature = "secp256k1")]
+            SignatureScheme::Secp256k1 => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self, CryptoError> {
+        match tag {
+            KEY_FORMAT_VERSION => Ok(SignatureScheme::Ed25519),
+            #[cfg(feature = "secp256k1")]
+            2 => Ok(SignatureScheme::Secp256k1),
+            _ => Err(CryptoError::InvalidKeyFormat(format!(
+                "unknown key version {tag}"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum Verifying {
+    Ed25519(VerifyingKey),
+    #[cfg(feature = "secp256k1")]
+    Secp256k1(k256::ecdsa::VerifyingKey),
+}
+
+impl Verifying {
+    pub(super) fn import(scheme: SignatureScheme, data: &[u8]) -> Result<Self, CryptoError> {
+        match scheme {
+            SignatureScheme::Ed25519 => VerifyingKey::try_from(data)
+                .map(Verifying::Ed25519)
+                .map_err(|_| CryptoError::InvalidKey),
+            #[cfg(feature = "secp256k1")]
+            SignatureScheme::Secp256k1 => k256::ecdsa::VerifyingKey::from_sec1_bytes(data)
+                .map(Verifying::Secp256k1)
+                .map_err(|_| CryptoError::InvalidKey),
+        }
+    }
+
+    pub(super) fn scheme(&self) -> SignatureScheme {
+        match self {
+            Verifying::Ed25519(_) => SignatureScheme::Ed25519,
+            #[cfg(feature = "secp256k1")]
+            Verifying::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    pub(super) fn export(&self) -> Vec<u8> {
+        match self {
+            Verifying::Ed25519(key) => key.as_bytes().to_vec(),
+            #[cfg(feature = "secp256k1")]
+            Verifying::Secp256k1(key) => key.to_encoded_point(true).as_bytes().to_vec(),
+        }
+    }
+
+    pub(super) fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Verifying::Ed25519(key) => match Signature::from_slice(signature) {
+                Ok(sign) => key.verify_strict(data, &sign).is_ok(),
+                Err(_) => false,
+            },
+            #[cfg(feature = "secp256k1")]
+            Verifying::Secp256k1(key) => {
+                use k256::ecdsa::signature::Verifier;
+
+                match k256::ecdsa::Signature::from_slice(signature) {
+                    Ok(sign) => key.verify(data, &sign).is_ok(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) enum Signing {
+    Ed25519(SigningKey),
+    #[cfg(feature = "secp256k1")]
+    Secp256k1(k256::ecdsa::SigningKey),
+}
+
+impl Signing {
+    pub(super) fn import(scheme: SignatureScheme, data: &[u8]) -> Result<Self, CryptoError> {
+        match scheme {
+            SignatureScheme::Ed25519 => SigningKey::try_from(data)
+                .map(Signing::Ed25519)
+                .map_err(|_| CryptoError::InvalidKey),
+            #[cfg(feature = "secp256k1")]
+            SignatureScheme::Secp256k1 => k256::ecdsa::SigningKey::from_slice(data)
+                .map(Signing::Secp256k1)
+                .map_err(|_| CryptoError::InvalidKey),
+        }
+    }
+
+    pub(super) fn export(&self) -> Zeroizing<Vec<u8>> {
+        match self {
+            Signing::Ed25519(key) => Zeroizing::new(key.as_bytes().to_vec()),
+            #[cfg(feature = "secp256k1")]
+            Signing::Secp256k1(key) => Zeroizing::new(key.to_bytes().to_vec()),
+        }
+    }
+
+    pub(super) fn verifying(&self) -> Verifying {
+        match self {
+            Signing::Ed25519(key) => Verifying::Ed25519(key.verifying_key()),
+            #[cfg(feature = "secp256k1")]
+            Signing::Secp256k1(key) => Verifying::Secp256k1(*key.verifying_key()),
+        }
+    }
+
+    pub(super) fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Signing::Ed25519(key) => ed25519_dalek::Signer::sign(key, data).to_vec(),
+            #[cfg(feature = "secp256k1")]
+            Signing::Secp256k1(key) => {
+                let signature: k256::ecdsa::Signature =
+                    k256::ecdsa::signature::Signer::sign(key, data);
+                signature.to_bytes().to_vec()
+            }
+        }
+    }
+}
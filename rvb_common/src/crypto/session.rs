@@ -0,0 +1,230 @@
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::Aead};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+
+use super::CryptoError;
+
+const INITIATOR_KEY: &[u8] = b"reverb-session-v1 initiator";
+const RESPONDER_KEY: &[u8] = b"reverb-session-v1 responder";
+
+/// One side of a session key exchange. Each side sends [`Handshake::public`]
+/// in its signed handshake message, which ties the exchange to both
+/// identities, then turns the other side's value into a [`Session`].
+pub struct Handshake {
+    secret: EphemeralSecret,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handshake {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            secret: EphemeralSecret::random_from_rng(OsRng),
+        }
+    }
+
+    #[must_use]
+    pub fn public(&self) -> Vec<u8> {
+        X25519PublicKey::from(&self.secret).as_bytes().to_vec()
+    }
+
+    /// Derives the session from the other side's public value. Exactly one
+    /// side, the one that opened the connection, is the `initiator`.
+    pub fn finish(self, peer_public: &[u8], initiator: bool) -> Result<Session, CryptoError> {
+        let peer_public: [u8; 32] = peer_public
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let shared = self
+            .secret
+            .diffie_hellman(&X25519PublicKey::from(peer_public));
+        if !shared.was_contributory() {
+            return Err(CryptoError::InvalidKey);
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(None, shared.as_bytes());
+        let derive = |label: &[u8]| {
+            let mut key = [0u8; 32];
+            hkdf.expand(label, &mut key)
+                .expect("32 bytes is a valid HKDF output length");
+            ChaCha20Poly1305::new(Key::from_slice(&key))
+        };
+
+        let (send, recv) = if initiator {
+            (INITIATOR_KEY, RESPONDER_KEY)
+        } else {
+            (RESPONDER_KEY, INITIATOR_KEY)
+        };
+        Ok(Session {
+            send: derive(send),
+            recv: derive(recv),
+            sent: 0,
+            received: 0,
+        })
+    }
+}
+
+/// Symmetric encryption for the messages of one connection, with a key per
+/// direction. Sealed messages start with their counter, and each must come
+/// after the last one opened, which rejects replayed and reordered messages
+/// while a lost message loses no more than itself.
+pub struct Session {
+    send: ChaCha20Poly1305,
+    recv: ChaCha20Poly1305,
+    sent: u64,
+    /// Counter the next message opened must at least have.
+    received: u64,
+}
+
+const COUNTER_SIZE: usize = 8;
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+impl Session {
+    pub fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut sealed = self.sent.to_be_bytes().to_vec();
+        sealed.extend(
+            self.send
+                .encrypt(&nonce(self.sent), data)
+                .map_err(|_| CryptoError::InvalidKey)?,
+        );
+        self.sent += 1;
+        Ok(sealed)
+    }
+
+    pub fn open(&mut self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (counter, sealed) = data
+            .split_first_chunk::<COUNTER_SIZE>()
+            .ok_or(CryptoError::InvalidKey)?;
+        let counter = u64::from_be_bytes(*counter);
+        if counter < self.received {
+            return Err(CryptoError::InvalidKey);
+        }
+        let opened = self
+            .recv
+            .decrypt(&nonce(counter), sealed)
+            .map_err(|_| CryptoError::InvalidKey)?;
+        self.received = counter + 1;
+        Ok(opened)
+    }
+}
+
+/// Encryption of one connection, from the key exchange in its handshake on.
+///
+/// Each side sends [`SessionState::public`] as the `session_key` of both its
+/// `Hello` and its `WhoAreYou`, so two nodes introducing themselves to each
+/// other agree on one session. The side with the lower public value takes
+/// the initiator's keys. A side may start sealing once it sent its own value
+/// and took the other's, as the other side then reads that value before any
+/// sealed message. Nodes and clients do so once welcomed or welcoming.
+///
+/// The receiving side tells sealed messages by whether they open. Once one
+/// did, messages that do not are refused, so the connection cannot fall back
+/// to plaintext.
+pub struct SessionState {
+    public: Vec<u8>,
+    handshake: Mutex<Option<Handshake>>,
+    session: Mutex<Option<Session>>,
+    sealing: AtomicBool,
+    opened: AtomicBool,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionState {
+    #[must_use]
+    pub fn new() -> Self {
+        let handshake = Handshake::new();
+        Self {
+            public: handshake.public(),
+            handshake: Mutex::new(Some(handshake)),
+            session: Mutex::new(None),
+            sealing: AtomicBool::new(false),
+            opened: AtomicBool::new(false),
+        }
+    }
+
+    /// This side's value of the exchange.
+    #[must_use]
+    pub fn public(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Agrees on the session with the other side's value. Values after the
+    /// first are ignored, as are empty ones from sides that do not encrypt.
+    pub fn agree(&self, peer_public: &[u8]) -> Result<(), CryptoError> {
+        if peer_public.is_empty() {
+            return Ok(());
+        }
+        let Some(handshake) = self.handshake.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let session = handshake.finish(peer_public, self.public.as_slice() < peer_public)?;
+        *self.session.lock().unwrap() = Some(session);
+        Ok(())
+    }
+
+    /// Whether a session was agreed on.
+    #[must_use]
+    pub fn is_agreed(&self) -> bool {
+        self.session.lock().unwrap().is_some()
+    }
+
+    /// Seals everything sent from now on, if a session was agreed on.
+    pub fn secure(&self) {
+        if self.is_agreed() {
+            self.sealing.store(true, Ordering::Release);
+        }
+    }
+
+    /// Whether messages sent are sealed.
+    #[must_use]
+    pub fn is_sealing(&self) -> bool {
+        self.sealing.load(Ordering::Acquire)
+    }
+
+    /// `data` sealed, or `None` if it is sent as it is. Sealed messages must
+    /// be sent in the order they were sealed.
+    pub fn seal(&self, data: &[u8]) -> Result<Option<Vec<u8>>, CryptoError> {
+        if !self.is_sealing() {
+            return Ok(None);
+        }
+        match self.session.lock().unwrap().as_mut() {
+            Some(session) => session.seal(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// `data` opened, or `None` if it was not sealed, which is only taken
+    /// before the first sealed message.
+    pub fn open(&self, data: &[u8]) -> Result<Option<Vec<u8>>, CryptoError> {
+        let mut session = self.session.lock().unwrap();
+        let Some(session) = session.as_mut() else {
+            return Ok(None);
+        };
+        match session.open(data) {
+            Ok(opened) => {
+                self.opened.store(true, Ordering::Release);
+                Ok(Some(opened))
+            }
+            Err(_) if !self.opened.load(Ordering::Acquire) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use super::*;
+
+fn sessions() -> (Session, Session) {
+    let initiator = Handshake::new();
+    let responder = Handshake::new();
+    let initiator_public = initiator.public();
+
+    (
+        initiator.finish(&responder.public(), true).unwrap(),
+        responder.finish(&initiator_public, false).unwrap(),
+    )
+}
+
+#[test]
+fn test_session_roundtrip() {
+    let (mut initiator, mut responder) = sessions();
+
+    let sealed = initiator.seal(b"hello").unwrap();
+    assert_ne!(sealed, b"hello");
+    assert_eq!(responder.open(&sealed).unwrap(), b"hello");
+
+    let sealed = responder.seal(b"welcome").unwrap();
+    assert_eq!(initiator.open(&sealed).unwrap(), b"welcome");
+}
+
+#[test]
+fn test_session_rejects_replay_and_tampering() {
+    let (mut initiator, mut responder) = sessions();
+
+    let sealed = initiator.seal(b"once").unwrap();
+    responder.open(&sealed).unwrap();
+    assert!(responder.open(&sealed).is_err());
+
+    let mut sealed = initiator.seal(b"twice").unwrap();
+    *sealed.last_mut().unwrap() ^= 1;
+    assert!(responder.open(&sealed).is_err());
+
+    // Lost messages are skipped, but not taken after a later one
+    let lost = initiator.seal(b"lost").unwrap();
+    let sealed = initiator.seal(b"after").unwrap();
+    assert_eq!(responder.open(&sealed).unwrap(), b"after");
+    assert!(responder.open(&lost).is_err());
+
+    // Each direction has its own key
+    let sealed = initiator.seal(b"echo").unwrap();
+    assert!(initiator.open(&sealed).is_err());
+}
+
+#[test]
+fn test_handshake_rejects_bad_keys() {
+    assert!(Handshake::new().finish(&[0u8; 31], true).is_err());
+    assert!(Handshake::new().finish(&[0u8; 32], true).is_err());
+}
+
+#[test]
+fn test_session_states_agree() {
+    let (a, b) = (SessionState::new(), SessionState::new());
+    // Not sealed before a session is agreed on and secured
+    a.secure();
+    assert_eq!(a.seal(b"hello").unwrap(), None);
+    assert_eq!(b.open(b"hello").unwrap(), None);
+
+    a.agree(b.public()).unwrap();
+    b.agree(a.public()).unwrap();
+    // Later values do not change the session
+    b.agree(SessionState::new().public()).unwrap();
+    a.secure();
+
+    let sealed = a.seal(b"secret").unwrap().unwrap();
+    assert_ne!(sealed, b"secret");
+    // Plaintext is taken until the first sealed message opens
+    assert_eq!(b.open(b"plain").unwrap(), None);
+    assert_eq!(b.open(&sealed).unwrap().unwrap(), b"secret");
+    assert!(b.open(b"plain").is_err());
+
+    // The other direction is sealed only once that side secures it
+    assert_eq!(b.seal(b"welcome").unwrap(), None);
+    b.secure();
+    let sealed = b.seal(b"welcome").unwrap().unwrap();
+    assert_eq!(a.open(&sealed).unwrap().unwrap(), b"welcome");
+}
+
+#[test]
+fn test_session_states_without_exchange() {
+    let state = SessionState::new();
+    state.agree(&[]).unwrap();
+    state.secure();
+    assert!(!state.is_agreed());
+    assert_eq!(state.seal(b"plain").unwrap(), None);
+}
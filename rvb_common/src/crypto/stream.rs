@@ -0,0 +1,122 @@
+//! Chunked authenticated encryption, for payloads such as snapshots and
+//! contract uploads that should not be held in memory at once. Uses the
+//! STREAM construction over ChaCha20-Poly1305: every chunk is authenticated
+//! with its position and the last one is marked as such, so reordered,
+//! dropped or truncated chunks are detected.
+//!
+//! The output starts with a random 7 byte nonce prefix, followed by chunks of
+//! [`STREAM_CHUNK_SIZE`] bytes of plaintext plus a 16 byte tag. The last chunk
+//! is always shorter than that, and is only a tag when the payload length is a
+//! multiple of the chunk size.
+
+use std::io::{Read, Write};
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit,
+    aead::stream::{DecryptorBE32, EncryptorBE32},
+};
+use rand::{RngCore, rngs::OsRng};
+use zeroize::Zeroizing;
+
+/// Plaintext bytes per chunk.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+const NONCE_PREFIX_LENGTH: usize = 7;
+const TAG_LENGTH: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error("Stream I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Stream ended early")]
+    Truncated,
+    #[error("Stream failed authentication")]
+    Corrupt,
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning how much
+/// was read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypts everything `reader` yields into `writer` under `key`.
+pub fn encrypt_stream(
+    key: &[u8; 32],
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<(), StreamError> {
+    let mut prefix = [0u8; NONCE_PREFIX_LENGTH];
+    OsRng.fill_bytes(&mut prefix);
+    writer.write_all(&prefix)?;
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let mut encryptor = EncryptorBE32::from_aead(cipher, (&prefix).into());
+    let mut chunk = Zeroizing::new(vec![0u8; STREAM_CHUNK_SIZE]);
+    loop {
+        let read = read_full(&mut reader, &mut chunk)?;
+        if read < STREAM_CHUNK_SIZE {
+            let sealed = encryptor
+                .encrypt_last(&chunk[..read])
+                .map_err(|_| StreamError::Corrupt)?;
+            writer.write_all(&sealed)?;
+            break;
+        }
+        let sealed = encryptor
+            .encrypt_next(&chunk[..])
+            .map_err(|_| StreamError::Corrupt)?;
+        writer.write_all(&sealed)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decrypts a stream written by [`encrypt_stream`] into `writer`. Chunks are
+/// written as they are verified, so on error `writer` may hold a prefix of
+/// the plaintext, which should be discarded.
+pub fn decrypt_stream(
+    key: &[u8; 32],
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<(), StreamError> {
+    let mut prefix = [0u8; NONCE_PREFIX_LENGTH];
+    if read_full(&mut reader, &mut prefix)? < NONCE_PREFIX_LENGTH {
+        return Err(StreamError::Truncated);
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let mut decryptor = DecryptorBE32::from_aead(cipher, (&prefix).into());
+    let mut chunk = vec![0u8; STREAM_CHUNK_SIZE + TAG_LENGTH];
+    loop {
+        let read = read_full(&mut reader, &mut chunk)?;
+        if read < TAG_LENGTH {
+            return Err(StreamError::Truncated);
+        }
+        if read < chunk.len() {
+            let opened = Zeroizing::new(
+                decryptor
+                    .decrypt_last(&chunk[..read])
+                    .map_err(|_| StreamError::Corrupt)?,
+            );
+            writer.write_all(&opened)?;
+            break;
+        }
+        let opened = Zeroizing::new(
+            decryptor
+                .decrypt_next(&chunk[..])
+                .map_err(|_| StreamError::Corrupt)?,
+        );
+        writer.write_all(&opened)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
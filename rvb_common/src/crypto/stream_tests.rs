@@ -0,0 +1,60 @@
+use super::*;
+
+const KEY: [u8; 32] = [7; 32];
+
+fn roundtrip(data: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::new();
+    encrypt_stream(&KEY, data, &mut sealed).unwrap();
+    let mut opened = Vec::new();
+    decrypt_stream(&KEY, &sealed[..], &mut opened).unwrap();
+    opened
+}
+
+#[test]
+fn test_stream_roundtrip() {
+    for len in [
+        0,
+        1,
+        STREAM_CHUNK_SIZE - 1,
+        STREAM_CHUNK_SIZE,
+        STREAM_CHUNK_SIZE * 2 + 5,
+    ] {
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        assert_eq!(roundtrip(&data), data, "length {len}");
+    }
+}
+
+#[test]
+fn test_stream_rejects_tampering() {
+    let data = vec![1u8; STREAM_CHUNK_SIZE * 2];
+    let mut sealed = Vec::new();
+    encrypt_stream(&KEY, &data[..], &mut sealed).unwrap();
+
+    let mut flipped = sealed.clone();
+    flipped[100] ^= 1;
+    assert!(matches!(
+        decrypt_stream(&KEY, &flipped[..], Vec::new()),
+        Err(StreamError::Corrupt)
+    ));
+
+    assert!(matches!(
+        decrypt_stream(&[8; 32], &sealed[..], Vec::new()),
+        Err(StreamError::Corrupt)
+    ));
+}
+
+#[test]
+fn test_stream_rejects_truncation() {
+    let data = vec![1u8; STREAM_CHUNK_SIZE * 2];
+    let mut sealed = Vec::new();
+    encrypt_stream(&KEY, &data[..], &mut sealed).unwrap();
+
+    // Dropping the final chunk leaves only full chunks.
+    let full_chunks = 7 + 2 * (STREAM_CHUNK_SIZE + 16);
+    assert!(decrypt_stream(&KEY, &sealed[..full_chunks], Vec::new()).is_err());
+    assert!(decrypt_stream(&KEY, &sealed[..7 + STREAM_CHUNK_SIZE + 16], Vec::new()).is_err());
+    assert!(matches!(
+        decrypt_stream(&KEY, &sealed[..3], Vec::new()),
+        Err(StreamError::Truncated)
+    ));
+}
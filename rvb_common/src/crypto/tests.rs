@@ -74,7 +74,7 @@ fn test_armor_public_key() {
 
 #[test]
 fn test_sign_and_verify() {
-    let mut keypair = KeyPair::generate();
+    let keypair = KeyPair::generate();
     let data = b"hello world";
     let signature = keypair.sign(data);
     assert!(keypair.verify(data, &signature));
@@ -82,7 +82,7 @@ fn test_sign_and_verify() {
 
 #[test]
 fn test_public_key_verify() {
-    let mut keypair = KeyPair::generate();
+    let keypair = KeyPair::generate();
     let public = keypair.public();
     let data = b"test message";
     let signature = keypair.sign(data);
@@ -119,7 +119,8 @@ fn test_import_invalid_length() {
 fn test_export_public() {
     let keypair = KeyPair::generate();
     let exported = keypair.export_public();
-    assert_eq!(exported.len(), 64);
+    assert_eq!(exported.len(), KEY_LENGTH);
+    assert_eq!(exported[0], KEY_FORMAT_VERSION);
 }
 
 #[test]
@@ -131,3 +132,64 @@ fn test_invalid_signature_verification() {
     assert!(!public.verify(data, &invalid_signature));
     assert!(!keypair.verify(data, &invalid_signature));
 }
+
+#[test]
+fn test_legacy_keys_are_migrated() {
+    let signing = SigningKey::generate(&mut OsRng);
+    let encrypting = SigningKey::generate(&mut OsRng);
+
+    let mut private = signing.to_bytes().to_vec();
+    private.extend_from_slice(encrypting.as_bytes());
+    let mut public = signing.verifying_key().to_bytes().to_vec();
+    public.extend_from_slice(encrypting.verifying_key().as_bytes());
+
+    let keypair = KeyPair::import(&private).unwrap();
+    let public = PublicKey::import(&public).unwrap();
+    assert_eq!(keypair.export_public(), public.export());
+
+    let encrypted = public.encrypt(b"kept readable").unwrap();
+    assert_eq!(keypair.decrypt(&encrypted).unwrap(), b"kept readable");
+}
+
+#[test]
+fn test_unknown_key_version() {
+    let mut exported = KeyPair::generate().export_private();
+    exported[0] = 0xff;
+    assert!(matches!(
+        KeyPair::import(&exported),
+        Err(CryptoError::InvalidKeyFormat(_))
+    ));
+}
+
+#[cfg(feature = "mnemonic")]
+#[test]
+fn test_mnemonic_recovery() {
+    let (keypair, phrase) = KeyPair::generate_with_mnemonic();
+    assert_eq!(phrase.split_whitespace().count(), 24);
+
+    let recovered = KeyPair::from_mnemonic(&phrase, "").unwrap();
+    assert_eq!(keypair.export_private(), recovered.export_private());
+
+    let other = KeyPair::from_mnemonic(&phrase, "passphrase").unwrap();
+    assert_ne!(keypair.export_private(), other.export_private());
+
+    assert!(KeyPair::from_mnemonic("not a phrase", "").is_err());
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn test_secp256k1_keys() {
+    let keypair = KeyPair::generate_secp256k1();
+    assert_eq!(keypair.scheme(), SignatureScheme::Secp256k1);
+
+    let imported = KeyPair::import(&keypair.export_private()).unwrap();
+    assert_eq!(imported.export_public(), keypair.export_public());
+
+    let public = PublicKey::import(&keypair.export_public()).unwrap();
+    let signature = keypair.sign(b"mixed mesh");
+    assert!(public.verify(b"mixed mesh", &signature));
+    assert!(!public.verify(b"other", &signature));
+
+    let ed25519 = KeyPair::generate().public();
+    assert!(!ed25519.verify(b"mixed mesh", &signature));
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CryptoError, PublicKey};
+
+/// Key of a group that signs only when `threshold` of its `members` agree,
+/// such as operators sharing control of a namespace. Members are exported
+/// public keys.
+///
+/// Members sign with their own keys and the signatures are collected into a
+/// [`ThresholdSignature`], so this is a k-of-n multisignature rather than an
+/// aggregated FROST signature: it grows with the threshold and is checked
+/// against the group, not as a single ed25519 signature.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ThresholdKey {
+    threshold: usize,
+    members: Vec<Vec<u8>>,
+}
+
+/// Signatures of some members of a [`ThresholdKey`], by member index.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ThresholdSignature {
+    pub shares: Vec<(usize, Vec<u8>)>,
+}
+
+impl ThresholdKey {
+    pub fn new(threshold: usize, members: Vec<Vec<u8>>) -> Result<Self, CryptoError> {
+        if threshold == 0 || threshold > members.len() {
+            return Err(CryptoError::InvalidKeyFormat(format!(
+                "threshold {threshold} for {} members",
+                members.len()
+            )));
+        }
+        for (i, member) in members.iter().enumerate() {
+            PublicKey::import(member)?;
+            if members[..i].contains(member) {
+                return Err(CryptoError::InvalidKeyFormat("duplicate member".into()));
+            }
+        }
+
+        Ok(Self { threshold, members })
+    }
+
+    #[must_use]
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    #[must_use]
+    pub fn members(&self) -> &[Vec<u8>] {
+        &self.members
+    }
+
+    /// Index of `member` in the group, for adding its signature to a
+    /// [`ThresholdSignature`].
+    #[must_use]
+    pub fn position(&self, member: &[u8]) -> Option<usize> {
+        self.members.iter().position(|m| m == member)
+    }
+
+    /// Whether at least `threshold` distinct members signed `data`.
+    #[must_use]
+    pub fn verify(&self, data: &[u8], signature: &ThresholdSignature) -> bool {
+        let mut signed = vec![false; self.members.len()];
+        for (index, share) in &signature.shares {
+            let Some(member) = self.members.get(*index) else {
+                continue;
+            };
+            if !signed[*index] && PublicKey::import(member).is_ok_and(|key| key.verify(data, share))
+            {
+                signed[*index] = true;
+            }
+        }
+
+        signed.iter().filter(|signed| **signed).count() >= self.threshold
+    }
+
+    #[must_use]
+    pub fn export(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).expect("Failed to encode threshold key")
+    }
+
+    pub fn import(data: &[u8]) -> Result<Self, CryptoError> {
+        let key: Self = rmp_serde::from_slice(data)
+            .map_err(|e| CryptoError::InvalidKeyFormat(e.to_string()))?;
+        Self::new(key.threshold, key.members)
+    }
+}
+
+impl ThresholdSignature {
+    /// Adds the signature of the member at `index`.
+    pub fn add(&mut self, index: usize, signature: Vec<u8>) {
+        self.shares.push((index, signature));
+    }
+}
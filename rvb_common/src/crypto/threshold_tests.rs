@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn test_threshold_signature() {
+    let members: Vec<_> = (0..3).map(|_| KeyPair::generate()).collect();
+    let key = ThresholdKey::new(2, members.iter().map(KeyPair::export_public).collect()).unwrap();
+    let data = b"rotate namespace admin";
+
+    let mut signature = ThresholdSignature::default();
+    signature.add(0, members[0].sign(data));
+    assert!(!key.verify(data, &signature));
+
+    // The same member twice does not count as two
+    signature.add(0, members[0].sign(data));
+    assert!(!key.verify(data, &signature));
+
+    let index = key.position(&members[2].export_public()).unwrap();
+    signature.add(index, members[2].sign(data));
+    assert!(key.verify(data, &signature));
+    assert!(!key.verify(b"other", &signature));
+
+    assert_eq!(ThresholdKey::import(&key.export()).unwrap(), key);
+}
+
+#[test]
+fn test_invalid_threshold_key() {
+    let member = KeyPair::generate().export_public();
+    assert!(ThresholdKey::new(0, vec![member.clone()]).is_err());
+    assert!(ThresholdKey::new(2, vec![member.clone()]).is_err());
+    assert!(ThresholdKey::new(1, vec![member.clone(), member]).is_err());
+}
@@ -0,0 +1,51 @@
+//! Binary patches between two versions of a contract's bytecode, so an
+//! upgrade over a slow link only sends what changed. A patch is the new
+//! bytecode compressed with zstd using the old bytecode as a raw dictionary:
+//! stretches found in both cost a few bytes of back-references.
+
+use zstd::bulk::{Compressor, Decompressor};
+use zstd::zstd_safe::{CParameter, DParameter, get_frame_content_size};
+
+const LEVEL: i32 = 19;
+/// Largest zstd window, so matches may reach back across the whole of the
+/// old bytecode. Decoders accept up to this much by default.
+const MAX_WINDOW_LOG: u32 = 27;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeltaError {
+    #[error("Invalid patch: {0}")]
+    Invalid(#[from] std::io::Error),
+    /// The patched bytecode would be larger than the caller allows.
+    #[error("Patched bytecode is over {0} bytes")]
+    TooLarge(usize),
+}
+
+fn window_log(old: &[u8], new: &[u8]) -> u32 {
+    let size = (old.len() + new.len()).max(1);
+    (usize::BITS - size.leading_zeros()).clamp(10, MAX_WINDOW_LOG)
+}
+
+/// Patch turning `old` into `new` with [`apply`].
+pub fn diff(old: &[u8], new: &[u8]) -> Result<Vec<u8>, DeltaError> {
+    let mut compressor = Compressor::with_dictionary(LEVEL, old)?;
+    compressor.set_parameter(CParameter::WindowLog(window_log(old, new)))?;
+    compressor.set_parameter(CParameter::EnableLongDistanceMatching(true))?;
+    Ok(compressor.compress(new)?)
+}
+
+/// Applies a patch made by [`diff`] to `old`, refusing to produce more than
+/// `max_size` bytes.
+pub fn apply(old: &[u8], patch: &[u8], max_size: usize) -> Result<Vec<u8>, DeltaError> {
+    let size = get_frame_content_size(patch)
+        .ok()
+        .flatten()
+        .ok_or_else(|| std::io::Error::other("missing content size"))?;
+    let size = usize::try_from(size)
+        .ok()
+        .filter(|size| *size <= max_size)
+        .ok_or(DeltaError::TooLarge(max_size))?;
+
+    let mut decompressor = Decompressor::with_dictionary(old)?;
+    decompressor.set_parameter(DParameter::WindowLogMax(MAX_WINDOW_LOG))?;
+    Ok(decompressor.decompress(patch, size)?)
+}
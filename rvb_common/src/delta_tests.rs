@@ -0,0 +1,39 @@
+use crate::delta::*;
+
+fn bytecode(seed: u8, len: usize) -> Vec<u8> {
+    let mut state = u32::from(seed) + 1;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_patch_roundtrip() {
+    let old = bytecode(1, 64 * 1024);
+    let mut new = old.clone();
+    new[1000..1010].copy_from_slice(b"0123456789");
+    new.extend_from_slice(b"appended section");
+
+    let patch = diff(&old, &new).unwrap();
+    assert!(patch.len() < 512, "patch of {} bytes", patch.len());
+    assert_eq!(apply(&old, &patch, new.len()).unwrap(), new);
+}
+
+#[test]
+fn test_patch_errors() {
+    let old = bytecode(1, 4096);
+    let new = bytecode(2, 4096);
+    let patch = diff(&old, &new).unwrap();
+
+    assert!(matches!(
+        apply(&old, &patch, 1024),
+        Err(DeltaError::TooLarge(1024))
+    ));
+    assert!(matches!(
+        apply(&old, b"not a patch", usize::MAX),
+        Err(DeltaError::Invalid(_))
+    ));
+}
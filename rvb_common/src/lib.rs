@@ -2,6 +2,10 @@
 pub mod contract;
 #[cfg(feature = "crypto")]
 pub mod crypto;
+#[cfg(feature = "delta")]
+pub mod delta;
+#[cfg(all(test, feature = "delta"))]
+mod delta_tests;
 #[cfg(feature = "protocol")]
 pub mod protocol;
 #[cfg(feature = "schema")]
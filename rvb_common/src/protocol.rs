@@ -1,6 +1,8 @@
+use crate::contract::{ContractMetadata, ExecutionReport};
 #[cfg(feature = "crypto")]
-use crate::crypto::{CryptoError, KeyPair, PublicKey};
-use crate::schema::DbValue;
+use crate::crypto::{CryptoError, KeyPair, PublicKey, Signer};
+use crate::schema::{DbValue, Filter};
+use bytes::Bytes;
 #[cfg(feature = "crypto_random")]
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
@@ -15,7 +17,7 @@ pub enum ProtocolError {
     Schema(rmp_serde::decode::Error),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Location {
     pub namespace: String,
     pub contract_space: String,
@@ -23,19 +25,110 @@ pub struct Location {
     pub key: String,
 }
 
+/// Event published by a contract through `DataAction::Emit`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractEvent {
+    pub namespace: String,
+    pub contract_space: String,
+    pub topic: String,
+    pub payload: DbValue,
+}
+
+/// Where a node runs, as it labels itself. Nodes of a region are expected to
+/// fail together, and those of a zone within it even more so.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Locality {
+    pub region: String,
+    pub zone: Option<String>,
+}
+
+/// A deployed contract, as reported in reply to `SearchTags`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractInfo {
+    pub id: Vec<u8>,
+    pub namespace: String,
+    pub tags: Vec<String>,
+    pub deployed_by: Vec<u8>,
+    pub metadata: Option<ContractMetadata>,
+}
+
+/// A node's signed statement that it stores the value with `value_digest` at
+/// `state` for `location`, sent along `GetResult` by nodes configured to prove
+/// their reads. Made and checked with `StateProof::sign` and
+/// `StateProof::verify`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateProof {
+    pub location: Location,
+    /// blake3 digest of the msgpack encoded value, or of `None` if the key is
+    /// not stored.
+    pub value_digest: Vec<u8>,
+    pub state: u64,
+    /// Where the key sits in the Merkle tree of its contract space, whose
+    /// root every node holding the same data agrees on. `None` if the node
+    /// does not anchor its proofs or the key is not stored.
+    pub anchor: Option<MerkleAnchor>,
+    pub signed_by: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Path from a key's leaf to the Merkle root of its contract space.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleAnchor {
+    pub root: Vec<u8>,
+    /// Siblings on the way up, starting next to the leaf.
+    pub path: Vec<MerkleStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleStep {
+    pub sibling: Vec<u8>,
+    /// Whether the sibling is the left child of their parent.
+    pub left: bool,
+}
+
+/// Optional service a node offers its peers, advertised with
+/// `Message::Capabilities`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Passes `Message::Forward` envelopes on to the peer they are addressed
+    /// to, so peers that cannot reach each other directly can still talk.
+    Relay,
+    /// Mirrors data without being a source of it: the node serves reads but
+    /// refuses writes sent to it by their own signer, so clients should send
+    /// their writes elsewhere.
+    Observer,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     Hello {
         public_key: Vec<u8>,
+        /// Public value of the sender's session key exchange, or empty to
+        /// keep the connection unencrypted.
+        session_key: Vec<u8>,
+        /// Encoded `IdentityCertificate`s vouching for the sender, checked
+        /// by nodes that only admit trusted peers.
+        certificates: Vec<Vec<u8>>,
     },
+    /// Challenge answering a `Hello`: the sender of the `Hello` proves its key
+    /// by signing `data` in an `ItsMe`.
     WhoAreYou {
         data: Vec<u8>,
         public_key: Vec<u8>,
+        /// The responder's side of the exchange started in `Hello`.
+        session_key: Vec<u8>,
+        /// The responder's certificates, as in `Hello`.
+        certificates: Vec<Vec<u8>>,
     },
+    /// Answer to a `WhoAreYou`, with `signature` made over
+    /// [`challenge_payload`] of its `data`.
     ItsMe {
         signature: Vec<u8>,
         data: Vec<u8>,
     },
+    /// Ends a handshake whose `ItsMe` checked out. `signature` is the
+    /// receiving node's over the same challenge, and `dht_ip` and `dht_port`
+    /// the address it accepts peers on, empty and 0 if it advertises none.
     Welcome {
         dht_ip: String,
         dht_port: u16,
@@ -51,18 +144,161 @@ pub enum Message {
         location: Location,
         select: Vec<Vec<String>>,
     },
+    /// Reply to `Get`: the contract's query result if it has a query, the
+    /// stored value otherwise, narrowed to the selected fields. `None` if there
+    /// is no value. `proof` is only sent by nodes proving their reads, when
+    /// the reply is the whole stored value.
+    GetResult {
+        location: Location,
+        value: Option<DbValue>,
+        proof: Option<StateProof>,
+    },
+    /// Resources the contract call for an `Insert` at `location` used. Sent
+    /// back to the sender only by nodes configured to report executions.
+    ExecutionReport {
+        location: Location,
+        report: ExecutionReport,
+    },
     DeployContract {
         contract_payload: Vec<u8>,
         namespace: String,
         params: HashMap<String, DbValue>,
         tags: Vec<String>,
+        /// Id the sender worked out with `crypto::contract_id` from the
+        /// payload and its key. The node refuses the deployment if the
+        /// payload does not give this id.
+        id: Option<Vec<u8>>,
+    },
+    /// Replaces the code of an existing contract, keeping its id, params and
+    /// data. Only the key that deployed the contract may upgrade it; the new
+    /// code's `rvb_migrate` hook then runs over every stored value.
+    UpgradeContract {
+        contract: Vec<u8>,
+        contract_payload: Vec<u8>,
     },
     SearchTags {
         namespace: String,
         query: Vec<String>,
     },
+    /// Contracts in `namespace` carrying every tag of a `SearchTags` query.
+    SearchResult {
+        namespace: String,
+        contracts: Vec<ContractInfo>,
+    },
+    /// Addresses and localities of nodes by public key, as far as the sender
+    /// knows them. A node sends each new peer one with its own.
     Gossip {
         peers: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+        localities: HashMap<Vec<u8>, Locality>,
+    },
+    /// Asks the receiving node to forward events emitted in `namespace`, either
+    /// on a single topic or on all of them. With a `filter`, only events whose
+    /// payload matches it are forwarded.
+    Subscribe {
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<Filter>,
+    },
+    Unsubscribe {
+        namespace: String,
+        topic: Option<String>,
+    },
+    Event {
+        event: ContractEvent,
+    },
+    /// Services the sender offers. Nodes with any to offer send it to each
+    /// new peer, which answers with its own, identifying itself by the key
+    /// that signed it.
+    Capabilities {
+        capabilities: Vec<Capability>,
+    },
+    /// Asks a relay to pass `payload`, an encoded `TransportMessage`, on to
+    /// its peer with public key `to`. The payload is delivered as it is, so
+    /// the recipient checks its signature as with any other message.
+    Forward {
+        to: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    /// Makes `namespace` private to a group at `epoch`: inserts into it must
+    /// carry values sealed with the group's key, given in `keys` wrapped to
+    /// the public key of each member. The first key to share a group for a
+    /// namespace owns it and is the only one that may start later epochs;
+    /// epochs older than the current one are ignored.
+    ShareGroupKey {
+        namespace: String,
+        epoch: u64,
+        keys: HashMap<Vec<u8>, Vec<u8>>,
+    },
+    /// Asks for the group key of `namespace` wrapped to the sender.
+    GetGroupKey {
+        namespace: String,
+    },
+    /// Reply to `GetGroupKey`: the current epoch of the group, with the key
+    /// wrapped to the sender, or `None` if it is not a member.
+    GroupKey {
+        namespace: String,
+        epoch: u64,
+        key: Option<Vec<u8>>,
+    },
+    /// Limits who may insert into `namespace` to the public keys in
+    /// `writers`, on top of what its contracts approve, or lifts the limit if
+    /// `None`. Only keys that deployed a contract in the namespace may set it,
+    /// and they may always write.
+    SetWriters {
+        namespace: String,
+        writers: Option<Vec<Vec<u8>>>,
+    },
+    /// Asks the receiving node to pass on the inserts and deletions it applies
+    /// in `namespaces`, as signed by their senders, replacing the namespaces
+    /// asked for before. Light nodes send it to each new peer, which answers
+    /// with its `Capabilities` so they know who passes the writes on.
+    Replicate {
+        namespaces: Vec<String>,
+    },
+    /// Asks the receiver to answer at once with a `Pong` carrying the same
+    /// `nonce`, measuring the round trip to it.
+    Ping {
+        nonce: u64,
+    },
+    Pong {
+        nonce: u64,
+    },
+    /// Upgrades a contract like `UpgradeContract`, sending a `delta` patch
+    /// against its current bytecode instead of the whole new bytecode. `base`
+    /// and `target` are the blake3 hashes of the bytecode before and after,
+    /// and the node refuses the patch if either does not match.
+    PatchContract {
+        contract: Vec<u8>,
+        base: Vec<u8>,
+        patch: Vec<u8>,
+        target: Vec<u8>,
+    },
+    /// Removes the value at `location` if the contract's delete handler
+    /// approves, as of `state`. Values written at a higher state are newer
+    /// and stay, while writes up to `state`, wherever they are still on
+    /// their way, are dropped instead of bringing the value back.
+    Delete {
+        location: Location,
+        state: u64,
+    },
+    /// Hands control of who writes to `namespace` to several operators:
+    /// `admins` is an exported `ThresholdKey`, a quorum of whose members then
+    /// sets the namespace's writers with `AdminSetWriters`, while `SetWriters`
+    /// from single deployers is refused. Only a deployer may send it, and
+    /// only while the namespace has no admins.
+    SetAdmins {
+        namespace: String,
+        admins: Vec<u8>,
+    },
+    /// `SetWriters` agreed on by the admins of `namespace`. `shares` are their
+    /// signatures over [`admin_payload`], by member index, and must reach the
+    /// threshold of their key. `sequence` must be above that of the last
+    /// change applied, so an agreed change cannot be replayed later.
+    AdminSetWriters {
+        namespace: String,
+        writers: Option<Vec<Vec<u8>>>,
+        sequence: u64,
+        shares: Vec<(usize, Vec<u8>)>,
     },
 }
 
@@ -90,23 +326,42 @@ impl TryFrom<TransportMessage> for Message {
     }
 }
 
+/// Seconds a signed batch stays valid for unless signed with another expiry.
+pub const MESSAGE_TTL: u64 = 300;
+
 #[cfg(feature = "crypto")]
 impl TransportMessage {
+    /// Signs `messages`, valid for [`MESSAGE_TTL`] seconds.
     pub fn sign(
         messages: &[Message],
         key: &mut KeyPair,
         publisher: String,
         #[cfg(not(feature = "crypto_random"))] id: Vec<u8>,
     ) -> TransportMessage {
-        let bin = rmp_serde::to_vec(messages).unwrap();
-        let signature = key.sign(&bin);
+        Self::sign_expiring(
+            messages,
+            key,
+            publisher,
+            default_expiry(),
+            #[cfg(not(feature = "crypto_random"))]
+            id,
+        )
+    }
 
+    /// Like [`TransportMessage::sign`], valid until `expires_at`, in seconds
+    /// since the Unix epoch. 0 never expires.
+    pub fn sign_expiring(
+        messages: &[Message],
+        key: &mut KeyPair,
+        publisher: String,
+        expires_at: u64,
+        #[cfg(not(feature = "crypto_random"))] id: Vec<u8>,
+    ) -> TransportMessage {
         #[cfg(feature = "crypto_random")]
-        let id = {
-            let mut buf = vec![0u8; 64];
-            rand::thread_rng().fill_bytes(&mut buf);
-            buf.to_vec()
-        };
+        let id = random_id();
+        let bin = rmp_serde::to_vec(messages).unwrap();
+        let signed_at = unix_time();
+        let signature = key.sign(&signed_payload(&bin, signed_at, expires_at, &id));
 
         TransportMessage {
             signature: MessageSignature {
@@ -114,11 +369,116 @@ impl TransportMessage {
                 data: signature,
             },
             id,
-            data: bin,
+            data: bin.into(),
+            signed_at,
+            expires_at,
             publisher,
-            received_by: Vec::new(),
         }
     }
+
+    /// Like [`TransportMessage::sign`], signing through `signer`.
+    pub async fn sign_with(
+        messages: &[Message],
+        signer: &dyn Signer,
+        publisher: String,
+        #[cfg(not(feature = "crypto_random"))] id: Vec<u8>,
+    ) -> Result<TransportMessage, CryptoError> {
+        #[cfg(feature = "crypto_random")]
+        let id = random_id();
+        let bin = rmp_serde::to_vec(messages).unwrap();
+        let signed_at = unix_time();
+        let expires_at = default_expiry();
+        let signed = signer
+            .sign(&signed_payload(&bin, signed_at, expires_at, &id))
+            .await?;
+
+        Ok(TransportMessage {
+            signature: MessageSignature {
+                signed_by: signed.public_key,
+                data: signed.signature,
+            },
+            id,
+            data: bin.into(),
+            signed_at,
+            expires_at,
+            publisher,
+        })
+    }
+}
+
+impl TransportMessage {
+    /// Whether the batch expired before `now`, in seconds since the Unix
+    /// epoch, allowing for clocks up to `skew` seconds apart.
+    #[must_use]
+    pub fn is_expired(&self, now: u64, skew: u64) -> bool {
+        self.expires_at != 0 && now > self.expires_at.saturating_add(skew)
+    }
+}
+
+const CHALLENGE_CONTEXT: &[u8] = b"reverb-challenge-v1 ";
+
+/// Bytes the answer to the challenge `data` of a `WhoAreYou` signs. The
+/// prefix keeps a peer from having a challenge signed that it could pass off
+/// as something else signed by the same key, such as a batch of messages.
+#[must_use]
+pub fn challenge_payload(data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(CHALLENGE_CONTEXT.len() + data.len());
+    payload.extend_from_slice(CHALLENGE_CONTEXT);
+    payload.extend_from_slice(data);
+    payload
+}
+
+const ADMIN_CONTEXT: &[u8] = b"reverb-admin-v1 ";
+
+/// Bytes the admins of `namespace` sign to agree on an `AdminSetWriters`,
+/// prefixed so the signature is not mistaken for one over anything else.
+#[must_use]
+pub fn admin_payload(namespace: &str, writers: Option<&[Vec<u8>]>, sequence: u64) -> Vec<u8> {
+    let mut payload = ADMIN_CONTEXT.to_vec();
+    payload.extend(
+        rmp_serde::to_vec(&(namespace, writers, sequence)).expect("Failed to encode admin change"),
+    );
+    payload
+}
+
+#[cfg(feature = "crypto_random")]
+fn random_id() -> Vec<u8> {
+    let mut id = vec![0u8; 64];
+    rand::thread_rng().fill_bytes(&mut id);
+    id
+}
+
+/// Bytes a transport signature covers: the encoded batch followed by the times
+/// it was signed at and expires at and its id, so none of them can be changed
+/// in transit.
+#[cfg(feature = "crypto")]
+fn signed_payload(data: &[u8], signed_at: u64, expires_at: u64, id: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(data.len() + 16 + id.len());
+    payload.extend_from_slice(data);
+    payload.extend_from_slice(&signed_at.to_be_bytes());
+    payload.extend_from_slice(&expires_at.to_be_bytes());
+    payload.extend_from_slice(id);
+    payload
+}
+
+/// Expiry of batches signed now, or none without a clock to tell the time.
+#[cfg(feature = "crypto")]
+fn default_expiry() -> u64 {
+    match unix_time() {
+        0 => 0,
+        now => now + MESSAGE_TTL,
+    }
+}
+
+/// Seconds since the Unix epoch, or 0 on targets without a clock.
+#[cfg(feature = "crypto")]
+fn unix_time() -> u64 {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return 0;
+    }
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
 }
 
 #[cfg(feature = "crypto")]
@@ -128,7 +488,10 @@ impl TryFrom<TransportMessage> for Vec<Message> {
     fn try_from(value: TransportMessage) -> Result<Self, Self::Error> {
         let public_key =
             PublicKey::import(&value.signature.signed_by).map_err(ProtocolError::Crypto)?;
-        let result = public_key.verify(&value.data, &value.signature.data);
+        let result = public_key.verify(
+            &signed_payload(&value.data, value.signed_at, value.expires_at, &value.id),
+            &value.signature.data,
+        );
 
         if result {
             rmp_serde::from_slice(&value.data).map_err(ProtocolError::Schema)
@@ -144,14 +507,24 @@ pub struct MessageSignature {
     pub signed_by: Vec<u8>,
 }
 
+/// A batch of messages with its signature. The encoded batch is kept as
+/// [`Bytes`], so clones share it.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransportMessage {
     #[cfg(feature = "crypto")]
-    data: Vec<u8>,
+    data: Bytes,
     #[cfg(not(feature = "crypto"))]
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub signature: MessageSignature,
+    /// When the batch was signed, in seconds since the Unix epoch. Covered by
+    /// the signature; 0 if the signer had no clock.
+    pub signed_at: u64,
+    /// When the batch stops being valid, in seconds since the Unix epoch.
+    /// Covered by the signature; 0 never expires.
+    pub expires_at: u64,
+    /// Originator of the batch. Nodes relaying it leave the envelope as it is
+    /// and recognize batches they have already seen by their id.
     pub publisher: String,
-    pub received_by: Vec<Vec<u8>>,
+    /// Random id of the batch, covered by the signature.
     pub id: Vec<u8>,
 }
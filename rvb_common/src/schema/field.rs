@@ -0,0 +1,143 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Longest name worth interning; longer ones are rarely repeated.
+const MAX_INTERNED_LEN: usize = 64;
+/// Number of distinct names the pool keeps, so maps keyed by ids or other
+/// unbounded sets of names cannot grow it forever.
+const MAX_INTERNED: usize = 1 << 16;
+
+static NAMES: OnceLock<Mutex<HashSet<Arc<str>>>> = OnceLock::new();
+
+fn intern(name: &str) -> Option<Arc<str>> {
+    if name.len() > MAX_INTERNED_LEN {
+        return None;
+    }
+
+    let mut names = NAMES.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(interned) = names.get(name) {
+        return Some(interned.clone());
+    }
+    if names.len() >= MAX_INTERNED {
+        return None;
+    }
+    let interned: Arc<str> = Arc::from(name);
+    names.insert(interned.clone());
+    Some(interned)
+}
+
+/// Name of a field of a [`DbValue::Object`](super::DbValue::Object). Short
+/// names are interned, so records sharing a layout share one allocation per
+/// field name instead of holding a copy each. Encoded as a plain string.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FieldName(Arc<str>);
+
+impl FieldName {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for FieldName {
+    fn from(name: &str) -> Self {
+        Self(intern(name).unwrap_or_else(|| Arc::from(name)))
+    }
+}
+
+impl From<String> for FieldName {
+    fn from(name: String) -> Self {
+        Self(intern(&name).unwrap_or_else(|| Arc::from(name)))
+    }
+}
+
+impl From<&String> for FieldName {
+    fn from(name: &String) -> Self {
+        name.as_str().into()
+    }
+}
+
+impl From<FieldName> for String {
+    fn from(name: FieldName) -> Self {
+        name.0.to_string()
+    }
+}
+
+impl Deref for FieldName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for FieldName {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for FieldName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for FieldName {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for FieldName {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl fmt::Debug for FieldName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for FieldName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for FieldName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+struct FieldNameVisitor;
+
+impl Visitor<'_> for FieldNameVisitor {
+    type Value = FieldName;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a field name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<FieldName, E> {
+        Ok(name.into())
+    }
+
+    fn visit_string<E: de::Error>(self, name: String) -> Result<FieldName, E> {
+        Ok(name.into())
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(FieldNameVisitor)
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+
+fn decode(value: &DbValue) -> DbValue {
+    rmp_serde::from_slice(&rmp_serde::to_vec(value).unwrap()).unwrap()
+}
+
+fn field_names(value: &DbValue) -> Vec<&FieldName> {
+    match value {
+        DbValue::Object(map) => map.keys().collect(),
+        _ => panic!("Expected object"),
+    }
+}
+
+#[test]
+fn test_decoded_names_are_shared() {
+    let record = DbValue::Object(HashMap::from([(
+        "stars".into(),
+        Box::new(DbValue::Number(3)),
+    )]));
+    let (a, b) = (decode(&record), decode(&record));
+
+    let (a, b) = (field_names(&a)[0], field_names(&b)[0]);
+    assert_eq!(a, "stars");
+    assert!(std::ptr::eq(a.as_str(), b.as_str()));
+}
+
+#[test]
+fn test_long_names_are_not_interned() {
+    let long = "x".repeat(100);
+    let (a, b) = (
+        FieldName::from(long.as_str()),
+        FieldName::from(long.clone()),
+    );
+
+    assert_eq!(a, b);
+    assert!(!std::ptr::eq(a.as_str(), b.as_str()));
+}
+
+#[test]
+fn test_names_encode_as_strings() {
+    let name = FieldName::from("stars");
+    assert_eq!(
+        rmp_serde::to_vec(&name).unwrap(),
+        rmp_serde::to_vec("stars").unwrap()
+    );
+    assert_eq!(name.to_string(), "stars");
+    assert_eq!(format!("{name:?}"), "\"stars\"");
+}
@@ -0,0 +1,342 @@
+//! Predicates over a [`DbValue`], such as `status == "open" && count > 3`,
+//! which nodes evaluate to forward subscribers only the events they want.
+//!
+//! A comparison takes the value at a dotted path, like `author.name`, and a
+//! literal: a string in double quotes, an integer, `true`, `false` or `null`.
+//! `==` and `!=` compare any values, while `<`, `<=`, `>` and `>=` hold only
+//! between two numbers or two strings. `contains` looks for the literal among
+//! the items of an array, or for a substring of a string. A path on its own
+//! holds if there is a value there. No comparison holds for a missing value,
+//! so `!(status == "open")` is the way to also match values without a
+//! status. Comparisons are combined with `!`, `&&` and `||`, in that order of
+//! precedence, and grouped with parentheses.
+
+use super::DbValue;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Deepest nesting of `!`, `&&`, `||` and parentheses a filter may have.
+/// Nodes refuse deeper filters, as they are evaluated recursively.
+pub const MAX_FILTER_DEPTH: usize = 32;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum FilterError {
+    #[error("Invalid filter at byte {at}: {reason}")]
+    Syntax { at: usize, reason: &'static str },
+    #[error("Filter nests deeper than {MAX_FILTER_DEPTH} levels")]
+    TooDeep,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+impl CompareOp {
+    fn holds(self, found: &DbValue, operand: &DbValue) -> bool {
+        let ordering = match (self, found, operand) {
+            (CompareOp::Eq, ..) => return found == operand,
+            (CompareOp::Ne, ..) => return found != operand,
+            (CompareOp::Contains, DbValue::Array(items), _) => {
+                return items.iter().any(|item| **item == *operand);
+            }
+            (CompareOp::Contains, DbValue::String(text), DbValue::String(part)) => {
+                return text.contains(part.as_str());
+            }
+            (CompareOp::Contains, ..) => return false,
+            (_, DbValue::Number(a), DbValue::Number(b)) => a.cmp(b),
+            (_, DbValue::String(a), DbValue::String(b)) => a.cmp(b),
+            _ => return false,
+        };
+        match self {
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            _ => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// A parsed filter expression; see the [module docs](self) for the syntax.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Exists {
+        path: Vec<String>,
+    },
+    Compare {
+        path: Vec<String>,
+        op: CompareOp,
+        value: DbValue,
+    },
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self, FilterError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            pos: 0,
+            depth: 0,
+            end: expression.len(),
+        };
+        let filter = parser.or()?;
+        if let Some((at, _)) = parser.tokens.get(parser.pos) {
+            return Err(FilterError::Syntax {
+                at: *at,
+                reason: "expected the end of the filter",
+            });
+        }
+        if filter.depth() > MAX_FILTER_DEPTH {
+            return Err(FilterError::TooDeep);
+        }
+        Ok(filter)
+    }
+
+    /// Whether `value` satisfies the filter.
+    #[must_use]
+    pub fn matches(&self, value: &DbValue) -> bool {
+        match self {
+            Filter::Exists { path } => value.get_path(path).is_some(),
+            Filter::Compare {
+                path,
+                op,
+                value: operand,
+            } => value
+                .get_path(path)
+                .is_some_and(|found| op.holds(found, operand)),
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(value)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(value)),
+            Filter::Not(filter) => !filter.matches(value),
+        }
+    }
+
+    /// Levels of nesting, 1 for a single comparison.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        match self {
+            Filter::Exists { .. } | Filter::Compare { .. } => 1,
+            Filter::And(filters) | Filter::Or(filters) => {
+                1 + filters.iter().map(Filter::depth).max().unwrap_or(0)
+            }
+            Filter::Not(filter) => 1 + filter.depth(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Path(Vec<String>),
+    Literal(DbValue),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let bytes = expression.as_bytes();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+
+    while at < bytes.len() {
+        let start = at;
+        let rest = &expression[at..];
+        let symbol = [
+            ("&&", Token::And),
+            ("||", Token::Or),
+            ("==", Token::Op(CompareOp::Eq)),
+            ("!=", Token::Op(CompareOp::Ne)),
+            ("<=", Token::Op(CompareOp::Le)),
+            (">=", Token::Op(CompareOp::Ge)),
+            ("<", Token::Op(CompareOp::Lt)),
+            (">", Token::Op(CompareOp::Gt)),
+            ("!", Token::Not),
+            ("(", Token::Open),
+            (")", Token::Close),
+        ]
+        .into_iter()
+        .find(|(text, _)| rest.starts_with(text));
+
+        if bytes[at].is_ascii_whitespace() {
+            at += 1;
+            continue;
+        }
+        if let Some((text, token)) = symbol {
+            at += text.len();
+            tokens.push((start, token));
+            continue;
+        }
+
+        let token = match bytes[at] {
+            b'"' => {
+                let (text, len) = string(&rest[1..]).ok_or(FilterError::Syntax {
+                    at,
+                    reason: "unterminated string",
+                })?;
+                at += len + 1;
+                Token::Literal(DbValue::String(text))
+            }
+            byte if byte == b'-' || byte.is_ascii_digit() => {
+                let len = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(rest.len(), |len| len + 1);
+                at += len;
+                let number = rest[..len].parse().map_err(|_| FilterError::Syntax {
+                    at: start,
+                    reason: "invalid number",
+                })?;
+                Token::Literal(DbValue::Number(number))
+            }
+            byte if byte == b'_' || byte.is_ascii_alphabetic() => {
+                let len = rest
+                    .find(|c: char| !(c == '_' || c == '.' || c.is_ascii_alphanumeric()))
+                    .unwrap_or(rest.len());
+                at += len;
+                match &rest[..len] {
+                    "true" => Token::Literal(DbValue::Boolean(true)),
+                    "false" => Token::Literal(DbValue::Boolean(false)),
+                    "null" => Token::Literal(DbValue::None),
+                    "contains" => Token::Op(CompareOp::Contains),
+                    path => {
+                        let path: Vec<String> = path.split('.').map(str::to_string).collect();
+                        if path.iter().any(String::is_empty) {
+                            return Err(FilterError::Syntax {
+                                at: start,
+                                reason: "empty field name in path",
+                            });
+                        }
+                        Token::Path(path)
+                    }
+                }
+            }
+            _ => {
+                return Err(FilterError::Syntax {
+                    at,
+                    reason: "unexpected character",
+                });
+            }
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Contents of the string literal `rest` starts with, after its opening
+/// quote, and the bytes up to and including the closing quote. Quotes and
+/// backslashes are escaped with a backslash.
+fn string(rest: &str) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '"' => return Some((text, at + 1)),
+            '\\' => text.push(chars.next()?.1),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    depth: usize,
+    /// Length of the expression, reported as where it ended too early.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn error(&self, reason: &'static str) -> FilterError {
+        let at = self.tokens.get(self.pos).map_or(self.end, |(at, _)| *at);
+        FilterError::Syntax { at, reason }
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, FilterError>,
+    ) -> Result<T, FilterError> {
+        self.depth += 1;
+        if self.depth > MAX_FILTER_DEPTH {
+            return Err(FilterError::TooDeep);
+        }
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn or(&mut self) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            filters.push(self.and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::Or(filters)
+        })
+    }
+
+    fn and(&mut self) -> Result<Filter, FilterError> {
+        let mut filters = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            filters.push(self.unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::And(filters)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Filter, FilterError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                self.nested(|parser| Ok(Filter::Not(Box::new(parser.unary()?))))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let filter = self.nested(Self::or)?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.error("expected )"));
+                }
+                self.pos += 1;
+                Ok(filter)
+            }
+            Some(Token::Path(_)) => self.comparison(),
+            _ => Err(self.error("expected a path, ! or (")),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Filter, FilterError> {
+        let Some(Token::Path(path)) = self.peek().cloned() else {
+            return Err(self.error("expected a path"));
+        };
+        self.pos += 1;
+        let Some(Token::Op(op)) = self.peek().cloned() else {
+            return Ok(Filter::Exists { path });
+        };
+        self.pos += 1;
+        let Some(Token::Literal(value)) = self.peek().cloned() else {
+            return Err(self.error("expected a literal"));
+        };
+        self.pos += 1;
+        Ok(Filter::Compare { path, op, value })
+    }
+}
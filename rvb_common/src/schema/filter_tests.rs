@@ -0,0 +1,110 @@
+use super::filter::{CompareOp, MAX_FILTER_DEPTH};
+use super::*;
+
+fn object(entries: &[(&str, DbValue)]) -> DbValue {
+    DbValue::Object(
+        entries
+            .iter()
+            .map(|(k, v)| ((*k).into(), Box::new(v.clone())))
+            .collect(),
+    )
+}
+
+fn ticket() -> DbValue {
+    object(&[
+        ("status", DbValue::String("open".into())),
+        ("votes", DbValue::Number(7)),
+        (
+            "labels",
+            DbValue::Array(vec![Box::new(DbValue::String("bug".into()))]),
+        ),
+        (
+            "author",
+            object(&[("name", DbValue::String("Ada \"A\" L".into()))]),
+        ),
+    ])
+}
+
+fn matches(expression: &str) -> bool {
+    Filter::parse(expression).unwrap().matches(&ticket())
+}
+
+#[test]
+fn test_parse_comparison() {
+    assert_eq!(
+        Filter::parse(r#"status == "open""#).unwrap(),
+        Filter::Compare {
+            path: vec!["status".into()],
+            op: CompareOp::Eq,
+            value: DbValue::String("open".into()),
+        }
+    );
+    assert_eq!(
+        Filter::parse("author.name").unwrap(),
+        Filter::Exists {
+            path: vec!["author".into(), "name".into()],
+        }
+    );
+}
+
+#[test]
+fn test_filter_matches() {
+    assert!(matches(r#"status == "open""#));
+    assert!(!matches(r#"status != "open""#));
+    assert!(matches("votes > 3 && votes <= 7"));
+    assert!(!matches("votes < -1"));
+    assert!(matches(r#"labels contains "bug""#));
+    assert!(matches(r#"author.name contains "\"A\"""#));
+    assert!(matches(r#"status == "closed" || !(votes >= 10)"#));
+    assert!(matches("author.name && !assignee"));
+}
+
+#[test]
+fn test_missing_and_mismatched_values() {
+    // No comparison holds for a value that is not there
+    assert!(!matches("assignee == null"));
+    assert!(!matches(r#"assignee != "me""#));
+    assert!(matches(r#"!(assignee == "me")"#));
+    // Ordering a number against a string never holds
+    assert!(!matches(r#"votes > "1""#));
+    assert!(!matches(r#"votes < "1""#));
+}
+
+#[test]
+fn test_invalid_filters() {
+    for expression in [
+        "",
+        "status ==",
+        r#"status == "open"#,
+        "== 1",
+        "(votes > 1",
+        "votes > 1)",
+        "votes 1",
+        "status..name",
+        "votes > 1 &",
+    ] {
+        assert!(
+            matches!(Filter::parse(expression), Err(FilterError::Syntax { .. })),
+            "{expression:?} parsed"
+        );
+    }
+    assert_eq!(
+        Filter::parse("votes # 1"),
+        Err(FilterError::Syntax {
+            at: 6,
+            reason: "unexpected character",
+        })
+    );
+}
+
+#[test]
+fn test_filter_depth_is_bounded() {
+    let depth = MAX_FILTER_DEPTH + 1;
+    let nested = format!("{}votes{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(Filter::parse(&nested), Err(FilterError::TooDeep));
+    let negated = format!("{}votes", "!".repeat(100_000));
+    assert_eq!(Filter::parse(&negated), Err(FilterError::TooDeep));
+
+    let shallow = format!("{}votes", "!".repeat(MAX_FILTER_DEPTH - 1));
+    assert_eq!(Filter::parse(&shallow).unwrap().depth(), MAX_FILTER_DEPTH);
+}
@@ -82,9 +82,9 @@ fn test_dbvalue_none_json() {
 #[test]
 fn test_dbvalue_nested_object_json() {
     let mut inner = HashMap::new();
-    inner.insert("x".to_string(), Box::new(DbValue::Number(5)));
+    inner.insert("x".into(), Box::new(DbValue::Number(5)));
     let mut outer = HashMap::new();
-    outer.insert("inner".to_string(), Box::new(DbValue::Object(inner)));
+    outer.insert("inner".into(), Box::new(DbValue::Object(inner)));
     let db_value = DbValue::Object(outer);
 
     let json_str = db_value.clone().into_json();
@@ -12,7 +12,7 @@ fn db_bool(b: bool) -> Box<DbValue> {
     Box::new(DbValue::Boolean(b))
 }
 
-fn db_obj(map: HashMap<String, Box<DbValue>>) -> Box<DbValue> {
+fn db_obj(map: HashMap<FieldName, Box<DbValue>>) -> Box<DbValue> {
     Box::new(DbValue::Object(map))
 }
 
@@ -98,11 +98,11 @@ fn test_merge_insert_new_key() {
 #[test]
 fn test_merge_nested_object_with_state() {
     let mut target_inner = HashMap::new();
-    target_inner.insert("x".to_string(), db_num(1));
+    target_inner.insert("x".into(), db_num(1));
     let mut target = HashMap::new();
     target.insert("obj".to_string(), db_obj(target_inner));
     let mut from_inner = HashMap::new();
-    from_inner.insert("y".to_string(), db_num(2));
+    from_inner.insert("y".into(), db_num(2));
     let mut from = HashMap::new();
     from.insert("obj".to_string(), db_obj(from_inner));
     let mut target_state = HashMap::new();
@@ -151,11 +151,11 @@ fn test_merge_empty_target_map() {
 #[test]
 fn test_merge_nested_object_state_greater() {
     let mut target_inner = HashMap::new();
-    target_inner.insert("x".to_string(), db_num(1));
+    target_inner.insert("x".into(), db_num(1));
     let mut target = HashMap::new();
     target.insert("obj".to_string(), db_obj(target_inner));
     let mut from_inner = HashMap::new();
-    from_inner.insert("x".to_string(), db_num(2));
+    from_inner.insert("x".into(), db_num(2));
     let mut from = HashMap::new();
     from.insert("obj".to_string(), db_obj(from_inner));
     let mut target_state = HashMap::new();
@@ -175,10 +175,10 @@ fn test_merge_nested_object_state_greater() {
 #[test]
 fn test_merge_simple_insert() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_str("foo"));
+    target.insert("a".into(), db_str("foo"));
 
     let mut from = HashMap::new();
-    from.insert("b".to_string(), db_str("bar"));
+    from.insert("b".into(), db_str("bar"));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -189,10 +189,10 @@ fn test_merge_simple_insert() {
 #[test]
 fn test_merge_overwrite_from_priority() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_str("foo"));
+    target.insert("a".into(), db_str("foo"));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_str("bar"));
+    from.insert("a".into(), db_str("bar"));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -202,10 +202,10 @@ fn test_merge_overwrite_from_priority() {
 #[test]
 fn test_merge_overwrite_target_priority() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_str("foo"));
+    target.insert("a".into(), db_str("foo"));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_str("bar"));
+    from.insert("a".into(), db_str("bar"));
 
     dumb_merge(&mut target, &from, DumbMergePriority::Target);
 
@@ -215,14 +215,14 @@ fn test_merge_overwrite_target_priority() {
 #[test]
 fn test_merge_nested_object() {
     let mut target_inner = HashMap::new();
-    target_inner.insert("x".to_string(), db_num(1));
+    target_inner.insert("x".into(), db_num(1));
     let mut target = HashMap::new();
-    target.insert("obj".to_string(), db_obj(target_inner));
+    target.insert("obj".into(), db_obj(target_inner));
 
     let mut from_inner = HashMap::new();
-    from_inner.insert("y".to_string(), db_num(2));
+    from_inner.insert("y".into(), db_num(2));
     let mut from = HashMap::new();
-    from.insert("obj".to_string(), db_obj(from_inner));
+    from.insert("obj".into(), db_obj(from_inner));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -237,14 +237,14 @@ fn test_merge_nested_object() {
 #[test]
 fn test_merge_nested_object_overwrite() {
     let mut target_inner = HashMap::new();
-    target_inner.insert("x".to_string(), db_num(1));
+    target_inner.insert("x".into(), db_num(1));
     let mut target = HashMap::new();
-    target.insert("obj".to_string(), db_obj(target_inner));
+    target.insert("obj".into(), db_obj(target_inner));
 
     let mut from_inner = HashMap::new();
-    from_inner.insert("x".to_string(), db_num(42));
+    from_inner.insert("x".into(), db_num(42));
     let mut from = HashMap::new();
-    from.insert("obj".to_string(), db_obj(from_inner));
+    from.insert("obj".into(), db_obj(from_inner));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -258,10 +258,10 @@ fn test_merge_nested_object_overwrite() {
 #[test]
 fn test_merge_array_value() {
     let mut target = HashMap::new();
-    target.insert("arr".to_string(), Box::new(DbValue::Array(vec![db_num(1)])));
+    target.insert("arr".into(), Box::new(DbValue::Array(vec![db_num(1)])));
 
     let mut from = HashMap::new();
-    from.insert("arr".to_string(), Box::new(DbValue::Array(vec![db_num(2)])));
+    from.insert("arr".into(), Box::new(DbValue::Array(vec![db_num(2)])));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -274,10 +274,10 @@ fn test_merge_array_value() {
 #[test]
 fn test_merge_boolean_value() {
     let mut target = HashMap::new();
-    target.insert("flag".to_string(), db_bool(false));
+    target.insert("flag".into(), db_bool(false));
 
     let mut from = HashMap::new();
-    from.insert("flag".to_string(), db_bool(true));
+    from.insert("flag".into(), db_bool(true));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -287,10 +287,10 @@ fn test_merge_boolean_value() {
 #[test]
 fn test_merge_content_priority_replaces_if_greater() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_num(1));
+    target.insert("a".into(), db_num(1));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_num(2));
+    from.insert("a".into(), db_num(2));
 
     dumb_merge(&mut target, &from, DumbMergePriority::Content);
 
@@ -300,10 +300,10 @@ fn test_merge_content_priority_replaces_if_greater() {
 #[test]
 fn test_merge_content_priority_does_not_replace_if_less() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_num(5));
+    target.insert("a".into(), db_num(5));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_num(2));
+    from.insert("a".into(), db_num(2));
 
     dumb_merge(&mut target, &from, DumbMergePriority::Content);
 
@@ -313,7 +313,7 @@ fn test_merge_content_priority_does_not_replace_if_less() {
 #[test]
 fn test_merge_with_empty_from() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_str("foo"));
+    target.insert("a".into(), db_str("foo"));
 
     let from = HashMap::new();
 
@@ -328,7 +328,7 @@ fn test_merge_with_empty_target() {
     let mut target = HashMap::new();
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_str("foo"));
+    from.insert("a".into(), db_str("foo"));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -339,14 +339,14 @@ fn test_merge_with_empty_target() {
 #[test]
 fn test_merge_nested_object_content_priority() {
     let mut target_inner = HashMap::new();
-    target_inner.insert("x".to_string(), db_num(1));
+    target_inner.insert("x".into(), db_num(1));
     let mut target = HashMap::new();
-    target.insert("obj".to_string(), db_obj(target_inner));
+    target.insert("obj".into(), db_obj(target_inner));
 
     let mut from_inner = HashMap::new();
-    from_inner.insert("x".to_string(), db_num(2));
+    from_inner.insert("x".into(), db_num(2));
     let mut from = HashMap::new();
-    from.insert("obj".to_string(), db_obj(from_inner));
+    from.insert("obj".into(), db_obj(from_inner));
 
     dumb_merge(&mut target, &from, DumbMergePriority::Content);
 
@@ -361,12 +361,12 @@ fn test_merge_nested_object_content_priority() {
 fn test_merge_array_with_different_lengths() {
     let mut target = HashMap::new();
     target.insert(
-        "arr".to_string(),
+        "arr".into(),
         Box::new(DbValue::Array(vec![db_num(1), db_num(2)])),
     );
 
     let mut from = HashMap::new();
-    from.insert("arr".to_string(), Box::new(DbValue::Array(vec![db_num(3)])));
+    from.insert("arr".into(), Box::new(DbValue::Array(vec![db_num(3)])));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -380,11 +380,11 @@ fn test_merge_array_with_different_lengths() {
 fn test_merge_object_and_non_object() {
     let mut target = HashMap::new();
     let mut obj = HashMap::new();
-    obj.insert("x".to_string(), db_num(1));
-    target.insert("a".to_string(), db_obj(obj));
+    obj.insert("x".into(), db_num(1));
+    target.insert("a".into(), db_obj(obj));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_num(42));
+    from.insert("a".into(), db_num(42));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -394,12 +394,12 @@ fn test_merge_object_and_non_object() {
 #[test]
 fn test_merge_non_object_and_object() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_num(42));
+    target.insert("a".into(), db_num(42));
 
     let mut from = HashMap::new();
     let mut obj = HashMap::new();
-    obj.insert("x".to_string(), db_num(1));
-    from.insert("a".to_string(), db_obj(obj));
+    obj.insert("x".into(), db_num(1));
+    from.insert("a".into(), db_obj(obj));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -412,10 +412,10 @@ fn test_merge_non_object_and_object() {
 #[test]
 fn test_merge_boolean_and_number() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_bool(true));
+    target.insert("a".into(), db_bool(true));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_num(1));
+    from.insert("a".into(), db_num(1));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
@@ -425,10 +425,10 @@ fn test_merge_boolean_and_number() {
 #[test]
 fn test_merge_number_and_boolean() {
     let mut target = HashMap::new();
-    target.insert("a".to_string(), db_num(1));
+    target.insert("a".into(), db_num(1));
 
     let mut from = HashMap::new();
-    from.insert("a".to_string(), db_bool(false));
+    from.insert("a".into(), db_bool(false));
 
     dumb_merge(&mut target, &from, DumbMergePriority::From);
 
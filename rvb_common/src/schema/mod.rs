@@ -7,6 +7,12 @@ use serde_json::{Map, Value};
 use std::str::FromStr;
 use std::{cmp::Ordering, collections::HashMap};
 
+mod field;
+pub mod filter;
+
+pub use field::FieldName;
+pub use filter::{Filter, FilterError};
+
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum DataAction {
     Insert {
@@ -14,6 +20,118 @@ pub enum DataAction {
         incoming_data: DbValue,
         params: HashMap<String, DbValue>,
     },
+    Delete {
+        key: String,
+    },
+    Patch {
+        key: String,
+        ops: Vec<PatchOp>,
+    },
+    Emit {
+        topic: String,
+        payload: DbValue,
+    },
+    /// Read of `key`. Only ever handed to contracts as the action being
+    /// checked; returning it changes nothing.
+    Get {
+        key: String,
+    },
+}
+
+impl DataAction {
+    /// Key the action touches, if any.
+    #[must_use]
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            DataAction::Insert { key, .. }
+            | DataAction::Delete { key }
+            | DataAction::Patch { key, .. }
+            | DataAction::Get { key } => Some(key),
+            DataAction::Emit { .. } => None,
+        }
+    }
+}
+
+/// A single in-place edit of a stored value. Paths address fields of nested
+/// objects; an empty path refers to the value itself.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum PatchOp {
+    /// Sets the field at `path`, creating intermediate objects as needed.
+    Set { path: Vec<String>, value: DbValue },
+    /// Removes the field at `path`. Missing fields are ignored.
+    Remove { path: Vec<String> },
+}
+
+impl DbValue {
+    /// Applies `ops` in order. Setting a path through a non-object value
+    /// replaces that value with an object.
+    pub fn apply_patch(&mut self, ops: &[PatchOp]) {
+        for op in ops {
+            match op {
+                PatchOp::Set { path, value } => self.set_path(path, value.clone()),
+                PatchOp::Remove { path } => self.remove_path(path),
+            }
+        }
+    }
+
+    /// Value at `path`, if every field along it exists.
+    #[must_use]
+    pub fn get_path(&self, path: &[String]) -> Option<&DbValue> {
+        match (path.split_first(), self) {
+            (None, value) => Some(value),
+            (Some((field, rest)), DbValue::Object(map)) => map.get(field.as_str())?.get_path(rest),
+            _ => None,
+        }
+    }
+
+    /// Copy holding only the fields at `paths`, nested as in the original.
+    /// No paths selects the whole value.
+    #[must_use]
+    pub fn select(&self, paths: &[Vec<String>]) -> DbValue {
+        if paths.is_empty() {
+            return self.clone();
+        }
+
+        let mut selected = DbValue::Object(HashMap::new());
+        for path in paths {
+            if let Some(value) = self.get_path(path) {
+                selected.set_path(path, value.clone());
+            }
+        }
+        selected
+    }
+
+    fn set_path(&mut self, path: &[String], value: DbValue) {
+        let Some((field, rest)) = path.split_first() else {
+            *self = value;
+            return;
+        };
+
+        if !matches!(self, DbValue::Object(_)) {
+            *self = DbValue::Object(HashMap::new());
+        }
+
+        if let DbValue::Object(map) = self {
+            map.entry(field.into())
+                .or_insert_with(|| Box::new(DbValue::None))
+                .set_path(rest, value);
+        }
+    }
+
+    fn remove_path(&mut self, path: &[String]) {
+        match (path, self) {
+            ([field], DbValue::Object(map)) => {
+                map.remove(field.as_str());
+            }
+            ([field, rest @ ..], DbValue::Object(map)) => {
+                if let Some(inner) = map.get_mut(field.as_str()) {
+                    inner.remove_path(rest);
+                }
+            }
+            ([], value) => *value = DbValue::None,
+            _ => {}
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -28,7 +146,7 @@ pub enum DbValue {
     String(String),
     Number(i128),
     Boolean(bool),
-    Object(HashMap<String, Box<DbValue>>),
+    Object(HashMap<FieldName, Box<DbValue>>),
     Array(Vec<Box<DbValue>>),
     None,
 }
@@ -59,11 +177,11 @@ impl DbValue {
 }
 
 #[cfg(feature = "json_schema")]
-fn hashmap_into_json_map(map: HashMap<String, Box<DbValue>>) -> Map<String, Value> {
+fn hashmap_into_json_map(map: HashMap<FieldName, Box<DbValue>>) -> Map<String, Value> {
     let mut serde_map = Map::new();
 
     for (k, v) in map {
-        serde_map.insert(k, (*v).into());
+        serde_map.insert(k.into(), (*v).into());
     }
 
     serde_map
@@ -92,7 +210,8 @@ impl From<Value> for DbValue {
                     .collect(),
             ),
             Value::Object(map) => DbValue::Object(HashMap::from_iter(
-                map.into_iter().map(|x| (x.0, Box::new(DbValue::from(x.1)))),
+                map.into_iter()
+                    .map(|x| (x.0.into(), Box::new(DbValue::from(x.1)))),
             )),
         }
     }
@@ -141,8 +260,8 @@ pub enum DumbMergePriority {
 }
 
 pub fn dumb_merge(
-    target: &mut HashMap<String, Box<DbValue>>,
-    from: &HashMap<String, Box<DbValue>>,
+    target: &mut HashMap<FieldName, Box<DbValue>>,
+    from: &HashMap<FieldName, Box<DbValue>>,
     priority: DumbMergePriority,
 ) {
     for (key, from_value) in from {
@@ -169,6 +288,32 @@ pub fn dumb_merge(
     }
 }
 
+/// Resolves one key present on both sides, given the state each side holds
+/// it at.
+fn merge_entry(target_value: &mut Box<DbValue>, from_value: &DbValue, t_state: u64, f_state: u64) {
+    match t_state.cmp(&f_state) {
+        Ordering::Equal => {
+            let should_replace = from_value > &**target_value;
+            match (&mut **target_value, from_value) {
+                (DbValue::Object(target_map), DbValue::Object(from_map)) => {
+                    dumb_merge(target_map, from_map, DumbMergePriority::Content);
+                }
+                (_, _) if should_replace => {
+                    **target_value = from_value.clone();
+                }
+                _ => {}
+            }
+        }
+        Ordering::Less => match (&mut **target_value, from_value) {
+            (DbValue::Object(target_map), DbValue::Object(from_map)) => {
+                dumb_merge(target_map, from_map, DumbMergePriority::From);
+            }
+            _ => **target_value = from_value.clone(),
+        },
+        _ => {}
+    }
+}
+
 pub fn merge(
     target: &mut HashMap<String, Box<DbValue>>,
     from: &HashMap<String, Box<DbValue>>,
@@ -182,34 +327,55 @@ pub fn merge(
         );
 
         if let Some(target_value) = target.get_mut(key) {
-            match t_state.cmp(&f_state) {
-                Ordering::Equal => {
-                    let should_replace = from_value > target_value;
-                    match (&mut **target_value, &**from_value) {
-                        (DbValue::Object(target_map), DbValue::Object(from_map)) => {
-                            dumb_merge(target_map, from_map, DumbMergePriority::Content);
-                        }
-                        (_, _) if should_replace => {
-                            *target_value = from_value.clone();
-                        }
-                        _ => {}
-                    }
-                }
-                Ordering::Less => match (&mut **target_value, &**from_value) {
-                    (DbValue::Object(target_map), DbValue::Object(from_map)) => {
-                        dumb_merge(target_map, from_map, DumbMergePriority::From);
-                    }
-                    _ => *target_value = from_value.clone(),
-                },
-                _ => {}
-            }
+            merge_entry(target_value, from_value, t_state, f_state);
         } else {
             target.insert(key.clone(), from_value.clone());
         }
     }
 }
 
+/// [`merge`] spread over rayon's thread pool, for maps with many keys such as
+/// whole namespaces during an initial sync. Keys are resolved independently,
+/// so the result is the same as with [`merge`].
+#[cfg(feature = "parallel")]
+pub fn par_merge(
+    target: &mut HashMap<String, Box<DbValue>>,
+    from: &HashMap<String, Box<DbValue>>,
+    target_state: &HashMap<String, u64>,
+    from_state: &HashMap<String, u64>,
+) {
+    use rayon::prelude::*;
+
+    let missing = from
+        .par_iter()
+        .filter(|(key, _)| !target.contains_key(*key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<Vec<_>>();
+
+    target.par_iter_mut().for_each(|(key, target_value)| {
+        if let Some(from_value) = from.get(key) {
+            merge_entry(
+                target_value,
+                from_value,
+                target_state.get(key).copied().unwrap_or(0),
+                from_state.get(key).copied().unwrap_or(0),
+            );
+        }
+    });
+    target.extend(missing);
+}
+
+#[cfg(test)]
+mod field_tests;
+#[cfg(test)]
+mod filter_tests;
 #[cfg(all(test, feature = "json_schema"))]
 mod json_schema_tests;
 #[cfg(test)]
 mod merge_tests;
+#[cfg(test)]
+mod patch_tests;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(all(test, feature = "testing"))]
+mod testing_tests;
@@ -0,0 +1,115 @@
+use super::*;
+
+fn path(fields: &[&str]) -> Vec<String> {
+    fields.iter().map(ToString::to_string).collect()
+}
+
+fn object(entries: &[(&str, DbValue)]) -> DbValue {
+    DbValue::Object(
+        entries
+            .iter()
+            .map(|(k, v)| ((*k).into(), Box::new(v.clone())))
+            .collect(),
+    )
+}
+
+#[test]
+fn test_patch_set_top_level() {
+    let mut value = object(&[("a", DbValue::Number(1))]);
+    value.apply_patch(&[PatchOp::Set {
+        path: path(&["b"]),
+        value: DbValue::Boolean(true),
+    }]);
+
+    assert_eq!(
+        value,
+        object(&[("a", DbValue::Number(1)), ("b", DbValue::Boolean(true))])
+    );
+}
+
+#[test]
+fn test_patch_set_creates_intermediate_objects() {
+    let mut value = DbValue::None;
+    value.apply_patch(&[PatchOp::Set {
+        path: path(&["a", "b"]),
+        value: DbValue::Number(3),
+    }]);
+
+    assert_eq!(
+        value,
+        object(&[("a", object(&[("b", DbValue::Number(3))]))])
+    );
+}
+
+#[test]
+fn test_patch_set_empty_path_replaces_value() {
+    let mut value = object(&[("a", DbValue::Number(1))]);
+    value.apply_patch(&[PatchOp::Set {
+        path: vec![],
+        value: DbValue::String("x".into()),
+    }]);
+
+    assert_eq!(value, DbValue::String("x".into()));
+}
+
+#[test]
+fn test_patch_remove_nested() {
+    let mut value = object(&[(
+        "a",
+        object(&[("b", DbValue::Number(1)), ("c", DbValue::Number(2))]),
+    )]);
+    value.apply_patch(&[PatchOp::Remove {
+        path: path(&["a", "b"]),
+    }]);
+
+    assert_eq!(
+        value,
+        object(&[("a", object(&[("c", DbValue::Number(2))]))])
+    );
+}
+
+#[test]
+fn test_patch_remove_missing_is_noop() {
+    let mut value = object(&[("a", DbValue::Number(1))]);
+    value.apply_patch(&[PatchOp::Remove {
+        path: path(&["x", "y"]),
+    }]);
+
+    assert_eq!(value, object(&[("a", DbValue::Number(1))]));
+}
+
+#[test]
+fn test_patch_ops_apply_in_order() {
+    let mut value = DbValue::None;
+    value.apply_patch(&[
+        PatchOp::Set {
+            path: path(&["a"]),
+            value: DbValue::Number(1),
+        },
+        PatchOp::Remove { path: path(&["a"]) },
+        PatchOp::Set {
+            path: path(&["b"]),
+            value: DbValue::Number(2),
+        },
+    ]);
+
+    assert_eq!(value, object(&[("b", DbValue::Number(2))]));
+}
+
+#[test]
+fn test_select_paths() {
+    let value = object(&[
+        ("a", DbValue::Number(1)),
+        (
+            "b",
+            object(&[("c", DbValue::Number(2)), ("d", DbValue::Number(3))]),
+        ),
+    ]);
+
+    assert_eq!(value.select(&[]), value);
+    assert_eq!(
+        value.select(&[path(&["b", "c"]), path(&["missing"])]),
+        object(&[("b", object(&[("c", DbValue::Number(2))]))])
+    );
+    assert_eq!(value.get_path(&path(&["a", "x"])), None);
+}
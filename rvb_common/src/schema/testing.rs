@@ -0,0 +1,125 @@
+use super::{DbValue, FieldName};
+use proptest::{
+    collection::{hash_map, vec},
+    prelude::*,
+    test_runner::TestCaseError,
+};
+use std::collections::HashMap;
+
+pub type ValueMap = HashMap<String, Box<DbValue>>;
+pub type StateMap = HashMap<String, u64>;
+
+/// A replica's data together with its per-key state counters.
+pub type Replica = (ValueMap, StateMap);
+
+/// Keys are drawn from a tiny alphabet so that independently generated maps
+/// collide often enough to exercise conflict resolution.
+pub fn arb_key() -> impl Strategy<Value = String> {
+    "[a-d]{1,2}"
+}
+
+pub fn arb_scalar() -> impl Strategy<Value = DbValue> {
+    prop_oneof![
+        "[a-z]{0,8}".prop_map(DbValue::String),
+        any::<i128>().prop_map(DbValue::Number),
+        any::<bool>().prop_map(DbValue::Boolean),
+        Just(DbValue::None),
+    ]
+}
+
+pub fn arb_db_value() -> impl Strategy<Value = DbValue> {
+    arb_scalar().prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone().prop_map(Box::new), 0..4).prop_map(DbValue::Array),
+            hash_map(
+                arb_key().prop_map(FieldName::from),
+                inner.prop_map(Box::new),
+                0..4
+            )
+            .prop_map(DbValue::Object),
+        ]
+    })
+}
+
+pub fn arb_value_map() -> impl Strategy<Value = ValueMap> {
+    hash_map(arb_key(), arb_db_value().prop_map(Box::new), 0..8)
+}
+
+/// State counters are kept small so equal states, the interesting case for
+/// content-based resolution, come up regularly.
+pub fn arb_state_map() -> impl Strategy<Value = StateMap> {
+    hash_map(arb_key(), 0u64..4, 0..8)
+}
+
+/// A value map with a state counter for each of its keys.
+pub fn arb_replica() -> impl Strategy<Value = Replica> {
+    arb_value_map().prop_flat_map(|values| {
+        let keys = values.keys().cloned().collect::<Vec<_>>();
+        let states = vec(0u64..4, keys.len())
+            .prop_map(move |states| keys.iter().cloned().zip(states).collect());
+        (Just(values), states)
+    })
+}
+
+/// State map a replica ends up with after merging `a` and `b`.
+#[must_use]
+pub fn merge_states(a: &StateMap, b: &StateMap) -> StateMap {
+    let mut result = a.clone();
+
+    for (key, state) in b {
+        let entry = result.entry(key.clone()).or_insert(*state);
+        *entry = (*entry).max(*state);
+    }
+
+    result
+}
+
+/// Merging a replica into itself must not change it.
+pub fn assert_idempotent<F>(merge: F, replica: &Replica) -> Result<(), TestCaseError>
+where
+    F: Fn(&mut ValueMap, &ValueMap, &StateMap, &StateMap),
+{
+    let (values, state) = replica;
+    let mut target = values.clone();
+    merge(&mut target, values, state, state);
+    prop_assert_eq!(&target, values);
+    Ok(())
+}
+
+/// Merging `b` into `a` must give the same data as merging `a` into `b`.
+pub fn assert_commutative<F>(merge: F, a: &Replica, b: &Replica) -> Result<(), TestCaseError>
+where
+    F: Fn(&mut ValueMap, &ValueMap, &StateMap, &StateMap),
+{
+    let mut ab = a.0.clone();
+    merge(&mut ab, &b.0, &a.1, &b.1);
+
+    let mut ba = b.0.clone();
+    merge(&mut ba, &a.0, &b.1, &a.1);
+
+    prop_assert_eq!(ab, ba);
+    Ok(())
+}
+
+/// `(a <- b) <- c` must give the same data as `a <- (b <- c)`.
+pub fn assert_associative<F>(
+    merge: F,
+    a: &Replica,
+    b: &Replica,
+    c: &Replica,
+) -> Result<(), TestCaseError>
+where
+    F: Fn(&mut ValueMap, &ValueMap, &StateMap, &StateMap),
+{
+    let mut left = a.0.clone();
+    merge(&mut left, &b.0, &a.1, &b.1);
+    merge(&mut left, &c.0, &merge_states(&a.1, &b.1), &c.1);
+
+    let mut bc = b.0.clone();
+    merge(&mut bc, &c.0, &b.1, &c.1);
+    let mut right = a.0.clone();
+    merge(&mut right, &bc, &a.1, &merge_states(&b.1, &c.1));
+
+    prop_assert_eq!(left, right);
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use super::testing::*;
+use super::*;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn test_merge_idempotent(replica in arb_replica()) {
+        assert_idempotent(merge, &replica)?;
+    }
+
+    #[test]
+    fn test_merge_commutative_with_shared_state(
+        a in arb_value_map(),
+        b in arb_value_map(),
+        state in arb_state_map(),
+    ) {
+        assert_commutative(merge, &(a, state.clone()), &(b, state))?;
+    }
+
+    #[test]
+    fn test_merge_associative_with_shared_state(
+        a in arb_value_map(),
+        b in arb_value_map(),
+        c in arb_value_map(),
+        state in arb_state_map(),
+    ) {
+        assert_associative(merge, &(a, state.clone()), &(b, state.clone()), &(c, state))?;
+    }
+
+    #[test]
+    fn test_merge_commutative_scalars(a in arb_replica(), b in arb_replica()) {
+        let scalars_only = |(values, state): Replica| -> Replica {
+            let values = values
+                .into_iter()
+                .filter(|(_, v)| !matches!(**v, DbValue::Object(_)))
+                .collect::<ValueMap>();
+            let state = state.into_iter().filter(|(k, _)| values.contains_key(k)).collect();
+            (values, state)
+        };
+        assert_commutative(merge, &scalars_only(a), &scalars_only(b))?;
+    }
+}
+
+#[cfg(feature = "parallel")]
+proptest! {
+    #[test]
+    fn test_par_merge_matches_merge(a in arb_replica(), b in arb_replica()) {
+        let mut sequential = a.0.clone();
+        merge(&mut sequential, &b.0, &a.1, &b.1);
+        let mut parallel = a.0.clone();
+        par_merge(&mut parallel, &b.0, &a.1, &b.1);
+        prop_assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn test_par_merge_idempotent(replica in arb_replica()) {
+        assert_idempotent(par_merge, &replica)?;
+    }
+}
@@ -1,4 +1,6 @@
 use async_trait::async_trait;
+use bytes::Bytes;
+use std::net::SocketAddr;
 
 #[derive(Debug)]
 pub enum TransportError {
@@ -7,11 +9,29 @@ pub enum TransportError {
     ConnectionClosed,
 }
 
+/// What a transport knows of a connection besides the messages on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerMetadata {
+    /// Address the other end is seen at, for transports that have addresses.
+    /// For a connection accepted by a server, this is where it came from
+    /// rather than where the other end accepts connections.
+    pub remote_addr: Option<SocketAddr>,
+    /// Whether the other end opened the connection.
+    pub inbound: bool,
+}
+
+/// A connection carrying whole messages. Messages are [`Bytes`], so one
+/// buffer sent to many peers is shared rather than copied for each of them.
 #[async_trait]
 pub trait TransportPeer: Send + Sync {
     async fn bye(self) -> Result<(), TransportError>;
-    async fn send(&self, msg: Vec<u8>) -> Result<(), TransportError>;
-    async fn recv(&self) -> Result<Vec<u8>, TransportError>;
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError>;
+    async fn recv(&self) -> Result<Bytes, TransportError>;
+
+    /// Nothing is known by default.
+    fn metadata(&self) -> PeerMetadata {
+        PeerMetadata::default()
+    }
 }
 
 #[async_trait]
@@ -22,4 +42,4 @@ pub trait Server: Send + Sync {
 #[async_trait]
 pub trait Client: Send + Sync {
     async fn connect(&self, addr: &str) -> Result<Box<dyn TransportPeer>, TransportError>;
-}
\ No newline at end of file
+}
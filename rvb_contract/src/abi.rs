@@ -0,0 +1,385 @@
+//! Runtime-independent parts of the contract ABI. A contract imports its host
+//! functions from [`HOST_MODULE`] and exports [`MEMORY_EXPORT`] along with
+//! [`ENTRY_POINT`], which returns the length of its encoded response in the low
+//! 32 bits and a pointer to it in the high 32 bits.
+//!
+//! Instead of matching on the action itself, a contract may export typed
+//! handlers with the same signature: [`ON_INSERT_EXPORT`], [`ON_GET_EXPORT`]
+//! and [`ON_DELETE_EXPORT`] take precedence over the entry point for their
+//! actions, and [`INIT_EXPORT`] runs once per contract space before the first
+//! action there. [`QUERY_EXPORT`] answers reads with an encoded value rather
+//! than actions. It may also export
+//! [`VERSION_EXPORT`] reporting the ABI version it was built against, and
+//! [`METADATA_EXPORT`], returning its encoded metadata the same way. After an
+//! upgrade, [`MIGRATE_EXPORT`] is called like the entry point for every stored
+//! value. Contracts exporting [`FREE_EXPORT`] get each response handed back
+//! to it after the host has read it.
+//!
+//! Contracts read their context with `read_context(codec, ptr, capacity)`,
+//! which writes it only if it fits in `capacity` bytes; see [`read_context`].
+//! Older contracts ask for `get_context_length` and then `write_context`,
+//! trusting the host to write no more than the length it reported. The
+//! wasmtime runtime also accepts components of the WIT world in
+//! `wit/contract.wit`, which wrap the entry point in the component model.
+//!
+//! Modules are checked against this when they are compiled: they may import
+//! only [`HOST_FUNCTIONS`], and runtimes that support threads or SIMD compile
+//! them with those proposals disabled. Bulk memory stays enabled, since current
+//! Rust toolchains emit it by default.
+//!
+//! Every replica must reach the same result for the same call, so runtimes
+//! follow one determinism profile: no threads, no SIMD, no clock or randomness
+//! besides the host's seed, and NaNs canonicalized wherever a float
+//! instruction could produce one, since NaN bit patterns otherwise depend on
+//! the host CPU. Runtimes that cannot canonicalize NaNs offer to reject float
+//! instructions instead.
+
+use log::debug;
+use rvb_common::{
+    contract::{
+        ABI_VERSION, CONTEXT_CODEC, CONTEXT_TOO_LARGE, CONTEXT_UNSUPPORTED, CONTEXT_WRITE_FAILED,
+        CONTEXT_WRITTEN, ContractContext, ContractError, ContractHost, ContractMetadata,
+        MIN_ABI_VERSION, NullHost,
+    },
+    crypto::PublicKey,
+    schema::{DataAction, DbValue},
+};
+use std::sync::Arc;
+
+pub const HOST_MODULE: &str = "rvb_host";
+pub const ENTRY_POINT: &str = "rvb_contract";
+pub const MEMORY_EXPORT: &str = "memory";
+pub const VERSION_EXPORT: &str = "rvb_abi_version";
+pub const METADATA_EXPORT: &str = "rvb_metadata";
+pub const MIGRATE_EXPORT: &str = "rvb_migrate";
+pub const INIT_EXPORT: &str = "rvb_init";
+pub const QUERY_EXPORT: &str = "rvb_query";
+pub const ON_INSERT_EXPORT: &str = "rvb_on_insert";
+pub const ON_GET_EXPORT: &str = "rvb_on_get";
+pub const ON_DELETE_EXPORT: &str = "rvb_on_delete";
+/// Optional `(len: i32) -> i32` allocator, for hosts writing into the guest.
+pub const ALLOC_EXPORT: &str = "rvb_alloc";
+/// Optional `(ptr: i32, len: i32)` export releasing a response once the host
+/// has copied it out, so instances that outlive a call do not leak it.
+pub const FREE_EXPORT: &str = "rvb_free";
+
+/// Exports that handle actions. A module must export at least one of them.
+pub const HANDLERS: &[&str] = &[
+    ENTRY_POINT,
+    ON_INSERT_EXPORT,
+    ON_GET_EXPORT,
+    ON_DELETE_EXPORT,
+    INIT_EXPORT,
+    QUERY_EXPORT,
+];
+
+/// Functions contracts may import from [`HOST_MODULE`].
+pub const HOST_FUNCTIONS: &[&str] = &[
+    "get_context_length",
+    "write_context",
+    "read_context",
+    "get",
+    "write_value",
+    "random_seed",
+    "blake3",
+    "sha256",
+    "verify",
+    "state_get",
+    "state_set",
+    "log",
+    "panic",
+];
+
+/// Pages the guest memory is grown by before each call.
+pub const EXTRA_PAGES: u32 = 1024;
+
+pub const ALLOC_ERROR_CODE: u8 = 1;
+
+/// Rejects modules importing anything other than [`HOST_FUNCTIONS`].
+pub fn check_imports<'a>(
+    imports: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(), ContractError> {
+    for (module, name) in imports {
+        if module != HOST_MODULE || !HOST_FUNCTIONS.contains(&name) {
+            return Err(ContractError::InvalidModule(format!(
+                "imports {module}::{name}, which is not a host function"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects modules without a memory or any of the [`HANDLERS`]. `handler`
+/// tells whether an export is a function of type `() -> i64`, or returns
+/// `None` if the module does not export it.
+pub fn check_exports(
+    handler: impl Fn(&str) -> Option<bool>,
+    memory: bool,
+) -> Result<(), ContractError> {
+    let mut found = false;
+    for name in HANDLERS {
+        match handler(name) {
+            Some(true) => found = true,
+            Some(false) => {
+                return Err(ContractError::InvalidModule(format!(
+                    "{name} must be a function taking nothing and returning i64"
+                )));
+            }
+            None => {}
+        }
+    }
+
+    if !found {
+        return Err(ContractError::InvalidModule(format!(
+            "exports neither {ENTRY_POINT} nor a typed handler"
+        )));
+    }
+    if !memory {
+        return Err(ContractError::InvalidModule(format!(
+            "does not export its memory as {MEMORY_EXPORT}"
+        )));
+    }
+    Ok(())
+}
+
+/// Picks the export handling `action`: its typed handler if the contract has
+/// one, the generic entry point otherwise.
+pub fn select_handler(
+    action: &DataAction,
+    mut exported: impl FnMut(&str) -> bool,
+) -> Result<&'static str, ContractError> {
+    let typed = match action {
+        DataAction::Insert { .. } | DataAction::Patch { .. } => Some(ON_INSERT_EXPORT),
+        DataAction::Get { .. } => Some(ON_GET_EXPORT),
+        DataAction::Delete { .. } => Some(ON_DELETE_EXPORT),
+        DataAction::Emit { .. } => None,
+    };
+
+    typed
+        .into_iter()
+        .chain([ENTRY_POINT])
+        .find(|name| exported(name))
+        .ok_or(ContractError::ContractNotImplemented)
+}
+
+/// Encodes `ctx` the way a contract built against ABI `version` decodes it.
+/// Version 1 contracts reject contexts with more fields than they know of, so
+/// they get the context without the message provenance.
+pub fn encode_context(ctx: &ContractContext, version: u32) -> Result<Vec<u8>, ContractError> {
+    let encoded = if version < 2 {
+        rmp_serde::to_vec(&(
+            &ctx.action,
+            &ctx.namespace,
+            &ctx.contract_space,
+            &ctx.signed_by,
+            &ctx.contract_params,
+        ))
+    } else {
+        rmp_serde::to_vec(ctx)
+    };
+    encoded.map_err(|x| ContractError::RuntimeError(Box::new(x)))
+}
+
+/// Answers `read_context(codec, ptr, capacity)` with the call's `context`,
+/// written with `write` only if the guest decodes [`CONTEXT_CODEC`] and set
+/// aside room for all of it. Returns the status in the high 32 bits and the
+/// bytes written, or needed, in the low ones.
+pub fn read_context(
+    context: &[u8],
+    codec: u64,
+    capacity: u64,
+    write: impl FnOnce(&[u8]) -> bool,
+) -> u64 {
+    let len = context.len() as u64;
+    let status = if codec != u64::from(CONTEXT_CODEC) {
+        return u64::from(CONTEXT_UNSUPPORTED) << 32;
+    } else if len > capacity {
+        CONTEXT_TOO_LARGE
+    } else if write(context) {
+        CONTEXT_WRITTEN
+    } else {
+        CONTEXT_WRITE_FAILED
+    };
+    (u64::from(status) << 32) | len
+}
+
+/// Per-call data the host functions operate on.
+pub struct CallState {
+    /// The call's context, encoded for the contract's ABI version.
+    pub context: Vec<u8>,
+    /// The context before encoding, kept for [`Self::set_abi_version`].
+    call_context: Option<ContractContext>,
+    pub namespace: String,
+    pub contract_space: String,
+    pub host: Arc<dyn ContractHost>,
+    /// Value looked up by the last `get`, waiting to be copied out by `write_value`.
+    pub pending_value: Vec<u8>,
+    /// Fuel the call started with, for runtimes that meter execution.
+    pub fuel_budget: u64,
+    /// Message the contract reported through `panic` before trapping.
+    pub panic_message: Option<String>,
+    /// WASI state of component calls.
+    #[cfg(feature = "wasi")]
+    pub wasi: Option<crate::wasmtime::wasi::WasiState>,
+}
+
+impl CallState {
+    pub fn new(ctx: ContractContext, host: Arc<dyn ContractHost>) -> Result<Self, ContractError> {
+        Ok(Self {
+            context: encode_context(&ctx, ABI_VERSION)?,
+            namespace: ctx.namespace.clone(),
+            contract_space: ctx.contract_space.clone(),
+            call_context: Some(ctx),
+            host,
+            pending_value: Vec::new(),
+            panic_message: None,
+            fuel_budget: 0,
+            #[cfg(feature = "wasi")]
+            wasi: None,
+        })
+    }
+
+    /// State for calls made outside of any action, such as reading metadata.
+    pub fn detached() -> Self {
+        Self {
+            context: Vec::new(),
+            call_context: None,
+            namespace: String::new(),
+            contract_space: String::new(),
+            host: Arc::new(NullHost),
+            pending_value: Vec::new(),
+            panic_message: None,
+            fuel_budget: 0,
+            #[cfg(feature = "wasi")]
+            wasi: None,
+        }
+    }
+
+    /// Encodes the context again for a contract reporting ABI `version`, as
+    /// returned by [`check_version`].
+    pub fn set_abi_version(&mut self, version: u32) -> Result<(), ContractError> {
+        if let Some(ctx) = &self.call_context {
+            self.context = encode_context(ctx, version)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the message of a panicking contract, so the trap that follows is
+    /// reported as [`ContractError::Panicked`] instead of an opaque failure.
+    pub fn record_panic(&mut self, message: &[u8]) {
+        let message = String::from_utf8_lossy(message).into_owned();
+        debug!("Contract panicked: {message}");
+        self.panic_message = Some(message);
+    }
+
+    /// Reports the fuel the call used to the host, given how much of its
+    /// budget is left.
+    pub fn report_fuel(&self, remaining: u64) {
+        self.host
+            .record_fuel(self.fuel_budget.saturating_sub(remaining));
+    }
+
+    /// Looks `key` up in the contract's own space and keeps the encoded value
+    /// for `write_value`. Returns its length, 0 if the key is missing.
+    pub fn lookup(&mut self, key: &str) -> Result<u64, rmp_serde::encode::Error> {
+        let value = self.host.get(&self.namespace, &self.contract_space, key);
+        self.keep_value(value)
+    }
+
+    /// Like [`Self::lookup`], but in the contract's private state.
+    pub fn state_lookup(&mut self, key: &str) -> Result<u64, rmp_serde::encode::Error> {
+        let value = self.host.state_get(key);
+        self.keep_value(value)
+    }
+
+    fn keep_value(&mut self, value: Option<DbValue>) -> Result<u64, rmp_serde::encode::Error> {
+        self.pending_value = match value {
+            Some(value) => rmp_serde::to_vec(&value)?,
+            None => Vec::new(),
+        };
+
+        Ok(self.pending_value.len() as u64)
+    }
+
+    /// Decodes `value` and stores it under `key` in the contract's private
+    /// state.
+    pub fn store_state(&self, key: &str, value: &[u8]) -> Result<(), rmp_serde::decode::Error> {
+        self.host.state_set(key, rmp_serde::from_slice(value)?);
+        Ok(())
+    }
+
+    pub fn take_value(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending_value)
+    }
+}
+
+pub use rvb_common::crypto::hash::{blake3, sha256};
+
+/// Error the `panic` host function traps with.
+pub const PANIC_TRAP: &str = "contract panicked";
+
+/// Forwards a message the contract logged to the node's log.
+pub fn log(message: &[u8]) {
+    debug!("Contract: {}", String::from_utf8_lossy(message));
+}
+
+pub fn verify(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    PublicKey::import(key)
+        .map(|key| key.verify(data, signature))
+        .unwrap_or(false)
+}
+
+/// Checks the version a contract reported, `None` meaning it exports no version
+/// and predates versioning. Returns the version the contract speaks.
+pub fn check_version(version: Option<u32>) -> Result<u32, ContractError> {
+    let version = version.unwrap_or(1);
+
+    if (MIN_ABI_VERSION..=ABI_VERSION).contains(&version) {
+        Ok(version)
+    } else {
+        Err(ContractError::AbiMismatch(version))
+    }
+}
+
+/// The `len` bytes at `ptr` in a guest's `memory`, or `None` if they run past
+/// its end. Guests choose both numbers, so they are checked before the host
+/// copies anything or allocates a buffer that large.
+#[must_use]
+pub fn guest_slice(memory: &[u8], ptr: u64, len: u64) -> Option<&[u8]> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    memory.get(start..end)
+}
+
+/// Splits the entry point's return value into the response's `(ptr, len)`. A
+/// zero length means the contract failed with the code in the high bits.
+pub fn split_result(res: u64) -> Result<(usize, usize), ContractError> {
+    let (len, ptr) = (res as u32, (res >> 32) as u32);
+
+    if len == 0 {
+        return Err(ContractError::ContractFailed(ptr as usize));
+    }
+
+    Ok((ptr as usize, len as usize))
+}
+
+pub fn decode_actions(buffer: &[u8]) -> Result<Vec<DataAction>, ContractError> {
+    rmp_serde::from_slice(buffer).map_err(|e| {
+        debug!("Error deserializing contract response: {e:?}");
+        ContractError::InvalidResponse
+    })
+}
+
+pub fn decode_value(buffer: &[u8]) -> Result<DbValue, ContractError> {
+    rmp_serde::from_slice(buffer).map_err(|e| {
+        debug!("Error deserializing contract query result: {e:?}");
+        ContractError::InvalidResponse
+    })
+}
+
+pub fn decode_metadata(buffer: &[u8]) -> Result<ContractMetadata, ContractError> {
+    rmp_serde::from_slice(buffer).map_err(|e| {
+        debug!("Error deserializing contract metadata: {e:?}");
+        ContractError::InvalidResponse
+    })
+}
@@ -1,7 +1,8 @@
 use rvb_common::{
-    contract::{Contract, ContractCompiler, ContractContext, ContractError},
+    contract::{Contract, ContractCompiler, ContractContext, ContractError, ContractHost},
     schema::DataAction,
 };
+use std::sync::Arc;
 
 pub struct AcceptContractCompiler;
 
@@ -14,7 +15,11 @@ impl ContractCompiler for AcceptContractCompiler {
 pub struct AcceptContract;
 
 impl Contract for AcceptContract {
-    fn execute(&mut self, ctx: ContractContext) -> Result<Vec<DataAction>, ContractError> {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        _host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
         Ok(vec![ctx.action])
     }
 }
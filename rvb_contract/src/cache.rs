@@ -0,0 +1,64 @@
+use log::debug;
+use rvb_common::contract::{ArtifactCache, ContractError};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Compiled artifacts kept as one file per key in a directory, so node
+/// processes sharing a host, and restarts of one, skip recompiling the same
+/// bytecode. Runtimes put the module hash and their engine's compatibility
+/// hash, which covers the CPU features code is generated for, in the key, so
+/// one directory can serve differently configured engines.
+///
+/// Files are written under a temporary name and renamed into place, so a
+/// process never sees another's partial write.
+pub struct DirArtifactCache {
+    dir: PathBuf,
+    writes: AtomicU64,
+}
+
+impl DirArtifactCache {
+    /// Uses `dir` for artifacts, creating it if it does not exist.
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, ContractError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|x| ContractError::RuntimeError(Box::new(x)))?;
+
+        Ok(Self {
+            dir,
+            writes: AtomicU64::new(0),
+        })
+    }
+
+    fn path(&self, key: &[u8]) -> PathBuf {
+        let mut name = String::with_capacity(key.len() * 2);
+        for byte in key {
+            let _ = write!(name, "{byte:02x}");
+        }
+        self.dir.join(name)
+    }
+}
+
+impl ArtifactCache for DirArtifactCache {
+    fn load(&self, key: &[u8]) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    fn store(&self, key: &[u8], artifact: &[u8]) {
+        let path = self.path(key);
+        let temp = path.with_extension(format!(
+            "{}.{}.tmp",
+            process::id(),
+            self.writes.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let res = fs::write(&temp, artifact).and_then(|()| fs::rename(&temp, &path));
+        if let Err(e) = res {
+            debug!("Failed to store compiled contract: {e:?}");
+            let _ = fs::remove_file(&temp);
+        }
+    }
+}
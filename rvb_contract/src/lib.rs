@@ -1,9 +1,24 @@
 use crate::accept::AcceptContractCompiler;
-use crate::wasmtime::WasmtimeContractCompiler;
+#[cfg(feature = "rhai")]
+use crate::rhai::{RhaiConfig, RhaiContractCompiler};
+#[cfg(feature = "wasmer")]
+use crate::wasmer::{WasmerConfig, WasmerContractCompiler};
+#[cfg(feature = "wasmi")]
+use crate::wasmi::{WasmiConfig, WasmiContractCompiler};
 #[cfg(feature = "runtime")]
-use rvb_common::contract::ContractCompiler;
+use crate::wasmtime::{WasmtimeConfig, WasmtimeContractCompiler};
+use rvb_common::contract::{ContractCompiler, ContractError};
 
+#[cfg(feature = "wasm")]
+mod abi;
 pub mod accept;
+pub mod cache;
+#[cfg(feature = "rhai")]
+pub mod rhai;
+#[cfg(feature = "wasmer")]
+pub mod wasmer;
+#[cfg(feature = "wasmi")]
+pub mod wasmi;
 #[cfg(feature = "runtime")]
 pub mod wasmtime;
 
@@ -11,14 +26,47 @@ pub mod wasmtime;
 pub enum ContractCompilerType {
     #[cfg(feature = "runtime")]
     Wasmtime,
+    #[cfg(feature = "wasmer")]
+    Wasmer,
+    #[cfg(feature = "wasmi")]
+    Wasmi,
+    #[cfg(feature = "rhai")]
+    Rhai,
+    /// Wasmtime, interpreting contracts if the platform forbids JIT
+    /// compilation.
+    #[cfg(feature = "runtime")]
+    Auto,
     Accept,
 }
 
-#[must_use]
-pub fn resolve_contract_runtime(feature: ContractCompilerType) -> Box<dyn ContractCompiler> {
+pub fn resolve_contract_runtime(
+    feature: ContractCompilerType,
+) -> Result<Box<dyn ContractCompiler>, ContractError> {
     match feature {
-        ContractCompilerType::Accept => Box::new(AcceptContractCompiler),
+        ContractCompilerType::Accept => Ok(Box::new(AcceptContractCompiler)),
+        #[cfg(feature = "runtime")]
+        ContractCompilerType::Wasmtime => Ok(Box::new(WasmtimeContractCompiler::new(
+            WasmtimeConfig::default(),
+        )?)),
         #[cfg(feature = "runtime")]
-        ContractCompilerType::Wasmtime => Box::new(WasmtimeContractCompiler),
+        ContractCompilerType::Auto => {
+            let config = WasmtimeConfig {
+                interpreted: !crate::wasmtime::jit_available(),
+                ..Default::default()
+            };
+            Ok(Box::new(WasmtimeContractCompiler::new(config)?))
+        }
+        #[cfg(feature = "wasmer")]
+        ContractCompilerType::Wasmer => Ok(Box::new(WasmerContractCompiler::new(
+            WasmerConfig::default(),
+        ))),
+        #[cfg(feature = "wasmi")]
+        ContractCompilerType::Wasmi => Ok(Box::new(WasmiContractCompiler::new(
+            WasmiConfig::default(),
+        )?)),
+        #[cfg(feature = "rhai")]
+        ContractCompilerType::Rhai => {
+            Ok(Box::new(RhaiContractCompiler::new(RhaiConfig::default())))
+        }
     }
 }
@@ -0,0 +1,579 @@
+use log::debug;
+use rhai::{AST, Array, Blob, Dynamic, Engine, EvalAltResult, Map, Scope};
+use rvb_common::{
+    contract::{
+        Contract, ContractCompiler, ContractContext, ContractError, ContractHost, ContractMetadata,
+    },
+    crypto::{PublicKey, hash},
+    schema::{DataAction, DbValue, PatchOp},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Name of the script function called for actions without a typed handler.
+pub const ENTRY_POINT: &str = "execute";
+/// Optional typed handlers, called instead of [`ENTRY_POINT`] for their actions.
+pub const ON_INSERT_FN: &str = "on_insert";
+pub const ON_GET_FN: &str = "on_get";
+pub const ON_DELETE_FN: &str = "on_delete";
+/// Optional script function answering reads with a value.
+pub const QUERY_FN: &str = "query";
+/// Optional script function called once per contract space, before the first
+/// action there.
+pub const INIT_FN: &str = "init";
+/// Optional script function returning the contract's metadata as a map.
+pub const METADATA_FN: &str = "metadata";
+/// Optional script function called like [`ENTRY_POINT`] for every stored value
+/// after an upgrade.
+pub const MIGRATE_FN: &str = "migrate";
+
+#[derive(Debug, Clone, Copy)]
+pub struct RhaiConfig {
+    /// Operations a single call may perform before it is stopped.
+    pub max_operations: u64,
+    pub max_expr_depth: usize,
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+}
+
+impl Default for RhaiConfig {
+    fn default() -> Self {
+        Self {
+            max_operations: 1_000_000,
+            max_expr_depth: 64,
+            max_call_levels: 32,
+            max_string_size: 1024 * 1024,
+            max_array_size: 10_000,
+            max_map_size: 10_000,
+        }
+    }
+}
+
+/// Runs contracts written as Rhai scripts and deployed as source text. A script
+/// defines `fn execute(ctx)` returning an array of actions, or throws an
+/// integer to reject the action with that error code. Typed handlers such as
+/// `fn on_insert(ctx)` take precedence over `execute` for their actions.
+///
+/// The engine is built without time or floating point support, so scripts
+/// stay deterministic across nodes.
+pub struct RhaiContractCompiler {
+    config: RhaiConfig,
+}
+
+impl RhaiContractCompiler {
+    #[must_use]
+    pub fn new(config: RhaiConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl ContractCompiler for RhaiContractCompiler {
+    fn create_contract(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
+        let source = std::str::from_utf8(bytecode)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+
+        let slot = HostSlot::default();
+        let operations = Arc::new(AtomicU64::new(0));
+        let engine = build_engine(&self.config, slot.clone(), operations.clone());
+        let ast = engine
+            .compile(source)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+
+        Ok(Box::new(RhaiContract {
+            engine,
+            ast,
+            slot,
+            operations,
+        }))
+    }
+}
+
+/// Host of the call currently executing. Each contract owns its engine, so
+/// the functions registered on it only ever see that contract's calls.
+type HostSlot = Arc<Mutex<Option<CallHost>>>;
+
+struct CallHost {
+    host: Arc<dyn ContractHost>,
+    namespace: String,
+    contract_space: String,
+}
+
+fn with_host<T>(slot: &HostSlot, f: impl FnOnce(&CallHost) -> T) -> Result<T, Box<EvalAltResult>> {
+    let guard = slot.lock().map_err(|e| e.to_string())?;
+    let host = guard.as_ref().ok_or("no contract call in progress")?;
+    Ok(f(host))
+}
+
+fn build_engine(config: &RhaiConfig, slot: HostSlot, operations: Arc<AtomicU64>) -> Engine {
+    let mut engine = Engine::new();
+
+    engine
+        .set_max_operations(config.max_operations)
+        .set_max_expr_depths(config.max_expr_depth, config.max_expr_depth)
+        .set_max_call_levels(config.max_call_levels)
+        .set_max_string_size(config.max_string_size)
+        .set_max_array_size(config.max_array_size)
+        .set_max_map_size(config.max_map_size);
+
+    engine.on_print(|text| debug!("Contract: {text}"));
+    engine.on_debug(|text, _, pos| debug!("Contract ({pos}): {text}"));
+    // Operations stand in for fuel, as they are what the limit above counts
+    engine.on_progress(move |count| {
+        operations.store(count, Ordering::Relaxed);
+        None
+    });
+
+    register_actions(&mut engine);
+
+    let get_slot = slot.clone();
+    engine.register_fn(
+        "get",
+        move |key: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let value = with_host(&get_slot, |call| {
+                call.host.get(&call.namespace, &call.contract_space, key)
+            })?;
+
+            match value {
+                Some(value) => Ok(to_dynamic(&value)?),
+                None => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+
+    let state_slot = slot.clone();
+    engine.register_fn(
+        "state_get",
+        move |key: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            match with_host(&state_slot, |call| call.host.state_get(key))? {
+                Some(value) => Ok(to_dynamic(&value)?),
+                None => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+
+    let state_slot = slot.clone();
+    engine.register_fn(
+        "state_set",
+        move |key: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let value = from_dynamic(value)?;
+            with_host(&state_slot, |call| call.host.state_set(key, value))
+        },
+    );
+
+    engine.register_fn(
+        "random_seed",
+        move || -> Result<Blob, Box<EvalAltResult>> {
+            with_host(&slot, |call| call.host.random_seed().to_vec())
+        },
+    );
+
+    engine.register_fn("blake3", |data: Blob| -> Blob {
+        hash::blake3(&data).to_vec()
+    });
+    engine.register_fn("sha256", |data: Blob| -> Blob {
+        hash::sha256(&data).to_vec()
+    });
+    engine.register_fn("verify", |key: Blob, data: Blob, signature: Blob| -> bool {
+        PublicKey::import(&key)
+            .map(|key| key.verify(&data, &signature))
+            .unwrap_or(false)
+    });
+
+    engine
+}
+
+/// Actions are an opaque `Action` type for scripts: built with `insert`,
+/// `delete`, `patch` and `emit`, and read through their properties.
+fn register_actions(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<DataAction>("Action")
+        .register_type_with_name::<PatchOp>("PatchOp");
+
+    engine.register_fn(
+        "insert",
+        |key: &str, value: Dynamic| -> Result<DataAction, Box<EvalAltResult>> {
+            Ok(DataAction::Insert {
+                key: key.into(),
+                incoming_data: from_dynamic(value)?,
+                params: HashMap::new(),
+            })
+        },
+    );
+    engine.register_fn("delete", |key: &str| DataAction::Delete { key: key.into() });
+    engine.register_fn(
+        "patch",
+        |key: &str, ops: Array| -> Result<DataAction, Box<EvalAltResult>> {
+            let ops = ops
+                .into_iter()
+                .map(|op| {
+                    op.try_cast::<PatchOp>()
+                        .ok_or("patch expects PatchOp values")
+                })
+                .collect::<Result<_, _>>()?;
+
+            Ok(DataAction::Patch {
+                key: key.into(),
+                ops,
+            })
+        },
+    );
+    engine.register_fn(
+        "emit",
+        |topic: &str, payload: Dynamic| -> Result<DataAction, Box<EvalAltResult>> {
+            Ok(DataAction::Emit {
+                topic: topic.into(),
+                payload: from_dynamic(payload)?,
+            })
+        },
+    );
+
+    engine.register_fn(
+        "set_op",
+        |path: Array, value: Dynamic| -> Result<PatchOp, Box<EvalAltResult>> {
+            Ok(PatchOp::Set {
+                path: to_path(path)?,
+                value: from_dynamic(value)?,
+            })
+        },
+    );
+    engine.register_fn(
+        "remove_op",
+        |path: Array| -> Result<PatchOp, Box<EvalAltResult>> {
+            Ok(PatchOp::Remove {
+                path: to_path(path)?,
+            })
+        },
+    );
+
+    engine.register_get("kind", |action: &mut DataAction| -> String {
+        match action {
+            DataAction::Insert { .. } => "insert",
+            DataAction::Delete { .. } => "delete",
+            DataAction::Patch { .. } => "patch",
+            DataAction::Emit { .. } => "emit",
+            DataAction::Get { .. } => "get",
+        }
+        .into()
+    });
+    engine.register_get("key", |action: &mut DataAction| -> Dynamic {
+        action
+            .key()
+            .map_or(Dynamic::UNIT, |key| key.to_string().into())
+    });
+    engine.register_get(
+        "value",
+        |action: &mut DataAction| -> Result<Dynamic, Box<EvalAltResult>> {
+            match action {
+                DataAction::Insert { incoming_data, .. } => Ok(to_dynamic(incoming_data)?),
+                _ => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+    engine.register_get(
+        "params",
+        |action: &mut DataAction| -> Result<Dynamic, Box<EvalAltResult>> {
+            match action {
+                DataAction::Insert { params, .. } => Ok(to_dynamic_map(params)?),
+                _ => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+    engine.register_get("topic", |action: &mut DataAction| -> Dynamic {
+        match action {
+            DataAction::Emit { topic, .. } => topic.clone().into(),
+            _ => Dynamic::UNIT,
+        }
+    });
+    engine.register_get(
+        "payload",
+        |action: &mut DataAction| -> Result<Dynamic, Box<EvalAltResult>> {
+            match action {
+                DataAction::Emit { payload, .. } => Ok(to_dynamic(payload)?),
+                _ => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+}
+
+fn to_path(path: Array) -> Result<Vec<String>, String> {
+    path.into_iter()
+        .map(|field| {
+            field
+                .into_string()
+                .map_err(|t| format!("path expects strings, got {t}"))
+        })
+        .collect()
+}
+
+/// Scripts only have 64-bit integers, so larger numbers can't be passed in.
+fn to_dynamic(value: &DbValue) -> Result<Dynamic, String> {
+    Ok(match value {
+        DbValue::String(s) => s.clone().into(),
+        DbValue::Number(n) => i64::try_from(*n)
+            .map_err(|_| format!("number {n} does not fit in a script integer"))?
+            .into(),
+        DbValue::Boolean(b) => (*b).into(),
+        DbValue::Object(map) => {
+            let map = map
+                .iter()
+                .map(|(k, v)| Ok((k.as_str().into(), to_dynamic(v)?)))
+                .collect::<Result<Map, String>>()?;
+            Dynamic::from_map(map)
+        }
+        DbValue::Array(values) => Dynamic::from_array(
+            values
+                .iter()
+                .map(|v| to_dynamic(v))
+                .collect::<Result<_, _>>()?,
+        ),
+        DbValue::None => Dynamic::UNIT,
+    })
+}
+
+fn to_dynamic_map(map: &HashMap<String, DbValue>) -> Result<Dynamic, String> {
+    let map = map
+        .iter()
+        .map(|(k, v)| Ok((k.into(), to_dynamic(v)?)))
+        .collect::<Result<Map, String>>()?;
+    Ok(Dynamic::from_map(map))
+}
+
+fn from_dynamic(value: Dynamic) -> Result<DbValue, String> {
+    if value.is_unit() {
+        return Ok(DbValue::None);
+    }
+    if let Ok(n) = value.as_int() {
+        return Ok(DbValue::Number(n.into()));
+    }
+    if let Ok(b) = value.as_bool() {
+        return Ok(DbValue::Boolean(b));
+    }
+    if let Ok(c) = value.as_char() {
+        return Ok(DbValue::String(c.into()));
+    }
+    if value.is_string() {
+        return Ok(DbValue::String(value.into_string()?));
+    }
+    if value.is_array() {
+        return Ok(DbValue::Array(
+            value
+                .into_array()?
+                .into_iter()
+                .map(|v| from_dynamic(v).map(Box::new))
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    if let Some(map) = value.clone().try_cast::<Map>() {
+        return Ok(DbValue::Object(
+            map.into_iter()
+                .map(|(k, v)| Ok((k.as_str().into(), Box::new(from_dynamic(v)?))))
+                .collect::<Result<_, String>>()?,
+        ));
+    }
+
+    Err(format!("{} can't be stored", value.type_name()))
+}
+
+pub struct RhaiContract {
+    engine: Engine,
+    ast: AST,
+    slot: HostSlot,
+    /// Operations run by the call in progress.
+    operations: Arc<AtomicU64>,
+}
+
+impl RhaiContract {
+    fn context(ctx: ContractContext) -> Result<Dynamic, ContractError> {
+        let mut map = Map::new();
+        map.insert("action".into(), Dynamic::from(ctx.action));
+        map.insert("namespace".into(), ctx.namespace.into());
+        map.insert("contract_space".into(), ctx.contract_space.into());
+        map.insert("signed_by".into(), Dynamic::from_blob(ctx.signed_by));
+        map.insert("message_id".into(), Dynamic::from_blob(ctx.message_id));
+        map.insert("signed_at".into(), (ctx.signed_at as i64).into());
+        map.insert("publisher".into(), ctx.publisher.into());
+        map.insert("state".into(), (ctx.state as i64).into());
+        map.insert(
+            "params".into(),
+            to_dynamic_map(&ctx.contract_params)
+                .map_err(|x| ContractError::RuntimeError(x.into()))?,
+        );
+        Ok(Dynamic::from_map(map))
+    }
+
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+
+    /// Calls a script function taking the context map, with `host` reachable
+    /// from host functions for the call.
+    fn call(
+        &mut self,
+        name: &str,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Dynamic, ContractError> {
+        let call = CallHost {
+            host,
+            namespace: ctx.namespace.clone(),
+            contract_space: ctx.contract_space.clone(),
+        };
+        let context = Self::context(ctx)?;
+
+        self.operations.store(0, Ordering::Relaxed);
+        *self.slot.lock().unwrap() = Some(call);
+        let res = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, (context,));
+        if let Some(call) = self.slot.lock().unwrap().take() {
+            call.host
+                .record_fuel(self.operations.load(Ordering::Relaxed));
+        }
+
+        res.map_err(|e| map_error(&e))
+    }
+
+    /// Like [`Self::call`], for functions returning an array of actions.
+    fn call_actions(
+        &mut self,
+        name: &str,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        self.call(name, ctx, host)?
+            .into_array()
+            .map_err(|_| {
+                debug!("Contract returned something other than an array");
+                ContractError::InvalidResponse
+            })?
+            .into_iter()
+            .map(|action| {
+                action.try_cast::<DataAction>().ok_or_else(|| {
+                    debug!("Contract returned something other than an action");
+                    ContractError::InvalidResponse
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_metadata(mut map: Map) -> Result<ContractMetadata, ContractError> {
+    let mut field = |name: &str| map.remove(name).unwrap_or(Dynamic::UNIT);
+    let invalid = |_| {
+        debug!("Contract metadata has fields of the wrong type");
+        ContractError::InvalidResponse
+    };
+
+    Ok(ContractMetadata {
+        name: field("name").into_string().map_err(invalid)?,
+        version: field("version").into_string().map_err(invalid)?,
+        author: field("author").into_blob().map_err(invalid)?,
+        required_params: field("required_params")
+            .into_typed_array::<rhai::ImmutableString>()
+            .map_err(invalid)?
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    })
+}
+
+fn map_error(err: &EvalAltResult) -> ContractError {
+    match err.unwrap_inner() {
+        EvalAltResult::ErrorTooManyOperations(..) => {
+            debug!("Contract exceeded its operation limit");
+            ContractError::OutOfFuel
+        }
+        EvalAltResult::ErrorFunctionNotFound(name, ..) if name.starts_with(ENTRY_POINT) => {
+            ContractError::ContractNotImplemented
+        }
+        EvalAltResult::ErrorRuntime(code, ..) if code.is_int() => {
+            ContractError::ContractFailed(code.as_int().unwrap_or_default() as usize)
+        }
+        e => {
+            debug!("Error calling contract function: {e}");
+            ContractError::RuntimeError(e.to_string().into())
+        }
+    }
+}
+
+impl Contract for RhaiContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        let typed = match ctx.action {
+            DataAction::Insert { .. } | DataAction::Patch { .. } => Some(ON_INSERT_FN),
+            DataAction::Get { .. } => Some(ON_GET_FN),
+            DataAction::Delete { .. } => Some(ON_DELETE_FN),
+            DataAction::Emit { .. } => None,
+        };
+        let handler = typed
+            .filter(|name| self.defines(name, 1))
+            .unwrap_or(ENTRY_POINT);
+
+        self.call_actions(handler, ctx, host)
+    }
+
+    fn query(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<DbValue>, ContractError> {
+        if !self.defines(QUERY_FN, 1) {
+            return Ok(None);
+        }
+
+        let value = self.call(QUERY_FN, ctx, host)?;
+        from_dynamic(value).map(Some).map_err(|e| {
+            debug!("Contract query returned an unsupported value: {e}");
+            ContractError::InvalidResponse
+        })
+    }
+
+    fn init(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        if !self.defines(INIT_FN, 1) {
+            return Ok(None);
+        }
+        self.call_actions(INIT_FN, ctx, host).map(Some)
+    }
+
+    fn migrate(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        if !self.defines(MIGRATE_FN, 1) {
+            return Ok(None);
+        }
+        self.call_actions(MIGRATE_FN, ctx, host).map(Some)
+    }
+
+    fn metadata(&mut self) -> Result<Option<ContractMetadata>, ContractError> {
+        if !self.defines(METADATA_FN, 0) {
+            return Ok(None);
+        }
+
+        let map = self
+            .engine
+            .call_fn::<Map>(&mut Scope::new(), &self.ast, METADATA_FN, ())
+            .map_err(|e| map_error(&e))?;
+        parse_metadata(map).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests;
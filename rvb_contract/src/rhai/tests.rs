@@ -0,0 +1,301 @@
+use rvb_common::contract::NullHost;
+
+use super::*;
+
+fn test_context() -> ContractContext {
+    ContractContext {
+        action: DataAction::Insert {
+            incoming_data: DbValue::Number(45),
+            key: String::from("vadim"),
+            params: HashMap::new(),
+        },
+        namespace: "test".into(),
+        contract_space: "contract".into(),
+        signed_by: vec![1, 2, 3],
+        contract_params: HashMap::new(),
+        message_id: vec![4, 5, 6],
+        signed_at: 1_700_000_000,
+        publisher: "publisher".into(),
+        state: 3,
+    }
+}
+
+fn run(script: &str, host: Arc<dyn ContractHost>) -> Result<Vec<DataAction>, ContractError> {
+    let compiler = RhaiContractCompiler::new(RhaiConfig::default());
+    let mut contract = compiler.create_contract(script.as_bytes())?;
+    contract.execute(test_context(), host)
+}
+
+#[test]
+fn run_contract() {
+    let actions = run(
+        r#"
+        fn execute(ctx) {
+            if ctx.action.value > 40 {
+                [ctx.action, insert(ctx.action.key + "_big", true)]
+            } else {
+                throw 7;
+            }
+        }
+        "#,
+        Arc::new(NullHost),
+    )
+    .unwrap();
+
+    assert_eq!(
+        actions,
+        vec![
+            test_context().action,
+            DataAction::Insert {
+                key: "vadim_big".into(),
+                incoming_data: DbValue::Boolean(true),
+                params: HashMap::new(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn message_provenance() {
+    let actions = run(
+        r#"
+        fn execute(ctx) {
+            if ctx.state == 3 && ctx.signed_at == 1700000000 && ctx.publisher == "publisher" {
+                [insert("seen", ctx.message_id.len())]
+            } else {
+                throw 1;
+            }
+        }
+        "#,
+        Arc::new(NullHost),
+    )
+    .unwrap();
+
+    assert_eq!(
+        actions,
+        vec![DataAction::Insert {
+            key: "seen".into(),
+            incoming_data: DbValue::Number(3),
+            params: HashMap::new(),
+        }]
+    );
+}
+
+#[test]
+fn thrown_code_fails_contract() {
+    assert!(matches!(
+        run("fn execute(ctx) { throw 7; }", Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(7))
+    ));
+}
+
+#[test]
+fn missing_entry_point() {
+    assert!(matches!(
+        run("fn other(ctx) { [] }", Arc::new(NullHost)),
+        Err(ContractError::ContractNotImplemented)
+    ));
+}
+
+#[test]
+fn operation_limit() {
+    assert!(matches!(
+        run("fn execute(ctx) { loop {} }", Arc::new(NullHost)),
+        Err(ContractError::OutOfFuel)
+    ));
+}
+
+#[test]
+fn clock_is_unavailable() {
+    assert!(run("fn execute(ctx) { timestamp(); [] }", Arc::new(NullHost)).is_err());
+}
+
+struct FixedHost;
+
+impl ContractHost for FixedHost {
+    fn get(&self, namespace: &str, contract_space: &str, key: &str) -> Option<DbValue> {
+        (namespace == "test" && contract_space == "contract" && key == "vadim").then(|| {
+            DbValue::Object(HashMap::from([(
+                "count".into(),
+                Box::new(DbValue::Number(2)),
+            )]))
+        })
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        [0x2a; 32]
+    }
+}
+
+#[test]
+fn host_functions() {
+    let actions = run(
+        r#"
+        fn execute(ctx) {
+            let stored = get(ctx.action.key);
+            let seed = random_seed();
+            [
+                patch(ctx.action.key, [set_op(["count"], stored.count + 1), remove_op(["old"])]),
+                emit("seed", seed[0]),
+                emit("missing", get("other")),
+            ]
+        }
+        "#,
+        Arc::new(FixedHost),
+    )
+    .unwrap();
+
+    assert_eq!(
+        actions,
+        vec![
+            DataAction::Patch {
+                key: "vadim".into(),
+                ops: vec![
+                    PatchOp::Set {
+                        path: vec!["count".into()],
+                        value: DbValue::Number(3),
+                    },
+                    PatchOp::Remove {
+                        path: vec!["old".into()],
+                    },
+                ],
+            },
+            DataAction::Emit {
+                topic: "seed".into(),
+                payload: DbValue::Number(0x2a),
+            },
+            DataAction::Emit {
+                topic: "missing".into(),
+                payload: DbValue::None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn metadata() {
+    let compiler = RhaiContractCompiler::new(RhaiConfig::default());
+
+    let mut contract = compiler
+        .create_contract(
+            br#"
+            fn metadata() {
+                #{
+                    name: "counter",
+                    version: "1.0.0",
+                    author: blob(2, 7),
+                    required_params: ["owner"],
+                }
+            }
+            fn execute(ctx) { [] }
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        contract.metadata().unwrap(),
+        Some(ContractMetadata {
+            name: "counter".into(),
+            version: "1.0.0".into(),
+            author: vec![7, 7],
+            required_params: vec!["owner".into()],
+        })
+    );
+
+    let mut contract = compiler.create_contract(b"fn execute(ctx) { [] }").unwrap();
+    assert_eq!(contract.metadata().unwrap(), None);
+}
+
+#[test]
+fn migrate() {
+    let compiler = RhaiContractCompiler::new(RhaiConfig::default());
+
+    let mut contract = compiler
+        .create_contract(
+            br#"
+            fn migrate(ctx) { [insert(ctx.action.key, #{ count: ctx.action.value })] }
+            fn execute(ctx) { [] }
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        contract
+            .migrate(test_context(), Arc::new(NullHost))
+            .unwrap(),
+        Some(vec![DataAction::Insert {
+            key: "vadim".into(),
+            incoming_data: DbValue::Object(HashMap::from([(
+                "count".into(),
+                Box::new(DbValue::Number(45))
+            )])),
+            params: HashMap::new(),
+        }])
+    );
+
+    let mut contract = compiler.create_contract(b"fn execute(ctx) { [] }").unwrap();
+    assert_eq!(
+        contract
+            .migrate(test_context(), Arc::new(NullHost))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn typed_handlers() {
+    let script = r#"
+        fn on_insert(ctx) { throw 1; }
+        fn execute(ctx) { throw 2; }
+    "#;
+
+    assert!(matches!(
+        run(script, Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(1))
+    ));
+
+    let compiler = RhaiContractCompiler::new(RhaiConfig::default());
+    let mut contract = compiler.create_contract(script.as_bytes()).unwrap();
+    assert!(matches!(
+        contract.execute(
+            ContractContext {
+                action: DataAction::Delete { key: "k".into() },
+                ..test_context()
+            },
+            Arc::new(NullHost)
+        ),
+        Err(ContractError::ContractFailed(2))
+    ));
+    assert_eq!(
+        contract.init(test_context(), Arc::new(NullHost)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn query() {
+    let compiler = RhaiContractCompiler::new(RhaiConfig::default());
+
+    let mut contract = compiler
+        .create_contract(
+            br#"
+            fn query(ctx) { #{ key: ctx.action.key, doubled: get(ctx.action.key).count * 2 } }
+            fn execute(ctx) { [] }
+            "#,
+        )
+        .unwrap();
+    let ctx = ContractContext {
+        action: DataAction::Get {
+            key: "vadim".into(),
+        },
+        ..test_context()
+    };
+    assert_eq!(
+        contract.query(ctx.clone(), Arc::new(FixedHost)).unwrap(),
+        Some(DbValue::Object(HashMap::from([
+            ("key".into(), Box::new(DbValue::String("vadim".into()))),
+            ("doubled".into(), Box::new(DbValue::Number(4))),
+        ])))
+    );
+
+    let mut contract = compiler.create_contract(b"fn execute(ctx) { [] }").unwrap();
+    assert_eq!(contract.query(ctx, Arc::new(FixedHost)).unwrap(), None);
+}
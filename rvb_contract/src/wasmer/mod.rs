@@ -0,0 +1,554 @@
+use crate::abi::{
+    self, ALLOC_ERROR_CODE, CallState, EXTRA_PAGES, HOST_MODULE, INIT_EXPORT, MEMORY_EXPORT,
+    METADATA_EXPORT, MIGRATE_EXPORT, QUERY_EXPORT, VERSION_EXPORT,
+};
+use log::debug;
+use rvb_common::{
+    contract::{
+        Contract, ContractCompiler, ContractContext, ContractError, ContractHost, ContractMetadata,
+    },
+    schema::{DataAction, DbValue},
+};
+use std::sync::Arc;
+use wasmer::{
+    CompilerConfig, Cranelift, Engine, EngineBuilder, ExternType, Features, Function, FunctionEnv,
+    FunctionEnvMut, Imports, Instance, Memory, MemoryView, Module, Pages, RuntimeError, Singlepass,
+    Store, Type, wasmparser::Operator,
+};
+use wasmer_middlewares::{
+    Metering,
+    metering::{MeteringPoints, get_remaining_points},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum WasmerBackend {
+    /// Compiles in a single pass, trading generated code quality for
+    /// compilation speed.
+    #[default]
+    Singlepass,
+    Cranelift,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmerConfig {
+    pub backend: WasmerBackend,
+    /// Operators a single contract call may execute before it is stopped.
+    /// Wasmer has no epoch interruption, so metering stands in for a deadline.
+    pub fuel: u64,
+}
+
+impl Default for WasmerConfig {
+    fn default() -> Self {
+        Self {
+            backend: WasmerBackend::default(),
+            fuel: 1_000_000_000,
+        }
+    }
+}
+
+pub struct WasmerContractCompiler {
+    engine: Engine,
+    fuel: u64,
+}
+
+impl WasmerContractCompiler {
+    pub fn new(config: WasmerConfig) -> Self {
+        let metering = Arc::new(Metering::new(config.fuel, |_: &Operator| 1));
+
+        let mut features = Features::default();
+        features.threads(false).simd(false).relaxed_simd(false);
+
+        let builder = match config.backend {
+            WasmerBackend::Singlepass => {
+                let mut compiler = Singlepass::default();
+                compiler.canonicalize_nans(true);
+                compiler.push_middleware(metering);
+                EngineBuilder::new(compiler)
+            }
+            WasmerBackend::Cranelift => {
+                let mut compiler = Cranelift::default();
+                compiler.canonicalize_nans(true);
+                compiler.push_middleware(metering);
+                EngineBuilder::new(compiler)
+            }
+        };
+        let engine = builder.set_features(Some(features)).engine();
+
+        Self {
+            engine,
+            fuel: config.fuel,
+        }
+    }
+}
+
+fn validate(module: &Module) -> Result<(), ContractError> {
+    abi::check_imports(module.imports().map(|i| (i.module(), i.name())))?;
+
+    let exports = module.exports().collect::<Vec<_>>();
+    let handler = |name: &str| {
+        exports
+            .iter()
+            .find(|export| export.name() == name)
+            .map(|export| match export.ty() {
+                ExternType::Function(f) => f.params().is_empty() && f.results() == [Type::I64],
+                _ => false,
+            })
+    };
+    let memory = exports.iter().any(|export| {
+        export.name() == MEMORY_EXPORT && matches!(export.ty(), ExternType::Memory(_))
+    });
+    abi::check_exports(handler, memory)
+}
+
+impl ContractCompiler for WasmerContractCompiler {
+    fn create_contract(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
+        let module = Module::new(&self.engine, bytecode)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+        validate(&module)?;
+
+        Ok(Box::new(WasmerContract {
+            module,
+            engine: self.engine.clone(),
+            fuel: self.fuel,
+        }))
+    }
+}
+
+pub struct WasmerContract {
+    module: Module,
+    engine: Engine,
+    fuel: u64,
+}
+
+struct WasmerState {
+    call: CallState,
+    /// Guest memory, known only once the instance exists.
+    memory: Option<Memory>,
+}
+
+/// Copies the `len` bytes at `ptr` out of `view`, or returns `None` if they run
+/// past its end. Checked up front so a guest-chosen length never sizes the
+/// host's buffer on its own.
+fn copy_guest(view: &MemoryView, ptr: u64, len: u64) -> Option<Vec<u8>> {
+    if ptr.checked_add(len)? > view.data_size() {
+        return None;
+    }
+    let mut buf = vec![0u8; usize::try_from(len).ok()?];
+    view.read(ptr, &mut buf).ok()?;
+    Some(buf)
+}
+
+fn read_guest(
+    env: &mut FunctionEnvMut<WasmerState>,
+    ptr: u64,
+    len: u64,
+) -> Result<Vec<u8>, RuntimeError> {
+    let (state, store) = env.data_and_store_mut();
+    let memory = state
+        .memory
+        .as_ref()
+        .ok_or_else(|| RuntimeError::new("contract exports no memory"))?;
+
+    copy_guest(&memory.view(&store), ptr, len)
+        .ok_or_else(|| RuntimeError::new("contract passed a buffer outside its memory"))
+}
+
+fn write_guest(
+    env: &mut FunctionEnvMut<WasmerState>,
+    ptr: u64,
+    data: &[u8],
+) -> Result<(), RuntimeError> {
+    let (state, store) = env.data_and_store_mut();
+    let memory = state
+        .memory
+        .as_ref()
+        .ok_or_else(|| RuntimeError::new("contract exports no memory"))?;
+
+    memory
+        .view(&store)
+        .write(ptr, data)
+        .map_err(|e| RuntimeError::new(e.to_string()))
+}
+
+fn get_context_length(env: FunctionEnvMut<WasmerState>) -> u64 {
+    env.data().call.context.len() as u64
+}
+
+fn write_context(mut env: FunctionEnvMut<WasmerState>, ptr: u64) -> u64 {
+    let buf = env.data().call.context.clone();
+    if let Err(e) = write_guest(&mut env, ptr, &buf) {
+        debug!("Failed to write to memory {e}");
+        ALLOC_ERROR_CODE.into()
+    } else {
+        0
+    }
+}
+
+fn read_context(mut env: FunctionEnvMut<WasmerState>, codec: u64, ptr: u64, capacity: u64) -> u64 {
+    let context = env.data().call.context.clone();
+    abi::read_context(&context, codec, capacity, |data| {
+        write_guest(&mut env, ptr, data)
+            .inspect_err(|e| debug!("Failed to write to memory {e}"))
+            .is_ok()
+    })
+}
+
+fn get(
+    mut env: FunctionEnvMut<WasmerState>,
+    key_ptr: u64,
+    key_len: u64,
+) -> Result<u64, RuntimeError> {
+    let key = String::from_utf8(read_guest(&mut env, key_ptr, key_len)?)
+        .map_err(|e| RuntimeError::new(e.to_string()))?;
+
+    env.data_mut()
+        .call
+        .lookup(&key)
+        .map_err(|e| RuntimeError::new(e.to_string()))
+}
+
+fn state_get(
+    mut env: FunctionEnvMut<WasmerState>,
+    key_ptr: u64,
+    key_len: u64,
+) -> Result<u64, RuntimeError> {
+    let key = String::from_utf8(read_guest(&mut env, key_ptr, key_len)?)
+        .map_err(|e| RuntimeError::new(e.to_string()))?;
+
+    env.data_mut()
+        .call
+        .state_lookup(&key)
+        .map_err(|e| RuntimeError::new(e.to_string()))
+}
+
+fn state_set(
+    mut env: FunctionEnvMut<WasmerState>,
+    key_ptr: u64,
+    key_len: u64,
+    value_ptr: u64,
+    value_len: u64,
+) -> Result<(), RuntimeError> {
+    let key = String::from_utf8(read_guest(&mut env, key_ptr, key_len)?)
+        .map_err(|e| RuntimeError::new(e.to_string()))?;
+    let value = read_guest(&mut env, value_ptr, value_len)?;
+
+    env.data()
+        .call
+        .store_state(&key, &value)
+        .map_err(|e| RuntimeError::new(e.to_string()))
+}
+
+fn write_value(mut env: FunctionEnvMut<WasmerState>, ptr: u64) -> u64 {
+    let buf = env.data_mut().call.take_value();
+    if let Err(e) = write_guest(&mut env, ptr, &buf) {
+        debug!("Failed to write value to memory {e}");
+        ALLOC_ERROR_CODE.into()
+    } else {
+        0
+    }
+}
+
+fn random_seed(mut env: FunctionEnvMut<WasmerState>, out_ptr: u64) -> Result<(), RuntimeError> {
+    let seed = env.data().call.host.random_seed();
+    write_guest(&mut env, out_ptr, &seed)
+}
+
+fn blake3(
+    mut env: FunctionEnvMut<WasmerState>,
+    ptr: u64,
+    len: u64,
+    out_ptr: u64,
+) -> Result<(), RuntimeError> {
+    let data = read_guest(&mut env, ptr, len)?;
+    write_guest(&mut env, out_ptr, &abi::blake3(&data))
+}
+
+fn sha256(
+    mut env: FunctionEnvMut<WasmerState>,
+    ptr: u64,
+    len: u64,
+    out_ptr: u64,
+) -> Result<(), RuntimeError> {
+    let data = read_guest(&mut env, ptr, len)?;
+    write_guest(&mut env, out_ptr, &abi::sha256(&data))
+}
+
+fn verify(
+    mut env: FunctionEnvMut<WasmerState>,
+    key_ptr: u64,
+    key_len: u64,
+    data_ptr: u64,
+    data_len: u64,
+    sig_ptr: u64,
+    sig_len: u64,
+) -> Result<u64, RuntimeError> {
+    let key = read_guest(&mut env, key_ptr, key_len)?;
+    let data = read_guest(&mut env, data_ptr, data_len)?;
+    let signature = read_guest(&mut env, sig_ptr, sig_len)?;
+
+    Ok(abi::verify(&key, &data, &signature).into())
+}
+
+fn host_log(mut env: FunctionEnvMut<WasmerState>, ptr: u64, len: u64) -> Result<(), RuntimeError> {
+    abi::log(&read_guest(&mut env, ptr, len)?);
+    Ok(())
+}
+
+fn host_panic(
+    mut env: FunctionEnvMut<WasmerState>,
+    ptr: u64,
+    len: u64,
+) -> Result<(), RuntimeError> {
+    let message = read_guest(&mut env, ptr, len)?;
+    env.data_mut().call.record_panic(&message);
+    Err(RuntimeError::new(abi::PANIC_TRAP))
+}
+
+fn host_imports(store: &mut Store, env: &FunctionEnv<WasmerState>) -> Imports {
+    let mut imports = Imports::new();
+
+    imports.define(
+        HOST_MODULE,
+        "get_context_length",
+        Function::new_typed_with_env(store, env, get_context_length),
+    );
+    imports.define(
+        HOST_MODULE,
+        "write_context",
+        Function::new_typed_with_env(store, env, write_context),
+    );
+    imports.define(
+        HOST_MODULE,
+        "read_context",
+        Function::new_typed_with_env(store, env, read_context),
+    );
+    imports.define(
+        HOST_MODULE,
+        "get",
+        Function::new_typed_with_env(store, env, get),
+    );
+    imports.define(
+        HOST_MODULE,
+        "state_get",
+        Function::new_typed_with_env(store, env, state_get),
+    );
+    imports.define(
+        HOST_MODULE,
+        "state_set",
+        Function::new_typed_with_env(store, env, state_set),
+    );
+    imports.define(
+        HOST_MODULE,
+        "write_value",
+        Function::new_typed_with_env(store, env, write_value),
+    );
+    imports.define(
+        HOST_MODULE,
+        "random_seed",
+        Function::new_typed_with_env(store, env, random_seed),
+    );
+    imports.define(
+        HOST_MODULE,
+        "blake3",
+        Function::new_typed_with_env(store, env, blake3),
+    );
+    imports.define(
+        HOST_MODULE,
+        "sha256",
+        Function::new_typed_with_env(store, env, sha256),
+    );
+    imports.define(
+        HOST_MODULE,
+        "verify",
+        Function::new_typed_with_env(store, env, verify),
+    );
+    imports.define(
+        HOST_MODULE,
+        "log",
+        Function::new_typed_with_env(store, env, host_log),
+    );
+    imports.define(
+        HOST_MODULE,
+        "panic",
+        Function::new_typed_with_env(store, env, host_panic),
+    );
+
+    imports
+}
+
+impl WasmerContract {
+    /// Instantiates the contract in a fresh store, growing its memory and
+    /// checking its ABI version.
+    fn instantiate(
+        &self,
+        mut state: CallState,
+    ) -> Result<(Store, Instance, Memory, FunctionEnv<WasmerState>), ContractError> {
+        // Metering starts every instance with the full budget
+        state.fuel_budget = self.fuel;
+
+        let mut store = Store::new(self.engine.clone());
+        let env = FunctionEnv::new(
+            &mut store,
+            WasmerState {
+                call: state,
+                memory: None,
+            },
+        );
+        let imports = host_imports(&mut store, &env);
+
+        let instance = Instance::new(&mut store, &self.module, &imports).map_err(|x| {
+            debug!("Instantiate error {x}");
+            ContractError::CompilationError(x.to_string())
+        })?;
+
+        let memory = instance
+            .exports
+            .get_memory(MEMORY_EXPORT)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?
+            .clone();
+        memory.grow(&mut store, Pages(EXTRA_PAGES)).map_err(|x| {
+            debug!("Failed to grow WASM memory {x}");
+            ContractError::CompilationError(x.to_string())
+        })?;
+        env.as_mut(&mut store).memory = Some(memory.clone());
+
+        let version = match instance.exports.get_function(VERSION_EXPORT) {
+            Ok(f) => Some(
+                f.typed::<(), u32>(&store)
+                    .map_err(|x| ContractError::CompilationError(x.to_string()))?
+                    .call(&mut store)
+                    .map_err(|x| {
+                        debug!("Failed to read ABI version {x}");
+                        ContractError::CompilationError(x.to_string())
+                    })?,
+            ),
+            Err(_) => None,
+        };
+        let version = abi::check_version(version)?;
+        env.as_mut(&mut store).call.set_abi_version(version)?;
+
+        Ok((store, instance, memory, env))
+    }
+
+    /// Calls an export following the entry point's calling convention and
+    /// copies out the response it points to.
+    fn call_export(
+        store: &mut Store,
+        instance: &Instance,
+        memory: &Memory,
+        env: &FunctionEnv<WasmerState>,
+        name: &str,
+    ) -> Result<Vec<u8>, ContractError> {
+        let f = instance
+            .exports
+            .get_typed_function::<(), u64>(&*store, name)
+            .map_err(|x| {
+                debug!("Function getter error {x}");
+                ContractError::CompilationError(x.to_string())
+            })?;
+
+        let res = f.call(&mut *store);
+        let remaining = match get_remaining_points(&mut *store, instance) {
+            MeteringPoints::Remaining(points) => points,
+            MeteringPoints::Exhausted => 0,
+        };
+        let call = &env.as_ref(&*store).call;
+        call.report_fuel(remaining);
+        call.host.record_memory(memory.view(&*store).data_size());
+        let panic_message = call.panic_message.clone();
+
+        let res = res.map_err(|e| {
+            if let Some(message) = panic_message {
+                return ContractError::Panicked(message);
+            }
+            if remaining == 0 {
+                debug!("Contract ran out of fuel");
+                return ContractError::OutOfFuel;
+            }
+            debug!("Error calling contract function: {e:?}");
+            ContractError::ContractNotImplemented
+        })?;
+        let (ptr, len) = abi::split_result(res)?;
+
+        copy_guest(&memory.view(&*store), ptr as u64, len as u64).ok_or_else(|| {
+            debug!("Contract response at {ptr} runs past its memory");
+            ContractError::ContractNotImplemented
+        })
+    }
+
+    /// Calls an optional export returning actions, or returns `None` if the
+    /// contract does not export it.
+    fn call_optional(
+        &self,
+        name: &str,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        let (mut store, instance, memory, env) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.exports.get_function(name).is_err() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, &env, name)?;
+        abi::decode_actions(&buffer).map(Some)
+    }
+}
+
+impl Contract for WasmerContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        let action = ctx.action.clone();
+        let (mut store, instance, memory, env) = self.instantiate(CallState::new(ctx, host)?)?;
+        let handler =
+            abi::select_handler(&action, |name| instance.exports.get_function(name).is_ok())?;
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, &env, handler)?;
+        abi::decode_actions(&buffer)
+    }
+
+    fn query(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<DbValue>, ContractError> {
+        let (mut store, instance, memory, env) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.exports.get_function(QUERY_EXPORT).is_err() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, &env, QUERY_EXPORT)?;
+        abi::decode_value(&buffer).map(Some)
+    }
+
+    fn init(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(INIT_EXPORT, ctx, host)
+    }
+
+    fn migrate(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(MIGRATE_EXPORT, ctx, host)
+    }
+
+    fn metadata(&mut self) -> Result<Option<ContractMetadata>, ContractError> {
+        let (mut store, instance, memory, env) = self.instantiate(CallState::detached())?;
+        if instance.exports.get_function(METADATA_EXPORT).is_err() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, &env, METADATA_EXPORT)?;
+        abi::decode_metadata(&buffer).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,90 @@
+use rvb_common::contract::NullHost;
+use std::collections::HashMap;
+
+use super::*;
+const TEST_DATA: &[u8] = include_bytes!("../test_contract.wasm");
+
+fn test_context() -> ContractContext {
+    ContractContext {
+        action: DataAction::Insert {
+            incoming_data: rvb_common::schema::DbValue::Number(45),
+            key: String::from("vadim"),
+            params: HashMap::new(),
+        },
+        namespace: "test".into(),
+        contract_space: "contract".into(),
+        signed_by: vec![1, 2, 3],
+        contract_params: HashMap::new(),
+        message_id: vec![4, 5, 6],
+        signed_at: 1_700_000_000,
+        publisher: "publisher".into(),
+        state: 3,
+    }
+}
+
+#[test]
+fn run_contract() {
+    for backend in [WasmerBackend::Singlepass, WasmerBackend::Cranelift] {
+        let compiler = WasmerContractCompiler::new(WasmerConfig {
+            backend,
+            ..Default::default()
+        });
+        let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+        let ctx = test_context();
+        let actions = contract.execute(ctx.clone(), Arc::new(NullHost)).unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                ctx.action.clone(),
+                DataAction::Insert {
+                    params: HashMap::new(),
+                    incoming_data: rvb_common::schema::DbValue::Boolean(false),
+                    key: String::from("vadim"),
+                },
+            ],
+        );
+    }
+}
+
+#[test]
+fn contract_out_of_fuel() {
+    let compiler = WasmerContractCompiler::new(WasmerConfig {
+        fuel: 10_000,
+        ..Default::default()
+    });
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (loop $spin (br $spin))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::OutOfFuel)
+    ));
+}
+
+#[test]
+fn host_reads_outside_guest_memory() {
+    let compiler = WasmerContractCompiler::new(WasmerConfig::default());
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (import "rvb_host" "blake3" (func $blake3 (param i64 i64 i64)))
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (call $blake3 (i64.const 0) (i64.const 0x100000000000) (i64.const 0))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractNotImplemented)
+    ));
+}
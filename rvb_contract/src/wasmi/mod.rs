@@ -0,0 +1,511 @@
+use crate::abi::{
+    self, ALLOC_ERROR_CODE, CallState, EXTRA_PAGES, HOST_MODULE, INIT_EXPORT, MEMORY_EXPORT,
+    METADATA_EXPORT, MIGRATE_EXPORT, QUERY_EXPORT, VERSION_EXPORT,
+};
+use log::debug;
+use rvb_common::{
+    contract::{
+        Contract, ContractCompiler, ContractContext, ContractError, ContractHost, ContractMetadata,
+    },
+    schema::{DataAction, DbValue},
+};
+use std::sync::Arc;
+use wasmi::{
+    Caller, Config, Engine, Extern, ExternType, Instance, Linker, Memory, Module, Store,
+    core::{TrapCode, ValType},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmiConfig {
+    /// Fuel a single contract call may consume before it is stopped. Most
+    /// instructions cost one unit.
+    pub fuel: u64,
+    /// Whether contracts may use float instructions. wasmi cannot canonicalize
+    /// NaNs, so a contract inspecting NaN bits may see different values on
+    /// different CPUs. Disable this for nodes that must match other replicas
+    /// bit for bit; contracts built with `rvb_clib` decode messages with float
+    /// instructions and are then rejected.
+    pub floats: bool,
+}
+
+impl Default for WasmiConfig {
+    fn default() -> Self {
+        Self {
+            fuel: 100_000_000,
+            floats: true,
+        }
+    }
+}
+
+/// Interpreter-based runtime. Much slower than the compiling runtimes, but
+/// small and portable enough for constrained nodes, including nodes that are
+/// themselves compiled to WASM.
+pub struct WasmiContractCompiler {
+    engine: Engine,
+    linker: Arc<Linker<CallState>>,
+    config: WasmiConfig,
+}
+
+impl WasmiContractCompiler {
+    pub fn new(config: WasmiConfig) -> Result<Self, ContractError> {
+        let mut engine_config = Config::default();
+        engine_config.consume_fuel(true).floats(config.floats);
+
+        let engine = Engine::new(&engine_config);
+        let mut linker = Linker::new(&engine);
+        register_functions(&mut linker)?;
+
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+            config,
+        })
+    }
+}
+
+/// wasmi implements neither threads nor SIMD, so only the module's interface
+/// needs checking.
+fn validate(module: &Module) -> Result<(), ContractError> {
+    abi::check_imports(module.imports().map(|i| (i.module(), i.name())))?;
+
+    let handler = |name: &str| {
+        module.get_export(name).map(|ty| match ty {
+            ExternType::Func(f) => f.params().is_empty() && f.results() == [ValType::I64],
+            _ => false,
+        })
+    };
+    let memory = matches!(
+        module.get_export(MEMORY_EXPORT),
+        Some(ExternType::Memory(_))
+    );
+    abi::check_exports(handler, memory)
+}
+
+impl ContractCompiler for WasmiContractCompiler {
+    fn create_contract(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
+        let module = Module::new(&self.engine, bytecode)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+        validate(&module)?;
+
+        Ok(Box::new(WasmiContract {
+            module,
+            engine: self.engine.clone(),
+            linker: self.linker.clone(),
+            fuel: self.config.fuel,
+        }))
+    }
+}
+
+pub struct WasmiContract {
+    module: Module,
+    engine: Engine,
+    linker: Arc<Linker<CallState>>,
+    fuel: u64,
+}
+
+fn guest_memory(caller: &Caller<'_, CallState>) -> Option<Memory> {
+    match caller.get_export(MEMORY_EXPORT) {
+        Some(Extern::Memory(mem)) => Some(mem),
+        _ => None,
+    }
+}
+
+fn read_guest(
+    caller: &mut Caller<'_, CallState>,
+    ptr: u64,
+    len: u64,
+) -> Result<Vec<u8>, wasmi::Error> {
+    let memory =
+        guest_memory(caller).ok_or_else(|| wasmi::Error::new("contract exports no memory"))?;
+    abi::guest_slice(memory.data(&caller), ptr, len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmi::Error::new("contract passed a buffer outside its memory"))
+}
+
+fn write_guest(
+    caller: &mut Caller<'_, CallState>,
+    ptr: u64,
+    data: &[u8],
+) -> Result<(), wasmi::Error> {
+    let memory =
+        guest_memory(caller).ok_or_else(|| wasmi::Error::new("contract exports no memory"))?;
+    memory
+        .write(caller, ptr as usize, data)
+        .map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+fn register_functions(linker: &mut Linker<CallState>) -> Result<(), ContractError> {
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get_context_length",
+            |caller: Caller<'_, CallState>| -> u64 { caller.data().context.len() as u64 },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "write_context",
+            |mut caller: Caller<'_, CallState>, ptr: u64| -> u64 {
+                let buf = caller.data().context.clone();
+                if let Err(e) = write_guest(&mut caller, ptr, &buf) {
+                    debug!("Failed to write to memory {e}");
+                    ALLOC_ERROR_CODE.into()
+                } else {
+                    0
+                }
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "read_context",
+            |mut caller: Caller<'_, CallState>, codec: u64, ptr: u64, capacity: u64| -> u64 {
+                let context = caller.data().context.clone();
+                abi::read_context(&context, codec, capacity, |data| {
+                    write_guest(&mut caller, ptr, data)
+                        .inspect_err(|e| debug!("Failed to write to memory {e}"))
+                        .is_ok()
+                })
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64|
+             -> Result<u64, wasmi::Error> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+
+                caller
+                    .data_mut()
+                    .lookup(&key)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "state_get",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64|
+             -> Result<u64, wasmi::Error> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+
+                caller
+                    .data_mut()
+                    .state_lookup(&key)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "state_set",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64,
+             value_ptr: u64,
+             value_len: u64|
+             -> Result<(), wasmi::Error> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+                let value = read_guest(&mut caller, value_ptr, value_len)?;
+
+                caller
+                    .data()
+                    .store_state(&key, &value)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "write_value",
+            |mut caller: Caller<'_, CallState>, ptr: u64| -> u64 {
+                let buf = caller.data_mut().take_value();
+                if let Err(e) = write_guest(&mut caller, ptr, &buf) {
+                    debug!("Failed to write value to memory {e}");
+                    ALLOC_ERROR_CODE.into()
+                } else {
+                    0
+                }
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "random_seed",
+            |mut caller: Caller<'_, CallState>, out_ptr: u64| -> Result<(), wasmi::Error> {
+                let seed = caller.data().host.random_seed();
+                write_guest(&mut caller, out_ptr, &seed)
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "blake3",
+            |mut caller: Caller<'_, CallState>,
+             ptr: u64,
+             len: u64,
+             out_ptr: u64|
+             -> Result<(), wasmi::Error> {
+                let data = read_guest(&mut caller, ptr, len)?;
+                write_guest(&mut caller, out_ptr, &abi::blake3(&data))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "sha256",
+            |mut caller: Caller<'_, CallState>,
+             ptr: u64,
+             len: u64,
+             out_ptr: u64|
+             -> Result<(), wasmi::Error> {
+                let data = read_guest(&mut caller, ptr, len)?;
+                write_guest(&mut caller, out_ptr, &abi::sha256(&data))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "verify",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64,
+             data_ptr: u64,
+             data_len: u64,
+             sig_ptr: u64,
+             sig_len: u64|
+             -> Result<u64, wasmi::Error> {
+                let key = read_guest(&mut caller, key_ptr, key_len)?;
+                let data = read_guest(&mut caller, data_ptr, data_len)?;
+                let signature = read_guest(&mut caller, sig_ptr, sig_len)?;
+
+                Ok(abi::verify(&key, &data, &signature).into())
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, CallState>, ptr: u64, len: u64| -> Result<(), wasmi::Error> {
+                abi::log(&read_guest(&mut caller, ptr, len)?);
+                Ok(())
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "panic",
+            |mut caller: Caller<'_, CallState>, ptr: u64, len: u64| -> Result<(), wasmi::Error> {
+                let message = read_guest(&mut caller, ptr, len)?;
+                caller.data_mut().record_panic(&message);
+                Err(wasmi::Error::new(abi::PANIC_TRAP))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    Ok(())
+}
+
+impl WasmiContract {
+    /// Instantiates the contract in a fresh store, growing its memory and
+    /// checking its ABI version.
+    fn instantiate(
+        &self,
+        mut state: CallState,
+    ) -> Result<(Store<CallState>, Instance, Memory), ContractError> {
+        state.fuel_budget = self.fuel;
+
+        let mut store = Store::new(&self.engine, state);
+        store
+            .set_fuel(self.fuel)
+            .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|x| {
+                debug!("Instantiate error {x}");
+                ContractError::CompilationError(x.to_string())
+            })?;
+
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT)
+            .ok_or_else(|| ContractError::CompilationError("contract exports no memory".into()))?;
+        memory.grow(&mut store, EXTRA_PAGES.into()).map_err(|x| {
+            debug!("Failed to grow WASM memory {x}");
+            ContractError::CompilationError(x.to_string())
+        })?;
+
+        let version = match instance.get_func(&store, VERSION_EXPORT) {
+            Some(f) => Some(
+                f.typed::<(), u32>(&store)
+                    .and_then(|f| f.call(&mut store, ()))
+                    .map_err(|x| {
+                        debug!("Failed to read ABI version {x}");
+                        ContractError::CompilationError(x.to_string())
+                    })?,
+            ),
+            None => None,
+        };
+        let version = abi::check_version(version)?;
+        store.data_mut().set_abi_version(version)?;
+
+        Ok((store, instance, memory))
+    }
+
+    /// Calls an export following the entry point's calling convention and
+    /// copies out the response it points to.
+    fn call_export(
+        store: &mut Store<CallState>,
+        instance: &Instance,
+        memory: &Memory,
+        name: &str,
+    ) -> Result<Vec<u8>, ContractError> {
+        let f = instance
+            .get_typed_func::<(), u64>(&*store, name)
+            .map_err(|x| {
+                debug!("Function getter error {x}");
+                ContractError::CompilationError(x.to_string())
+            })?;
+
+        let res = f.call(&mut *store, ());
+        if let Ok(remaining) = store.get_fuel() {
+            store.data().report_fuel(remaining);
+        }
+        store
+            .data()
+            .host
+            .record_memory(memory.data(&*store).len() as u64);
+
+        let res = res.map_err(|e| {
+            if let Some(message) = &store.data().panic_message {
+                return ContractError::Panicked(message.clone());
+            }
+            if e.as_trap_code() == Some(TrapCode::OutOfFuel) {
+                debug!("Contract ran out of fuel");
+                return ContractError::OutOfFuel;
+            }
+            debug!("Error calling contract function: {e:?}");
+            ContractError::ContractNotImplemented
+        })?;
+        let (ptr, len) = abi::split_result(res)?;
+
+        let buffer = abi::guest_slice(memory.data(&*store), ptr as u64, len as u64)
+            .ok_or_else(|| {
+                debug!("Contract response at {ptr} runs past its memory");
+                ContractError::ContractNotImplemented
+            })?
+            .to_vec();
+
+        Ok(buffer)
+    }
+
+    /// Calls an optional export returning actions, or returns `None` if the
+    /// contract does not export it.
+    fn call_optional(
+        &self,
+        name: &str,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.get_func(&store, name).is_none() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, name)?;
+        abi::decode_actions(&buffer).map(Some)
+    }
+}
+
+impl Contract for WasmiContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        let action = ctx.action.clone();
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        let handler =
+            abi::select_handler(&action, |name| instance.get_func(&store, name).is_some())?;
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, handler)?;
+        abi::decode_actions(&buffer)
+    }
+
+    fn query(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<DbValue>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.get_func(&store, QUERY_EXPORT).is_none() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, QUERY_EXPORT)?;
+        abi::decode_value(&buffer).map(Some)
+    }
+
+    fn init(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(INIT_EXPORT, ctx, host)
+    }
+
+    fn migrate(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(MIGRATE_EXPORT, ctx, host)
+    }
+
+    fn metadata(&mut self) -> Result<Option<ContractMetadata>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::detached())?;
+        if instance.get_func(&store, METADATA_EXPORT).is_none() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, METADATA_EXPORT)?;
+        abi::decode_metadata(&buffer).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,103 @@
+//! Contracts built as components of the `reverb:contract` world defined in
+//! `wit/contract.wit`, for toolchains that target the component model rather
+//! than the pointer and length ABI of core modules. The context and actions
+//! keep their msgpack encoding, so only the calling convention differs.
+//!
+//! WASI preview 2 is linked with the `wasi` feature, in a form that keeps
+//! calls deterministic; see [`super::wasi`]. Without it, components importing
+//! WASI interfaces, as those of most toolchains do, are rejected.
+//! Components implement only the entry point; typed handlers, init, query,
+//! migrate and metadata are left to core modules for now.
+
+use super::{EpochTicker, finish_call, new_store};
+use crate::abi::{self, CallState};
+use log::debug;
+use rvb_common::{
+    contract::{Contract, ContractContext, ContractError, ContractHost},
+    schema::DataAction,
+};
+use std::sync::Arc;
+use wasmtime::Engine;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "contract",
+    });
+}
+
+pub use bindings::{Contract as ContractWorld, ContractPre};
+
+/// Whether `bytecode` is a binary component rather than a core module. Both
+/// share the magic number and differ in the layer field after the version.
+#[must_use]
+pub fn is_component(bytecode: &[u8]) -> bool {
+    bytecode.len() >= 8 && bytecode[..4] == *b"\0asm" && bytecode[6..8] == [1, 0]
+}
+
+impl bindings::reverb::contract::host::Host for CallState {
+    fn get(&mut self, key: String) -> Option<Vec<u8>> {
+        let value = self.host.get(&self.namespace, &self.contract_space, &key)?;
+        rmp_serde::to_vec(&value).ok()
+    }
+
+    fn state_get(&mut self, key: String) -> Option<Vec<u8>> {
+        rmp_serde::to_vec(&self.host.state_get(&key)?).ok()
+    }
+
+    fn state_set(&mut self, key: String, value: Vec<u8>) -> bool {
+        self.store_state(&key, &value)
+            .inspect_err(|e| debug!("Contract stored an invalid value: {e:?}"))
+            .is_ok()
+    }
+
+    fn random_seed(&mut self) -> Vec<u8> {
+        self.host.random_seed().to_vec()
+    }
+
+    fn blake3(&mut self, data: Vec<u8>) -> Vec<u8> {
+        abi::blake3(&data).to_vec()
+    }
+
+    fn sha256(&mut self, data: Vec<u8>) -> Vec<u8> {
+        abi::sha256(&data).to_vec()
+    }
+
+    fn verify(&mut self, key: Vec<u8>, data: Vec<u8>, signature: Vec<u8>) -> bool {
+        abi::verify(&key, &data, &signature)
+    }
+}
+
+pub struct ComponentContract {
+    pub(super) instance_pre: ContractPre<CallState>,
+    pub(super) engine: Engine,
+    pub(super) deadline_ticks: u64,
+    pub(super) fuel: Option<u64>,
+    pub(super) _ticker: Arc<EpochTicker>,
+}
+
+impl Contract for ComponentContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        #[cfg(feature = "wasi")]
+        let seed = host.random_seed();
+        let state = CallState::new(ctx, host)?;
+        #[cfg(feature = "wasi")]
+        let state = state.with_wasi(seed);
+        let mut store = new_store(&self.engine, state, self.deadline_ticks, self.fuel)?;
+        let contract = self.instance_pre.instantiate(&mut store).map_err(|x| {
+            debug!("Instantiate error {x}");
+            ContractError::CompilationError(x.to_string())
+        })?;
+
+        let context = std::mem::take(&mut store.data_mut().context);
+        let res = contract.call_execute(&mut store, &context);
+        match finish_call(&store, res)? {
+            Ok(buffer) => abi::decode_actions(&buffer),
+            Err(code) => Err(ContractError::ContractFailed(code as usize)),
+        }
+    }
+}
@@ -1,116 +1,795 @@
+use crate::abi::{
+    self, ALLOC_EXPORT, CallState, EXTRA_PAGES, FREE_EXPORT, HOST_MODULE, INIT_EXPORT,
+    MEMORY_EXPORT, METADATA_EXPORT, MIGRATE_EXPORT, QUERY_EXPORT, VERSION_EXPORT,
+};
 use log::debug;
 use rvb_common::{
-    contract::{Contract, ContractCompiler, ContractContext, ContractError},
-    schema::DataAction,
+    contract::{
+        ArtifactCache, Contract, ContractCompiler, ContractContext, ContractError, ContractHost,
+        ContractMetadata,
+    },
+    crypto::hash::{HashAlgorithm, Hasher},
+    schema::{DataAction, DbValue},
+};
+use std::{
+    hash::{self, Hash},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use wasmtime::component::{self as wasm_component, Component, HasSelf};
+use wasmtime::{
+    Caller, Config, Engine, ExternType, Instance, InstanceAllocationStrategy, InstancePre, Linker,
+    Memory, Module, PoolingAllocationConfig, Store, Trap, ValType,
 };
-use wasmtime::{Caller, Config, Engine, Linker, Module, Store};
 
-pub struct WasmtimeContractCompiler;
+mod component;
+#[cfg(feature = "wasi")]
+pub(crate) mod wasi;
+
+use component::ComponentContract;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmtimeConfig {
+    /// Wall-clock time a single contract call may run before it is interrupted.
+    pub deadline: Duration,
+    /// How often the engine epoch advances. Deadlines are rounded up to a whole
+    /// number of intervals.
+    pub epoch_interval: Duration,
+    /// Number of instances the pooling allocator reserves slots for. `None`
+    /// allocates every instance on demand instead.
+    pub pooled_instances: Option<u32>,
+    /// Fuel a single contract call may consume. Setting it makes calls report
+    /// the fuel they used, at some cost in execution speed; the deadline keeps
+    /// applying either way.
+    pub fuel: Option<u64>,
+    /// Compile contracts to Pulley bytecode and interpret it rather than
+    /// generating native code, for platforms that forbid JIT compilation.
+    /// Slower, but keeps the same determinism profile.
+    pub interpreted: bool,
+}
+
+impl Default for WasmtimeConfig {
+    fn default() -> Self {
+        Self {
+            deadline: Duration::from_secs(1),
+            epoch_interval: Duration::from_millis(10),
+            pooled_instances: Some(64),
+            fuel: None,
+            interpreted: false,
+        }
+    }
+}
+
+impl WasmtimeConfig {
+    fn deadline_ticks(&self) -> u64 {
+        let interval = self.epoch_interval.as_nanos().max(1);
+        (self.deadline.as_nanos().div_ceil(interval) as u64).max(1)
+    }
+}
+
+/// Pulley target matching the host's pointer width and endianness.
+const PULLEY_TARGET: &str = match (
+    cfg!(target_pointer_width = "64"),
+    cfg!(target_endian = "big"),
+) {
+    (true, false) => "pulley64",
+    (true, true) => "pulley64be",
+    (false, false) => "pulley32",
+    (false, true) => "pulley32be",
+};
+
+/// Whether this process may run natively compiled code, found by compiling and
+/// calling an empty function. Fails on platforms that forbid JIT compilation,
+/// such as iOS, and in sandboxes denying executable memory.
+#[must_use]
+pub fn jit_available() -> bool {
+    let probe = || -> wasmtime::Result<()> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, r#"(module (func (export "probe")))"#)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+        instance
+            .get_typed_func::<(), ()>(&mut store, "probe")?
+            .call(&mut store, ())
+    };
+
+    probe()
+        .inspect_err(|e| debug!("JIT compilation is unavailable: {e}"))
+        .is_ok()
+}
+
+/// Background thread advancing the engine epoch, which is what lets wasmtime
+/// interrupt a contract stuck in a loop.
+struct EpochTicker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EpochTicker {
+    fn spawn(engine: Engine, interval: Duration) -> Result<Self, ContractError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+
+        let handle = thread::Builder::new()
+            .name("rvb-epoch-ticker".into())
+            .spawn(move || {
+                while !flag.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    engine.increment_epoch();
+                }
+            })
+            .map_err(|x| ContractError::RuntimeError(Box::new(x)))?;
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Feeds values hashed through [`Hash`] into a digest, for hashes wasmtime
+/// only exposes that way.
+struct DigestWriter<'a>(&'a mut Hasher);
+
+impl hash::Hasher for DigestWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+            .finalize()
+            .first_chunk()
+            .copied()
+            .map_or(0, u64::from_be_bytes)
+    }
+}
+
+pub struct WasmtimeContractCompiler {
+    engine: Engine,
+    linker: Linker<CallState>,
+    component_linker: wasm_component::Linker<CallState>,
+    config: WasmtimeConfig,
+    ticker: Arc<EpochTicker>,
+    cache: Option<Arc<dyn ArtifactCache>>,
+}
+
+impl WasmtimeContractCompiler {
+    pub fn new(config: WasmtimeConfig) -> Result<Self, ContractError> {
+        let mut engine_config = Config::default();
+        engine_config
+            .epoch_interruption(true)
+            .cranelift_nan_canonicalization(true)
+            .consume_fuel(config.fuel.is_some())
+            .wasm_threads(false)
+            .wasm_relaxed_simd(false)
+            .wasm_simd(false);
+
+        if config.interpreted {
+            engine_config
+                .target(PULLEY_TARGET)
+                .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+        }
+
+        if let Some(instances) = config.pooled_instances {
+            let mut pooling = PoolingAllocationConfig::default();
+            pooling
+                .total_core_instances(instances)
+                .total_memories(instances)
+                .total_tables(instances);
+            engine_config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        }
+
+        let engine = Engine::new(&engine_config)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+        let ticker = Arc::new(EpochTicker::spawn(engine.clone(), config.epoch_interval)?);
+
+        let mut linker = Linker::new(&engine);
+        register_functions(&mut linker)?;
+
+        let mut component_linker = wasm_component::Linker::new(&engine);
+        component::ContractWorld::add_to_linker::<_, HasSelf<_>>(&mut component_linker, |state| {
+            state
+        })
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+        #[cfg(feature = "wasi")]
+        wasmtime_wasi::p2::add_to_linker_sync(&mut component_linker)
+            .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+        Ok(Self {
+            engine,
+            linker,
+            component_linker,
+            config,
+            ticker,
+            cache: None,
+        })
+    }
+
+    /// Reuses compiled modules from `cache` instead of recompiling bytecode
+    /// after every restart.
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<dyn ArtifactCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Artifacts are keyed by a blake3 digest of the bytecode and the engine's
+    /// compatibility hash, which changes with the wasmtime version and config.
+    /// The cache may live on disk across releases, so the key cannot come from
+    /// std's hasher, whose output is not stable between Rust versions.
+    fn cache_key(&self, bytecode: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(HashAlgorithm::Blake3);
+        hasher.update(bytecode);
+        self.engine
+            .precompile_compatibility_hash()
+            .hash(&mut DigestWriter(&mut hasher));
+        hasher.finalize().to_vec()
+    }
+
+    fn compile(&self, bytecode: &[u8]) -> Result<Module, ContractError> {
+        let Some(cache) = &self.cache else {
+            return Module::new(&self.engine, bytecode)
+                .map_err(|x| ContractError::CompilationError(x.to_string()));
+        };
+
+        let key = self.cache_key(bytecode);
+        if let Some(artifact) = cache.load(&key) {
+            // SAFETY: the cache only holds output of `Module::serialize`, and the key
+            // pins it to an engine compatible with this one
+            match unsafe { Module::deserialize(&self.engine, &artifact) } {
+                Ok(module) => return Ok(module),
+                Err(e) => debug!("Discarding unusable cached module: {e}"),
+            }
+        }
+
+        let module = Module::new(&self.engine, bytecode)
+            .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+
+        match module.serialize() {
+            Ok(artifact) => cache.store(&key, &artifact),
+            Err(e) => debug!("Failed to serialize compiled module: {e}"),
+        }
+
+        Ok(module)
+    }
+}
+
+fn validate(module: &Module) -> Result<(), ContractError> {
+    abi::check_imports(module.imports().map(|i| (i.module(), i.name())))?;
+
+    let handler = |name: &str| {
+        module.get_export(name).map(|ty| match ty {
+            ExternType::Func(f) => {
+                f.params().len() == 0
+                    && matches!(f.results().collect::<Vec<_>>()[..], [ValType::I64])
+            }
+            _ => false,
+        })
+    };
+    let memory = matches!(
+        module.get_export(MEMORY_EXPORT),
+        Some(ExternType::Memory(_))
+    );
+    abi::check_exports(handler, memory)?;
+
+    let allocator = [(ALLOC_EXPORT, 1, 1), (FREE_EXPORT, 2, 0)];
+    for (name, params, results) in allocator {
+        let valid = match module.get_export(name) {
+            Some(ExternType::Func(f)) => {
+                f.params().len() == params
+                    && f.results().len() == results
+                    && f.params()
+                        .chain(f.results())
+                        .all(|ty| matches!(ty, ValType::I32))
+            }
+            Some(_) => false,
+            None => true,
+        };
+        if !valid {
+            return Err(ContractError::InvalidModule(format!(
+                "{name} must take {params} and return {results} i32 values"
+            )));
+        }
+    }
+    Ok(())
+}
 
 impl ContractCompiler for WasmtimeContractCompiler {
     fn create_contract(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
-        let engine = Engine::new(&Config::default())
+        if component::is_component(bytecode) {
+            return self.create_component(bytecode);
+        }
+
+        let module = self.compile(bytecode)?;
+        validate(&module)?;
+        // Import resolution happens once here, so each call only has to
+        // allocate and initialize the instance
+        let instance_pre = self
+            .linker
+            .instantiate_pre(&module)
             .map_err(|x| ContractError::CompilationError(x.to_string()))?;
-        let module = Module::new(&engine, bytecode)
+
+        Ok(Box::new(WasmtimeContract {
+            instance_pre,
+            engine: self.engine.clone(),
+            deadline_ticks: self.config.deadline_ticks(),
+            fuel: self.config.fuel,
+            _ticker: self.ticker.clone(),
+        }))
+    }
+}
+
+impl WasmtimeContractCompiler {
+    fn create_component(&self, bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
+        let component = Component::new(&self.engine, bytecode)
             .map_err(|x| ContractError::CompilationError(x.to_string()))?;
+        // Fails for components importing anything besides the host interface
+        // and, with the `wasi` feature, WASI, or not exporting the world's
+        // functions
+        let instance_pre = self
+            .component_linker
+            .instantiate_pre(&component)
+            .and_then(component::ContractPre::new)
+            .map_err(|x| ContractError::InvalidModule(x.to_string()))?;
 
-        Ok(Box::new(WasmtimeContract { module, engine }))
+        Ok(Box::new(ComponentContract {
+            instance_pre,
+            engine: self.engine.clone(),
+            deadline_ticks: self.config.deadline_ticks(),
+            fuel: self.config.fuel,
+            _ticker: self.ticker.clone(),
+        }))
     }
 }
 
+/// Store for a single call, with the deadline and fuel budget applied.
+fn new_store(
+    engine: &Engine,
+    mut state: CallState,
+    deadline_ticks: u64,
+    fuel: Option<u64>,
+) -> Result<Store<CallState>, ContractError> {
+    if let Some(fuel) = fuel {
+        state.fuel_budget = fuel;
+    }
+
+    let mut store = Store::new(engine, state);
+    store.set_epoch_deadline(deadline_ticks);
+    if let Some(fuel) = fuel {
+        store
+            .set_fuel(fuel)
+            .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+    }
+    Ok(store)
+}
+
+/// Reports the fuel a finished call used and maps its traps to errors.
+fn finish_call<T>(store: &Store<CallState>, res: wasmtime::Result<T>) -> Result<T, ContractError> {
+    // Fails only if fuel is disabled
+    if let Ok(remaining) = store.get_fuel() {
+        store.data().report_fuel(remaining);
+    }
+
+    res.map_err(|e| {
+        if let Some(message) = &store.data().panic_message {
+            return ContractError::Panicked(message.clone());
+        }
+        match e.downcast_ref::<Trap>() {
+            Some(Trap::Interrupt) => {
+                debug!("Contract exceeded its deadline");
+                return ContractError::Timeout;
+            }
+            Some(Trap::OutOfFuel) => {
+                debug!("Contract ran out of fuel");
+                return ContractError::OutOfFuel;
+            }
+            _ => {}
+        }
+        debug!("Error calling contract function: {e:?}");
+        ContractError::ContractNotImplemented
+    })
+}
+
 pub struct WasmtimeContract {
-    module: Module,
+    instance_pre: InstancePre<CallState>,
     engine: Engine,
+    deadline_ticks: u64,
+    fuel: Option<u64>,
+    _ticker: Arc<EpochTicker>,
 }
 
-pub const ALLOC_ERROR_CODE: u8 = 1;
+pub use crate::abi::ALLOC_ERROR_CODE;
 
-impl WasmtimeContract {
-    fn register_functions(&self, linker: &mut Linker<Vec<u8>>) -> Result<(), ContractError> {
-        linker
-            .func_wrap(
-                "rvb_host",
-                "get_context_length",
-                |caller: Caller<'_, Vec<u8>>| -> u64 { caller.data().len() as u64 },
-            )
-            .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+fn guest_memory(caller: &mut Caller<'_, CallState>) -> Option<Memory> {
+    match caller.get_export(MEMORY_EXPORT) {
+        Some(wasmtime::Extern::Memory(mem)) => Some(mem),
+        _ => None,
+    }
+}
 
-        linker
-            .func_wrap(
-                "rvb_host",
-                "write_context",
-                |mut caller: Caller<'_, Vec<u8>>, ptr: u64| -> u64 {
-                    let memory = match caller.get_export("memory") {
-                        Some(wasmtime::Extern::Memory(mem)) => mem,
-                        _ => return 1, // ALLOC_ERROR_CODE
-                    };
-
-                    let buf = caller.data().clone();
-                    if let Err(e) = memory.write(&mut caller, ptr as usize, &buf) {
-                        debug!("Failed to write to memory {e}");
-                        1
-                    } else {
-                        debug!("wrote to memory");
-                        0
-                    }
-                },
-            )
-            .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+fn read_guest(caller: &mut Caller<'_, CallState>, ptr: u64, len: u64) -> wasmtime::Result<Vec<u8>> {
+    let memory =
+        guest_memory(caller).ok_or_else(|| wasmtime::Error::msg("contract exports no memory"))?;
+    abi::guest_slice(memory.data(&caller), ptr, len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("contract passed a buffer outside its memory"))
+}
 
-        Ok(())
-    }
+fn write_guest(caller: &mut Caller<'_, CallState>, ptr: u64, data: &[u8]) -> wasmtime::Result<()> {
+    let memory =
+        guest_memory(caller).ok_or_else(|| wasmtime::Error::msg("contract exports no memory"))?;
+    memory.write(caller, ptr as usize, data)?;
+    Ok(())
 }
 
-impl Contract for WasmtimeContract {
-    fn execute(&mut self, ctx: ContractContext) -> Result<Vec<DataAction>, ContractError> {
-        let fmt_ctx =
-            rmp_serde::to_vec(&ctx).map_err(|x| ContractError::RuntimeError(Box::new(x)))?;
+fn register_functions(linker: &mut Linker<CallState>) -> Result<(), ContractError> {
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get_context_length",
+            |caller: Caller<'_, CallState>| -> u64 { caller.data().context.len() as u64 },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "write_context",
+            |mut caller: Caller<'_, CallState>, ptr: u64| -> u64 {
+                let Some(memory) = guest_memory(&mut caller) else {
+                    return ALLOC_ERROR_CODE.into();
+                };
+
+                let buf = caller.data().context.clone();
+                if let Err(e) = memory.write(&mut caller, ptr as usize, &buf) {
+                    debug!("Failed to write to memory {e}");
+                    ALLOC_ERROR_CODE.into()
+                } else {
+                    debug!("wrote to memory");
+                    0
+                }
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "read_context",
+            |mut caller: Caller<'_, CallState>, codec: u64, ptr: u64, capacity: u64| -> u64 {
+                let context = caller.data().context.clone();
+                abi::read_context(&context, codec, capacity, |data| {
+                    write_guest(&mut caller, ptr, data)
+                        .inspect_err(|e| debug!("Failed to write to memory {e}"))
+                        .is_ok()
+                })
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64|
+             -> wasmtime::Result<u64> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)?;
+
+                Ok(caller.data_mut().lookup(&key)?)
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "state_get",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64|
+             -> wasmtime::Result<u64> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)?;
+
+                Ok(caller.data_mut().state_lookup(&key)?)
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "state_set",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64,
+             value_ptr: u64,
+             value_len: u64|
+             -> wasmtime::Result<()> {
+                let key = String::from_utf8(read_guest(&mut caller, key_ptr, key_len)?)?;
+                let value = read_guest(&mut caller, value_ptr, value_len)?;
+
+                Ok(caller.data().store_state(&key, &value)?)
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "write_value",
+            |mut caller: Caller<'_, CallState>, ptr: u64| -> u64 {
+                let Some(memory) = guest_memory(&mut caller) else {
+                    return ALLOC_ERROR_CODE.into();
+                };
+
+                let buf = caller.data_mut().take_value();
+                if let Err(e) = memory.write(&mut caller, ptr as usize, &buf) {
+                    debug!("Failed to write value to memory {e}");
+                    ALLOC_ERROR_CODE.into()
+                } else {
+                    0
+                }
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "random_seed",
+            |mut caller: Caller<'_, CallState>, out_ptr: u64| -> wasmtime::Result<()> {
+                let seed = caller.data().host.random_seed();
+                write_guest(&mut caller, out_ptr, &seed)
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
 
-        let mut linker = Linker::new(&self.engine);
+    register_crypto_functions(linker)
+}
+
+/// Hashing and signature checks, so contracts don't have to bundle their
+/// own crypto. Digests are always 32 bytes written to `out_ptr`.
+fn register_crypto_functions(linker: &mut Linker<CallState>) -> Result<(), ContractError> {
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "blake3",
+            |mut caller: Caller<'_, CallState>,
+             ptr: u64,
+             len: u64,
+             out_ptr: u64|
+             -> wasmtime::Result<()> {
+                let data = read_guest(&mut caller, ptr, len)?;
+                write_guest(&mut caller, out_ptr, &abi::blake3(&data))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "sha256",
+            |mut caller: Caller<'_, CallState>,
+             ptr: u64,
+             len: u64,
+             out_ptr: u64|
+             -> wasmtime::Result<()> {
+                let data = read_guest(&mut caller, ptr, len)?;
+                write_guest(&mut caller, out_ptr, &abi::sha256(&data))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "verify",
+            |mut caller: Caller<'_, CallState>,
+             key_ptr: u64,
+             key_len: u64,
+             data_ptr: u64,
+             data_len: u64,
+             sig_ptr: u64,
+             sig_len: u64|
+             -> wasmtime::Result<u64> {
+                let key = read_guest(&mut caller, key_ptr, key_len)?;
+                let data = read_guest(&mut caller, data_ptr, data_len)?;
+                let signature = read_guest(&mut caller, sig_ptr, sig_len)?;
+
+                Ok(abi::verify(&key, &data, &signature).into())
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
 
-        self.register_functions(&mut linker)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, CallState>, ptr: u64, len: u64| -> wasmtime::Result<()> {
+                abi::log(&read_guest(&mut caller, ptr, len)?);
+                Ok(())
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
 
-        let mut store = Store::new(&self.engine, fmt_ctx);
-        let instance = linker.instantiate(&mut store, &self.module).map_err(|x| {
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "panic",
+            |mut caller: Caller<'_, CallState>, ptr: u64, len: u64| -> wasmtime::Result<()> {
+                let message = read_guest(&mut caller, ptr, len)?;
+                caller.data_mut().record_panic(&message);
+                Err(wasmtime::Error::msg(abi::PANIC_TRAP))
+            },
+        )
+        .map_err(|x| ContractError::RuntimeError(x.to_string().into()))?;
+
+    Ok(())
+}
+
+impl WasmtimeContract {
+    /// Instantiates the contract in a fresh store, growing its memory and
+    /// checking its ABI version.
+    fn instantiate(
+        &self,
+        state: CallState,
+    ) -> Result<(Store<CallState>, Instance, Memory), ContractError> {
+        let mut store = new_store(&self.engine, state, self.deadline_ticks, self.fuel)?;
+        let instance = self.instance_pre.instantiate(&mut store).map_err(|x| {
             debug!("Instantiate error {x}");
             ContractError::CompilationError(x.to_string())
         })?;
 
-        let memory = instance.get_memory(&mut store, "memory").unwrap();
-        memory.grow(&mut store, 1024).map_err(|x| {
+        let memory = instance.get_memory(&mut store, MEMORY_EXPORT).unwrap();
+        memory.grow(&mut store, EXTRA_PAGES.into()).map_err(|x| {
             debug!("Failed to grow WASM memory {x}");
             ContractError::CompilationError(x.to_string())
         })?;
 
+        let version = match instance.get_func(&mut store, VERSION_EXPORT) {
+            Some(f) => Some(
+                f.typed::<(), u32>(&store)
+                    .and_then(|f| f.call(&mut store, ()))
+                    .map_err(|x| {
+                        debug!("Failed to read ABI version {x}");
+                        ContractError::CompilationError(x.to_string())
+                    })?,
+            ),
+            None => None,
+        };
+        let version = abi::check_version(version)?;
+        store.data_mut().set_abi_version(version)?;
+
+        Ok((store, instance, memory))
+    }
+
+    /// Calls an export following the entry point's calling convention and
+    /// copies out the response it points to.
+    fn call_export(
+        store: &mut Store<CallState>,
+        instance: &Instance,
+        memory: &Memory,
+        name: &str,
+    ) -> Result<Vec<u8>, ContractError> {
         let f = instance
-            .get_typed_func::<(), u64>(&mut store, "rvb_contract")
+            .get_typed_func::<(), u64>(&mut *store, name)
             .map_err(|x| {
                 debug!("Function getter error {x}");
                 ContractError::CompilationError(x.to_string())
             })?;
 
-        let res = f.call(&mut store, ()).map_err(|e| {
-            debug!("Error calling contract function: {e:?}");
-            ContractError::ContractNotImplemented
+        let res = f.call(&mut *store, ());
+        store
+            .data()
+            .host
+            .record_memory(memory.data_size(&*store) as u64);
+        let res = finish_call(store, res)?;
+        let (ptr, len) = abi::split_result(res)?;
+
+        let buffer = abi::guest_slice(memory.data(&*store), ptr as u64, len as u64)
+            .ok_or_else(|| {
+                debug!("Contract response at {ptr} runs past its memory");
+                ContractError::ContractNotImplemented
+            })?
+            .to_vec();
+
+        if let Ok(free) = instance.get_typed_func::<(u32, u32), ()>(&mut *store, FREE_EXPORT)
+            && let Err(e) = free.call(&mut *store, (ptr as u32, len as u32))
+        {
+            debug!("Contract failed to free its response: {e:?}");
+        }
+
+        Ok(buffer)
+    }
+
+    /// Calls an optional export returning actions, or returns `None` if the
+    /// contract does not export it.
+    fn call_optional(
+        &self,
+        name: &str,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.get_func(&mut store, name).is_none() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, name)?;
+        abi::decode_actions(&buffer).map(Some)
+    }
+}
+
+impl Contract for WasmtimeContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        let action = ctx.action.clone();
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        let handler = abi::select_handler(&action, |name| {
+            instance.get_func(&mut store, name).is_some()
         })?;
-        let res = (res as u32, (res >> 32) as u32);
 
-        if res.0 == 0 {
-            return Err(ContractError::ContractFailed(res.1 as usize));
+        let buffer = Self::call_export(&mut store, &instance, &memory, handler)?;
+        abi::decode_actions(&buffer)
+    }
+
+    fn query(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<DbValue>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::new(ctx, host)?)?;
+        if instance.get_func(&mut store, QUERY_EXPORT).is_none() {
+            return Ok(None);
         }
 
-        let mut buffer = vec![0u8; res.0 as usize];
-        memory
-            .read(&mut store, res.1 as usize, &mut buffer)
-            .map_err(|e| {
-                debug!("Error reading memory: {e:?}");
-                ContractError::ContractNotImplemented
-            })?;
+        let buffer = Self::call_export(&mut store, &instance, &memory, QUERY_EXPORT)?;
+        abi::decode_value(&buffer).map(Some)
+    }
 
-        rmp_serde::from_slice(&buffer).map_err(|e| {
-            debug!("Error deserializing contract response: {e:?}");
-            ContractError::InvalidResponse
-        })
+    fn init(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(INIT_EXPORT, ctx, host)
+    }
+
+    fn migrate(
+        &mut self,
+        ctx: ContractContext,
+        host: Arc<dyn ContractHost>,
+    ) -> Result<Option<Vec<DataAction>>, ContractError> {
+        self.call_optional(MIGRATE_EXPORT, ctx, host)
+    }
+
+    fn metadata(&mut self) -> Result<Option<ContractMetadata>, ContractError> {
+        let (mut store, instance, memory) = self.instantiate(CallState::detached())?;
+        if instance.get_func(&mut store, METADATA_EXPORT).is_none() {
+            return Ok(None);
+        }
+
+        let buffer = Self::call_export(&mut store, &instance, &memory, METADATA_EXPORT)?;
+        abi::decode_metadata(&buffer).map(Some)
     }
 }
 
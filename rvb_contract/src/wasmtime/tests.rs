@@ -1,14 +1,20 @@
 use env_logger::Env;
-use std::collections::HashMap;
+use rvb_common::{
+    contract::{
+        ABI_VERSION, CONTEXT_CODEC, CONTEXT_TOO_LARGE, CONTEXT_UNSUPPORTED, CONTEXT_WRITE_FAILED,
+        CONTEXT_WRITTEN, ContractMetadata, NullHost,
+    },
+    crypto::KeyPair,
+    schema::DbValue,
+};
+use std::{collections::HashMap, sync::Mutex};
 
 use super::*;
+use crate::cache::DirArtifactCache;
 const TEST_DATA: &[u8] = include_bytes!("../test_contract.wasm");
 
-#[test]
-fn run_contract() {
-    env_logger::init_from_env(Env::new().default_filter_or("rvb_contract=trace"));
-    let mut contract = WasmtimeContractCompiler.create_contract(TEST_DATA).unwrap();
-    let ctx = ContractContext {
+fn test_context() -> ContractContext {
+    ContractContext {
         action: DataAction::Insert {
             incoming_data: rvb_common::schema::DbValue::Number(45),
             key: String::from("vadim"),
@@ -18,10 +24,22 @@ fn run_contract() {
         contract_space: "contract".into(),
         signed_by: vec![1, 2, 3],
         contract_params: HashMap::new(),
-    };
-    let actions = contract.execute(ctx.clone()).unwrap();
-    let actions2 = contract.execute(ctx.clone()).unwrap();
-    let actions3 = contract.execute(ctx.clone()).unwrap();
+        message_id: vec![4, 5, 6],
+        signed_at: 1_700_000_000,
+        publisher: "publisher".into(),
+        state: 3,
+    }
+}
+
+#[test]
+fn run_contract() {
+    env_logger::init_from_env(Env::new().default_filter_or("rvb_contract=trace"));
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    let ctx = test_context();
+    let actions = contract.execute(ctx.clone(), Arc::new(NullHost)).unwrap();
+    let actions2 = contract.execute(ctx.clone(), Arc::new(NullHost)).unwrap();
+    let actions3 = contract.execute(ctx.clone(), Arc::new(NullHost)).unwrap();
 
     assert_eq!(actions, actions2);
     assert_eq!(actions2, actions3);
@@ -37,3 +55,969 @@ fn run_contract() {
         ],
     );
 }
+
+#[test]
+fn contract_deadline() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        deadline: Duration::from_millis(50),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (loop $spin (br $spin))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::Timeout)
+    ));
+}
+
+struct FixedHost;
+
+impl ContractHost for FixedHost {
+    fn get(&self, namespace: &str, contract_space: &str, key: &str) -> Option<DbValue> {
+        (namespace == "test" && contract_space == "contract" && key == "vadim")
+            .then_some(DbValue::String("stored".into()))
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        [0x2a; 32]
+    }
+}
+
+/// Looks up the key stored at offset 0 and fails with the reported length as
+/// the error code, so the test can observe what `get` returned.
+fn lookup_contract(key: &str) -> Vec<u8> {
+    format!(
+        r#"(module
+            (import "rvb_host" "get" (func $get (param i64 i64) (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{key}")
+            (func (export "rvb_contract") (result i64)
+                (i64.shl
+                    (call $get (i64.const 0) (i64.const {len}))
+                    (i64.const 32))))"#,
+        len = key.len()
+    )
+    .into_bytes()
+}
+
+#[test]
+fn host_get() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let expected = rmp_serde::to_vec(&DbValue::String("stored".into()))
+        .unwrap()
+        .len();
+
+    let mut contract = compiler.create_contract(&lookup_contract("vadim")).unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(FixedHost)),
+        Err(ContractError::ContractFailed(len)) if len == expected
+    ));
+
+    let mut contract = compiler.create_contract(&lookup_contract("other")).unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(FixedHost)),
+        Err(ContractError::ContractFailed(0))
+    ));
+}
+
+#[test]
+fn host_get_outside_guest_memory() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (import "rvb_host" "get" (func $get (param i64 i64) (result i64)))
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (call $get (i64.const 65530) (i64.const 0x100000000000))))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(FixedHost)),
+        Err(ContractError::ContractNotImplemented)
+    ));
+}
+
+#[test]
+fn host_random_seed() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (import "rvb_host" "random_seed" (func $seed (param i64)))
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (call $seed (i64.const 32))
+                    (i64.shl (i64.load32_u (i32.const 60)) (i64.const 32))))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(FixedHost)),
+        Err(ContractError::ContractFailed(0x2a2a2a2a))
+    ));
+}
+
+fn wat_bytes(data: &[u8]) -> String {
+    data.iter().map(|b| format!("\\{b:02x}")).collect()
+}
+
+#[test]
+fn host_blake3() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    // Hashes "reverb" into offset 64 and reports the first four digest bytes.
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (import "rvb_host" "blake3" (func $blake3 (param i64 i64 i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "reverb")
+                (func (export "rvb_contract") (result i64)
+                    (call $blake3 (i64.const 0) (i64.const 6) (i64.const 64))
+                    (i64.shl (i64.load32_u (i32.const 64)) (i64.const 32))))"#,
+        )
+        .unwrap();
+
+    let digest = abi::blake3(b"reverb");
+    let prefix = u32::from_le_bytes(digest[..4].try_into().unwrap()) as usize;
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(code)) if code == prefix
+    ));
+}
+
+#[test]
+fn host_reads_outside_guest_memory() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    // A length far past the guest's single page, and one that wraps around
+    for (ptr, len) in [(0, 0x1000_0000_0000_u64), (1, u64::MAX)] {
+        let mut contract = compiler
+            .create_contract(
+                format!(
+                    r#"(module
+                        (import "rvb_host" "blake3" (func $blake3 (param i64 i64 i64)))
+                        (memory (export "memory") 1)
+                        (func (export "rvb_contract") (result i64)
+                            (call $blake3 (i64.const {ptr}) (i64.const {len}) (i64.const 0))
+                            (i64.const 0)))"#,
+                    len = len as i64
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            contract.execute(test_context(), Arc::new(NullHost)),
+            Err(ContractError::ContractNotImplemented)
+        ));
+    }
+}
+
+fn verify_contract(key: &[u8], data: &[u8], signature: &[u8]) -> Vec<u8> {
+    format!(
+        r#"(module
+            (import "rvb_host" "verify"
+                (func $verify (param i64 i64 i64 i64 i64 i64) (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{key}")
+            (data (i32.const 128) "{data}")
+            (data (i32.const 256) "{signature}")
+            (func (export "rvb_contract") (result i64)
+                (i64.shl
+                    (call $verify
+                        (i64.const 0) (i64.const {key_len})
+                        (i64.const 128) (i64.const {data_len})
+                        (i64.const 256) (i64.const {sig_len}))
+                    (i64.const 32))))"#,
+        key = wat_bytes(key),
+        data = wat_bytes(data),
+        signature = wat_bytes(signature),
+        key_len = key.len(),
+        data_len = data.len(),
+        sig_len = signature.len(),
+    )
+    .into_bytes()
+}
+
+#[test]
+fn host_verify() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let keypair = KeyPair::import(&[7u8; 64]).unwrap();
+    let signature = keypair.sign(b"payload");
+
+    let mut contract = compiler
+        .create_contract(&verify_contract(
+            &keypair.export_public(),
+            b"payload",
+            &signature,
+        ))
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(1))
+    ));
+
+    let mut contract = compiler
+        .create_contract(&verify_contract(
+            &keypair.export_public(),
+            b"tampered",
+            &signature,
+        ))
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(0))
+    ));
+}
+
+/// Reads the context into `ptr` and fails with either the status
+/// `read_context` returned or the length it reported.
+fn read_context_contract(codec: u32, ptr: u32, capacity: u32, status: bool) -> Vec<u8> {
+    let report = if status {
+        "(i64.and (local.get $res) (i64.const 0xffffffff00000000))"
+    } else {
+        "(i64.shl (local.get $res) (i64.const 32))"
+    };
+    format!(
+        r#"(module
+            (import "rvb_host" "read_context"
+                (func $read_context (param i64 i64 i64) (result i64)))
+            (memory (export "memory") 1)
+            (func (export "rvb_contract") (result i64)
+                (local $res i64)
+                (local.set $res
+                    (call $read_context
+                        (i64.const {codec}) (i64.const {ptr}) (i64.const {capacity})))
+                {report}))"#
+    )
+    .into_bytes()
+}
+
+#[test]
+fn host_read_context() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let len = abi::encode_context(&test_context(), 1).unwrap().len();
+    let run = |codec, ptr, capacity, status| {
+        let mut contract = compiler
+            .create_contract(&read_context_contract(codec, ptr, capacity, status))
+            .unwrap();
+        match contract.execute(test_context(), Arc::new(NullHost)) {
+            Err(ContractError::ContractFailed(code)) => code,
+            other => panic!("unexpected result {other:?}"),
+        }
+    };
+
+    assert_eq!(run(CONTEXT_CODEC, 0, 4096, true), CONTEXT_WRITTEN as usize);
+    assert_eq!(run(CONTEXT_CODEC, 0, 4096, false), len);
+
+    assert_eq!(run(CONTEXT_CODEC, 0, 4, true), CONTEXT_TOO_LARGE as usize);
+    assert_eq!(run(CONTEXT_CODEC, 0, 4, false), len);
+
+    assert_eq!(
+        run(CONTEXT_CODEC, u32::MAX - 16, 4096, true),
+        CONTEXT_WRITE_FAILED as usize
+    );
+    assert_eq!(run(99, 0, 4096, true), CONTEXT_UNSUPPORTED as usize);
+}
+
+#[derive(Default)]
+struct MemoryCache {
+    artifacts: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl ArtifactCache for MemoryCache {
+    fn load(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.artifacts.lock().unwrap().get(key).cloned()
+    }
+
+    fn store(&self, key: &[u8], artifact: &[u8]) {
+        self.artifacts
+            .lock()
+            .unwrap()
+            .insert(key.to_vec(), artifact.to_vec());
+    }
+}
+
+#[test]
+fn cached_module_is_reused() {
+    let cache = Arc::new(MemoryCache::default());
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default())
+        .unwrap()
+        .with_cache(cache.clone());
+
+    compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(cache.artifacts.lock().unwrap().len(), 1);
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(cache.artifacts.lock().unwrap().len(), 1);
+    assert!(contract.execute(test_context(), Arc::new(NullHost)).is_ok());
+}
+
+#[test]
+fn cache_key_is_a_digest() {
+    let compile = || WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let key = compile().cache_key(TEST_DATA);
+
+    assert_eq!(key.len(), 32);
+    assert_eq!(compile().cache_key(TEST_DATA), key);
+    assert_ne!(compile().cache_key(b"other bytecode"), key);
+}
+
+#[test]
+fn corrupt_cached_module_is_recompiled() {
+    let cache = Arc::new(MemoryCache::default());
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default())
+        .unwrap()
+        .with_cache(cache.clone());
+
+    let key = compiler.cache_key(TEST_DATA);
+    cache.store(&key, b"not a module");
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert!(contract.execute(test_context(), Arc::new(NullHost)).is_ok());
+    assert_ne!(cache.load(&key).unwrap(), b"not a module");
+}
+
+#[test]
+fn pooled_slots_are_reused() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        pooled_instances: Some(1),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+
+    for _ in 0..4 {
+        assert!(contract.execute(test_context(), Arc::new(NullHost)).is_ok());
+    }
+}
+
+fn versioned_contract(version: u32) -> Vec<u8> {
+    format!(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "rvb_abi_version") (result i32) (i32.const {version}))
+            (func (export "rvb_contract") (result i64) (i64.const 0x500000000)))"#
+    )
+    .into_bytes()
+}
+
+#[test]
+fn abi_version() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+
+    let mut contract = compiler.create_contract(&versioned_contract(1)).unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(5))
+    ));
+
+    let mut contract = compiler.create_contract(&versioned_contract(99)).unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::AbiMismatch(99))
+    ));
+}
+
+#[test]
+fn context_encoding() {
+    let ctx = test_context();
+
+    let legacy = abi::encode_context(&ctx, 1).unwrap();
+    assert!(rmp_serde::from_slice::<ContractContext>(&legacy).is_err());
+    let (action, namespace, contract_space, signed_by, contract_params): (
+        DataAction,
+        String,
+        String,
+        Vec<u8>,
+        HashMap<String, DbValue>,
+    ) = rmp_serde::from_slice(&legacy).unwrap();
+    assert_eq!(
+        (
+            action,
+            namespace,
+            contract_space,
+            signed_by,
+            contract_params
+        ),
+        (
+            ctx.action.clone(),
+            ctx.namespace.clone(),
+            ctx.contract_space.clone(),
+            ctx.signed_by.clone(),
+            ctx.contract_params.clone()
+        )
+    );
+
+    let current = abi::encode_context(&ctx, ABI_VERSION).unwrap();
+    assert_eq!(
+        rmp_serde::from_slice::<ContractContext>(&current).unwrap(),
+        ctx
+    );
+}
+
+#[test]
+fn metadata() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let expected = ContractMetadata {
+        name: "counter".into(),
+        version: "1.0.0".into(),
+        author: vec![7; 32],
+        required_params: vec!["owner".into()],
+    };
+    let encoded = rmp_serde::to_vec(&expected).unwrap();
+
+    let mut contract = compiler
+        .create_contract(
+            format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (data (i32.const 16) "{data}")
+                    (func (export "rvb_metadata") (result i64)
+                        (i64.const {packed}))
+                    (func (export "rvb_contract") (result i64) (i64.const 0)))"#,
+                data = wat_bytes(&encoded),
+                packed = (16u64 << 32) | encoded.len() as u64,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(contract.metadata().unwrap(), Some(expected));
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(contract.metadata().unwrap(), None);
+}
+
+#[test]
+fn migrate() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_migrate") (result i64) (i64.const 0x300000000))
+                (func (export "rvb_contract") (result i64) (i64.const 0)))"#,
+        )
+        .unwrap();
+    assert!(matches!(
+        contract.migrate(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(3))
+    ));
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(
+        contract
+            .migrate(test_context(), Arc::new(NullHost))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn invalid_modules_are_rejected() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let reject = |wat: &str| match compiler.create_contract(wat.as_bytes()) {
+        Err(ContractError::InvalidModule(reason)) => reason,
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("module was accepted"),
+    };
+
+    assert!(
+        reject(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64) (i64.const 0)))"#
+        )
+        .contains("fd_write")
+    );
+    assert!(
+        reject(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (param i32) (result i32) (i32.const 0)))"#
+        )
+        .contains("returning i64")
+    );
+    assert!(reject(r#"(module (memory (export "memory") 1))"#).contains("rvb_contract"));
+    assert!(
+        reject(r#"(module (func (export "rvb_contract") (result i64) (i64.const 0)))"#)
+            .contains("memory")
+    );
+
+    assert!(matches!(
+        compiler.create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (drop (v128.const i64x2 0 0))
+                    (i64.const 0)))"#
+        ),
+        Err(ContractError::CompilationError(_))
+    ));
+}
+
+#[test]
+fn nans_are_canonical() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+
+    // Fails with the bit pattern of 0/0 as its error code
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (i64.shl
+                        (i64.extend_i32_u
+                            (i32.reinterpret_f32 (f32.div (f32.const 0) (f32.const 0))))
+                        (i64.const 32))))"#,
+        )
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(0x7fc0_0000))
+    ));
+}
+
+#[test]
+fn typed_handlers() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    // Each handler fails with its own code, so the one called can be told apart
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_on_delete") (result i64) (i64.const 0x200000000))
+                (func (export "rvb_init") (result i64) (i64.const 0x300000000))
+                (func (export "rvb_contract") (result i64) (i64.const 0x100000000)))"#,
+        )
+        .unwrap();
+    let run = |contract: &mut Box<dyn Contract>, action| {
+        contract.execute(
+            ContractContext {
+                action,
+                ..test_context()
+            },
+            Arc::new(NullHost),
+        )
+    };
+
+    assert!(matches!(
+        run(&mut contract, test_context().action),
+        Err(ContractError::ContractFailed(1))
+    ));
+    assert!(matches!(
+        run(&mut contract, DataAction::Delete { key: "k".into() }),
+        Err(ContractError::ContractFailed(2))
+    ));
+    assert!(matches!(
+        contract.init(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(3))
+    ));
+
+    // Without a generic entry point, actions lacking a handler are refused
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_on_get") (result i64) (i64.const 0x200000000)))"#,
+        )
+        .unwrap();
+    assert!(matches!(
+        run(&mut contract, DataAction::Get { key: "k".into() }),
+        Err(ContractError::ContractFailed(2))
+    ));
+    assert!(matches!(
+        run(&mut contract, test_context().action),
+        Err(ContractError::ContractNotImplemented)
+    ));
+    assert_eq!(
+        contract.init(test_context(), Arc::new(NullHost)).unwrap(),
+        None
+    );
+}
+
+#[derive(Default)]
+struct StateHost {
+    state: Mutex<HashMap<String, DbValue>>,
+}
+
+impl ContractHost for StateHost {
+    fn get(&self, _namespace: &str, _contract_space: &str, _key: &str) -> Option<DbValue> {
+        None
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        [0; 32]
+    }
+
+    fn state_get(&self, key: &str) -> Option<DbValue> {
+        self.state.lock().unwrap().get(key).cloned()
+    }
+
+    fn state_set(&self, key: &str, value: DbValue) {
+        self.state.lock().unwrap().insert(key.into(), value);
+    }
+}
+
+#[test]
+fn host_private_state() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let value = rmp_serde::to_vec(&DbValue::Number(7)).unwrap();
+
+    // Stores the value, then fails with the length `state_get` reports for it
+    let mut contract = compiler
+        .create_contract(
+            format!(
+                r#"(module
+                    (import "rvb_host" "state_get" (func $get (param i64 i64) (result i64)))
+                    (import "rvb_host" "state_set" (func $set (param i64 i64 i64 i64)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "hits")
+                    (data (i32.const 16) "{value}")
+                    (func (export "rvb_contract") (result i64)
+                        (call $set (i64.const 0) (i64.const 4) (i64.const 16) (i64.const {len}))
+                        (i64.shl
+                            (call $get (i64.const 0) (i64.const 4))
+                            (i64.const 32))))"#,
+                value = wat_bytes(&value),
+                len = value.len(),
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+    let host = Arc::new(StateHost::default());
+    assert!(matches!(
+        contract.execute(test_context(), host.clone()),
+        Err(ContractError::ContractFailed(len)) if len == value.len()
+    ));
+    assert_eq!(host.state_get("hits"), Some(DbValue::Number(7)));
+}
+
+#[test]
+fn query() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let value = rmp_serde::to_vec(&DbValue::Number(7)).unwrap();
+
+    let mut contract = compiler
+        .create_contract(
+            format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (data (i32.const 16) "{data}")
+                    (func (export "rvb_query") (result i64) (i64.const {packed})))"#,
+                data = wat_bytes(&value),
+                packed = (16u64 << 32) | value.len() as u64,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(
+        contract.query(test_context(), Arc::new(NullHost)).unwrap(),
+        Some(DbValue::Number(7))
+    );
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(
+        contract.query(test_context(), Arc::new(NullHost)).unwrap(),
+        None
+    );
+}
+
+#[derive(Default)]
+struct UsageHost {
+    fuel: Mutex<Vec<u64>>,
+    memory: Mutex<Vec<u64>>,
+}
+
+impl ContractHost for UsageHost {
+    fn get(&self, _namespace: &str, _contract_space: &str, _key: &str) -> Option<DbValue> {
+        None
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        [0; 32]
+    }
+
+    fn record_fuel(&self, fuel: u64) {
+        self.fuel.lock().unwrap().push(fuel);
+    }
+
+    fn record_memory(&self, bytes: u64) {
+        self.memory.lock().unwrap().push(bytes);
+    }
+}
+
+#[test]
+fn usage_is_reported() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        fuel: Some(1_000_000),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let host = Arc::new(UsageHost::default());
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    contract.execute(test_context(), host.clone()).unwrap();
+    let used = host.fuel.lock().unwrap().clone();
+    assert!(matches!(used[..], [fuel] if fuel > 0 && fuel < 1_000_000));
+    // The contract's own pages plus the ones the runtime adds
+    let memory = host.memory.lock().unwrap().clone();
+    assert!(matches!(memory[..], [bytes] if bytes > u64::from(EXTRA_PAGES) * 65536));
+
+    let host = Arc::new(UsageHost::default());
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (loop $spin (br $spin))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), host.clone()),
+        Err(ContractError::OutOfFuel)
+    ));
+    assert_eq!(*host.fuel.lock().unwrap(), vec![1_000_000]);
+}
+
+/// Component whose `execute` writes `result<list<u8>, u32>` to the return
+/// area at offset 0: `ok` pointing at `data`, or `err` with `code`.
+fn component_contract(data: &[u8], code: Option<u32>) -> Vec<u8> {
+    let (tag, payload) = match code {
+        Some(code) => (1, code),
+        None => (0, 16),
+    };
+    wat::parse_str(format!(
+        r#"(component
+            (core module $m
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{data}")
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                    (i32.const 1024))
+                (func (export "execute") (param i32 i32) (result i32)
+                    (i32.store8 (i32.const 0) (i32.const {tag}))
+                    (i32.store (i32.const 4) (i32.const {payload}))
+                    (i32.store (i32.const 8) (i32.const {len}))
+                    (i32.const 0)))
+            (core instance $i (instantiate $m))
+            (func (export "execute")
+                (param "context" (list u8))
+                (result (result (list u8) (error u32)))
+                (canon lift (core func $i "execute")
+                    (memory $i "memory")
+                    (realloc (func $i "realloc")))))"#,
+        data = wat_bytes(data),
+        len = data.len(),
+    ))
+    .unwrap()
+}
+
+#[test]
+fn component_contract_runs() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let actions = vec![DataAction::Emit {
+        topic: "hello".into(),
+        payload: DbValue::Number(1),
+    }];
+
+    let mut contract = compiler
+        .create_contract(&component_contract(
+            &rmp_serde::to_vec(&actions).unwrap(),
+            None,
+        ))
+        .unwrap();
+    assert_eq!(
+        contract
+            .execute(test_context(), Arc::new(NullHost))
+            .unwrap(),
+        actions
+    );
+
+    let mut contract = compiler
+        .create_contract(&component_contract(&[], Some(7)))
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::ContractFailed(7))
+    ));
+}
+
+#[test]
+fn component_with_wasi_imports_is_rejected() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let component = wat::parse_str(
+        r#"(component
+            (import "wasi:clocks/wall-clock@0.2.0" (instance
+                (export "now" (func (result u64))))))"#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        compiler.create_contract(&component),
+        Err(ContractError::InvalidModule(_))
+    ));
+}
+
+#[test]
+fn dir_cache_is_shared_between_compilers() {
+    let dir = std::env::temp_dir().join(format!("rvb-artifacts-{}", std::process::id()));
+    let compile = || {
+        let cache = Arc::new(DirArtifactCache::new(&dir).unwrap());
+        WasmtimeContractCompiler::new(WasmtimeConfig::default())
+            .unwrap()
+            .with_cache(cache)
+    };
+
+    let first = compile();
+    first.create_contract(TEST_DATA).unwrap();
+    let key = first.cache_key(TEST_DATA);
+    let stored = DirArtifactCache::new(&dir).unwrap().load(&key).unwrap();
+
+    // A second compiler, as in another process, finds the artifact on disk
+    let mut contract = compile().create_contract(TEST_DATA).unwrap();
+    assert!(contract.execute(test_context(), Arc::new(NullHost)).is_ok());
+    assert_eq!(
+        DirArtifactCache::new(&dir).unwrap().load(&key).unwrap(),
+        stored
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn interpreted_contract_runs() {
+    assert!(jit_available());
+
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        interpreted: true,
+        deadline: Duration::from_millis(50),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    let interpreted = contract
+        .execute(test_context(), Arc::new(NullHost))
+        .unwrap();
+
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let mut contract = compiler.create_contract(TEST_DATA).unwrap();
+    assert_eq!(
+        contract
+            .execute(test_context(), Arc::new(NullHost))
+            .unwrap(),
+        interpreted
+    );
+
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+        interpreted: true,
+        deadline: Duration::from_millis(50),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64)
+                    (loop $spin (br $spin))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::Timeout)
+    ));
+}
+
+#[test]
+fn responses_are_freed() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let response = rmp_serde::to_vec(&Vec::<DataAction>::new()).unwrap();
+    let value = rmp_serde::to_vec(&DbValue::Boolean(true)).unwrap();
+
+    // Records in its state that it was asked to free exactly the response
+    let mut contract = compiler
+        .create_contract(
+            format!(
+                r#"(module
+                    (import "rvb_host" "state_set" (func $set (param i64 i64 i64 i64)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "freed")
+                    (data (i32.const 16) "{value}")
+                    (data (i32.const 64) "{response}")
+                    (func (export "rvb_contract") (result i64) (i64.const {packed}))
+                    (func (export "rvb_free") (param $ptr i32) (param $len i32)
+                        (if (i32.and
+                                (i32.eq (local.get $ptr) (i32.const 64))
+                                (i32.eq (local.get $len) (i32.const {len})))
+                            (then (call $set
+                                (i64.const 0) (i64.const 5)
+                                (i64.const 16) (i64.const {value_len}))))))"#,
+                value = wat_bytes(&value),
+                value_len = value.len(),
+                response = wat_bytes(&response),
+                len = response.len(),
+                packed = (64u64 << 32) | response.len() as u64,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+    let host = Arc::new(StateHost::default());
+    assert_eq!(
+        contract.execute(test_context(), host.clone()).unwrap(),
+        vec![]
+    );
+    assert_eq!(host.state_get("freed"), Some(DbValue::Boolean(true)));
+}
+
+#[test]
+fn mistyped_free_is_rejected() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+
+    assert!(matches!(
+        compiler.create_contract(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "rvb_contract") (result i64) (i64.const 0))
+                (func (export "rvb_free") (param i64)))"#,
+        ),
+        Err(ContractError::InvalidModule(_))
+    ));
+}
+
+#[test]
+fn guest_panics_are_reported() {
+    let compiler = WasmtimeContractCompiler::new(WasmtimeConfig::default()).unwrap();
+    let mut contract = compiler
+        .create_contract(
+            br#"(module
+                (import "rvb_host" "panic" (func $panic (param i64 i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "boom")
+                (func (export "rvb_contract") (result i64)
+                    (call $panic (i64.const 0) (i64.const 4))
+                    (i64.const 0)))"#,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        contract.execute(test_context(), Arc::new(NullHost)),
+        Err(ContractError::Panicked(m)) if m == "boom"
+    ));
+}
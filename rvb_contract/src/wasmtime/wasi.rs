@@ -0,0 +1,85 @@
+//! WASI preview 2 for components, with every interface that could make
+//! replicas disagree pinned down: clocks stand still at the Unix epoch,
+//! randomness is drawn from the seed every replica running the call agrees
+//! on, and there are no arguments, environment variables, preopened
+//! directories or sockets. Standard output and error are discarded.
+
+use crate::abi::CallState;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::time::Duration;
+use wasmtime_wasi::{
+    HostMonotonicClock, HostWallClock, ResourceTable, WasiCtx, WasiCtxBuilder, WasiCtxView,
+    WasiView,
+};
+
+/// WASI state of one component call.
+pub struct WasiState {
+    ctx: WasiCtx,
+    table: ResourceTable,
+}
+
+impl WasiState {
+    /// State for a call agreeing on `seed`; see
+    /// [`ContractHost::random_seed`](rvb_common::contract::ContractHost::random_seed).
+    fn new(seed: [u8; 32]) -> Self {
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .wall_clock(Epoch)
+            .monotonic_clock(Epoch)
+            .secure_random(StdRng::from_seed(seed))
+            .insecure_random(StdRng::from_seed(seed))
+            .insecure_random_seed(u128::from_le_bytes(seed[..16].try_into().unwrap()))
+            .allow_tcp(false)
+            .allow_udp(false)
+            .allow_ip_name_lookup(false);
+        Self {
+            ctx: builder.build(),
+            table: ResourceTable::new(),
+        }
+    }
+}
+
+impl CallState {
+    /// Sets up WASI for a component call agreeing on `seed`.
+    pub fn with_wasi(mut self, seed: [u8; 32]) -> Self {
+        self.wasi = Some(WasiState::new(seed));
+        self
+    }
+}
+
+impl WasiView for CallState {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        let wasi = self
+            .wasi
+            .as_mut()
+            .expect("Component calls must set up WASI");
+        WasiCtxView {
+            ctx: &mut wasi.ctx,
+            table: &mut wasi.table,
+        }
+    }
+}
+
+/// Clock standing still at the Unix epoch.
+struct Epoch;
+
+impl HostWallClock for Epoch {
+    fn resolution(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+impl HostMonotonicClock for Epoch {
+    fn resolution(&self) -> u64 {
+        1_000_000_000
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
@@ -0,0 +1,244 @@
+//! WebSocket gateway pushing contract events to browsers and other clients
+//! that do not run a node.
+//!
+//! Every message is a JSON object tagged by `type`. On connecting the gateway
+//! sends a `challenge`, which the client answers with an `auth` message
+//! signing the nonce with its reverb key. Once authenticated it sends
+//! `subscribe` and `unsubscribe` messages with prefixes of
+//! `namespace/contract_space/topic`, and receives an `event` for every
+//! matching event the node sees. A `subscribe` may also carry a `filter`
+//! expression, such as `status == "open"`, leaving out events whose payload
+//! does not match it.
+
+use futures::{SinkExt, StreamExt};
+use log::debug;
+use rand::RngCore;
+use rvb_common::crypto::{PublicKey, b64_decode, b64_encode};
+use rvb_common::protocol::ContractEvent;
+use rvb_common::schema::Filter;
+use rvb_node::Node;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+#[cfg(test)]
+mod tests;
+
+/// Where the gateway gets its events from.
+pub trait EventSource: Send + Sync {
+    fn watch(&self) -> broadcast::Receiver<ContractEvent>;
+}
+
+impl EventSource for Node {
+    fn watch(&self) -> broadcast::Receiver<ContractEvent> {
+        self.watch_events()
+    }
+}
+
+impl EventSource for broadcast::Sender<ContractEvent> {
+    fn watch(&self) -> broadcast::Receiver<ContractEvent> {
+        self.subscribe()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Proof of identity: `signature` signs the challenge nonce, both in
+    /// base64, with the key exported as `public_key`.
+    Auth {
+        public_key: String,
+        signature: String,
+    },
+    Subscribe {
+        prefix: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<String>,
+    },
+    /// Drops every subscription to `prefix`, whatever its filter.
+    Unsubscribe { prefix: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Challenge {
+        nonce: String,
+    },
+    Authenticated,
+    Event {
+        namespace: String,
+        contract_space: String,
+        topic: String,
+        payload: serde_json::Value,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl From<ContractEvent> for ServerMessage {
+    fn from(event: ContractEvent) -> Self {
+        Self::Event {
+            namespace: event.namespace,
+            contract_space: event.contract_space,
+            topic: event.topic,
+            payload: event.payload.into(),
+        }
+    }
+}
+
+/// Path events are matched against by subscription prefixes.
+#[must_use]
+pub fn event_path(event: &ContractEvent) -> String {
+    format!(
+        "{}/{}/{}",
+        event.namespace, event.contract_space, event.topic
+    )
+}
+
+pub struct Gateway {
+    events: Arc<dyn EventSource>,
+    /// Keys allowed to connect; any key that proves itself if `None`.
+    allowed_keys: Option<Vec<Vec<u8>>>,
+}
+
+impl Gateway {
+    #[must_use]
+    pub fn new(events: Arc<dyn EventSource>, allowed_keys: Option<Vec<Vec<u8>>>) -> Self {
+        Self {
+            events,
+            allowed_keys,
+        }
+    }
+
+    /// Accepts connections on `listener` until it fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let gateway = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = gateway.handle(stream).await {
+                    debug!("Gateway connection from {addr} ended: {e}");
+                }
+            });
+        }
+    }
+
+    fn authenticate(&self, nonce: &[u8], message: &ClientMessage) -> Result<(), String> {
+        let ClientMessage::Auth {
+            public_key,
+            signature,
+        } = message
+        else {
+            return Err("authenticate first".into());
+        };
+        let key = b64_decode(public_key).map_err(|_| "invalid public key")?;
+        let signature = b64_decode(signature).map_err(|_| "invalid signature")?;
+        if !PublicKey::import(&key)
+            .map_err(|e| e.to_string())?
+            .verify(nonce, &signature)
+        {
+            return Err("bad signature".into());
+        }
+        if self
+            .allowed_keys
+            .as_ref()
+            .is_some_and(|allowed| !allowed.contains(&key))
+        {
+            return Err("key not allowed".into());
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, stream: TcpStream) -> Result<(), String> {
+        let mut ws = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(|e| e.to_string())?;
+        let send = |message: ServerMessage| {
+            WsMessage::text(serde_json::to_string(&message).expect("Failed to encode message"))
+        };
+
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        ws.send(send(ServerMessage::Challenge {
+            nonce: b64_encode(&nonce),
+        }))
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut events = self.events.watch();
+        let mut authenticated = false;
+        let mut subscriptions: Vec<(String, Option<Filter>)> = Vec::new();
+
+        loop {
+            tokio::select! {
+                incoming = ws.next() => {
+                    let text = match incoming {
+                        Some(Ok(WsMessage::Text(text))) => text,
+                        Some(Ok(WsMessage::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return Err(e.to_string()),
+                    };
+                    let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                        Err(e) => Some(ServerMessage::Error { message: e.to_string() }),
+                        Ok(message) if !authenticated => {
+                            match self.authenticate(&nonce, &message) {
+                                Ok(()) => {
+                                    authenticated = true;
+                                    Some(ServerMessage::Authenticated)
+                                }
+                                Err(message) => {
+                                    let _ = ws.send(send(ServerMessage::Error { message })).await;
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        Ok(ClientMessage::Auth { .. }) => Some(ServerMessage::Error {
+                            message: "already authenticated".into(),
+                        }),
+                        Ok(ClientMessage::Subscribe { prefix, filter }) => {
+                            match filter.as_deref().map(Filter::parse).transpose() {
+                                Ok(filter) => {
+                                    let subscription = (prefix, filter);
+                                    if !subscriptions.contains(&subscription) {
+                                        subscriptions.push(subscription);
+                                    }
+                                    None
+                                }
+                                Err(e) => Some(ServerMessage::Error { message: e.to_string() }),
+                            }
+                        }
+                        Ok(ClientMessage::Unsubscribe { prefix }) => {
+                            subscriptions.retain(|(p, _)| *p != prefix);
+                            None
+                        }
+                    };
+                    if let Some(reply) = reply {
+                        ws.send(send(reply)).await.map_err(|e| e.to_string())?;
+                    }
+                }
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            debug!("Gateway connection missed {missed} events");
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    };
+                    let path = event_path(&event);
+                    let wanted = subscriptions.iter().any(|(prefix, filter)| {
+                        path.starts_with(prefix.as_str())
+                            && filter.as_ref().is_none_or(|f| f.matches(&event.payload))
+                    });
+                    if authenticated && wanted {
+                        ws.send(send(event.into())).await.map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+        }
+    }
+}
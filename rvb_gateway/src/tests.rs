@@ -0,0 +1,134 @@
+use super::*;
+use rvb_common::crypto::KeyPair;
+use rvb_common::schema::DbValue;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn start(allowed_keys: Option<Vec<Vec<u8>>>) -> (broadcast::Sender<ContractEvent>, String) {
+    let (events, _) = broadcast::channel(16);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("ws://{}", listener.local_addr().unwrap());
+    let gateway = Arc::new(Gateway::new(Arc::new(events.clone()), allowed_keys));
+    tokio::spawn(gateway.serve(listener));
+    (events, addr)
+}
+
+async fn send(ws: &mut Socket, message: &ClientMessage) {
+    ws.send(WsMessage::text(serde_json::to_string(message).unwrap()))
+        .await
+        .unwrap();
+}
+
+async fn recv(ws: &mut Socket) -> ServerMessage {
+    loop {
+        if let WsMessage::Text(text) = ws.next().await.unwrap().unwrap() {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+async fn connect(addr: &str, keypair: &KeyPair) -> (Socket, ServerMessage) {
+    let (mut ws, _) = tokio_tungstenite::connect_async(addr).await.unwrap();
+    let ServerMessage::Challenge { nonce } = recv(&mut ws).await else {
+        panic!("expected a challenge");
+    };
+    let signature = keypair.sign(&b64_decode(&nonce).unwrap());
+    send(
+        &mut ws,
+        &ClientMessage::Auth {
+            public_key: b64_encode(&keypair.export_public()),
+            signature: b64_encode(&signature),
+        },
+    )
+    .await;
+    let reply = recv(&mut ws).await;
+    (ws, reply)
+}
+
+fn event(namespace: &str, topic: &str) -> ContractEvent {
+    ContractEvent {
+        namespace: namespace.into(),
+        contract_space: "space".into(),
+        topic: topic.into(),
+        payload: DbValue::Number(1),
+    }
+}
+
+#[tokio::test]
+async fn test_subscribed_events_are_pushed() {
+    let (events, addr) = start(None).await;
+    let (mut ws, reply) = connect(&addr, &KeyPair::generate()).await;
+    assert_eq!(reply, ServerMessage::Authenticated);
+
+    send(
+        &mut ws,
+        &ClientMessage::Subscribe {
+            prefix: "shop/space/order".into(),
+            filter: None,
+        },
+    )
+    .await;
+    // Let the gateway register the prefix before events are sent.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    events.send(event("blog", "orders")).unwrap();
+    events.send(event("shop", "orders")).unwrap();
+    assert_eq!(
+        recv(&mut ws).await,
+        ServerMessage::Event {
+            namespace: "shop".into(),
+            contract_space: "space".into(),
+            topic: "orders".into(),
+            payload: serde_json::json!(1),
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_keys_are_refused() {
+    let allowed = KeyPair::generate();
+    let (_events, addr) = start(Some(vec![allowed.export_public()])).await;
+
+    let (_, reply) = connect(&addr, &allowed).await;
+    assert_eq!(reply, ServerMessage::Authenticated);
+
+    let (_, reply) = connect(&addr, &KeyPair::generate()).await;
+    assert!(matches!(reply, ServerMessage::Error { .. }));
+}
+
+#[tokio::test]
+async fn test_filtered_subscriptions() {
+    let (events, addr) = start(None).await;
+    let (mut ws, _) = connect(&addr, &KeyPair::generate()).await;
+
+    send(
+        &mut ws,
+        &ClientMessage::Subscribe {
+            prefix: "shop".into(),
+            filter: Some("status ==".into()),
+        },
+    )
+    .await;
+    assert!(matches!(recv(&mut ws).await, ServerMessage::Error { .. }));
+
+    send(
+        &mut ws,
+        &ClientMessage::Subscribe {
+            prefix: "shop".into(),
+            filter: Some(r#"status == "open""#.into()),
+        },
+    )
+    .await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    for status in ["done", "open"] {
+        let mut event = event("shop", "orders");
+        event.payload = serde_json::json!({ "status": status }).into();
+        events.send(event).unwrap();
+    }
+    assert!(matches!(
+        recv(&mut ws).await,
+        ServerMessage::Event { payload, .. } if payload["status"] == "open"
+    ));
+}
@@ -0,0 +1,293 @@
+//! gRPC interface to a node, defined in `proto/reverb.proto`, for services
+//! written in other languages. The service is a client of the node like any
+//! other: it connects in process through a memory transport and signs its
+//! requests with its own key, so the node applies them exactly as it would
+//! the same requests from a peer.
+//!
+//! [`AdminService`] is different: it reads the node's own records, such as its
+//! audit log or the actions it refused for each contract, so it holds the
+//! node itself.
+
+use rvb_client::{Client, ClientError};
+use rvb_common::crypto::KeyPair;
+use rvb_common::protocol::{ContractEvent, Location};
+use rvb_common::schema::{DbValue, Filter};
+use rvb_node::metrics::ActionRejection;
+use rvb_node::storage::AuditRecord;
+use rvb_node::{Node, NodeError};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("reverb.v1");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::reverb_server::{Reverb, ReverbServer};
+
+#[cfg(test)]
+mod tests;
+
+const EVENT_BUFFER: usize = 64;
+const AUDIT_LOG_LIMIT: u32 = 100;
+
+pub struct ReverbService {
+    client: Client,
+}
+
+impl ReverbService {
+    #[must_use]
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Connects to `node` in process, signing requests with `keypair`. The
+    /// node must be processing messages to finish the handshake.
+    pub async fn attach(node: &Node, keypair: KeyPair) -> Result<Self, ClientError> {
+        let (service, node_side) = rvb_transport::memory::pair();
+        node.connect_peer(Box::new(node_side)).await;
+        Ok(Self::new(
+            Client::handshake(Box::new(service), keypair, &[]).await?,
+        ))
+    }
+
+    /// The service, ready for `tonic::transport::Server::add_service`.
+    #[must_use]
+    pub fn into_server(self) -> ReverbServer<Self> {
+        ReverbServer::new(self)
+    }
+}
+
+fn client_status(e: ClientError) -> Status {
+    match e {
+        ClientError::Timeout => Status::deadline_exceeded("the node did not reply"),
+        ClientError::Closed | ClientError::TransportError(_) => {
+            Status::unavailable("the connection to the node is closed")
+        }
+        e => Status::internal(format!("{e:?}")),
+    }
+}
+
+fn from_json(field: &str, text: &str) -> Result<DbValue, Status> {
+    serde_json::from_str::<serde_json::Value>(text)
+        .map(DbValue::from)
+        .map_err(|e| Status::invalid_argument(format!("{field} is not valid JSON: {e}")))
+}
+
+fn to_json(value: DbValue) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+fn from_json_map(
+    field: &str,
+    map: HashMap<String, String>,
+) -> Result<HashMap<String, DbValue>, Status> {
+    map.into_iter()
+        .map(|(name, text)| Ok((name.clone(), from_json(&format!("{field}.{name}"), &text)?)))
+        .collect()
+}
+
+fn location(location: Option<proto::Location>) -> Result<Location, Status> {
+    let location = location.ok_or_else(|| Status::invalid_argument("location is required"))?;
+    Ok(Location {
+        namespace: location.namespace,
+        contract_space: location.contract_space,
+        contract: location.contract,
+        key: location.key,
+    })
+}
+
+impl From<ContractEvent> for proto::Event {
+    fn from(event: ContractEvent) -> Self {
+        Self {
+            namespace: event.namespace,
+            contract_space: event.contract_space,
+            topic: event.topic,
+            payload_json: to_json(event.payload),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Reverb for ReverbService {
+    async fn insert(
+        &self,
+        request: Request<proto::InsertRequest>,
+    ) -> Result<Response<proto::InsertResponse>, Status> {
+        let request = request.into_inner();
+        self.client
+            .insert(
+                location(request.location)?,
+                from_json("value_json", &request.value_json)?,
+                from_json_map("metadata_json", request.metadata_json)?,
+                request.state,
+            )
+            .await
+            .map_err(client_status)?;
+        Ok(Response::new(proto::InsertResponse {}))
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let request = request.into_inner();
+        let select = request
+            .select
+            .iter()
+            .map(|path| path.split('.').map(str::to_string).collect())
+            .collect();
+        let value = self
+            .client
+            .get(location(request.location)?, select)
+            .await
+            .map_err(client_status)?;
+        Ok(Response::new(proto::GetResponse {
+            value_json: value.map(to_json),
+        }))
+    }
+
+    async fn deploy(
+        &self,
+        request: Request<proto::DeployRequest>,
+    ) -> Result<Response<proto::DeployResponse>, Status> {
+        let request = request.into_inner();
+        let id = self
+            .client
+            .deploy_contract(
+                request.contract_payload,
+                request.namespace,
+                from_json_map("params_json", request.params_json)?,
+                request.tags,
+            )
+            .await
+            .map_err(client_status)?;
+        Ok(Response::new(proto::DeployResponse { id }))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<proto::Event, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let filter = request
+            .filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let mut subscription = self
+            .client
+            .subscribe(request.namespace, request.topic, filter)
+            .await
+            .map_err(client_status)?;
+
+        let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+        tokio::spawn(async move {
+            while let Some(event) = subscription.next().await {
+                if tx.send(Ok(event.into())).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+pub struct AdminService {
+    node: Arc<Node>,
+}
+
+impl AdminService {
+    #[must_use]
+    pub fn new(node: Arc<Node>) -> Self {
+        Self { node }
+    }
+
+    /// The service, ready for `tonic::transport::Server::add_service`.
+    #[must_use]
+    pub fn into_server(self) -> AdminServer<Self> {
+        AdminServer::new(self)
+    }
+}
+
+fn node_status(e: NodeError) -> Status {
+    match e {
+        NodeError::AuditChainBroken(sequence) => Status::data_loss(format!(
+            "audit log record {sequence} does not match the chain"
+        )),
+        e => Status::internal(format!("{e:?}")),
+    }
+}
+
+impl From<AuditRecord> for proto::AuditRecord {
+    fn from(record: AuditRecord) -> Self {
+        Self {
+            sequence: record.sequence,
+            message_id: record.message_id,
+            signed_by: record.signed_by,
+            namespace: record.namespace,
+            action: record.action,
+            state: record.state,
+            previous: record.previous.to_vec(),
+            hash: record.hash.to_vec(),
+        }
+    }
+}
+
+impl From<ActionRejection> for proto::ActionRejection {
+    fn from(rejection: ActionRejection) -> Self {
+        Self {
+            action: rejection.action.into(),
+            namespace: rejection.namespace,
+            contract_space: rejection.contract_space,
+            key: rejection.key,
+            limit: rejection.limit.map(|limit| format!("{limit:?}")),
+            reason: rejection.reason,
+            rejected_at: rejection.rejected_at,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn audit_log(
+        &self,
+        request: Request<proto::AuditLogRequest>,
+    ) -> Result<Response<proto::AuditLogResponse>, Status> {
+        let request = request.into_inner();
+        let limit = request.limit.unwrap_or(AUDIT_LOG_LIMIT);
+        let records = self
+            .node
+            .audit_log(request.from, limit as usize)
+            .map_err(node_status)?;
+        Ok(Response::new(proto::AuditLogResponse {
+            records: records.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn verify_audit_log(
+        &self,
+        _request: Request<proto::VerifyAuditLogRequest>,
+    ) -> Result<Response<proto::VerifyAuditLogResponse>, Status> {
+        let records = self.node.verify_audit_log().map_err(node_status)?;
+        Ok(Response::new(proto::VerifyAuditLogResponse { records }))
+    }
+
+    async fn contract_diagnostics(
+        &self,
+        request: Request<proto::ContractDiagnosticsRequest>,
+    ) -> Result<Response<proto::ContractDiagnosticsResponse>, Status> {
+        let rejections = self
+            .node
+            .contract_diagnostics(&request.into_inner().contract);
+        Ok(Response::new(proto::ContractDiagnosticsResponse {
+            rejections: rejections.into_iter().map(Into::into).collect(),
+        }))
+    }
+}
@@ -0,0 +1,239 @@
+use super::*;
+use rvb_common::transport::{Server, TransportError, TransportPeer};
+use rvb_contract::accept::AcceptContractCompiler;
+use rvb_node::{Limits, NodeConfig};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Server for a node that is only reached in process.
+struct NoServer;
+
+#[tonic::async_trait]
+impl Server for NoServer {
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        std::future::pending().await
+    }
+}
+
+async fn services() -> (ReverbService, AdminService) {
+    services_with(Limits::default()).await
+}
+
+async fn services_with(limits: Limits) -> (ReverbService, AdminService) {
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig {
+            audit: true,
+            limits,
+            ..NodeConfig::default()
+        },
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    ));
+    let admin = AdminService::new(node.clone());
+    tokio::spawn({
+        let node = node.clone();
+        async move { node.process().await }
+    });
+    let service = ReverbService::attach(&node, KeyPair::generate())
+        .await
+        .unwrap();
+    (service, admin)
+}
+
+async fn service() -> ReverbService {
+    services().await.0
+}
+
+#[tokio::test]
+async fn test_deploy_insert_get() {
+    let service = service().await;
+    let id = service
+        .deploy(Request::new(proto::DeployRequest {
+            contract_payload: b"contract".to_vec(),
+            namespace: "ns".into(),
+            params_json: HashMap::new(),
+            tags: Vec::new(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let location = proto::Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: id,
+        key: "key".into(),
+    };
+    service
+        .insert(Request::new(proto::InsertRequest {
+            location: Some(location.clone()),
+            value_json: r#"{"name":"reverb","stars":3}"#.into(),
+            metadata_json: HashMap::new(),
+            state: 1,
+        }))
+        .await
+        .unwrap();
+
+    // Inserts are applied asynchronously.
+    let mut value = None;
+    for _ in 0..50 {
+        value = service
+            .get(Request::new(proto::GetRequest {
+                location: Some(location.clone()),
+                select: vec!["stars".into()],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .value_json;
+        if value.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(value.as_deref(), Some(r#"{"stars":3}"#));
+}
+
+#[tokio::test]
+async fn test_invalid_requests() {
+    let service = service().await;
+
+    let status = service
+        .get(Request::new(proto::GetRequest {
+            location: None,
+            select: Vec::new(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    let status = service
+        .insert(Request::new(proto::InsertRequest {
+            location: Some(proto::Location::default()),
+            value_json: "{oops".into(),
+            metadata_json: HashMap::new(),
+            state: 1,
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let (service, admin) = services().await;
+    let id = service
+        .deploy(Request::new(proto::DeployRequest {
+            contract_payload: b"contract".to_vec(),
+            namespace: "ns".into(),
+            params_json: HashMap::new(),
+            tags: Vec::new(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    service
+        .insert(Request::new(proto::InsertRequest {
+            location: Some(proto::Location {
+                namespace: "ns".into(),
+                contract_space: "space".into(),
+                contract: id,
+                key: "key".into(),
+            }),
+            value_json: "1".into(),
+            metadata_json: HashMap::new(),
+            state: 7,
+        }))
+        .await
+        .unwrap();
+
+    // Messages are applied asynchronously.
+    let mut records = Vec::new();
+    for _ in 0..50 {
+        records = admin
+            .audit_log(Request::new(proto::AuditLogRequest {
+                from: 0,
+                limit: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .records;
+        if records.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(records.len(), 2);
+    assert!(records[0].action.starts_with("deploy "));
+    assert_eq!(records[1].action, "insert space/key");
+    assert_eq!(records[1].state, Some(7));
+    assert_eq!(records[1].previous, records[0].hash);
+
+    let verified = admin
+        .verify_audit_log(Request::new(proto::VerifyAuditLogRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(verified.records, 2);
+}
+
+#[tokio::test]
+async fn test_contract_diagnostics() {
+    let (service, admin) = services_with(Limits {
+        max_value_size: 16,
+        ..Limits::default()
+    })
+    .await;
+    let id = service
+        .deploy(Request::new(proto::DeployRequest {
+            contract_payload: b"contract".to_vec(),
+            namespace: "ns".into(),
+            params_json: HashMap::new(),
+            tags: Vec::new(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    service
+        .insert(Request::new(proto::InsertRequest {
+            location: Some(proto::Location {
+                namespace: "ns".into(),
+                contract_space: "space".into(),
+                contract: id.clone(),
+                key: "key".into(),
+            }),
+            value_json: format!("\"{}\"", "x".repeat(64)),
+            metadata_json: HashMap::new(),
+            state: 1,
+        }))
+        .await
+        .unwrap();
+
+    // Inserts are applied asynchronously.
+    let mut rejections = Vec::new();
+    for _ in 0..50 {
+        rejections = admin
+            .contract_diagnostics(Request::new(proto::ContractDiagnosticsRequest {
+                contract: id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .rejections;
+        if !rejections.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(rejections.len(), 1);
+    assert_eq!(rejections[0].action, "insert");
+    assert_eq!(rejections[0].contract_space, "space");
+    assert_eq!(rejections[0].key, "key");
+    assert_eq!(rejections[0].limit.as_deref(), Some("ValueSize"));
+}
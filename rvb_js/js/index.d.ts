@@ -0,0 +1,91 @@
+export type Json =
+  | null
+  | boolean
+  | number
+  | string
+  | Json[]
+  | { [key: string]: Json };
+
+export interface Location {
+  namespace: string;
+  contract_space: string;
+  /** Contract id, in base64. */
+  contract: string;
+  key: string;
+}
+
+export interface ContractEvent {
+  type: "event";
+  namespace: string;
+  contract_space: string;
+  topic: string;
+  payload: Json;
+}
+
+export interface ConnectOptions {
+  /** Armored private key; a new key is generated if unset. */
+  key?: string | null;
+  /** Milliseconds a `get` waits for its reply. Defaults to 30 seconds. */
+  timeout?: number;
+}
+
+/** Id a contract gets when deployed with `payload` by the base64 key `deployer`, in base64. */
+export function contractId(payload: Uint8Array, deployer: string): string;
+
+export class ReverbClient {
+  static connect(url: string, options?: ConnectOptions): Promise<ReverbClient>;
+
+  /** Public key of the client, in base64. */
+  readonly identity: string;
+
+  armorPrivate(): string;
+
+  insert(
+    location: Location,
+    value: Json,
+    options?: { metadata?: Record<string, Json>; state?: number | bigint },
+  ): void;
+
+  get(location: Location, select?: string[][]): Promise<Json | null>;
+
+  deployContract(
+    payload: Uint8Array,
+    namespace: string,
+    options?: { params?: Record<string, Json>; tags?: string[] },
+  ): string;
+
+  subscribe(
+    namespace: string,
+    topic: string | null,
+    callback: (event: ContractEvent) => void,
+    options?: { filter?: string },
+  ): () => void;
+
+  close(): void;
+}
+
+export interface QueueOptions {
+  /** Where writes are kept. Defaults to `localStorage`. */
+  storage?: Storage;
+  /** Start of the storage keys of the writes. Defaults to `reverb-offline`. */
+  prefix?: string;
+}
+
+/** Inserts kept in `localStorage`, signed as they are queued, until replayed. */
+export class OfflineQueue {
+  static open(key: string, options?: QueueOptions): Promise<OfflineQueue>;
+
+  /** Number of writes waiting to be sent. */
+  readonly length: number;
+
+  insert(
+    location: Location,
+    value: Json,
+    options?: { metadata?: Record<string, Json>; state?: number | bigint },
+  ): void;
+
+  /** Sends the queued writes through `client`, returning how many were sent. */
+  replay(client: ReverbClient): number;
+
+  close(): void;
+}
@@ -0,0 +1,294 @@
+// Client for web applications, talking to a reverb node over WebSocket. The
+// signing and the protocol encoding happen in the WebAssembly core built from
+// `rvb_js`; this wrapper owns the socket and routes the node's replies.
+
+import init, { ClientCore, contractId, matchesFilter } from "./pkg/rvb_js.js";
+
+export { contractId };
+
+const DEFAULT_TIMEOUT = 30000;
+
+function locationKey(location) {
+  return JSON.stringify([
+    location.namespace,
+    location.contract_space,
+    location.contract,
+    location.key,
+  ]);
+}
+
+export class ReverbClient {
+  /**
+   * Connects to the node at `url`, signing with the armored private key
+   * `key`, or with a new key if there is none.
+   */
+  static async connect(url, { key = null, timeout = DEFAULT_TIMEOUT } = {}) {
+    await init();
+    const core = new ClientCore(key);
+    const socket = new WebSocket(url);
+    socket.binaryType = "arraybuffer";
+    await new Promise((resolve, reject) => {
+      socket.addEventListener("open", resolve, { once: true });
+      socket.addEventListener("error", reject, { once: true });
+    });
+
+    const client = new ReverbClient(socket, core, timeout);
+    socket.send(core.hello());
+    // The node takes inserts and reads once it welcomed the client
+    await new Promise((resolve, reject) => {
+      const timer = setTimeout(() => {
+        socket.close();
+        reject(new Error("handshake timed out"));
+      }, timeout);
+      client.welcomed.then(() => {
+        clearTimeout(timer);
+        resolve();
+      });
+    });
+    return client;
+  }
+
+  constructor(socket, core, timeout) {
+    this.socket = socket;
+    this.core = core;
+    this.timeout = timeout;
+    // Replies carry no request id: a result goes to the oldest pending get of
+    // the same location.
+    this.gets = new Map();
+    this.subscriptions = new Set();
+    this.welcomed = new Promise((resolve) => {
+      this.welcome = resolve;
+    });
+
+    socket.addEventListener("message", (event) =>
+      this.receive(new Uint8Array(event.data)),
+    );
+    socket.addEventListener("close", () => {
+      for (const waiters of this.gets.values()) {
+        waiters.forEach(({ reject }) => reject(new Error("connection closed")));
+      }
+      this.gets.clear();
+    });
+  }
+
+  /** Public key of the client, in base64. */
+  get identity() {
+    return this.core.identity;
+  }
+
+  /** The client's private key, armored, to reconnect with the same identity. */
+  armorPrivate() {
+    return this.core.armorPrivate();
+  }
+
+  receive(raw) {
+    let messages;
+    try {
+      messages = JSON.parse(this.core.decode(raw));
+    } catch (e) {
+      console.debug("Dropping message from node", e);
+      return;
+    }
+
+    for (const message of messages) {
+      switch (message.type) {
+        case "get_result": {
+          const waiter = this.gets.get(locationKey(message.location))?.shift();
+          waiter?.resolve(message.value);
+          break;
+        }
+        case "event":
+          for (const subscription of this.subscriptions) {
+            if (
+              subscription.namespace === message.namespace &&
+              (subscription.topic == null || subscription.topic === message.topic) &&
+              (subscription.filter == null ||
+                matchesFilter(subscription.filter, JSON.stringify(message.payload)))
+            ) {
+              subscription.callback(message);
+            }
+          }
+          break;
+        case "who_are_you":
+          this.socket.send(this.core.itsMe(message.data));
+          break;
+        case "welcome":
+          this.welcome();
+          break;
+      }
+    }
+  }
+
+  /**
+   * Inserts `value` at `location`. The node applies it asynchronously, at
+   * `state` or later.
+   */
+  insert(location, value, { metadata = {}, state = 1 } = {}) {
+    this.socket.send(
+      this.core.insert(
+        JSON.stringify(location),
+        JSON.stringify(value),
+        JSON.stringify(metadata),
+        BigInt(state),
+      ),
+    );
+  }
+
+  /**
+   * Reads the value at `location`, narrowed to the `select`ed field paths, or
+   * all of it if `select` is empty. Resolves to `null` if there is no value.
+   */
+  get(location, select = []) {
+    const key = locationKey(location);
+    const request = this.core.get(JSON.stringify(location), JSON.stringify(select));
+
+    return new Promise((resolve, reject) => {
+      const waiter = { resolve, reject };
+      if (!this.gets.has(key)) {
+        this.gets.set(key, []);
+      }
+      this.gets.get(key).push(waiter);
+
+      const timer = setTimeout(() => {
+        const waiters = this.gets.get(key) ?? [];
+        const index = waiters.indexOf(waiter);
+        if (index >= 0) {
+          waiters.splice(index, 1);
+          reject(new Error("the node did not reply"));
+        }
+      }, this.timeout);
+      waiter.resolve = (value) => {
+        clearTimeout(timer);
+        resolve(value);
+      };
+
+      this.socket.send(request);
+    });
+  }
+
+  /**
+   * Deploys a contract and returns its id. Ids are the hash of the bytecode
+   * and the client's key, so they are known without waiting for the node.
+   */
+  deployContract(payload, namespace, { params = {}, tags = [] } = {}) {
+    this.socket.send(
+      this.core.deployContract(payload, namespace, JSON.stringify(params), tags),
+    );
+    return contractId(payload, this.identity);
+  }
+
+  /**
+   * Calls `callback` with the events emitted in `namespace`, on `topic` or on
+   * all topics. With `filter`, an expression such as `status == "open"`, the
+   * node only sends the events whose payload matches it. Returns a function
+   * cancelling the subscription.
+   */
+  subscribe(namespace, topic, callback, { filter } = {}) {
+    const request = this.core.subscribe(
+      namespace,
+      topic ?? undefined,
+      filter ?? undefined,
+    );
+    const subscription = { namespace, topic, filter, callback };
+    this.subscriptions.add(subscription);
+    this.socket.send(request);
+
+    return () => {
+      this.subscriptions.delete(subscription);
+      this.socket.send(this.core.unsubscribe(namespace, topic ?? undefined));
+    };
+  }
+
+  close() {
+    this.socket.close();
+    this.core.free();
+  }
+}
+
+const QUEUE_PREFIX = "reverb-offline";
+
+function toBase64(bytes) {
+  let text = "";
+  for (const byte of bytes) {
+    text += String.fromCharCode(byte);
+  }
+  return btoa(text);
+}
+
+function fromBase64(text) {
+  return Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+}
+
+/**
+ * Inserts made while no node is reachable, kept in `localStorage` so they
+ * outlive the page until `replay` sends them. They are signed as they are
+ * queued, by the key the queue was opened with, and never expire; nodes
+ * apply a write replayed twice once.
+ */
+export class OfflineQueue {
+  /**
+   * Queue signing with the armored private key `key`, keeping its writes in
+   * `storage` under keys starting with `prefix`.
+   */
+  static async open(key, { storage = localStorage, prefix = QUEUE_PREFIX } = {}) {
+    await init();
+    return new OfflineQueue(new ClientCore(key), storage, prefix);
+  }
+
+  constructor(core, storage, prefix) {
+    this.core = core;
+    this.storage = storage;
+    this.prefix = prefix;
+  }
+
+  /** Storage keys of the queued writes, oldest first. */
+  keys() {
+    const sequences = [];
+    for (let i = 0; i < this.storage.length; i++) {
+      const key = this.storage.key(i);
+      if (key.startsWith(`${this.prefix}:`)) {
+        sequences.push(Number(key.slice(this.prefix.length + 1)));
+      }
+    }
+    return sequences.sort((a, b) => a - b).map((s) => `${this.prefix}:${s}`);
+  }
+
+  /** Signs and queues an insert, taking the options of `ReverbClient.insert`. */
+  insert(location, value, { metadata = {}, state = 1 } = {}) {
+    const batch = this.core.insertOffline(
+      JSON.stringify(location),
+      JSON.stringify(value),
+      JSON.stringify(metadata),
+      BigInt(state),
+    );
+    const keys = this.keys();
+    const last = keys.length ? Number(keys[keys.length - 1].split(":").pop()) : -1;
+    this.storage.setItem(`${this.prefix}:${last + 1}`, toBase64(batch));
+  }
+
+  /** Number of writes waiting to be sent. */
+  get length() {
+    return this.keys().length;
+  }
+
+  /**
+   * Sends the queued writes through `client`, oldest first, forgetting each
+   * once sent. Returns how many were sent.
+   */
+  replay(client) {
+    let sent = 0;
+    for (const key of this.keys()) {
+      if (client.socket.readyState !== WebSocket.OPEN) {
+        break;
+      }
+      client.socket.send(fromBase64(this.storage.getItem(key)));
+      this.storage.removeItem(key);
+      sent++;
+    }
+    return sent;
+  }
+
+  close() {
+    this.core.free();
+  }
+}
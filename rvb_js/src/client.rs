@@ -0,0 +1,294 @@
+use rvb_common::crypto::{self, CryptoError, KeyPair, b64_decode, b64_encode};
+use rvb_common::protocol::{Location, Message, ProtocolError, TransportMessage, challenge_payload};
+use rvb_common::schema::{DbValue, Filter, FilterError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoreError {
+    #[error("{0} is not valid JSON: {1}")]
+    InvalidJson(&'static str, serde_json::Error),
+    #[error("{0} is not valid base64")]
+    InvalidBase64(&'static str),
+    #[error("Invalid key: {0}")]
+    InvalidKey(CryptoError),
+    #[error("Invalid message from the node: {0}")]
+    ProtocolError(ProtocolError),
+    #[error("{0}")]
+    InvalidFilter(FilterError),
+}
+
+/// A location as JavaScript sees it, with the contract id in base64.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonLocation {
+    pub namespace: String,
+    pub contract_space: String,
+    pub contract: String,
+    pub key: String,
+}
+
+impl JsonLocation {
+    fn parse(text: &str) -> Result<Location, CoreError> {
+        let location: JsonLocation =
+            serde_json::from_str(text).map_err(|e| CoreError::InvalidJson("location", e))?;
+        Ok(Location {
+            contract: b64_decode(&location.contract)
+                .map_err(|_| CoreError::InvalidBase64("location.contract"))?,
+            namespace: location.namespace,
+            contract_space: location.contract_space,
+            key: location.key,
+        })
+    }
+}
+
+impl From<Location> for JsonLocation {
+    fn from(location: Location) -> Self {
+        Self {
+            namespace: location.namespace,
+            contract_space: location.contract_space,
+            contract: b64_encode(&location.contract),
+            key: location.key,
+        }
+    }
+}
+
+/// Message from the node a JavaScript client acts on, decoded by
+/// [`Core::decode`]. Values and payloads are plain JSON.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeMessage {
+    GetResult {
+        location: JsonLocation,
+        value: Option<Value>,
+    },
+    Event {
+        namespace: String,
+        contract_space: String,
+        topic: String,
+        payload: Value,
+    },
+    /// Challenge to answer with [`Core::its_me`].
+    WhoAreYou { data: String },
+    /// The node took the answer to its challenge, and now takes inserts and
+    /// reads.
+    Welcome,
+}
+
+fn parse_value(field: &'static str, text: &str) -> Result<DbValue, CoreError> {
+    serde_json::from_str::<Value>(text)
+        .map(DbValue::from)
+        .map_err(|e| CoreError::InvalidJson(field, e))
+}
+
+fn parse_map(field: &'static str, text: &str) -> Result<HashMap<String, DbValue>, CoreError> {
+    serde_json::from_str::<HashMap<String, Value>>(text)
+        .map(|map| {
+            map.into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect()
+        })
+        .map_err(|e| CoreError::InvalidJson(field, e))
+}
+
+/// Id a contract gets when deployed with `contract_payload` by the key
+/// `deployer`, both ids and keys in base64.
+pub fn contract_id(contract_payload: &[u8], deployer: &str) -> Result<String, CoreError> {
+    let deployer = b64_decode(deployer).map_err(|_| CoreError::InvalidBase64("deployer"))?;
+    Ok(b64_encode(&crypto::contract_id(
+        contract_payload,
+        &deployer,
+    )))
+}
+
+/// Whether the JSON `payload` of an event matches the filter expression
+/// `filter`.
+pub fn matches_filter(filter: &str, payload: &str) -> Result<bool, CoreError> {
+    let filter = Filter::parse(filter).map_err(CoreError::InvalidFilter)?;
+    Ok(filter.matches(&parse_value("payload", payload)?))
+}
+
+/// The client side of the protocol without the connection: builds signed
+/// requests and decodes what the node sends back, leaving the transport to
+/// the caller. Requests and replies are the same as those of the Rust client,
+/// with values passed around as JSON text.
+pub struct Core {
+    keypair: KeyPair,
+    identity: Vec<u8>,
+}
+
+impl Core {
+    #[must_use]
+    pub fn new(keypair: KeyPair) -> Self {
+        Self {
+            identity: keypair.export_public(),
+            keypair,
+        }
+    }
+
+    /// Client with the armored private key `armored`.
+    pub fn import(armored: &str) -> Result<Self, CoreError> {
+        KeyPair::import_armored(armored)
+            .map(Self::new)
+            .map_err(CoreError::InvalidKey)
+    }
+
+    #[must_use]
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// The private key, armored, for the application to keep.
+    #[must_use]
+    pub fn armor_private(&self) -> String {
+        self.keypair.armor_private()
+    }
+
+    fn encode(&mut self, messages: &[Message]) -> Vec<u8> {
+        let publisher = b64_encode(&self.identity);
+        let msg = TransportMessage::sign(messages, &mut self.keypair, publisher);
+        rmp_serde::to_vec(&msg).expect("Failed to encode message")
+    }
+
+    fn parse_insert(
+        location: &str,
+        data: &str,
+        metadata: &str,
+        state: u64,
+    ) -> Result<Message, CoreError> {
+        Ok(Message::Insert {
+            location: JsonLocation::parse(location)?,
+            incoming_data: parse_value("data", data)?,
+            metadata: parse_map("metadata", metadata)?,
+            state,
+        })
+    }
+
+    /// `Hello` introducing the client, to send first.
+    pub fn hello(&mut self) -> Vec<u8> {
+        let public_key = self.identity.clone();
+        self.encode(&[Message::Hello {
+            public_key,
+            session_key: Vec::new(),
+            certificates: Vec::new(),
+        }])
+    }
+
+    /// Answer to a `who_are_you` challenge carrying `data`.
+    pub fn its_me(&mut self, data: &str) -> Result<Vec<u8>, CoreError> {
+        let data = b64_decode(data).map_err(|_| CoreError::InvalidBase64("data"))?;
+        let signature = self.keypair.sign(&challenge_payload(&data));
+        Ok(self.encode(&[Message::ItsMe { signature, data }]))
+    }
+
+    pub fn insert(
+        &mut self,
+        location: &str,
+        data: &str,
+        metadata: &str,
+        state: u64,
+    ) -> Result<Vec<u8>, CoreError> {
+        let message = Self::parse_insert(location, data, metadata, state)?;
+        Ok(self.encode(&[message]))
+    }
+
+    /// Like [`Core::insert`], signed to be queued while offline and sent
+    /// later: the batch never expires, and nodes take it once however often
+    /// it is sent.
+    pub fn insert_offline(
+        &mut self,
+        location: &str,
+        data: &str,
+        metadata: &str,
+        state: u64,
+    ) -> Result<Vec<u8>, CoreError> {
+        let message = Self::parse_insert(location, data, metadata, state)?;
+        let publisher = b64_encode(&self.identity);
+        let msg = TransportMessage::sign_expiring(&[message], &mut self.keypair, publisher, 0);
+        Ok(rmp_serde::to_vec(&msg).expect("Failed to encode message"))
+    }
+
+    /// `Get` of `location`, narrowed to `select`, a JSON array of field paths.
+    pub fn get(&mut self, location: &str, select: &str) -> Result<Vec<u8>, CoreError> {
+        let message = Message::Get {
+            location: JsonLocation::parse(location)?,
+            select: serde_json::from_str(select)
+                .map_err(|e| CoreError::InvalidJson("select", e))?,
+        };
+        Ok(self.encode(&[message]))
+    }
+
+    /// Deployment of a contract, whose id is [`contract_id`] of the payload and
+    /// the client's key.
+    pub fn deploy_contract(
+        &mut self,
+        contract_payload: Vec<u8>,
+        namespace: String,
+        params: &str,
+        tags: Vec<String>,
+    ) -> Result<Vec<u8>, CoreError> {
+        let id = crypto::contract_id(&contract_payload, &self.identity).to_vec();
+        let message = Message::DeployContract {
+            contract_payload,
+            namespace,
+            params: parse_map("params", params)?,
+            tags,
+            id: Some(id),
+        };
+        Ok(self.encode(&[message]))
+    }
+
+    /// Asks for the events in `namespace`, on `topic` or all topics, with a
+    /// payload matching the expression `filter` if one is given.
+    pub fn subscribe(
+        &mut self,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<&str>,
+    ) -> Result<Vec<u8>, CoreError> {
+        let filter = filter
+            .map(Filter::parse)
+            .transpose()
+            .map_err(CoreError::InvalidFilter)?;
+        Ok(self.encode(&[Message::Subscribe {
+            namespace,
+            topic,
+            filter,
+        }]))
+    }
+
+    pub fn unsubscribe(&mut self, namespace: String, topic: Option<String>) -> Vec<u8> {
+        self.encode(&[Message::Unsubscribe { namespace, topic }])
+    }
+
+    /// Checks the signature of a message from the node and returns what the
+    /// client acts on in it. Other messages are left out.
+    pub fn decode(&self, raw: &[u8]) -> Result<Vec<NodeMessage>, CoreError> {
+        let msg: TransportMessage = rmp_serde::from_slice(raw)
+            .map_err(|e| CoreError::ProtocolError(ProtocolError::Schema(e)))?;
+        let messages: Vec<Message> = msg.try_into().map_err(CoreError::ProtocolError)?;
+
+        Ok(messages
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::GetResult {
+                    location, value, ..
+                } => Some(NodeMessage::GetResult {
+                    location: location.into(),
+                    value: value.map(Into::into),
+                }),
+                Message::Event { event } => Some(NodeMessage::Event {
+                    namespace: event.namespace,
+                    contract_space: event.contract_space,
+                    topic: event.topic,
+                    payload: event.payload.into(),
+                }),
+                Message::WhoAreYou { data, .. } => Some(NodeMessage::WhoAreYou {
+                    data: b64_encode(&data),
+                }),
+                Message::Welcome { .. } => Some(NodeMessage::Welcome),
+                _ => None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,136 @@
+//! Client core for web applications, compiled to WebAssembly with
+//! wasm-bindgen. It signs requests and checks replies exactly like the Rust
+//! client; the wrapper in `js/` carries the messages over a WebSocket to a
+//! node accepting peers on one (`websocket` in `rvbd.toml`).
+//!
+//! Values cross the boundary as JSON text and contract ids as base64, so the
+//! wrapper only has to parse and stringify.
+
+use wasm_bindgen::prelude::*;
+
+pub mod client;
+
+use crate::client::{Core, CoreError};
+use rvb_common::crypto::{KeyPair, b64_encode};
+
+#[cfg(test)]
+mod tests;
+
+fn js_error(e: CoreError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+#[wasm_bindgen]
+pub struct ClientCore {
+    core: Core,
+}
+
+#[wasm_bindgen]
+impl ClientCore {
+    /// Client signing with the armored private key `key`, or a new key.
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: Option<String>) -> Result<ClientCore, JsError> {
+        let core = match key {
+            Some(key) => Core::import(&key).map_err(js_error)?,
+            None => Core::new(KeyPair::generate()),
+        };
+        Ok(Self { core })
+    }
+
+    /// Public key of the client, in base64.
+    #[wasm_bindgen(getter)]
+    pub fn identity(&self) -> String {
+        b64_encode(self.core.identity())
+    }
+
+    #[wasm_bindgen(js_name = armorPrivate)]
+    pub fn armor_private(&self) -> String {
+        self.core.armor_private()
+    }
+
+    pub fn hello(&mut self) -> Vec<u8> {
+        self.core.hello()
+    }
+
+    #[wasm_bindgen(js_name = itsMe)]
+    pub fn its_me(&mut self, data: &str) -> Result<Vec<u8>, JsError> {
+        self.core.its_me(data).map_err(js_error)
+    }
+
+    pub fn insert(
+        &mut self,
+        location: &str,
+        data: &str,
+        metadata: &str,
+        state: u64,
+    ) -> Result<Vec<u8>, JsError> {
+        self.core
+            .insert(location, data, metadata, state)
+            .map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = insertOffline)]
+    pub fn insert_offline(
+        &mut self,
+        location: &str,
+        data: &str,
+        metadata: &str,
+        state: u64,
+    ) -> Result<Vec<u8>, JsError> {
+        self.core
+            .insert_offline(location, data, metadata, state)
+            .map_err(js_error)
+    }
+
+    pub fn get(&mut self, location: &str, select: &str) -> Result<Vec<u8>, JsError> {
+        self.core.get(location, select).map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = deployContract)]
+    pub fn deploy_contract(
+        &mut self,
+        contract_payload: Vec<u8>,
+        namespace: String,
+        params: &str,
+        tags: Vec<String>,
+    ) -> Result<Vec<u8>, JsError> {
+        self.core
+            .deploy_contract(contract_payload, namespace, params, tags)
+            .map_err(js_error)
+    }
+
+    pub fn subscribe(
+        &mut self,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<String>,
+    ) -> Result<Vec<u8>, JsError> {
+        self.core
+            .subscribe(namespace, topic, filter.as_deref())
+            .map_err(js_error)
+    }
+
+    pub fn unsubscribe(&mut self, namespace: String, topic: Option<String>) -> Vec<u8> {
+        self.core.unsubscribe(namespace, topic)
+    }
+
+    /// Messages the client acts on in `raw`, as a JSON array.
+    pub fn decode(&self, raw: &[u8]) -> Result<String, JsError> {
+        let messages = self.core.decode(raw).map_err(js_error)?;
+        Ok(serde_json::to_string(&messages).expect("Failed to encode messages"))
+    }
+}
+
+/// Id a contract gets when deployed with `contract_payload` by the key
+/// `deployer`, both in base64.
+#[wasm_bindgen(js_name = contractId)]
+pub fn contract_id(contract_payload: &[u8], deployer: &str) -> Result<String, JsError> {
+    client::contract_id(contract_payload, deployer).map_err(js_error)
+}
+
+/// Whether the JSON `payload` of an event matches the filter expression
+/// `filter`.
+#[wasm_bindgen(js_name = matchesFilter)]
+pub fn matches_filter(filter: &str, payload: &str) -> Result<bool, JsError> {
+    client::matches_filter(filter, payload).map_err(js_error)
+}
@@ -0,0 +1,250 @@
+use crate::client::*;
+use rvb_common::crypto::{self, KeyPair, PublicKey, b64_decode, b64_encode};
+use rvb_common::protocol::{ContractEvent, Location, Message, TransportMessage, challenge_payload};
+use rvb_common::schema::DbValue;
+use serde_json::json;
+
+const LOCATION: &str =
+    r#"{"namespace":"ns","contract_space":"space","contract":"AQID","key":"key"}"#;
+
+fn location() -> Location {
+    Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: vec![1, 2, 3],
+        key: "key".into(),
+    }
+}
+
+/// Messages in `raw`, checking they were signed by `core`.
+fn messages(core: &Core, raw: &[u8]) -> Vec<Message> {
+    let msg: TransportMessage = rmp_serde::from_slice(raw).unwrap();
+    assert_eq!(msg.signature.signed_by, core.identity());
+    msg.try_into().unwrap()
+}
+
+fn from_node(node: &mut KeyPair, messages: &[Message]) -> Vec<u8> {
+    rmp_serde::to_vec(&TransportMessage::sign(messages, node, "node".into())).unwrap()
+}
+
+#[test]
+fn test_hello() {
+    let mut core = Core::new(KeyPair::generate());
+    let raw = core.hello();
+    match messages(&core, &raw).as_slice() {
+        [Message::Hello { public_key, .. }] => assert_eq!(public_key, core.identity()),
+        other => panic!("unexpected messages {other:?}"),
+    }
+}
+
+#[test]
+fn test_import() {
+    let keypair = KeyPair::generate();
+    let core = Core::import(&keypair.armor_private()).unwrap();
+    assert_eq!(core.identity(), keypair.export_public());
+    assert_eq!(
+        Core::import(&core.armor_private()).unwrap().identity(),
+        core.identity()
+    );
+    assert!(matches!(
+        Core::import("oops"),
+        Err(CoreError::InvalidKey(_))
+    ));
+}
+
+#[test]
+fn test_insert() {
+    let mut core = Core::new(KeyPair::generate());
+    let raw = core
+        .insert(LOCATION, r#"{"stars":3}"#, r#"{"note":"hi"}"#, 2)
+        .unwrap();
+
+    match messages(&core, &raw).as_slice() {
+        [
+            Message::Insert {
+                location: inserted,
+                incoming_data,
+                metadata,
+                state,
+            },
+        ] => {
+            assert_eq!(*inserted, location());
+            assert_eq!(*incoming_data, DbValue::from(json!({"stars": 3})));
+            assert_eq!(metadata["note"], DbValue::from(json!("hi")));
+            assert_eq!(*state, 2);
+        }
+        other => panic!("unexpected messages {other:?}"),
+    }
+}
+
+#[test]
+fn test_insert_offline() {
+    let mut core = Core::new(KeyPair::generate());
+    let raw = core.insert_offline(LOCATION, "1", "{}", 3).unwrap();
+    let msg: TransportMessage = rmp_serde::from_slice(&raw).unwrap();
+    assert_eq!(msg.expires_at, 0);
+    assert!(matches!(
+        messages(&core, &raw).as_slice(),
+        [Message::Insert { state: 3, .. }]
+    ));
+}
+
+#[test]
+fn test_invalid_requests() {
+    let mut core = Core::new(KeyPair::generate());
+    assert!(matches!(
+        core.insert("{", "1", "{}", 1),
+        Err(CoreError::InvalidJson("location", _))
+    ));
+    assert!(matches!(
+        core.insert(LOCATION, "{oops", "{}", 1),
+        Err(CoreError::InvalidJson("data", _))
+    ));
+    assert!(matches!(
+        core.get(&LOCATION.replace("AQID", "!"), "[]"),
+        Err(CoreError::InvalidBase64("location.contract"))
+    ));
+    assert!(matches!(
+        core.deploy_contract(Vec::new(), "ns".into(), "[]", Vec::new()),
+        Err(CoreError::InvalidJson("params", _))
+    ));
+}
+
+#[test]
+fn test_its_me() {
+    let mut core = Core::new(KeyPair::generate());
+    let raw = core.its_me(&b64_encode(b"challenge")).unwrap();
+
+    match messages(&core, &raw).as_slice() {
+        [Message::ItsMe { signature, data }] => {
+            assert_eq!(data, b"challenge");
+            assert!(
+                PublicKey::import(core.identity())
+                    .unwrap()
+                    .verify(&challenge_payload(data), signature)
+            );
+        }
+        other => panic!("unexpected messages {other:?}"),
+    }
+}
+
+#[test]
+fn test_decode() {
+    let core = Core::new(KeyPair::generate());
+    let mut node = KeyPair::generate();
+    let node_key = node.export_public();
+    let raw = from_node(
+        &mut node,
+        &[
+            Message::GetResult {
+                location: location(),
+                value: Some(DbValue::from(json!({"stars": 3}))),
+                proof: None,
+            },
+            Message::Event {
+                event: ContractEvent {
+                    namespace: "ns".into(),
+                    contract_space: "space".into(),
+                    topic: "starred".into(),
+                    payload: DbValue::from(json!([1, 2])),
+                },
+            },
+            Message::WhoAreYou {
+                data: b"challenge".to_vec(),
+                public_key: node_key,
+                session_key: Vec::new(),
+                certificates: Vec::new(),
+            },
+            Message::Welcome {
+                dht_ip: String::new(),
+                dht_port: 0,
+                signature: Vec::new(),
+            },
+            Message::Unsubscribe {
+                namespace: "ns".into(),
+                topic: None,
+            },
+        ],
+    );
+
+    let decoded = core.decode(&raw).unwrap();
+    assert_eq!(
+        serde_json::to_value(&decoded).unwrap(),
+        json!([
+            {
+                "type": "get_result",
+                "location": serde_json::from_str::<serde_json::Value>(LOCATION).unwrap(),
+                "value": {"stars": 3},
+            },
+            {
+                "type": "event",
+                "namespace": "ns",
+                "contract_space": "space",
+                "topic": "starred",
+                "payload": [1, 2],
+            },
+            {"type": "who_are_you", "data": b64_encode(b"challenge")},
+            {"type": "welcome"},
+        ])
+    );
+}
+
+#[test]
+fn test_decode_rejects_forged_messages() {
+    let core = Core::new(KeyPair::generate());
+    let mut node = KeyPair::generate();
+    let mut msg = TransportMessage::sign(
+        &[Message::GetResult {
+            location: location(),
+            value: None,
+            proof: None,
+        }],
+        &mut node,
+        "node".into(),
+    );
+    msg.signature.signed_by = KeyPair::generate().export_public();
+
+    assert!(matches!(
+        core.decode(&rmp_serde::to_vec(&msg).unwrap()),
+        Err(CoreError::ProtocolError(_))
+    ));
+    assert!(core.decode(b"garbage").is_err());
+}
+
+#[test]
+fn test_contract_id() {
+    assert_eq!(
+        b64_decode(&contract_id(b"contract", "AQID").unwrap()).unwrap(),
+        crypto::contract_id(b"contract", &[1, 2, 3])
+    );
+    assert!(matches!(
+        contract_id(b"contract", "!"),
+        Err(CoreError::InvalidBase64("deployer"))
+    ));
+}
+
+#[test]
+fn test_filtered_subscription() {
+    let mut core = Core::new(KeyPair::generate());
+    let raw = core
+        .subscribe("ns".into(), None, Some(r#"status == "open""#))
+        .unwrap();
+    assert!(matches!(
+        &messages(&core, &raw)[..],
+        [Message::Subscribe {
+            filter: Some(_),
+            ..
+        }]
+    ));
+    assert!(matches!(
+        core.subscribe("ns".into(), None, Some("status ==")),
+        Err(CoreError::InvalidFilter(_))
+    ));
+
+    assert!(matches_filter(r#"status == "open""#, r#"{"status":"open"}"#).unwrap());
+    assert!(!matches_filter(r#"status == "open""#, r#"{"status":"done"}"#).unwrap());
+    assert!(matches!(
+        matches_filter("status", "{oops"),
+        Err(CoreError::InvalidJson("payload", _))
+    ));
+}
@@ -0,0 +1,149 @@
+//! Addresses other nodes accept connections on, learned without configuring
+//! them. A node sends each new peer a `Gossip` with the address it advertises
+//! and those it confirmed for other nodes. An advertised address with an
+//! unspecified host, as in `0.0.0.0:7700`, is completed with the address the
+//! connection from that node came in from.
+//!
+//! No address is trusted as sent: before it enters the [`AddressBook`] it is
+//! dialed and pinged, and only kept if the answer is signed by the key it was
+//! given for. Addresses confirmed this way are passed on in further gossip.
+
+use crate::encode;
+use rvb_common::protocol::{Message, TransportMessage};
+use rvb_common::transport::Client;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Addresses kept per node; the oldest is dropped for a newer one.
+pub const MAX_ADDRESSES_PER_NODE: usize = 4;
+/// Nodes the book holds addresses of. Addresses of further nodes are not
+/// probed until some are forgotten.
+pub const MAX_KNOWN_NODES: usize = 4096;
+/// Probes running at once. Addresses learned while all are busy are dropped,
+/// to be probed when they are gossiped again.
+pub const MAX_PROBES: usize = 32;
+
+/// How a node learns the addresses of others; see [`crate::addresses`].
+pub struct DiscoveryConfig {
+    /// Address the node accepts peers on, sent to each new peer. Leave the
+    /// host unspecified to have peers fill in the one they see.
+    pub advertise: Option<String>,
+    /// Opens the connections probing learned addresses.
+    pub client: Arc<dyn Client>,
+    /// How long a probed node has to answer.
+    pub probe_timeout: Duration,
+}
+
+/// Confirmed addresses of other nodes, by key.
+#[derive(Default)]
+pub struct AddressBook {
+    confirmed: Mutex<HashMap<Vec<u8>, Vec<String>>>,
+    probing: Mutex<HashSet<(Vec<u8>, String)>>,
+}
+
+impl AddressBook {
+    /// The confirmed addresses, oldest first for each node.
+    #[must_use]
+    pub fn addresses(&self) -> HashMap<Vec<u8>, Vec<String>> {
+        self.confirmed.lock().unwrap().clone()
+    }
+
+    /// Marks `addr` of `key` as being probed. Returns `false` if it is
+    /// confirmed or being probed already, or no probe can start.
+    pub(crate) fn begin_probe(&self, key: &[u8], addr: &str) -> bool {
+        {
+            let confirmed = self.confirmed.lock().unwrap();
+            match confirmed.get(key) {
+                Some(addrs) if addrs.iter().any(|known| known == addr) => return false,
+                None if confirmed.len() >= MAX_KNOWN_NODES => return false,
+                _ => {}
+            }
+        }
+        let mut probing = self.probing.lock().unwrap();
+        probing.len() < MAX_PROBES && probing.insert((key.to_vec(), addr.to_string()))
+    }
+
+    /// Ends the probe of `addr`, adding it to the addresses of `key` if the
+    /// node answered. Returns whether it was added.
+    pub(crate) fn end_probe(&self, key: &[u8], addr: &str, confirmed: bool) -> bool {
+        self.probing
+            .lock()
+            .unwrap()
+            .remove(&(key.to_vec(), addr.to_string()));
+        if !confirmed {
+            return false;
+        }
+
+        let mut book = self.confirmed.lock().unwrap();
+        if !book.contains_key(key) && book.len() >= MAX_KNOWN_NODES {
+            return false;
+        }
+        let addrs = book.entry(key.to_vec()).or_default();
+        if addrs.iter().any(|known| known == addr) {
+            return false;
+        }
+        if addrs.len() >= MAX_ADDRESSES_PER_NODE {
+            addrs.remove(0);
+        }
+        addrs.push(addr.to_string());
+        true
+    }
+}
+
+/// Address to dial for `advertised`: itself, or with an unspecified host
+/// replaced by that of `observed`. `None` if the host is unspecified and
+/// there is nothing observed to fill it in with.
+#[must_use]
+pub fn resolve(advertised: &str, observed: Option<SocketAddr>) -> Option<String> {
+    match advertised.parse::<SocketAddr>() {
+        Ok(addr) if addr.ip().is_unspecified() => {
+            let observed = observed?;
+            Some(SocketAddr::new(observed.ip(), addr.port()).to_string())
+        }
+        _ => Some(advertised.to_string()),
+    }
+}
+
+/// Dials `addr` and sends `ping`, returning whether a `Pong` with `nonce`
+/// signed by `key` came back within `timeout`. Messages over `max_size`
+/// bytes are skipped unread.
+pub(crate) async fn probe(
+    client: &dyn Client,
+    addr: &str,
+    key: &[u8],
+    ping: &TransportMessage,
+    nonce: u64,
+    max_size: usize,
+    timeout: Duration,
+) -> bool {
+    let answer = async {
+        let peer = client.connect(addr).await.ok()?;
+        peer.send(encode(ping)).await.ok()?;
+        loop {
+            let raw = peer.recv().await.ok()?;
+            if raw.len() > max_size {
+                continue;
+            }
+            let Ok(msg) = rmp_serde::from_slice::<TransportMessage>(&raw) else {
+                continue;
+            };
+            if msg.signature.signed_by != key {
+                continue;
+            }
+            let messages: Vec<Message> = msg.try_into().unwrap_or_default();
+            if messages
+                .iter()
+                .any(|message| matches!(message, Message::Pong { nonce: n } if *n == nonce))
+            {
+                return Some(());
+            }
+        }
+    };
+    tokio::time::timeout(timeout, answer)
+        .await
+        .ok()
+        .flatten()
+        .is_some()
+}
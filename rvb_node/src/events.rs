@@ -0,0 +1,107 @@
+use crate::Peer;
+use rvb_common::protocol::ContractEvent;
+use rvb_common::schema::Filter;
+use std::sync::Arc;
+use tokio::sync::{RwLock, broadcast};
+
+struct Subscription {
+    peer: Arc<Peer>,
+    namespace: String,
+    topic: Option<String>,
+    /// Only events with a payload matching it are forwarded.
+    filter: Option<Filter>,
+}
+
+impl Subscription {
+    fn matches(&self, event: &ContractEvent) -> bool {
+        self.namespace == event.namespace
+            && self
+                .topic
+                .as_ref()
+                .is_none_or(|topic| *topic == event.topic)
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(&event.payload))
+    }
+}
+
+/// Fans contract events out to local watchers and to peers that subscribed to
+/// them.
+pub struct EventRouter {
+    local: broadcast::Sender<ContractEvent>,
+    remote: RwLock<Vec<Subscription>>,
+}
+
+impl EventRouter {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            local: broadcast::channel(capacity).0,
+            remote: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Stream of every event seen by this node. Lagging watchers lose the
+    /// oldest events rather than blocking contract execution.
+    #[must_use]
+    pub fn watch(&self) -> broadcast::Receiver<ContractEvent> {
+        self.local.subscribe()
+    }
+
+    pub fn deliver_local(&self, event: ContractEvent) {
+        // No watchers is not an error, the event is simply dropped.
+        let _ = self.local.send(event);
+    }
+
+    /// Forwards `peer` the events in `namespace` on `topic` matching
+    /// `filter`. A peer subscribing again with another filter also gets the
+    /// events matching that one.
+    pub async fn subscribe(
+        &self,
+        peer: Arc<Peer>,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<Filter>,
+    ) {
+        let mut remote = self.remote.write().await;
+
+        let exists = remote.iter().any(|sub| {
+            Arc::ptr_eq(&sub.peer, &peer)
+                && sub.namespace == namespace
+                && sub.topic == topic
+                && sub.filter == filter
+        });
+        if !exists {
+            remote.push(Subscription {
+                peer,
+                namespace,
+                topic,
+                filter,
+            });
+        }
+    }
+
+    /// Drops the subscriptions of `peer` to `namespace` and `topic`, whatever
+    /// their filter.
+    pub async fn unsubscribe(&self, peer: &Arc<Peer>, namespace: &str, topic: Option<&str>) {
+        self.remote.write().await.retain(|sub| {
+            !(Arc::ptr_eq(&sub.peer, peer)
+                && sub.namespace == namespace
+                && sub.topic.as_deref() == topic)
+        });
+    }
+
+    /// Peers that should receive `event`, each listed once.
+    pub async fn subscribers(&self, event: &ContractEvent) -> Vec<Arc<Peer>> {
+        let mut peers: Vec<Arc<Peer>> = Vec::new();
+
+        for sub in self.remote.read().await.iter() {
+            if sub.matches(event) && !peers.iter().any(|p| Arc::ptr_eq(p, &sub.peer)) {
+                peers.push(sub.peer.clone());
+            }
+        }
+
+        peers
+    }
+}
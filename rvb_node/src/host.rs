@@ -0,0 +1,149 @@
+//! Several nodes in one process, each with its own identity, storage and
+//! server, linked to each other in memory rather than over the network. Useful
+//! for gateways fronting several tenants, and for trying out topologies
+//! without a node process per identity.
+
+use crate::{Node, NodeError};
+use rvb_common::protocol::Message;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long [`Host::link`] waits for two nodes to introduce themselves.
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+struct Hosted {
+    node: Arc<Node>,
+    /// Processing and accepting tasks of the node.
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// Nodes run on the current tokio runtime, keyed by identity.
+#[derive(Default)]
+pub struct Host {
+    nodes: Mutex<HashMap<Vec<u8>, Hosted>>,
+    /// Pairs of identities linked in memory, lowest first.
+    links: Mutex<HashSet<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl Host {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `node`, processing its messages and accepting peers on its
+    /// server. Must be called within a tokio runtime.
+    pub fn add(&self, node: Node) -> Result<Arc<Node>, NodeError> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if nodes.contains_key(node.identity()) {
+            return Err(NodeError::AlreadyHosted);
+        }
+
+        let node = Arc::new(node);
+        let tasks = vec![
+            tokio::spawn({
+                let node = node.clone();
+                async move { node.process().await }
+            }),
+            tokio::spawn({
+                let node = node.clone();
+                async move { node.receive_peers().await }
+            }),
+        ];
+        nodes.insert(
+            node.identity.clone(),
+            Hosted {
+                node: node.clone(),
+                tasks,
+            },
+        );
+        Ok(node)
+    }
+
+    /// Stops the node with `identity` and forgets its links. Its peers see
+    /// the connection close.
+    pub fn remove(&self, identity: &[u8]) -> Option<Arc<Node>> {
+        let hosted = self.nodes.lock().unwrap().remove(identity)?;
+        for task in &hosted.tasks {
+            task.abort();
+        }
+        self.links
+            .lock()
+            .unwrap()
+            .retain(|(a, b)| a != identity && b != identity);
+        Some(hosted.node)
+    }
+
+    #[must_use]
+    pub fn node(&self, identity: &[u8]) -> Option<Arc<Node>> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(identity)
+            .map(|hosted| hosted.node.clone())
+    }
+
+    #[must_use]
+    pub fn identities(&self) -> Vec<Vec<u8>> {
+        self.nodes.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Connects the nodes with identities `a` and `b` in memory, if they are
+    /// not already, and waits until each has welcomed the other. A link that
+    /// fails is forgotten, so the next call tries again.
+    pub async fn link(&self, a: &[u8], b: &[u8]) -> Result<(), NodeError> {
+        let (node_a, node_b) = (self.hosted(a)?, self.hosted(b)?);
+        let pair = if a < b {
+            (a.to_vec(), b.to_vec())
+        } else {
+            (b.to_vec(), a.to_vec())
+        };
+        if !self.links.lock().unwrap().insert(pair.clone()) {
+            return Ok(());
+        }
+
+        let (a_side, b_side) = rvb_transport::memory::pair();
+        node_a.connect_peer(Box::new(a_side)).await;
+        node_b.connect_peer(Box::new(b_side)).await;
+
+        let deadline = Instant::now() + LINK_TIMEOUT;
+        while !(node_a.has_welcomed(b).await && node_b.has_welcomed(a).await) {
+            if Instant::now() >= deadline {
+                self.links.lock().unwrap().remove(&pair);
+                return Err(NodeError::UnreachablePeer);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Sends `messages`, signed by the node with identity `from`, to the node
+    /// with identity `to`, linking the two first.
+    pub async fn route(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        messages: &[Message],
+    ) -> Result<(), NodeError> {
+        self.link(from, to).await?;
+        self.hosted(from)?.send_to(to, messages).await
+    }
+
+    fn hosted(&self, identity: &[u8]) -> Result<Arc<Node>, NodeError> {
+        self.node(identity).ok_or(NodeError::NotHosted)
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        for hosted in self.nodes.get_mut().unwrap().values() {
+            for task in &hosted.tasks {
+                task.abort();
+            }
+        }
+    }
+}
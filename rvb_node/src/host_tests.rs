@@ -0,0 +1,83 @@
+use crate::host::Host;
+use crate::testing::{location, node, node_with, wait_until};
+use crate::{NodeConfig, NodeError};
+use rvb_common::crypto::{KeyPair, TrustStore};
+use rvb_common::protocol::Message;
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+fn temporary() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+#[tokio::test]
+async fn test_host_routes_between_identities() {
+    let host = Host::new();
+    let keypair = KeyPair::generate();
+    let (a, b) = (
+        host.add(node_with(
+            keypair.clone(),
+            NodeConfig::default(),
+            temporary(),
+        ))
+        .unwrap(),
+        host.add(node(NodeConfig::default())).unwrap(),
+    );
+    assert!(matches!(
+        host.add(node_with(keypair, NodeConfig::default(), temporary())),
+        Err(NodeError::AlreadyHosted)
+    ));
+    assert_eq!(host.identities().len(), 2);
+
+    let mut contract = Vec::new();
+    for node in [&a, &b] {
+        contract = node
+            .deploy_contract(
+                b"contract".to_vec(),
+                "ns".into(),
+                HashMap::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+    }
+    let insert = Message::Insert {
+        location: location(&contract, "key"),
+        incoming_data: DbValue::Number(1),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    host.route(a.identity(), b.identity(), &[insert])
+        .await
+        .unwrap();
+    wait_until("the routed write", || !b.entries("ns").unwrap().is_empty()).await;
+    // Each node keeps its own storage, so the sender holds nothing
+    assert!(a.entries("ns").unwrap().is_empty());
+
+    assert!(host.remove(b.identity()).is_some());
+    assert!(matches!(
+        host.route(a.identity(), b.identity(), &[]).await,
+        Err(NodeError::NotHosted)
+    ));
+}
+
+#[tokio::test]
+async fn test_failed_links_are_retried() {
+    let host = Host::new();
+    let a = host.add(node(NodeConfig::default())).unwrap();
+    // Refuses every peer, none having a certificate from its authority
+    let b = host
+        .add(node(NodeConfig {
+            trust: Some(TrustStore::authority(KeyPair::generate().export_public())),
+            ..NodeConfig::default()
+        }))
+        .unwrap();
+
+    for _ in 0..2 {
+        assert!(matches!(
+            host.link(a.identity(), b.identity()).await,
+            Err(NodeError::UnreachablePeer)
+        ));
+    }
+}
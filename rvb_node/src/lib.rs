@@ -1,257 +1,2910 @@
-use log::debug;
-use rvb_common::contract::{Contract, ContractCompiler};
-use rvb_common::crypto::b64_encode;
-use rvb_common::protocol::{Message, TransportMessage};
-use rvb_common::transport::{Server, TransportError, TransportPeer};
-use std::collections::HashMap;
+use crate::addresses::{AddressBook, DiscoveryConfig};
+use crate::events::EventRouter;
+use crate::locality::LocalityBook;
+use crate::metrics::{
+    ActionRejection, ContractUsage, Diagnostics, Limit, Rejections, UsageMetrics,
+};
+use crate::policy::{NamespacePolicy, PolicyEngine, PolicyRule};
+use crate::priority::Priority;
+use crate::seen::SeenFilter;
+use crate::storage::{
+    AuditLog, AuditRecord, ContractStore, DataStore, Entry, FuelLedger, GroupStore, Journal,
+    MessageHost, PendingWrite, SeenStats, SeenStore,
+};
+use bytes::Bytes;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use rvb_common::contract::{
+    Contract, ContractCompiler, ContractContext, ContractError, ContractMetadata, ExecutionReport,
+};
+use rvb_common::crypto::{
+    CryptoError, Identity, IdentityCertificate, KeyPair, PublicKey, SessionState, Signer,
+    ThresholdKey, ThresholdSignature, TrustStore, b64_encode, contract_id, hash, leaf_digest,
+    merkle_anchor, sealed_epoch, value_digest, verify_contract_id,
+};
+use rvb_common::delta::{self, DeltaError};
+use rvb_common::protocol::{
+    Capability, ContractEvent, ContractInfo, Locality, Location, Message, StateProof,
+    TransportMessage, admin_payload, challenge_payload,
+};
+use rvb_common::schema::filter::MAX_FILTER_DEPTH;
+use rvb_common::schema::{DataAction, DbValue, Filter, FilterError};
+use rvb_common::transport::{PeerMetadata, Server, TransportError, TransportPeer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{Mutex, RwLock};
-use tokio::task::{JoinHandle, yield_now};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::task::{JoinError, JoinHandle, yield_now};
+
+pub mod addresses;
+pub mod events;
+pub mod host;
+#[cfg(test)]
+mod host_tests;
+pub mod locality;
+#[cfg(test)]
+mod locality_tests;
+pub mod metrics;
+pub mod policy;
+#[cfg(test)]
+mod policy_tests;
+pub mod priority;
+#[cfg(test)]
+mod priority_tests;
+mod seen;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+#[cfg(test)]
+mod tests;
+
+const CHANNEL_CAPACITY: usize = 1024;
+/// Bytes of the challenge a peer signs to prove its key.
+const CHALLENGE_SIZE: usize = 32;
+/// Pings awaiting an answer kept per peer; older ones are taken as lost.
+const MAX_PENDING_PINGS: usize = 8;
+
+type SharedContract = Arc<Mutex<Box<dyn Contract>>>;
 
 #[derive(Debug)]
 pub enum NodeError {
     TransportError(TransportError),
     SchemaError(rmp_serde::decode::Error),
     ProtocolError(rvb_common::protocol::ProtocolError),
+    StorageError(sled::Error),
+    ContractError(ContractError),
+    RuntimeError(JoinError),
+    UnknownContract,
+    MissingContractParam(String),
+    /// A contract upgrade was signed by a key other than the deployer's, or a
+    /// deployment into a namespace by a key that deployed none of its
+    /// contracts.
+    NotDeployer,
+    /// A deployment named an id its bytecode and signer do not give.
+    ContractIdMismatch,
+    /// The bytecode a contract patch was made against, or the bytecode it
+    /// gave, does not have the hash the patch named.
+    PatchMismatch,
+    InvalidPatch(DeltaError),
+    /// The namespace has spent its fuel budget.
+    FuelBudgetExceeded,
+    SigningError(CryptoError),
+    /// A message could not be sealed or opened with the peer's session key.
+    SessionError(CryptoError),
+    /// The peer's certificates do not satisfy the node's trust store.
+    Untrusted(CryptoError),
+    /// The audit log record with this sequence number does not match its
+    /// hash or the record before it.
+    AuditChainBroken(u64),
+    /// A peer asked this node to relay a message, but it does not relay.
+    NotRelaying,
+    /// The sender of a relayed message has used up its relay quota.
+    RelayQuotaExceeded,
+    /// There is neither a connection to the peer nor a relay to reach it
+    /// through.
+    UnreachablePeer,
+    /// The signer may not insert into the namespace, or may not limit who
+    /// does or make it private as it deployed no contract there. Also given
+    /// for changes to a namespace with admins that were not agreed on by
+    /// enough of them, or were already applied.
+    NotAuthorized,
+    /// The admins a namespace was to be handed to are no valid threshold
+    /// key.
+    InvalidAdmins(CryptoError),
+    /// A group for the namespace was shared by another key.
+    NotGroupOwner,
+    /// An insert into a private namespace carried a value not sealed with the
+    /// group's current key.
+    Unsealed,
+    /// The node is an observer, which neither takes writes from their signer
+    /// nor sends its own.
+    ReadOnly,
+    /// A node with the same identity is already on the [`host::Host`].
+    AlreadyHosted,
+    /// No node with the identity is on the [`host::Host`].
+    NotHosted,
+    /// An insert broke a rule of its namespace's [`NamespacePolicy`].
+    PolicyViolation(PolicyRule),
+    /// A message, value or call went over one of the node's [`Limits`].
+    LimitExceeded(Limit),
+    /// A subscription filter the node will not evaluate.
+    InvalidFilter(FilterError),
+    /// A handshake message came at a stage of the peer's handshake it does
+    /// not belong to.
+    UnexpectedHandshake(PeerInitStage),
+    /// A `Hello` was not signed by the key it introduced, or an `ItsMe` did
+    /// not sign the challenge with that key.
+    ChallengeFailed,
+    /// The peer sent an insert, read, gossip or capabilities before finishing
+    /// its handshake.
+    NotAuthenticated,
     NoMessage,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How far a peer got in proving its key to this node. A peer sends `Hello`
+/// with its key, the node challenges it with `WhoAreYou`, the peer signs the
+/// challenge in `ItsMe`, and the node lets it in with `Welcome`. Until then
+/// the node refuses its inserts, reads, gossip and capabilities, and the peer
+/// is not known by its key.
+///
+/// Both sides send their part of a key exchange in their `Hello` and
+/// `WhoAreYou`, and once a side sent or received a `Welcome` it encrypts
+/// what it sends with the agreed session; see [`SessionState`]. Peers that
+/// send no session key stay unencrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PeerInitStage {
     None,
+    /// The peer sent a `Hello` signed by the key in it, and was admitted.
     Hello,
+    /// The node sent the peer its challenge.
     WhoAreYou,
+    /// The peer signed the challenge with its key.
     ItsMe,
+    /// The node welcomed the peer, which may now insert and read.
     Welcome,
 }
 
 pub struct Peer {
     transport: Box<dyn TransportPeer>,
     stage: RwLock<PeerInitStage>,
+    /// Data of the `WhoAreYou` sent to the peer, with the key its `Hello`
+    /// claimed, until it answers.
+    challenge: std::sync::Mutex<Option<(Vec<u8>, Vec<u8>)>>,
+    /// Set while this node's own `Hello` awaits the peer's challenge, which is
+    /// answered only then.
+    awaiting_challenge: AtomicBool,
     read_thread: Mutex<Option<JoinHandle<()>>>,
+    session: SessionState,
+    /// Held while a message is sealed and sent, so they reach the peer in
+    /// the order they were sealed.
+    sending: Mutex<()>,
+    identity: std::sync::Mutex<Option<Identity>>,
+    /// Public key the peer proved it holds by signing its challenge.
+    key: std::sync::Mutex<Option<Vec<u8>>>,
+    /// Set once the peer welcomed this node, which then advertises itself.
+    welcomed: AtomicBool,
+    /// Services the peer advertised.
+    capabilities: std::sync::Mutex<Vec<Capability>>,
+    /// Namespaces the peer asked to be passed inserts in with `Replicate`.
+    replicated: std::sync::Mutex<HashSet<String>>,
+    /// Nonces of the `Ping`s sent to the peer and not answered yet, with when
+    /// they were sent, oldest first.
+    pings: std::sync::Mutex<VecDeque<(u64, Instant)>>,
+    /// Smoothed round trip time to the peer, once it answered a `Ping`.
+    rtt: std::sync::Mutex<Option<Duration>>,
+    /// Messages waiting for the next batch; see [`NodeConfig::batch_window`].
+    outbox: std::sync::Mutex<Vec<Message>>,
+    /// Held while a batch is signed and sent, so batches keep their order.
+    flushing: Mutex<()>,
 }
 
 impl Peer {
-    pub async fn next(&self) -> Result<TransportMessage, NodeError> {
-        let raw = self
+    /// Next message from the peer. Messages longer than `max_size` bytes are
+    /// refused before they are decoded.
+    pub async fn next(&self, max_size: usize) -> Result<TransportMessage, NodeError> {
+        let mut raw = self
             .transport
             .recv()
             .await
             .map_err(NodeError::TransportError)?;
+        if raw.len() > max_size {
+            return Err(NodeError::LimitExceeded(Limit::MessageSize));
+        }
+        if let Some(opened) = self.session.open(&raw).map_err(NodeError::SessionError)? {
+            raw = opened.into();
+        }
         let msg: TransportMessage = rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)?;
         Ok(msg)
     }
 
     pub async fn send(&self, msg: TransportMessage) -> Result<(), NodeError> {
+        self.send_encoded(encode(&msg)).await
+    }
+
+    /// Sends a message already encoded with msgpack. Peers without a session
+    /// share `raw` instead of copying it.
+    async fn send_encoded(&self, mut raw: Bytes) -> Result<(), NodeError> {
+        let _sending = self.sending.lock().await;
+        if let Some(sealed) = self.session.seal(&raw).map_err(NodeError::SessionError)? {
+            raw = sealed.into();
+        }
         self.transport
-            .send(rmp_serde::to_vec(&msg).unwrap())
+            .send(raw)
             .await
             .map_err(NodeError::TransportError)
     }
+
+    /// Takes the peer's part of the key exchange from the `Hello` or
+    /// `WhoAreYou` among `messages`, if `batch` was signed by the key they
+    /// introduce. Done as the batch is read, so the session is agreed on
+    /// before the peer's first sealed message.
+    fn agree(&self, batch: &TransportMessage, messages: &[Message]) {
+        for message in messages {
+            let (Message::Hello {
+                public_key,
+                session_key,
+                ..
+            }
+            | Message::WhoAreYou {
+                public_key,
+                session_key,
+                ..
+            }) = message
+            else {
+                continue;
+            };
+            if *public_key == batch.signature.signed_by
+                && let Err(e) = self.session.agree(session_key)
+            {
+                debug!("Failed to agree on a session: {e:?}");
+            }
+        }
+    }
+
+    /// Encrypts all further messages, if the peer took part in the key
+    /// exchange.
+    fn secure(&self) {
+        self.session.secure();
+    }
+
+    /// The identity the peer proved with its certificates, if the node
+    /// checked any; see [`Node::admit`].
+    pub fn identity(&self) -> Option<Identity> {
+        self.identity.lock().unwrap().clone()
+    }
+
+    /// What the transport knows of the connection, such as where it came
+    /// from.
+    pub fn metadata(&self) -> PeerMetadata {
+        self.transport.metadata()
+    }
+
+    /// How far the peer got in its handshake.
+    pub async fn stage(&self) -> PeerInitStage {
+        *self.stage.read().await
+    }
+
+    /// Public key the peer proved it holds, once it did.
+    pub fn key(&self) -> Option<Vec<u8>> {
+        self.key.lock().unwrap().clone()
+    }
+
+    /// Records the key the peer signed its challenge with. The first one
+    /// sticks, so messages a relay passes on do not change who it is.
+    fn identify(&self, key: Vec<u8>) {
+        self.key.lock().unwrap().get_or_insert(key);
+    }
+
+    /// Round trip time to the peer, averaged over the `Ping`s it answered.
+    pub fn rtt(&self) -> Option<Duration> {
+        *self.rtt.lock().unwrap()
+    }
+
+    /// Notes a `Ping` sent with `nonce`, forgetting the oldest unanswered
+    /// ones beyond [`MAX_PENDING_PINGS`].
+    fn record_ping(&self, nonce: u64) {
+        let mut pings = self.pings.lock().unwrap();
+        if pings.len() >= MAX_PENDING_PINGS {
+            pings.pop_front();
+        }
+        pings.push_back((nonce, Instant::now()));
+    }
+
+    /// Takes the round trip of the `Ping` with `nonce` into the average, if
+    /// one is pending. Pings sent before it are taken as lost.
+    fn record_pong(&self, nonce: u64) {
+        let sample = {
+            let mut pings = self.pings.lock().unwrap();
+            let Some(index) = pings
+                .iter()
+                .position(|(sent_nonce, _)| *sent_nonce == nonce)
+            else {
+                return;
+            };
+            let (_, sent) = pings[index];
+            pings.drain(..=index);
+            sent.elapsed()
+        };
+        let mut rtt = self.rtt.lock().unwrap();
+        *rtt = Some(rtt.map_or(sample, |rtt| (rtt * 7 + sample) / 8));
+    }
+
+    fn is_relay(&self) -> bool {
+        self.capabilities
+            .lock()
+            .unwrap()
+            .contains(&Capability::Relay)
+    }
+}
+
+/// Whether `message` changes data or contracts, which observers do not
+/// originate.
+fn is_write(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::Insert { .. }
+            | Message::Delete { .. }
+            | Message::DeployContract { .. }
+            | Message::UpgradeContract { .. }
+            | Message::PatchContract { .. }
+    )
+}
+
+/// Name of the kind of `action`, as reported in [`ActionRejection`]s.
+fn action_kind(action: &DataAction) -> &'static str {
+    match action {
+        DataAction::Insert { .. } => "insert",
+        DataAction::Delete { .. } => "delete",
+        DataAction::Patch { .. } => "patch",
+        DataAction::Emit { .. } => "emit",
+        DataAction::Get { .. } => "get",
+    }
+}
+
+/// Draws `count` of `peers` at random, the odds of each inverse to its round
+/// trip time. Peers not measured yet count as the average of those that were.
+fn pick_by_latency(peers: Vec<Arc<Peer>>, count: usize) -> Vec<Arc<Peer>> {
+    if peers.len() <= count {
+        return peers;
+    }
+    let rtts: Vec<_> = peers
+        .iter()
+        .map(|peer| peer.rtt().map(|rtt| rtt.as_secs_f64()))
+        .collect();
+    let measured: Vec<f64> = rtts.iter().flatten().copied().collect();
+    let fallback = if measured.is_empty() {
+        1.0
+    } else {
+        measured.iter().sum::<f64>() / measured.len() as f64
+    };
+
+    // Weighted sampling without replacement (Efraimidis and Spirakis): with
+    // weights 1 / rtt, the peers with the largest ln(u) * rtt are drawn
+    let mut keyed: Vec<_> = peers
+        .into_iter()
+        .zip(rtts)
+        .map(|(peer, rtt)| (rand::random::<f64>().ln() * rtt.unwrap_or(fallback), peer))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed
+        .into_iter()
+        .take(count)
+        .map(|(_, peer)| peer)
+        .collect()
+}
+
+/// Encodes `msg` once so it can be sent to several peers with
+/// [`Peer::send_encoded`], which only copies it for peers with a session.
+fn encode(msg: &TransportMessage) -> Bytes {
+    rmp_serde::to_vec(msg).unwrap().into()
 }
 
 pub struct NodeConfig {
-    pub max_received_by: usize,
+    /// Message ids remembered to drop duplicates arriving over other paths.
+    /// Ids are kept for at least this many further messages.
+    pub seen_messages: usize,
+    /// How long message ids are also kept on disk, so duplicates and replays
+    /// are still dropped after a restart. `None` keeps them in memory only.
+    pub seen_retention: Option<Duration>,
+    /// Whether to keep fuel totals per deployer key and namespace on disk.
+    pub fuel_accounting: bool,
+    /// Fuel a namespace may spend before contract calls in it are refused.
+    /// Spending is counted as with `fuel_accounting`, even when it is off.
+    pub namespace_fuel_budget: Option<u64>,
+    /// Whether to answer each `Insert` with an `ExecutionReport` of the
+    /// resources its contract call used.
+    pub execution_reports: bool,
+    /// Number of workers running contract calls. Independent contracts run
+    /// concurrently, while inserts and reads of the same contract and key
+    /// always go to the same worker and keep their order.
+    pub workers: usize,
+    /// Issuers whose certificates admit peers during the handshake. `None`
+    /// admits every peer without a name or permissions.
+    pub trust: Option<TrustStore>,
+    /// Whether to record every applied message in the audit log.
+    pub audit: bool,
+    /// How long replies and events wait for others to the same peer, to be
+    /// sent with them as one signed batch. `None` sends each at once.
+    pub batch_window: Option<Duration>,
+    /// Messages a batch holds at most. A full batch is sent without waiting
+    /// for the window to end.
+    pub max_batch: usize,
+    /// Limits for relaying messages between peers. `None` does not relay.
+    pub relay: Option<RelayConfig>,
+    /// How far the clocks of nodes may drift apart. Messages are dropped once
+    /// they expired longer than this ago.
+    pub clock_skew: Duration,
+    pub limits: Limits,
+    /// Conflict resolution of namespaces not using [`MergePolicy::Content`].
+    /// Every node holding a namespace must resolve its conflicts the same
+    /// way, or their copies diverge.
+    pub merge_policies: HashMap<String, MergePolicy>,
+    /// Guardrails checked before the contract of an insert runs, by
+    /// namespace.
+    pub namespace_policies: HashMap<String, NamespacePolicy>,
+    /// Whether the node only mirrors data, advertising
+    /// [`Capability::Observer`]. It verifies and stores writes other peers
+    /// pass on, but refuses those sent by their signer and never sends any.
+    pub observer: bool,
+    /// Namespaces held by a light node, which asks its peers to pass on the
+    /// inserts in them and ignores the rest. `None` holds every namespace.
+    pub namespaces: Option<HashSet<String>>,
+    pub state_proofs: StateProofs,
+    /// Peers inserts are passed on to, among those that asked for them.
+    pub fanout: Fanout,
+    /// Periodic clean-up of the database. `None` never runs it.
+    pub maintenance: Option<MaintenanceConfig>,
+    /// Learning the addresses of other nodes from those connecting to this
+    /// one. `None` neither learns nor gossips addresses.
+    pub discovery: Option<DiscoveryConfig>,
+    /// Where the node runs, gossiped to its peers so they can spread writes
+    /// over regions with [`Fanout::Regional`].
+    pub locality: Option<Locality>,
+}
+
+/// A node holding every namespace and trusting every peer, with every
+/// optional feature off.
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            seen_messages: 100_000,
+            seen_retention: None,
+            fuel_accounting: false,
+            namespace_fuel_budget: None,
+            execution_reports: false,
+            workers: 4,
+            trust: None,
+            audit: false,
+            batch_window: None,
+            max_batch: 64,
+            relay: None,
+            clock_skew: Duration::from_secs(30),
+            limits: Limits::default(),
+            merge_policies: HashMap::new(),
+            namespace_policies: HashMap::new(),
+            observer: false,
+            namespaces: None,
+            state_proofs: StateProofs::Off,
+            fanout: Fanout::All,
+            maintenance: None,
+            discovery: None,
+            locality: None,
+        }
+    }
+}
+
+/// Whether a node proves the values it serves with a [`StateProof`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StateProofs {
+    #[default]
+    Off,
+    /// Signs the digest and state of each value read.
+    Signed,
+    /// Also places the key in the Merkle tree of its contract space, which
+    /// takes reading every key of the space.
+    Anchored,
+}
+
+/// Which of the peers that asked for a namespace an insert applied in it is
+/// passed on to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fanout {
+    #[default]
+    All,
+    /// At most `peers` of them, drawn at random with odds inverse to their
+    /// round trip time, measured with a `Ping` every `ping_interval`. Each
+    /// receiver passes the insert on in turn, so in a connected mesh it
+    /// reaches every node asking for it with high probability once `peers` is
+    /// around the logarithm of the mesh size, with far fewer copies than
+    /// sending to all.
+    Latency {
+        peers: usize,
+        ping_interval: Duration,
+    },
+    /// At most `copies` of them in each region, counting this node in its
+    /// own, so a region going down leaves copies in the others. Peers of no
+    /// known region, as learned from their gossip, are taken as one more
+    /// region. Within a region, peers are drawn as with `Latency`, though
+    /// only at random unless pings measure them.
+    Regional { copies: usize },
+}
+
+/// How a node picks between two writes of a key at the same state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The value with the greater msgpack encoding wins, and objects are
+    /// merged field by field.
+    #[default]
+    Content,
+    /// The value of the message signed last wins, as if it had the higher
+    /// state. Writes signed in the same second are resolved by content.
+    LatestSigned,
+}
+
+/// Bounds on what peers can make a node decode, store and run. Anything going
+/// over them is refused and counted in [`Node::rejections`].
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Bytes a transport message may take on the wire.
+    pub max_message_size: usize,
+    /// Messages a single transport message may carry.
+    pub max_batch_messages: usize,
+    /// Bytes a stored value may take once encoded, after merging or patching.
+    pub max_value_size: usize,
+    /// Keys a namespace may hold across its contract spaces.
+    pub max_keys_per_namespace: u64,
+    /// Contract calls that may run at once. Calls beyond it are refused, except
+    /// for migrations, which wait for a free slot.
+    pub max_inflight_executions: usize,
+}
+
+impl Limits {
+    pub const UNLIMITED: Self = Self {
+        max_message_size: usize::MAX,
+        max_batch_messages: usize::MAX,
+        max_value_size: usize::MAX,
+        max_keys_per_namespace: u64::MAX,
+        max_inflight_executions: Semaphore::MAX_PERMITS,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 16 << 20,
+            max_batch_messages: 1024,
+            max_value_size: 4 << 20,
+            max_keys_per_namespace: 10_000_000,
+            max_inflight_executions: 256,
+        }
+    }
+}
+
+/// Limits of a node relaying `Forward` envelopes for its peers.
+#[derive(Debug, Clone, Copy)]
+pub struct RelayConfig {
+    /// Bytes each sender may have relayed per `window`.
+    pub quota: u64,
+    pub window: Duration,
+}
+
+/// Clean-up a node runs every `interval`, removing the tombstones and audit
+/// records it no longer needs so that sled can reuse their space.
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenanceConfig {
+    pub interval: Duration,
+    /// How long tombstones of deleted keys are kept. Writes from before a
+    /// deletion that arrive later bring the key back.
+    pub tombstone_ttl: Duration,
+    /// Newest audit records kept; all of them if `None`.
+    pub audit_retention: Option<u64>,
+}
+
+/// What a maintenance run cleaned up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub tombstones_pruned: usize,
+    pub audit_records_trimmed: u64,
+    /// Size of the database files once the run is done.
+    pub disk_bytes: u64,
+}
+
+/// Seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
 }
 
 pub struct IncomingMessage {
     peer: Arc<Peer>,
     message: TransportMessage,
+    /// The batch in `message`, decoded and verified by the peer's read task.
+    messages: Vec<Message>,
 }
 
 pub struct Node {
     pub identity: Vec<u8>,
     pub peers: RwLock<Vec<Arc<Peer>>>,
     pub config: NodeConfig,
-    storage: sled::Db,
-    contracts: HashMap<Vec<u8>, Arc<Mutex<Box<dyn Contract>>>>,
+    signer: Box<dyn Signer>,
+    events: EventRouter,
+    data: DataStore,
+    registry: ContractStore,
+    usage: UsageMetrics,
+    rejections: Arc<Rejections>,
+    diagnostics: Diagnostics,
+    /// Slots for running contract calls; see [`Limits::max_inflight_executions`].
+    executions: Arc<Semaphore>,
+    fuel: FuelLedger,
+    audit: AuditLog,
+    journal: Journal,
+    groups: GroupStore,
+    policies: PolicyEngine,
+    seen: SeenFilter,
+    /// Ids kept on disk; see [`NodeConfig::seen_retention`].
+    seen_store: Option<SeenStore>,
+    addresses: AddressBook,
+    localities: LocalityBook,
+    /// Addresses learned from gossip, with the key they are to be confirmed
+    /// for; see [`addresses`].
+    probe_tx: Sender<(Vec<u8>, String)>,
+    /// Bytes relayed per sending key, and when its current window started.
+    relayed: std::sync::Mutex<HashMap<Vec<u8>, (Instant, u64)>>,
+    contracts: RwLock<HashMap<Vec<u8>, SharedContract>>,
     contract_compiler: Box<dyn ContractCompiler>,
     server: Box<dyn Server>,
-    msg_tx: Sender<IncomingMessage>,
-    msg_rx: Mutex<Receiver<IncomingMessage>>,
+    /// Queues of incoming batches, by [`Priority`].
+    msg_tx: [Sender<IncomingMessage>; Priority::ALL.len()],
     peer_tx: Sender<Box<dyn TransportPeer>>,
-    peer_rx: Mutex<Receiver<Box<dyn TransportPeer>>>,
+    lane_tx: Vec<Sender<MessageContext>>,
+    /// Receiving ends of the node's channels, handed over to [`Node::process`]
+    /// when it starts.
+    inbox: std::sync::Mutex<Option<Inbox>>,
+}
+
+struct Inbox {
+    handshakes: Receiver<IncomingMessage>,
+    control: Receiver<IncomingMessage>,
+    data: Receiver<IncomingMessage>,
+    bulk: Receiver<IncomingMessage>,
+    peers: Receiver<Box<dyn TransportPeer>>,
+    lanes: Vec<Receiver<MessageContext>>,
+    probes: Receiver<(Vec<u8>, String)>,
 }
 
-enum BroadcastStatus {
-    Runtime,
-    Network,
-    Ok,
+/// Outcome of [`Node::simulate_contract`].
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// Actions the contract would have approved.
+    pub actions: Vec<DataAction>,
+    pub report: ExecutionReport,
 }
 
 struct MessageContext {
     message: Message,
     peer: Arc<Peer>,
-    transport: TransportMessage,
+    /// The batch the message came in, shared by all of its messages.
+    transport: Arc<TransportMessage>,
+    /// Position of the message in `transport`.
+    index: usize,
 }
 
 impl Node {
+    #[must_use]
+    pub fn new(
+        keypair: KeyPair,
+        config: NodeConfig,
+        storage: sled::Db,
+        contract_compiler: Box<dyn ContractCompiler>,
+        server: Box<dyn Server>,
+    ) -> Self {
+        Self::with_signer(
+            Box::new(keypair),
+            config,
+            storage,
+            contract_compiler,
+            server,
+        )
+    }
+
+    /// Like [`Node::new`], with the node identity held by `signer`.
+    #[must_use]
+    pub fn with_signer(
+        signer: Box<dyn Signer>,
+        config: NodeConfig,
+        storage: sled::Db,
+        contract_compiler: Box<dyn ContractCompiler>,
+        server: Box<dyn Server>,
+    ) -> Self {
+        let (seen_messages, limits) = (config.seen_messages, config.limits);
+        let policies = PolicyEngine::new(config.namespace_policies.clone());
+        let seen_store = config
+            .seen_retention
+            .map(|retention| SeenStore::new(storage.clone(), retention));
+        let rejections = Arc::new(Rejections::default());
+        let queues: [_; Priority::ALL.len()] = std::array::from_fn(|_| channel(CHANNEL_CAPACITY));
+        let msg_tx = queues.each_ref().map(|(tx, _)| tx.clone());
+        let [handshakes, control, data, bulk] = queues.map(|(_, rx)| rx);
+        let (peer_tx, peer_rx) = channel(CHANNEL_CAPACITY);
+        let (probe_tx, probes) = channel(addresses::MAX_PROBES);
+        let (lane_tx, lanes) = (0..config.workers.max(1))
+            .map(|_| {
+                let (tx, rx) = channel(CHANNEL_CAPACITY);
+                (tx, rx)
+            })
+            .unzip();
+
+        Self {
+            identity: signer.public_key(),
+            peers: RwLock::new(Vec::new()),
+            config,
+            signer,
+            events: EventRouter::new(CHANNEL_CAPACITY),
+            data: DataStore::new(storage.clone()).with_limits(limits, rejections.clone()),
+            registry: ContractStore::new(storage.clone()),
+            usage: UsageMetrics::default(),
+            rejections,
+            diagnostics: Diagnostics::default(),
+            executions: Arc::new(Semaphore::new(
+                limits.max_inflight_executions.min(Semaphore::MAX_PERMITS),
+            )),
+            fuel: FuelLedger::new(storage.clone()),
+            audit: AuditLog::new(storage.clone()),
+            journal: Journal::new(storage.clone()),
+            groups: GroupStore::new(storage),
+            policies,
+            seen: SeenFilter::new(seen_messages),
+            seen_store,
+            addresses: AddressBook::default(),
+            localities: LocalityBook::default(),
+            probe_tx,
+            relayed: std::sync::Mutex::new(HashMap::new()),
+            contracts: RwLock::new(HashMap::new()),
+            contract_compiler,
+            server,
+            msg_tx,
+            peer_tx,
+            lane_tx,
+            inbox: std::sync::Mutex::new(Some(Inbox {
+                handshakes,
+                control,
+                data,
+                bulk,
+                peers: peer_rx,
+                lanes,
+                probes,
+            })),
+        }
+    }
+
     #[must_use]
     pub fn identity(&self) -> &[u8] {
         &self.identity
     }
 
-    fn get_contract(&mut self, id: &[u8]) -> Option<Arc<Mutex<Box<dyn Contract>>>> {
-        let contract = self.contracts.get(id);
-        match contract {
-            Some(x) => return Some(x.clone()),
-            _ => {}
+    /// Signs `messages` with the node identity.
+    async fn sign(&self, messages: &[Message]) -> Result<TransportMessage, NodeError> {
+        TransportMessage::sign_with(messages, &*self.signer, b64_encode(&self.identity))
+            .await
+            .map_err(NodeError::SigningError)
+    }
+
+    /// Checks the certificates a peer sent in `Hello` or `WhoAreYou` for its
+    /// `public_key` against the trust store, and records the identity they
+    /// establish on the peer. Always succeeds when the node has no trust store.
+    pub fn admit(
+        &self,
+        peer: &Peer,
+        public_key: &[u8],
+        certificates: &[Vec<u8>],
+    ) -> Result<(), NodeError> {
+        let Some(trust) = &self.config.trust else {
+            return Ok(());
         };
+        let certificates = certificates
+            .iter()
+            .map(|data| IdentityCertificate::decode(data))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(NodeError::Untrusted)?;
+        let identity = trust
+            .check(public_key, &certificates, unix_time())
+            .map_err(NodeError::Untrusted)?;
+        debug!("Admitted peer {}", identity.name);
+        *peer.identity.lock().unwrap() = Some(identity);
+        Ok(())
+    }
 
-        let contract_bytecode = self
-            .storage
-            .open_tree(b"contracts")
-            .unwrap()
-            .get(id)
-            .ok()
-            .flatten()?;
+    /// Events emitted by contracts on this node, plus those forwarded by peers
+    /// this node subscribed to.
+    #[must_use]
+    pub fn watch_events(&self) -> broadcast::Receiver<ContractEvent> {
+        self.events.watch()
+    }
 
-        let contract = self.contract_compiler
-            .create_contract(contract_bytecode.as_ref())
-            .ok()
-            .map(|x| Arc::new(Mutex::new(x)))?;
-        
-        self.contracts.insert(id.to_vec(), contract.clone());
-        
-        Some(contract)
+    /// Calls made to contract `id` since the node started.
+    #[must_use]
+    pub fn contract_usage(&self, id: &[u8]) -> ContractUsage {
+        self.usage.get(id)
     }
 
-    pub async fn receive_peers(&self) {
-        let tx = self.peer_tx.clone();
+    /// Contracts called since the node started, most expensive first.
+    #[must_use]
+    pub fn usage_by_fuel(&self) -> Vec<(Vec<u8>, ContractUsage)> {
+        self.usage.by_fuel()
+    }
 
-        while let Ok(peer) = self.server.accept().await {
-            if let Some(peer) = peer {
-                if tx.send(peer).await.is_err() {
-                    debug!("Failed to send peer to the node");
-                }
-            } else {
-                debug!("Server returned None, no peer accepted");
-            }
-        }
+    /// Messages, values and calls refused for going over `limit` since the
+    /// node started.
+    #[must_use]
+    pub fn rejections(&self, limit: Limit) -> u64 {
+        self.rejections.get(limit)
     }
 
-    pub async fn process(&self) {
-        loop {
-            self.process_next().await;
-            yield_now().await;
+    /// The last actions of contract `id` the node refused to store, such as
+    /// values over [`Limits::max_value_size`], oldest first.
+    #[must_use]
+    pub fn contract_diagnostics(&self, id: &[u8]) -> Vec<ActionRejection> {
+        self.diagnostics.get(id)
+    }
+
+    /// Number and age of the message ids kept on disk, `None` unless
+    /// [`NodeConfig::seen_retention`] is set.
+    pub fn seen_stats(&self) -> Result<Option<SeenStats>, NodeError> {
+        self.seen_store
+            .as_ref()
+            .map(|store| store.stats(unix_time()))
+            .transpose()
+    }
+
+    /// How conflicting writes to `namespace` are resolved.
+    #[must_use]
+    pub fn merge_policy(&self, namespace: &str) -> MergePolicy {
+        self.config
+            .merge_policies
+            .get(namespace)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Slot for running a contract call, refused once
+    /// [`Limits::max_inflight_executions`] calls are running.
+    fn execution_permit(&self) -> Result<OwnedSemaphorePermit, NodeError> {
+        self.executions
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| self.rejections.reject(Limit::InflightExecutions))
+    }
+
+    /// Fuel spent by contracts deployed by `deployer`, if fuel accounting is on.
+    pub fn deployer_fuel(&self, deployer: &[u8]) -> Result<u64, NodeError> {
+        self.fuel.deployer(deployer)
+    }
+
+    /// Fuel spent by contract calls in `namespace`, if fuel accounting is on.
+    pub fn namespace_fuel(&self, namespace: &str) -> Result<u64, NodeError> {
+        self.fuel.namespace(namespace)
+    }
+
+    /// Every value this node stores in `namespace`, ordered by contract space
+    /// and key.
+    pub fn entries(&self, namespace: &str) -> Result<Vec<Entry>, NodeError> {
+        self.data.entries(namespace)
+    }
+
+    /// Up to `limit` audit log records, starting at sequence number `from`.
+    pub fn audit_log(&self, from: u64, limit: usize) -> Result<Vec<AuditRecord>, NodeError> {
+        self.audit.records(from, limit)
+    }
+
+    /// Checks the hash chain of the audit log, returning how many records it
+    /// holds.
+    pub fn verify_audit_log(&self) -> Result<u64, NodeError> {
+        self.audit.verify()
+    }
+
+    /// Records an applied message in the audit log.
+    fn record_audit(
+        &self,
+        transport: &TransportMessage,
+        namespace: &str,
+        action: String,
+        state: Option<u64>,
+    ) -> Result<(), NodeError> {
+        self.audit.append(
+            &transport.id,
+            &transport.signature.signed_by,
+            namespace,
+            action,
+            state,
+        )?;
+        Ok(())
+    }
+
+    /// Refuses inserts into `namespace` by keys neither among its writers nor
+    /// deployers of its contracts.
+    fn check_writer(&self, namespace: &str, signer: &[u8]) -> Result<(), NodeError> {
+        match self.registry.writers(namespace)? {
+            Some(writers)
+                if !writers.iter().any(|writer| writer == signer)
+                    && !self.registry.is_deployer(namespace, signer)? =>
+            {
+                debug!("Refusing insert into {namespace} by {}", b64_encode(signer));
+                Err(NodeError::NotAuthorized)
+            }
+            _ => Ok(()),
         }
     }
 
-    async fn process_next(&self) -> Result<(), NodeError> {
-        while let Ok(peer) = self.peer_rx.lock().await.try_recv() {
-            self.add_peer(peer).await;
+    fn check_budget(&self, namespace: &str) -> Result<(), NodeError> {
+        match self.config.namespace_fuel_budget {
+            Some(budget) if self.fuel.namespace(namespace)? >= budget => {
+                debug!("Namespace {namespace} is out of fuel");
+                Err(NodeError::FuelBudgetExceeded)
+            }
+            _ => Ok(()),
         }
+    }
 
-        yield_now().await;
+    /// Records the resources a finished call used, whether it succeeded or
+    /// not, given when it started.
+    fn account(
+        &self,
+        contract: &[u8],
+        namespace: &str,
+        host: &MessageHost,
+        started: Instant,
+    ) -> Result<ExecutionReport, NodeError> {
+        let report = host.report(started.elapsed());
+        self.usage.record(contract, &report);
 
-        let msg = match self.msg_rx.lock().await.recv().await {
-            Some(msg) => msg,
-            None => return Err(NodeError::NoMessage),
-        };
+        if self.config.fuel_accounting || self.config.namespace_fuel_budget.is_some() {
+            let deployer = self
+                .registry
+                .info(contract)?
+                .map(|info| info.deployed_by)
+                .unwrap_or_default();
+            self.fuel.charge(&deployer, namespace, report.fuel)?;
+        }
+        Ok(report)
+    }
 
-        let msgs: Vec<Message> = msg
-            .message
-            .clone()
-            .try_into()
-            .map_err(NodeError::ProtocolError)?;
-        let msg = msgs
-            .into_iter()
-            .map(|x| MessageContext {
-                message: x,
-                peer: msg.peer.clone(),
-                transport: msg.message.clone(),
-            })
-            .collect::<Vec<_>>();
+    /// Asks `peer` to forward events emitted in `namespace` to this node,
+    /// only those matching `filter` if one is given.
+    pub async fn subscribe_events(
+        &self,
+        peer: &Peer,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<Filter>,
+    ) -> Result<(), NodeError> {
+        peer.send(
+            self.sign(&[Message::Subscribe {
+                namespace,
+                topic,
+                filter,
+            }])
+            .await?,
+        )
+        .await
+    }
 
-        for msg in msg {
-            if let Err(e) = self.process_message(msg).await {
-                debug!("Failed to process message: {:?}", e);
-            }
+    /// Sends `message` to `peer`, or queues it for the peer's next batch when
+    /// batching is on.
+    async fn deliver(&self, peer: &Peer, message: Message) -> Result<(), NodeError> {
+        if self.config.batch_window.is_none() {
+            return peer.send(self.sign(&[message]).await?).await;
         }
 
+        let full = {
+            let mut outbox = peer.outbox.lock().unwrap();
+            outbox.push(message);
+            outbox.len() >= self.config.max_batch
+        };
+        if full {
+            self.flush(peer).await?;
+        }
         Ok(())
     }
 
-    async fn process_message(&self, msg: MessageContext) -> Result<(), NodeError> {
-        Ok(())
+    /// Sends the messages queued for `peer` as one batch.
+    async fn flush(&self, peer: &Peer) -> Result<(), NodeError> {
+        let _flushing = peer.flushing.lock().await;
+        let messages = std::mem::take(&mut *peer.outbox.lock().unwrap());
+        if messages.is_empty() {
+            return Ok(());
+        }
+        peer.send(self.sign(&messages).await?).await
     }
 
-    async fn add_peer(&self, peer: Box<dyn TransportPeer>) {
-        let peer = Arc::new(Peer {
-            transport: peer,
-            stage: RwLock::new(PeerInitStage::None),
-            read_thread: Mutex::new(None),
-        });
+    /// Sends the batches queued for every peer at the end of each window.
+    async fn flush_batches(&self) {
+        let Some(window) = self.config.batch_window else {
+            return;
+        };
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + window, window);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-        let mut read_thread_lock = peer.read_thread.lock().await;
-        let cloned_peer = peer.clone();
-        let tx = self.msg_tx.clone();
+        loop {
+            interval.tick().await;
+            let peers = self.peers.read().await.clone();
+            for peer in peers {
+                if let Err(e) = self.flush(&peer).await {
+                    debug!("Failed to send a batch: {e:?}");
+                }
+            }
+        }
+    }
 
-        *read_thread_lock = Some(tokio::task::spawn(async move {
-            let peer = cloned_peer;
+    /// Runs maintenance once with `config`, as the node does on its own
+    /// every `interval` when configured to.
+    pub fn maintain(&self, config: &MaintenanceConfig) -> Result<MaintenanceReport, NodeError> {
+        let expired = unix_time().saturating_sub(config.tombstone_ttl.as_secs());
+        let tombstones_pruned = self.data.prune_tombstones(expired)?;
+        let audit_records_trimmed = match config.audit_retention {
+            Some(keep) => self.audit.trim(keep)?,
+            None => 0,
+        };
+        Ok(MaintenanceReport {
+            tombstones_pruned,
+            audit_records_trimmed,
+            disk_bytes: self.data.flush()?,
+        })
+    }
 
-            while let Ok(msg) = peer.next().await {
-                tx.send(IncomingMessage {
-                    peer: peer.clone(),
-                    message: msg,
-                })
-                .await
-                .expect("The message channel must always be open");
-                yield_now().await;
+    async fn run_maintenance(&self) {
+        let Some(config) = self.config.maintenance else {
+            return;
+        };
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + config.interval,
+            config.interval,
+        );
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            match self.maintain(&config) {
+                Ok(report) => info!(
+                    "Maintenance pruned {} tombstones and {} audit records, database is {} bytes",
+                    report.tombstones_pruned, report.audit_records_trimmed, report.disk_bytes
+                ),
+                Err(e) => warn!("Maintenance failed: {e:?}"),
             }
-        }));
+        }
+    }
 
-        drop(read_thread_lock);
+    /// Pings every peer each `ping_interval` of [`Fanout::Latency`].
+    async fn run_pings(&self) {
+        let Fanout::Latency { ping_interval, .. } = self.config.fanout else {
+            return;
+        };
+        let mut interval = tokio::time::interval(ping_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-        self.peers.write().await.push(peer);
+        loop {
+            interval.tick().await;
+            let nonce = rand::random();
+            let raw = match self.sign(&[Message::Ping { nonce }]).await {
+                Ok(msg) => encode(&msg),
+                Err(e) => {
+                    warn!("Failed to sign a ping: {e:?}");
+                    continue;
+                }
+            };
+            let peers = self.peers.read().await.clone();
+            for peer in peers {
+                peer.record_ping(nonce);
+                if let Err(e) = peer.send_encoded(raw.clone()).await {
+                    debug!("Failed to ping a peer: {e:?}");
+                }
+            }
+        }
     }
 
-    async fn broadcast(&self, msg: TransportMessage) {
-        let peers = self.peers.read().await;
-        let mut handles = Vec::with_capacity(peers.len());
+    async fn publish_event(&self, event: ContractEvent) {
+        let subscribers = self.events.subscribers(&event).await;
+        self.events.deliver_local(event.clone());
 
-        for peer in peers.as_slice() {
-            let mut msg = msg.clone();
+        if subscribers.is_empty() {
+            return;
+        }
 
-            if !msg.received_by.contains(&self.identity) {
-                msg.received_by.push(self.identity.clone());
+        // Batched events are signed along with the rest of each peer's batch,
+        // otherwise every subscriber gets the same signed buffer.
+        if self.config.batch_window.is_some() {
+            for peer in subscribers {
+                if let Err(e) = self
+                    .deliver(
+                        &peer,
+                        Message::Event {
+                            event: event.clone(),
+                        },
+                    )
+                    .await
+                {
+                    debug!("Failed to forward event to a subscriber: {e:?}");
+                }
             }
+            return;
+        }
 
-            while msg.received_by.len() > self.config.max_received_by {
-                msg.received_by.remove(0);
+        let msg = match self.sign(&[Message::Event { event }]).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                debug!("Failed to sign event: {e:?}");
+                return;
             }
+        };
+        let raw = encode(&msg);
+        for peer in subscribers {
+            if let Err(e) = peer.send_encoded(raw.clone()).await {
+                debug!("Failed to forward event to a subscriber: {e:?}");
+            }
+        }
+    }
 
-            let peer = peer.clone();
-
-            handles.push(tokio::spawn(async move { peer.send(msg).await }));
+    async fn get_contract(&self, id: &[u8]) -> Option<SharedContract> {
+        if let Some(contract) = self.contracts.read().await.get(id) {
+            return Some(contract.clone());
         }
 
-        let res = futures::future::join_all(handles.into_iter()).await;
-        let x = res.iter().map(|x| match x {
-            Ok(e) => match e {
-                Ok(()) => BroadcastStatus::Ok,
-                _ => BroadcastStatus::Network,
-            },
-            Err(_) => BroadcastStatus::Runtime,
-        });
+        let contract_bytecode = self.registry.bytecode(id).ok().flatten()?;
+
+        let contract = self
+            .contract_compiler
+            .create_contract(contract_bytecode.as_ref())
+            .ok()
+            .map(|x| Arc::new(Mutex::new(x)))?;
+
+        self.contracts
+            .write()
+            .await
+            .insert(id.to_vec(), contract.clone());
+
+        Some(contract)
+    }
+
+    /// Compiles a contract and reads its metadata, checking that `params`
+    /// holds every parameter it requires.
+    async fn compile_contract(
+        &self,
+        bytecode: &[u8],
+        params: &HashMap<String, DbValue>,
+    ) -> Result<(SharedContract, Option<ContractMetadata>), NodeError> {
+        let contract: SharedContract = Arc::new(Mutex::new(
+            self.contract_compiler
+                .create_contract(bytecode)
+                .map_err(NodeError::ContractError)?,
+        ));
+
+        let reader = contract.clone();
+        let metadata = tokio::task::spawn_blocking(move || reader.blocking_lock().metadata())
+            .await
+            .map_err(NodeError::RuntimeError)?
+            .map_err(NodeError::ContractError)?;
+
+        let missing = metadata
+            .iter()
+            .flat_map(|metadata| &metadata.required_params)
+            .find(|param| !params.contains_key(*param));
+        if let Some(param) = missing {
+            return Err(NodeError::MissingContractParam(param.clone()));
+        }
 
-        let (failed_runtime, failed_network, success) =
-            x.fold((0, 0, 0), |(rt, net, ok), status| match status {
-                BroadcastStatus::Runtime => (rt + 1, net, ok),
-                BroadcastStatus::Network => (rt, net + 1, ok),
-                BroadcastStatus::Ok => (rt, net, ok + 1),
-            });
+        Ok((contract, metadata))
+    }
 
-        if failed_runtime + failed_network > 0 {
+    /// Compiles and stores a contract, returning its id, the
+    /// [`contract_id`] of the bytecode and `deployed_by`. Once a namespace
+    /// holds contracts, only keys that deployed one of them may deploy more.
+    pub async fn deploy_contract(
+        &self,
+        bytecode: Vec<u8>,
+        namespace: String,
+        params: HashMap<String, DbValue>,
+        tags: Vec<String>,
+        deployed_by: Vec<u8>,
+    ) -> Result<Vec<u8>, NodeError> {
+        if !self.registry.may_deploy(&namespace, &deployed_by)? {
             debug!(
-                "Failed to broadcast message ID {} to {} peers ({} runtime, {} network). Success: {}",
-                b64_encode(&msg.id),
-                peers.len(),
-                failed_runtime,
-                failed_network,
-                success
+                "Refusing deployment into {namespace} by {}",
+                b64_encode(&deployed_by)
             );
+            return Err(NodeError::NotDeployer);
+        }
+        let (contract, metadata) = self.compile_contract(&bytecode, &params).await?;
+
+        let id = contract_id(&bytecode, &deployed_by).to_vec();
+        let info = ContractInfo {
+            id: id.clone(),
+            namespace,
+            tags,
+            deployed_by,
+            metadata,
+        };
+        self.registry.insert(&info, &bytecode, &params)?;
+        self.contracts.write().await.insert(id.clone(), contract);
+
+        Ok(id)
+    }
+
+    /// Replaces the code of contract `id`, keeping its params and data, then
+    /// runs the new code's migration hook over every value stored through the
+    /// contract. Only the key that deployed the contract may upgrade it.
+    pub async fn upgrade_contract(
+        &self,
+        id: &[u8],
+        bytecode: Vec<u8>,
+        upgraded_by: &[u8],
+    ) -> Result<(), NodeError> {
+        let mut info = self.registry.info(id)?.ok_or(NodeError::UnknownContract)?;
+        if info.deployed_by != upgraded_by {
+            return Err(NodeError::NotDeployer);
+        }
+
+        let params = self.registry.params(id)?;
+        let (contract, metadata) = self.compile_contract(&bytecode, &params).await?;
+
+        info.metadata = metadata;
+        self.registry.insert(&info, &bytecode, &params)?;
+        self.contracts
+            .write()
+            .await
+            .insert(id.to_vec(), contract.clone());
+
+        let seed = hash::blake3(&bytecode);
+        self.migrate_data(id, contract, params, upgraded_by, seed)
+            .await
+    }
+
+    /// Upgrades contract `id` with `patch` against its current bytecode, as
+    /// made by [`rvb_common::delta::diff`]. `base` and `target` are the hashes
+    /// of the bytecode the patch was made against and of the one it gives.
+    pub async fn patch_contract(
+        &self,
+        id: &[u8],
+        base: &[u8],
+        patch: &[u8],
+        target: &[u8],
+        upgraded_by: &[u8],
+    ) -> Result<(), NodeError> {
+        let current = self
+            .registry
+            .bytecode(id)?
+            .ok_or(NodeError::UnknownContract)?;
+        if hash::blake3(&current) != base {
+            return Err(NodeError::PatchMismatch);
+        }
+        // Whole bytecode sent in an upgrade could be no larger
+        let bytecode = delta::apply(&current, patch, self.config.limits.max_message_size)
+            .map_err(NodeError::InvalidPatch)?;
+        if hash::blake3(&bytecode) != target {
+            return Err(NodeError::PatchMismatch);
+        }
+        self.upgrade_contract(id, bytecode, upgraded_by).await
+    }
+
+    /// Logs an upgrade of `contract` applied from `transport`.
+    fn upgraded(&self, contract: &[u8], transport: &TransportMessage) -> Result<(), NodeError> {
+        debug!("Upgraded contract {}", b64_encode(contract));
+        if self.config.audit {
+            let namespace = self
+                .registry
+                .info(contract)?
+                .map(|info| info.namespace)
+                .unwrap_or_default();
+            self.record_audit(
+                transport,
+                &namespace,
+                format!("upgrade {}", b64_encode(contract)),
+                None,
+            )?;
         }
+        Ok(())
+    }
+
+    /// Feeds every value stored through contract `id` to its migration hook,
+    /// applying the returned actions with a state newer than the value's.
+    async fn migrate_data(
+        &self,
+        id: &[u8],
+        contract: SharedContract,
+        params: HashMap<String, DbValue>,
+        upgraded_by: &[u8],
+        seed: [u8; 32],
+    ) -> Result<(), NodeError> {
+        for (namespace, contract_space) in self.registry.spaces(id)? {
+            for key in self.data.keys(&namespace, &contract_space)? {
+                let Some(value) = self.data.get(&namespace, &contract_space, &key)? else {
+                    continue;
+                };
+                let state = self.data.state(&namespace, &contract_space, &key)?;
+
+                let ctx = ContractContext {
+                    action: DataAction::Insert {
+                        key: key.clone(),
+                        incoming_data: value,
+                        params: HashMap::new(),
+                    },
+                    namespace: namespace.clone(),
+                    contract_space: contract_space.clone(),
+                    signed_by: upgraded_by.to_vec(),
+                    contract_params: params.clone(),
+                    message_id: Vec::new(),
+                    signed_at: 0,
+                    publisher: String::new(),
+                    state,
+                };
+                let host = Arc::new(MessageHost::seeded(self.data.clone(), id, seed));
+                let (runner, call_host) = (contract.clone(), host.clone());
+
+                let permit = self
+                    .executions
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("The execution semaphore is never closed");
+                let started = Instant::now();
+                let actions = tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    runner.blocking_lock().migrate(ctx, call_host)
+                })
+                .await
+                .map_err(NodeError::RuntimeError)?;
+                self.account(id, &namespace, &host, started)?;
+                let actions = actions.map_err(NodeError::ContractError)?;
+                host.commit()?;
+
+                // Without a hook there is nothing to migrate.
+                let Some(actions) = actions else {
+                    return Ok(());
+                };
+
+                let location = Location {
+                    namespace: namespace.clone(),
+                    contract_space: contract_space.clone(),
+                    contract: id.to_vec(),
+                    key,
+                };
+                self.apply_actions(&location, actions, state + 1, 0).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn contract_info(&self, id: &[u8]) -> Result<Option<ContractInfo>, NodeError> {
+        self.registry.info(id)
+    }
+
+    /// Keys allowed to insert into `namespace` besides the deployers of its
+    /// contracts, or `None` if anyone may.
+    pub fn writers(&self, namespace: &str) -> Result<Option<Vec<Vec<u8>>>, NodeError> {
+        self.registry.writers(namespace)
+    }
+
+    /// Sets the writers of `namespace` as its admins agreed in `signature`,
+    /// as an `AdminSetWriters` does. Fails with [`NodeError::NotAuthorized`]
+    /// if the namespace has no admins, fewer than their threshold signed, or
+    /// `sequence` is not above that of the last change applied.
+    pub fn admin_set_writers(
+        &self,
+        namespace: &str,
+        writers: Option<Vec<Vec<u8>>>,
+        sequence: u64,
+        signature: ThresholdSignature,
+    ) -> Result<(), NodeError> {
+        let Some((admins, last)) = self.registry.admins(namespace)? else {
+            return Err(NodeError::NotAuthorized);
+        };
+        let payload = admin_payload(namespace, writers.as_deref(), sequence);
+        if sequence <= last || !admins.verify(&payload, &signature) {
+            debug!("Refusing a change to the writers of {namespace} its admins did not agree on");
+            return Err(NodeError::NotAuthorized);
+        }
+        if !self
+            .registry
+            .advance_admins(namespace, &admins, last, sequence)?
+        {
+            return Err(NodeError::NotAuthorized);
+        }
+        self.registry.set_writers(namespace, writers.as_deref())
+    }
+
+    /// Contracts deployed in `namespace` carrying every tag in `query`.
+    pub fn search_contracts(
+        &self,
+        namespace: &str,
+        query: &[String],
+    ) -> Result<Vec<ContractInfo>, NodeError> {
+        self.registry.search(namespace, query)
+    }
+
+    /// Adds a connection this node opened itself, such as one to a bootstrap
+    /// peer, alongside those accepted by its server.
+    pub async fn connect_peer(&self, peer: Box<dyn TransportPeer>) {
+        if self.peer_tx.send(peer).await.is_err() {
+            debug!("Failed to send peer to the node");
+        }
+    }
+
+    pub async fn receive_peers(&self) {
+        let tx = self.peer_tx.clone();
+
+        while let Ok(peer) = self.server.accept().await {
+            if let Some(peer) = peer {
+                if tx.send(peer).await.is_err() {
+                    debug!("Failed to send peer to the node");
+                }
+            } else {
+                debug!("Server returned None, no peer accepted");
+            }
+        }
+    }
+
+    /// Processes incoming peers and messages until the node's channels close.
+    /// Only one call does any work; it takes over the receiving ends of the
+    /// channels, and later calls return at once.
+    ///
+    /// Writes left in the journal by a crash are applied first, before any
+    /// peer is added.
+    pub async fn process(&self) {
+        let Some(inbox) = self.inbox.lock().unwrap().take() else {
+            warn!("The node is already processing messages");
+            return;
+        };
+        self.replay_journal().await;
+        let workers = inbox.lanes.into_iter().map(|rx| self.work(rx));
+
+        futures::join!(
+            self.dispatch(inbox.handshakes, inbox.peers),
+            self.dispatch_queued(inbox.control, inbox.data, inbox.bulk),
+            futures::future::join_all(workers),
+            self.flush_batches(),
+            self.run_maintenance(),
+            self.run_pings(),
+            self.run_probes(inbox.probes)
+        );
+    }
+
+    /// Adds new peers and processes their handshakes and keepalives. These
+    /// never go to a lane, so they do not wait for busy workers.
+    async fn dispatch(
+        &self,
+        mut handshakes: Receiver<IncomingMessage>,
+        mut peers: Receiver<Box<dyn TransportPeer>>,
+    ) {
+        loop {
+            tokio::select! {
+                biased;
+                Some(peer) = peers.recv() => self.add_peer(peer).await,
+                Some(msg) = handshakes.recv() => self.dispatch_logged(msg).await,
+                else => break,
+            }
+            yield_now().await;
+        }
+    }
+
+    /// Routes the other batches to the workers, taking from the queue of the
+    /// highest [`Priority`] that has any. Messages that do not belong to a
+    /// lane are processed here.
+    async fn dispatch_queued(
+        &self,
+        mut control: Receiver<IncomingMessage>,
+        mut data: Receiver<IncomingMessage>,
+        mut bulk: Receiver<IncomingMessage>,
+    ) {
+        loop {
+            tokio::select! {
+                biased;
+                Some(msg) = control.recv() => self.dispatch_logged(msg).await,
+                Some(msg) = data.recv() => self.dispatch_logged(msg).await,
+                Some(msg) = bulk.recv() => self.dispatch_logged(msg).await,
+                else => break,
+            }
+            yield_now().await;
+        }
+    }
+
+    async fn dispatch_logged(&self, msg: IncomingMessage) {
+        if let Err(e) = self.dispatch_message(msg).await {
+            debug!("Failed to dispatch message: {:?}", e);
+        }
+    }
+
+    /// Worker processing the messages routed to one lane, in order.
+    async fn work(&self, mut rx: Receiver<MessageContext>) {
+        while let Some(msg) = rx.recv().await {
+            if let Err(e) = self.process_message(msg).await {
+                debug!("Failed to process message: {:?}", e);
+            }
+        }
+    }
+
+    /// Lane for messages that run a contract against a single key, picked by
+    /// contract id and key. Other messages are processed by the dispatcher.
+    fn lane(&self, message: &Message) -> Option<usize> {
+        let location = match message {
+            Message::Insert { location, .. }
+            | Message::Delete { location, .. }
+            | Message::Get { location, .. } => location,
+            _ => return None,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        location.contract.hash(&mut hasher);
+        location.key.hash(&mut hasher);
+        Some((hasher.finish() % self.lane_tx.len() as u64) as usize)
+    }
+
+    async fn dispatch_message(&self, msg: IncomingMessage) -> Result<(), NodeError> {
+        let msgs = msg.messages;
+        if msgs.len() > self.config.limits.max_batch_messages {
+            return Err(self.rejections.reject(Limit::BatchMessages));
+        }
+        if self.is_expired(&msg.message) {
+            debug!("Dropping expired message {}", b64_encode(&msg.message.id));
+            return Ok(());
+        }
+        // Checked only once the signature is, so forged copies cannot get an
+        // id marked as seen before the real message arrives
+        if !self.remember(&msg.message.id) {
+            debug!("Dropping duplicate message {}", b64_encode(&msg.message.id));
+            return Ok(());
+        }
+        let transport = Arc::new(msg.message);
+        let msg = msgs
+            .into_iter()
+            .enumerate()
+            .map(|(index, x)| MessageContext {
+                message: x,
+                peer: msg.peer.clone(),
+                transport: transport.clone(),
+                index,
+            })
+            .collect::<Vec<_>>();
+
+        for msg in msg {
+            if let Some(lane) = self.lane(&msg.message) {
+                if self.lane_tx[lane].send(msg).await.is_err() {
+                    debug!("Worker {lane} stopped");
+                }
+                continue;
+            }
+
+            if let Err(e) = self.process_message(msg).await {
+                debug!("Failed to process message: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_message(&self, msg: MessageContext) -> Result<(), NodeError> {
+        // Writes, reads and what peers say of themselves or others are only
+        // taken from peers that proved their key, though those passed on by a
+        // peer may be signed by anyone
+        if matches!(
+            msg.message,
+            Message::Insert { .. }
+                | Message::Delete { .. }
+                | Message::Get { .. }
+                | Message::Capabilities { .. }
+                | Message::Gossip { .. }
+        ) && msg.peer.stage().await != PeerInitStage::Welcome
+        {
+            return Err(NodeError::NotAuthenticated);
+        }
+        // An observer is never where a write enters the network, so it only
+        // takes those a peer passes on for someone else
+        if self.config.observer
+            && is_write(&msg.message)
+            && msg
+                .peer
+                .key()
+                .is_none_or(|key| key == msg.transport.signature.signed_by)
+        {
+            return Err(NodeError::ReadOnly);
+        }
+
+        match msg.message {
+            Message::Insert {
+                location,
+                incoming_data,
+                metadata,
+                state,
+            } => {
+                if !self.holds(&location.namespace) {
+                    debug!("Ignoring insert into {}, not held", location.namespace);
+                    return Ok(());
+                }
+                let sequence = self.journal.record(&msg.transport, msg.index)?;
+                let applied = self
+                    .insert(&location, incoming_data, metadata, state, &msg.transport)
+                    .await;
+                // Refused writes are done with as well; only a crash leaves
+                // one in the journal
+                self.journal.complete(sequence)?;
+                let report = applied?;
+                self.replicate(&location.namespace, &msg.peer, &msg.transport)
+                    .await;
+
+                if self.config.execution_reports {
+                    self.deliver(&msg.peer, Message::ExecutionReport { location, report })
+                        .await?;
+                }
+                Ok(())
+            }
+            Message::Delete { location, state } => {
+                if !self.holds(&location.namespace) {
+                    debug!("Ignoring deletion in {}, not held", location.namespace);
+                    return Ok(());
+                }
+                let sequence = self.journal.record(&msg.transport, msg.index)?;
+                let applied = self.delete(&location, state, &msg.transport).await;
+                self.journal.complete(sequence)?;
+                let report = applied?;
+                self.replicate(&location.namespace, &msg.peer, &msg.transport)
+                    .await;
+
+                if self.config.execution_reports {
+                    self.deliver(&msg.peer, Message::ExecutionReport { location, report })
+                        .await?;
+                }
+                Ok(())
+            }
+            Message::Get { location, select } => {
+                let value = self.read(&location, &msg.transport).await?;
+                let proof = if select.is_empty() {
+                    self.prove(&location, value.as_ref()).await?
+                } else {
+                    None
+                };
+                let value = value.map(|value| value.select(&select));
+                self.deliver(
+                    &msg.peer,
+                    Message::GetResult {
+                        location,
+                        value,
+                        proof,
+                    },
+                )
+                .await
+            }
+            Message::DeployContract {
+                contract_payload,
+                namespace,
+                params,
+                tags,
+                id: expected,
+            } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                if let Some(expected) = expected
+                    && !verify_contract_id(&expected, &contract_payload, signed_by)
+                {
+                    return Err(NodeError::ContractIdMismatch);
+                }
+                let id = self
+                    .deploy_contract(
+                        contract_payload,
+                        namespace.clone(),
+                        params,
+                        tags,
+                        signed_by.clone(),
+                    )
+                    .await?;
+                debug!("Deployed contract {}", b64_encode(&id));
+                if self.config.audit {
+                    self.record_audit(
+                        &msg.transport,
+                        &namespace,
+                        format!("deploy {}", b64_encode(&id)),
+                        None,
+                    )?;
+                }
+                Ok(())
+            }
+            Message::UpgradeContract {
+                contract,
+                contract_payload,
+            } => {
+                self.upgrade_contract(
+                    &contract,
+                    contract_payload,
+                    &msg.transport.signature.signed_by,
+                )
+                .await?;
+                self.upgraded(&contract, &msg.transport)
+            }
+            Message::PatchContract {
+                contract,
+                base,
+                patch,
+                target,
+            } => {
+                self.patch_contract(
+                    &contract,
+                    &base,
+                    &patch,
+                    &target,
+                    &msg.transport.signature.signed_by,
+                )
+                .await?;
+                self.upgraded(&contract, &msg.transport)
+            }
+            Message::SearchTags { namespace, query } => {
+                let contracts = self.search_contracts(&namespace, &query)?;
+                self.deliver(
+                    &msg.peer,
+                    Message::SearchResult {
+                        namespace,
+                        contracts,
+                    },
+                )
+                .await
+            }
+            Message::Subscribe {
+                namespace,
+                topic,
+                filter,
+            } => {
+                if filter
+                    .as_ref()
+                    .is_some_and(|f| f.depth() > MAX_FILTER_DEPTH)
+                {
+                    return Err(NodeError::InvalidFilter(FilterError::TooDeep));
+                }
+                self.events
+                    .subscribe(msg.peer, namespace, topic, filter)
+                    .await;
+                Ok(())
+            }
+            Message::Unsubscribe { namespace, topic } => {
+                self.events
+                    .unsubscribe(&msg.peer, &namespace, topic.as_deref())
+                    .await;
+                Ok(())
+            }
+            Message::Event { event } => {
+                self.events.deliver_local(event);
+                Ok(())
+            }
+            Message::Hello {
+                public_key,
+                certificates,
+                ..
+            } => {
+                if public_key != msg.transport.signature.signed_by {
+                    return Err(NodeError::ChallengeFailed);
+                }
+                self.challenge(&msg.peer, public_key, &certificates).await
+            }
+            Message::WhoAreYou { data, .. } => {
+                // Only challenges to this node's own Hello are signed, once
+                if !msg.peer.awaiting_challenge.swap(false, Ordering::AcqRel) {
+                    return Err(NodeError::UnexpectedHandshake(msg.peer.stage().await));
+                }
+                let signed = self
+                    .signer
+                    .sign(&challenge_payload(&data))
+                    .await
+                    .map_err(NodeError::SigningError)?;
+                let answer = Message::ItsMe {
+                    signature: signed.signature,
+                    data,
+                };
+                msg.peer.send(self.sign(&[answer]).await?).await
+            }
+            Message::ItsMe { signature, data } => self.welcome(&msg.peer, &signature, data).await,
+            Message::Welcome { .. } => {
+                debug!(
+                    "Welcomed by {}",
+                    b64_encode(&msg.transport.signature.signed_by)
+                );
+                msg.peer.secure();
+                // The peer takes what the node says of itself only from now
+                if msg.peer.awaiting_challenge.load(Ordering::Acquire)
+                    || msg.peer.welcomed.swap(true, Ordering::AcqRel)
+                {
+                    return Ok(());
+                }
+                self.advertise(&msg.peer).await;
+                Ok(())
+            }
+            Message::Capabilities { capabilities } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                // Capabilities of other nodes passed on by a relay say nothing
+                // about the relay itself
+                if msg.peer.key().as_ref() != Some(signed_by) {
+                    return Ok(());
+                }
+
+                *msg.peer.capabilities.lock().unwrap() = capabilities;
+                Ok(())
+            }
+            Message::Forward { to, payload } => {
+                self.relay(&msg.transport.signature.signed_by, &to, payload)
+                    .await
+            }
+            Message::ShareGroupKey {
+                namespace,
+                epoch,
+                keys,
+            } => {
+                let shared_by = &msg.transport.signature.signed_by;
+                // Later epochs are checked against the group's owner
+                if self.groups.get(&namespace)?.is_none()
+                    && !self.registry.is_deployer(&namespace, shared_by)?
+                {
+                    return Err(NodeError::NotAuthorized);
+                }
+                if !self.groups.share(&namespace, shared_by, epoch, keys)? {
+                    debug!("Ignoring epoch {epoch} of the group of {namespace}");
+                    return Ok(());
+                }
+                if self.config.audit {
+                    self.record_audit(
+                        &msg.transport,
+                        &namespace,
+                        format!("group epoch {epoch}"),
+                        None,
+                    )?;
+                }
+                Ok(())
+            }
+            Message::SetWriters { namespace, writers } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                // Namespaces with admins change writers only as they agree
+                if !self.registry.is_deployer(&namespace, signed_by)?
+                    || self.registry.admins(&namespace)?.is_some()
+                {
+                    return Err(NodeError::NotAuthorized);
+                }
+                self.registry.set_writers(&namespace, writers.as_deref())?;
+                if self.config.audit {
+                    self.record_audit(&msg.transport, &namespace, "set writers".to_string(), None)?;
+                }
+                Ok(())
+            }
+            Message::SetAdmins { namespace, admins } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                if !self.registry.is_deployer(&namespace, signed_by)?
+                    || self.registry.admins(&namespace)?.is_some()
+                {
+                    return Err(NodeError::NotAuthorized);
+                }
+                let admins = ThresholdKey::import(&admins).map_err(NodeError::InvalidAdmins)?;
+                self.registry.set_admins(&namespace, &admins, 0)?;
+                if self.config.audit {
+                    self.record_audit(&msg.transport, &namespace, "set admins".to_string(), None)?;
+                }
+                Ok(())
+            }
+            Message::AdminSetWriters {
+                namespace,
+                writers,
+                sequence,
+                shares,
+            } => {
+                self.admin_set_writers(
+                    &namespace,
+                    writers,
+                    sequence,
+                    ThresholdSignature { shares },
+                )?;
+                if self.config.audit {
+                    self.record_audit(&msg.transport, &namespace, "set writers".to_string(), None)?;
+                }
+                Ok(())
+            }
+            // Answered at once rather than batched, as waiting for the batch
+            // window would count towards the round trip
+            Message::Ping { nonce } => {
+                msg.peer
+                    .send(self.sign(&[Message::Pong { nonce }]).await?)
+                    .await
+            }
+            Message::Pong { nonce } => {
+                msg.peer.record_pong(nonce);
+                Ok(())
+            }
+            Message::Replicate { namespaces } => {
+                *msg.peer.replicated.lock().unwrap() = namespaces.into_iter().collect();
+                self.deliver(
+                    &msg.peer,
+                    Message::Capabilities {
+                        capabilities: self.capabilities(),
+                    },
+                )
+                .await
+            }
+            Message::Gossip { peers, localities } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                self.learn_addresses(&msg.peer, signed_by, peers);
+                self.learn_localities(signed_by, localities);
+                Ok(())
+            }
+            Message::GetGroupKey { namespace } => {
+                let signed_by = &msg.transport.signature.signed_by;
+                let (epoch, key) = match self.groups.get(&namespace)? {
+                    Some(mut group) => (group.epoch, group.keys.remove(signed_by)),
+                    None => (0, None),
+                };
+                self.deliver(
+                    &msg.peer,
+                    Message::GroupKey {
+                        namespace,
+                        epoch,
+                        key,
+                    },
+                )
+                .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the contract governing `location` against an insert and stores
+    /// the actions it approves.
+    async fn insert(
+        &self,
+        location: &Location,
+        incoming_data: DbValue,
+        metadata: HashMap<String, DbValue>,
+        state: u64,
+        transport: &TransportMessage,
+    ) -> Result<ExecutionReport, NodeError> {
+        self.check_writer(&location.namespace, &transport.signature.signed_by)?;
+        self.policies
+            .check(location, &incoming_data, &transport.signature.signed_by)
+            .map_err(NodeError::PolicyViolation)?;
+        if let Some(group) = self.groups.get(&location.namespace)?
+            && sealed_epoch(&incoming_data) != Some(group.epoch)
+        {
+            return Err(NodeError::Unsealed);
+        }
+        let action = DataAction::Insert {
+            key: location.key.clone(),
+            incoming_data,
+            params: metadata,
+        };
+        self.init_space(location, &action, transport).await?;
+        let (actions, report) = self.execute_contract(location, action, transport).await?;
+        self.apply_actions(location, actions, state, transport.signed_at)
+            .await?;
+        if self.config.audit {
+            let state =
+                self.data
+                    .state(&location.namespace, &location.contract_space, &location.key)?;
+            self.record_audit(
+                transport,
+                &location.namespace,
+                format!("insert {}/{}", location.contract_space, location.key),
+                Some(state),
+            )?;
+        }
+        Ok(report)
+    }
+
+    /// Runs the delete handler of the contract governing `location` and
+    /// stores the actions it approves, deletions among them as of `state`.
+    async fn delete(
+        &self,
+        location: &Location,
+        state: u64,
+        transport: &TransportMessage,
+    ) -> Result<ExecutionReport, NodeError> {
+        self.check_writer(&location.namespace, &transport.signature.signed_by)?;
+        let action = DataAction::Delete {
+            key: location.key.clone(),
+        };
+        self.init_space(location, &action, transport).await?;
+        let (actions, report) = self.execute_contract(location, action, transport).await?;
+        self.apply_actions(location, actions, state, transport.signed_at)
+            .await?;
+        if self.config.audit {
+            self.record_audit(
+                transport,
+                &location.namespace,
+                format!("delete {}/{}", location.contract_space, location.key),
+                Some(state),
+            )?;
+        }
+        Ok(report)
+    }
+
+    /// Applies the writes left in the journal by a crash, in the order they
+    /// were accepted. Writes may have been partly applied before, so their
+    /// contracts can run twice.
+    async fn replay_journal(&self) {
+        let pending = match self.journal.pending() {
+            Ok(pending) => pending,
+            Err(e) => {
+                warn!("Failed to read the write journal: {e:?}");
+                return;
+            }
+        };
+        if !pending.is_empty() {
+            info!("Replaying {} journaled writes", pending.len());
+        }
+
+        for write in pending {
+            // Copies still on their way from peers are not applied again
+            self.seen.insert(&write.transport.id);
+            if let Err(e) = self.replay(&write).await {
+                warn!("Failed to replay write {}: {e:?}", write.sequence);
+            }
+            if let Err(e) = self.journal.complete(write.sequence) {
+                warn!("Failed to complete write {}: {e:?}", write.sequence);
+            }
+        }
+    }
+
+    async fn replay(&self, write: &PendingWrite) -> Result<(), NodeError> {
+        let messages: Vec<Message> = write
+            .transport
+            .clone()
+            .try_into()
+            .map_err(NodeError::ProtocolError)?;
+        match messages.into_iter().nth(write.index) {
+            Some(Message::Insert {
+                location,
+                incoming_data,
+                metadata,
+                state,
+            }) => {
+                self.insert(&location, incoming_data, metadata, state, &write.transport)
+                    .await?;
+            }
+            Some(Message::Delete { location, state }) => {
+                self.delete(&location, state, &write.transport).await?;
+            }
+            _ => return Err(NodeError::NoMessage),
+        }
+        Ok(())
+    }
+
+    /// Runs the contract's init hook if it has not touched the contract space
+    /// of `location` yet. The hook's actions are applied with state 0, so any
+    /// write made through the contract takes precedence over them.
+    async fn init_space(
+        &self,
+        location: &Location,
+        action: &DataAction,
+        transport: &TransportMessage,
+    ) -> Result<(), NodeError> {
+        let (namespace, contract_space) = (&location.namespace, &location.contract_space);
+        if self
+            .registry
+            .has_space(&location.contract, namespace, contract_space)?
+        {
+            return Ok(());
+        }
+        self.check_budget(namespace)?;
+
+        let contract = self
+            .get_contract(&location.contract)
+            .await
+            .ok_or(NodeError::UnknownContract)?;
+
+        let ctx = self.context(location, action.clone(), transport)?;
+        let host = Arc::new(MessageHost::new(
+            self.data.clone(),
+            &location.contract,
+            transport,
+        ));
+        let call_host = host.clone();
+
+        let permit = self.execution_permit()?;
+        let started = Instant::now();
+        let actions = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            contract.blocking_lock().init(ctx, call_host)
+        })
+        .await
+        .map_err(NodeError::RuntimeError)?;
+        self.account(&location.contract, namespace, &host, started)?;
+        let actions = actions.map_err(NodeError::ContractError)?;
+        host.commit()?;
+
+        // Recorded even without a hook, so it is only looked for once per space
+        self.registry
+            .add_space(&location.contract, namespace, contract_space)?;
+        match actions {
+            Some(actions) => {
+                self.apply_actions(location, actions, 0, transport.signed_at)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Value served for a read of `location`: the result of the contract's
+    /// query if it has one, the stored value otherwise. Private state written
+    /// by the query is discarded.
+    async fn read(
+        &self,
+        location: &Location,
+        transport: &TransportMessage,
+    ) -> Result<Option<DbValue>, NodeError> {
+        self.check_budget(&location.namespace)?;
+        let contract = self
+            .get_contract(&location.contract)
+            .await
+            .ok_or(NodeError::UnknownContract)?;
+
+        let action = DataAction::Get {
+            key: location.key.clone(),
+        };
+        let ctx = self.context(location, action, transport)?;
+        let host = Arc::new(MessageHost::new(
+            self.data.clone(),
+            &location.contract,
+            transport,
+        ));
+
+        let call_host = host.clone();
+
+        let permit = self.execution_permit()?;
+        let started = Instant::now();
+        let queried = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            contract.blocking_lock().query(ctx, call_host)
+        })
+        .await
+        .map_err(NodeError::RuntimeError)?;
+        self.account(&location.contract, &location.namespace, &host, started)?;
+        let queried = queried.map_err(NodeError::ContractError)?;
+
+        match queried {
+            Some(value) => Ok(Some(value)),
+            None => self
+                .data
+                .get(&location.namespace, &location.contract_space, &location.key),
+        }
+    }
+
+    /// Proof that `served` is what the node stores at `location`, if it
+    /// proves its reads. Query results are not stored, so nothing proves
+    /// them.
+    async fn prove(
+        &self,
+        location: &Location,
+        served: Option<&DbValue>,
+    ) -> Result<Option<StateProof>, NodeError> {
+        if self.config.state_proofs == StateProofs::Off {
+            return Ok(None);
+        }
+        let (namespace, contract_space) = (&location.namespace, &location.contract_space);
+        let stored = self.data.get(namespace, contract_space, &location.key)?;
+        if stored.as_ref() != served {
+            return Ok(None);
+        }
+
+        let state = self.data.state(namespace, contract_space, &location.key)?;
+        let anchor = match self.config.state_proofs {
+            StateProofs::Anchored if stored.is_some() => {
+                let keys = self.data.keys(namespace, contract_space)?;
+                let mut leaves = Vec::with_capacity(keys.len());
+                for key in &keys {
+                    let value = self.data.get(namespace, contract_space, key)?;
+                    let state = self.data.state(namespace, contract_space, key)?;
+                    leaves.push(leaf_digest(key, &value_digest(value.as_ref()), state));
+                }
+                keys.iter()
+                    .position(|key| *key == location.key)
+                    .map(|index| merkle_anchor(&leaves, index))
+            }
+            _ => None,
+        };
+
+        StateProof::sign_with(
+            &*self.signer,
+            location.clone(),
+            stored.as_ref(),
+            state,
+            anchor,
+        )
+        .await
+        .map(Some)
+        .map_err(NodeError::SigningError)
+    }
+
+    /// Context for a call of the contract governing `location`, made on behalf
+    /// of `transport`.
+    fn context(
+        &self,
+        location: &Location,
+        action: DataAction,
+        transport: &TransportMessage,
+    ) -> Result<ContractContext, NodeError> {
+        Ok(ContractContext {
+            action,
+            namespace: location.namespace.clone(),
+            contract_space: location.contract_space.clone(),
+            signed_by: transport.signature.signed_by.clone(),
+            contract_params: self.registry.params(&location.contract)?,
+            message_id: transport.id.clone(),
+            signed_at: transport.signed_at,
+            publisher: transport.publisher.clone(),
+            state: self
+                .data
+                .state(&location.namespace, &location.contract_space, &location.key)?,
+        })
+    }
+
+    /// Runs the contract governing `location` against `action` and returns the
+    /// actions it approved, with the resources the call used.
+    async fn execute_contract(
+        &self,
+        location: &Location,
+        action: DataAction,
+        transport: &TransportMessage,
+    ) -> Result<(Vec<DataAction>, ExecutionReport), NodeError> {
+        self.check_budget(&location.namespace)?;
+        let contract = self
+            .get_contract(&location.contract)
+            .await
+            .ok_or(NodeError::UnknownContract)?;
+
+        let ctx = self.context(location, action, transport)?;
+        let host = Arc::new(MessageHost::new(
+            self.data.clone(),
+            &location.contract,
+            transport,
+        ));
+        let call_host = host.clone();
+
+        let permit = self.execution_permit()?;
+        let started = Instant::now();
+        let actions = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            contract.blocking_lock().execute(ctx, call_host)
+        })
+        .await
+        .map_err(NodeError::RuntimeError)?;
+        let report = self.account(&location.contract, &location.namespace, &host, started)?;
+        let actions = actions.map_err(NodeError::ContractError)?;
+        host.commit()?;
+
+        Ok((actions, report))
+    }
+
+    /// Runs contract `id` against `ctx` without committing anything: private
+    /// state it writes is discarded and the actions it returns are neither
+    /// applied nor broadcast. `ctx` gets the contract's deploy params filled
+    /// in. The contract's init hook is not run, so a simulated call in a space
+    /// the contract has not written to yet may differ from the real one.
+    pub async fn simulate_contract(
+        &self,
+        id: &[u8],
+        mut ctx: ContractContext,
+    ) -> Result<Simulation, NodeError> {
+        let contract = self
+            .get_contract(id)
+            .await
+            .ok_or(NodeError::UnknownContract)?;
+
+        ctx.contract_params = self.registry.params(id)?;
+        let seed = hash::blake3(&rmp_serde::to_vec(&ctx).unwrap());
+        let host = Arc::new(MessageHost::seeded(self.data.clone(), id, seed));
+        let call_host = host.clone();
+
+        let permit = self.execution_permit()?;
+        let started = Instant::now();
+        let actions = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            contract.blocking_lock().execute(ctx, call_host)
+        })
+        .await
+        .map_err(NodeError::RuntimeError)?
+        .map_err(NodeError::ContractError)?;
+
+        Ok(Simulation {
+            actions,
+            report: host.report(started.elapsed()),
+        })
+    }
+
+    /// Stores the actions a contract approved at `state`, on behalf of a
+    /// message signed at `signed_at`; 0 for calls not made for a message.
+    async fn apply_actions(
+        &self,
+        location: &Location,
+        actions: Vec<DataAction>,
+        state: u64,
+        signed_at: u64,
+    ) -> Result<(), NodeError> {
+        let (namespace, contract_space) = (&location.namespace, &location.contract_space);
+        self.registry
+            .add_space(&location.contract, namespace, contract_space)?;
+        let policy = self.merge_policy(namespace);
+
+        for action in actions {
+            let (kind, key) = (action_kind(&action), action.key().map(str::to_string));
+            if let Err(e) = self
+                .apply_action(location, policy, action, state, signed_at)
+                .await
+            {
+                self.diagnostics.record(
+                    &location.contract,
+                    ActionRejection {
+                        action: kind,
+                        namespace: namespace.clone(),
+                        contract_space: contract_space.clone(),
+                        key: key.unwrap_or_default(),
+                        limit: match e {
+                            NodeError::LimitExceeded(limit) => Some(limit),
+                            _ => None,
+                        },
+                        reason: format!("{e:?}"),
+                        rejected_at: unix_time(),
+                    },
+                );
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn apply_action(
+        &self,
+        location: &Location,
+        policy: MergePolicy,
+        action: DataAction,
+        state: u64,
+        signed_at: u64,
+    ) -> Result<(), NodeError> {
+        let (namespace, contract_space) = (&location.namespace, &location.contract_space);
+        match action {
+            DataAction::Insert {
+                key, incoming_data, ..
+            } => match policy {
+                MergePolicy::Content => {
+                    self.data
+                        .insert(namespace, contract_space, &key, incoming_data, state)?
+                }
+                MergePolicy::LatestSigned => self.data.insert_latest(
+                    namespace,
+                    contract_space,
+                    &key,
+                    incoming_data,
+                    state,
+                    signed_at,
+                )?,
+            },
+            DataAction::Delete { key } => {
+                self.data.delete(namespace, contract_space, &key, state)?
+            }
+            DataAction::Patch { key, ops } => {
+                self.data
+                    .patch(namespace, contract_space, &key, &ops, state)?
+            }
+            DataAction::Get { .. } => {}
+            DataAction::Emit { topic, payload } => {
+                self.publish_event(ContractEvent {
+                    namespace: namespace.clone(),
+                    contract_space: contract_space.clone(),
+                    topic,
+                    payload,
+                })
+                .await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_peer(&self, peer: Box<dyn TransportPeer>) {
+        let peer = Arc::new(Peer {
+            transport: peer,
+            stage: RwLock::new(PeerInitStage::None),
+            challenge: std::sync::Mutex::new(None),
+            awaiting_challenge: AtomicBool::new(false),
+            session: SessionState::new(),
+            sending: Mutex::new(()),
+            identity: std::sync::Mutex::new(None),
+            key: std::sync::Mutex::new(None),
+            welcomed: AtomicBool::new(false),
+            capabilities: std::sync::Mutex::new(Vec::new()),
+            replicated: std::sync::Mutex::new(HashSet::new()),
+            pings: std::sync::Mutex::new(VecDeque::new()),
+            rtt: std::sync::Mutex::new(None),
+            read_thread: Mutex::new(None),
+            outbox: std::sync::Mutex::new(Vec::new()),
+            flushing: Mutex::new(()),
+        });
+
+        let mut read_thread_lock = peer.read_thread.lock().await;
+        let cloned_peer = peer.clone();
+        let tx = self.msg_tx.clone();
+        let rejections = self.rejections.clone();
+        let max_size = self.config.limits.max_message_size;
+
+        *read_thread_lock = Some(tokio::task::spawn(async move {
+            let peer = cloned_peer;
+
+            loop {
+                let msg = match peer.next(max_size).await {
+                    Ok(msg) => msg,
+                    Err(NodeError::LimitExceeded(limit)) => {
+                        debug!("Dropping oversized message");
+                        rejections.record(limit);
+                        continue;
+                    }
+                    // Frames are delimited by the transport, so one that does
+                    // not decode is dropped without losing the next
+                    Err(NodeError::SchemaError(e)) => {
+                        debug!("Dropping undecodable message: {e:?}");
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                let messages: Vec<Message> = match msg.clone().try_into() {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        debug!("Dropping invalid message: {:?}", e);
+                        continue;
+                    }
+                };
+                peer.agree(&msg, &messages);
+                let relayed = peer.key().is_some_and(|key| key != msg.signature.signed_by);
+                let priority = Priority::of_batch(&messages, relayed);
+                tx[priority as usize]
+                    .send(IncomingMessage {
+                        peer: peer.clone(),
+                        message: msg,
+                        messages,
+                    })
+                    .await
+                    .expect("The message channel must always be open");
+                yield_now().await;
+            }
+        }));
+
+        drop(read_thread_lock);
+
+        self.peers.write().await.push(peer.clone());
+
+        if let Err(e) = self.introduce(&peer).await {
+            debug!("Failed to introduce the node: {e:?}");
+        }
+
+        if let Some(namespaces) = &self.config.namespaces {
+            let mut namespaces: Vec<_> = namespaces.iter().cloned().collect();
+            namespaces.sort();
+            if let Err(e) = self.deliver(&peer, Message::Replicate { namespaces }).await {
+                debug!("Failed to ask for replication: {e:?}");
+            }
+        }
+    }
+
+    /// Sends `peer` the node's capabilities and what it knows of other nodes,
+    /// once the peer welcomed it and so takes them.
+    async fn advertise(&self, peer: &Arc<Peer>) {
+        let capabilities = self.capabilities();
+        if !capabilities.is_empty()
+            && let Err(e) = self
+                .deliver(peer, Message::Capabilities { capabilities })
+                .await
+        {
+            debug!("Failed to advertise capabilities: {e:?}");
+        }
+
+        if let Some(gossip) = self.gossip()
+            && let Err(e) = self.deliver(peer, gossip).await
+        {
+            debug!("Failed to gossip addresses: {e:?}");
+        }
+    }
+
+    /// Sends `peer` a `Hello`, so it takes this node's inserts and reads once
+    /// the node answered its challenge.
+    async fn introduce(&self, peer: &Peer) -> Result<(), NodeError> {
+        let hello = Message::Hello {
+            public_key: self.identity.clone(),
+            session_key: peer.session.public().to_vec(),
+            certificates: Vec::new(),
+        };
+        peer.awaiting_challenge.store(true, Ordering::Release);
+        peer.send(self.sign(&[hello]).await?).await
+    }
+
+    /// Answers the `Hello` of `peer`, introducing `public_key`, with a
+    /// challenge, if `certificates` admit it.
+    async fn challenge(
+        &self,
+        peer: &Peer,
+        public_key: Vec<u8>,
+        certificates: &[Vec<u8>],
+    ) -> Result<(), NodeError> {
+        let mut stage = peer.stage.write().await;
+        if *stage != PeerInitStage::None {
+            return Err(NodeError::UnexpectedHandshake(*stage));
+        }
+        self.admit(peer, &public_key, certificates)?;
+        *stage = PeerInitStage::Hello;
+
+        // The peer is known by the key only once it signs the challenge
+        let data = rand::random::<[u8; CHALLENGE_SIZE]>().to_vec();
+        *peer.challenge.lock().unwrap() = Some((data.clone(), public_key));
+        let challenge = Message::WhoAreYou {
+            data,
+            public_key: self.identity.clone(),
+            session_key: peer.session.public().to_vec(),
+            certificates: Vec::new(),
+        };
+        peer.send(self.sign(&[challenge]).await?).await?;
+        *stage = PeerInitStage::WhoAreYou;
+        Ok(())
+    }
+
+    /// Checks the `ItsMe` of `peer` against the challenge it was sent, and
+    /// welcomes it if the challenge was signed with the key of its `Hello`.
+    async fn welcome(&self, peer: &Peer, signature: &[u8], data: Vec<u8>) -> Result<(), NodeError> {
+        let mut stage = peer.stage.write().await;
+        if *stage != PeerInitStage::WhoAreYou {
+            return Err(NodeError::UnexpectedHandshake(*stage));
+        }
+        // Taken either way, so each challenge is answered at most once
+        let challenge = peer.challenge.lock().unwrap().take();
+        let payload = challenge_payload(&data);
+        let key = challenge.and_then(|(challenge, key)| {
+            let signed = challenge == data
+                && PublicKey::import(&key).is_ok_and(|key| key.verify(&payload, signature));
+            signed.then_some(key)
+        });
+        let Some(key) = key else {
+            return Err(NodeError::ChallengeFailed);
+        };
+        peer.identify(key);
+        *stage = PeerInitStage::ItsMe;
+
+        let (dht_ip, dht_port) = self.advertised_address();
+        let signature = self
+            .signer
+            .sign(&payload)
+            .await
+            .map_err(NodeError::SigningError)?
+            .signature;
+        let welcome = Message::Welcome {
+            dht_ip,
+            dht_port,
+            signature,
+        };
+        peer.send(self.sign(&[welcome]).await?).await?;
+        peer.secure();
+        *stage = PeerInitStage::Welcome;
+        Ok(())
+    }
+
+    /// Host and port of the address the node advertises, empty and 0 if it
+    /// advertises none.
+    fn advertised_address(&self) -> (String, u16) {
+        self.config
+            .discovery
+            .as_ref()
+            .and_then(|discovery| discovery.advertise.as_deref())
+            .and_then(|addr| {
+                let (host, port) = addr.rsplit_once(':')?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                Some((host.to_string(), port.parse().ok()?))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Addresses of other nodes that answered at them; see [`addresses`].
+    #[must_use]
+    pub fn addresses(&self) -> HashMap<Vec<u8>, Vec<String>> {
+        self.addresses.addresses()
+    }
+
+    /// Localities of other nodes, as learned from gossip; see [`locality`].
+    #[must_use]
+    pub fn localities(&self) -> HashMap<Vec<u8>, Locality> {
+        self.localities.localities()
+    }
+
+    /// The address book with the node's own advertised address, and the
+    /// localities it knows with its own, for new peers. `None` if the node
+    /// neither learns addresses nor has a locality.
+    fn gossip(&self) -> Option<Message> {
+        let discovery = self.config.discovery.as_ref();
+        if discovery.is_none() && self.config.locality.is_none() {
+            return None;
+        }
+        let mut peers = HashMap::new();
+        if let Some(discovery) = discovery {
+            peers.extend(
+                self.addresses
+                    .addresses()
+                    .into_iter()
+                    .map(|(key, addrs)| (key, addrs.into_iter().map(String::into_bytes).collect())),
+            );
+            if let Some(advertise) = &discovery.advertise {
+                peers.insert(self.identity.clone(), vec![advertise.clone().into_bytes()]);
+            }
+        }
+        let mut localities = self.localities.localities();
+        if let Some(locality) = &self.config.locality {
+            localities.insert(self.identity.clone(), locality.clone());
+        }
+        Some(Message::Gossip { peers, localities })
+    }
+
+    /// Records the localities in a `Gossip` signed by `signed_by`, which
+    /// speaks for itself only about its own.
+    fn learn_localities(&self, signed_by: &[u8], gossip: HashMap<Vec<u8>, Locality>) {
+        for (key, locality) in gossip.into_iter().take(addresses::MAX_KNOWN_NODES) {
+            if key == self.identity {
+                continue;
+            }
+            let own = key == signed_by;
+            if self.localities.learn(&key, locality, own) && own {
+                debug!("Learned the locality of {}", b64_encode(&key));
+            }
+        }
+    }
+
+    /// Queues the addresses in a `Gossip` signed by `signed_by` for probing.
+    /// Only the address a node gives for itself may leave the host out, and
+    /// only if the peer it came from connected to this node.
+    fn learn_addresses(
+        &self,
+        peer: &Peer,
+        signed_by: &[u8],
+        gossip: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    ) {
+        if self.config.discovery.is_none() {
+            return;
+        }
+        let metadata = peer.metadata();
+        let own = metadata.inbound && peer.key().as_deref() == Some(signed_by);
+
+        for (key, addrs) in gossip.into_iter().take(addresses::MAX_KNOWN_NODES) {
+            if key == self.identity {
+                continue;
+            }
+            let observed = if own && key == signed_by {
+                metadata.remote_addr
+            } else {
+                None
+            };
+            for addr in addrs.into_iter().take(addresses::MAX_ADDRESSES_PER_NODE) {
+                let Some(addr) = String::from_utf8(addr)
+                    .ok()
+                    .and_then(|addr| addresses::resolve(&addr, observed))
+                else {
+                    continue;
+                };
+                if !self.addresses.begin_probe(&key, &addr) {
+                    continue;
+                }
+                if let Err(e) = self.probe_tx.try_send((key.clone(), addr.clone())) {
+                    debug!("Failed to queue a probe: {e:?}");
+                    self.addresses.end_probe(&key, &addr, false);
+                }
+            }
+        }
+    }
+
+    /// Probes the addresses queued by [`Node::learn_addresses`], gossiping
+    /// those that are confirmed to every peer.
+    async fn run_probes(&self, probes: Receiver<(Vec<u8>, String)>) {
+        let Some(discovery) = &self.config.discovery else {
+            return;
+        };
+        let probes = futures::stream::unfold(probes, |mut probes| async {
+            probes.recv().await.map(|probe| (probe, probes))
+        });
+
+        probes
+            .for_each_concurrent(addresses::MAX_PROBES, |(key, addr)| async move {
+                let nonce = rand::random();
+                let confirmed = match self.sign(&[Message::Ping { nonce }]).await {
+                    Ok(ping) => {
+                        addresses::probe(
+                            discovery.client.as_ref(),
+                            &addr,
+                            &key,
+                            &ping,
+                            nonce,
+                            self.config.limits.max_message_size,
+                            discovery.probe_timeout,
+                        )
+                        .await
+                    }
+                    Err(e) => {
+                        warn!("Failed to sign a probe: {e:?}");
+                        false
+                    }
+                };
+                if !self.addresses.end_probe(&key, &addr, confirmed) {
+                    return;
+                }
+
+                info!("Learned address {addr} of {}", b64_encode(&key));
+                let localities = self
+                    .localities
+                    .get(&key)
+                    .map(|locality| (key.clone(), locality))
+                    .into_iter()
+                    .collect();
+                let gossip = Message::Gossip {
+                    peers: HashMap::from([(key, vec![addr.into_bytes()])]),
+                    localities,
+                };
+                let peers = self.peers.read().await.clone();
+                for peer in peers {
+                    if let Err(e) = self.deliver(&peer, gossip.clone()).await {
+                        debug!("Failed to gossip an address: {e:?}");
+                    }
+                }
+            })
+            .await;
+    }
+
+    /// Whether the node stores `namespace`; see [`NodeConfig::namespaces`].
+    #[must_use]
+    pub fn holds(&self, namespace: &str) -> bool {
+        self.config
+            .namespaces
+            .as_ref()
+            .is_none_or(|namespaces| namespaces.contains(namespace))
+    }
+
+    /// Passes `transport`, the batch of a write applied in `namespace`, on to
+    /// the peers other than `from` that asked for it. A batch with writes in
+    /// several namespaces may reach a peer more than once, which drops the
+    /// copies as duplicates.
+    async fn replicate(&self, namespace: &str, from: &Arc<Peer>, transport: &TransportMessage) {
+        let peers: Vec<_> = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .filter(|peer| {
+                !Arc::ptr_eq(peer, from) && peer.replicated.lock().unwrap().contains(namespace)
+            })
+            .cloned()
+            .collect();
+        let peers = match self.config.fanout {
+            Fanout::All => peers,
+            Fanout::Latency { peers: count, .. } => pick_by_latency(peers, count.max(1)),
+            Fanout::Regional { copies } => {
+                let peers = peers
+                    .into_iter()
+                    .map(|peer| {
+                        let region = peer
+                            .key()
+                            .and_then(|key| self.localities.get(&key))
+                            .map(|locality| locality.region);
+                        (region, peer)
+                    })
+                    .collect();
+                let own = self.config.locality.as_ref().map(|l| l.region.as_str());
+                locality::per_region(peers, own, copies, pick_by_latency)
+            }
+        };
+        if peers.is_empty() {
+            return;
+        }
+
+        let raw = encode(transport);
+        for peer in peers {
+            if let Err(e) = peer.send_encoded(raw.clone()).await {
+                debug!("Failed to replicate an insert: {e:?}");
+            }
+        }
+    }
+
+    /// Services this node offers its peers.
+    fn capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if self.config.relay.is_some() {
+            capabilities.push(Capability::Relay);
+        }
+        if self.config.observer {
+            capabilities.push(Capability::Observer);
+        }
+        capabilities
+    }
+
+    /// Whether a peer that introduced itself with public key `key` is
+    /// connected.
+    pub async fn has_peer(&self, key: &[u8]) -> bool {
+        self.peer_by_key(key).await.is_some()
+    }
+
+    /// Whether the peer with public key `key` finished its handshake with
+    /// this node.
+    pub async fn has_welcomed(&self, key: &[u8]) -> bool {
+        match self.peer_by_key(key).await {
+            Some(peer) => peer.stage().await == PeerInitStage::Welcome,
+            None => false,
+        }
+    }
+
+    async fn peer_by_key(&self, key: &[u8]) -> Option<Arc<Peer>> {
+        self.peers
+            .read()
+            .await
+            .iter()
+            .find(|peer| peer.key().as_deref() == Some(key))
+            .cloned()
+    }
+
+    /// Sends `messages` to the peer with public key `to`: directly if it is
+    /// connected, through a relay among the peers otherwise. Replies the peer
+    /// sends to relayed messages go to the relay, which drops them.
+    pub async fn send_to(&self, to: &[u8], messages: &[Message]) -> Result<(), NodeError> {
+        if self.config.observer && messages.iter().any(is_write) {
+            return Err(NodeError::ReadOnly);
+        }
+        let msg = self.sign(messages).await?;
+        if let Some(peer) = self.peer_by_key(to).await {
+            return peer.send(msg).await;
+        }
+
+        let relay = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .find(|peer| peer.is_relay())
+            .cloned()
+            .ok_or(NodeError::UnreachablePeer)?;
+        let forward = Message::Forward {
+            to: to.to_vec(),
+            payload: encode(&msg).to_vec(),
+        };
+        relay.send(self.sign(&[forward]).await?).await
+    }
+
+    /// Passes the payload of a `Forward` signed by `from` on to the peer with
+    /// key `to`, charging its size to the sender's quota.
+    async fn relay(&self, from: &[u8], to: &[u8], payload: Vec<u8>) -> Result<(), NodeError> {
+        let limits = self.config.relay.ok_or(NodeError::NotRelaying)?;
+        {
+            let now = Instant::now();
+            let mut relayed = self.relayed.lock().unwrap();
+            relayed.retain(|_, (started, _)| now.duration_since(*started) < limits.window);
+            let (_, used) = relayed.entry(from.to_vec()).or_insert((now, 0));
+            let used_after = used.saturating_add(payload.len() as u64);
+            if used_after > limits.quota {
+                return Err(NodeError::RelayQuotaExceeded);
+            }
+            *used = used_after;
+        }
+
+        let peer = self
+            .peer_by_key(to)
+            .await
+            .ok_or(NodeError::UnreachablePeer)?;
+        peer.send_encoded(payload.into()).await
+    }
+
+    /// Records `id` as seen, returning `false` if it was before. With
+    /// [`NodeConfig::seen_retention`] set, the ids kept on disk are checked
+    /// too, and settle the ids the in-memory filter mistakes for seen ones.
+    fn remember(&self, id: &[u8]) -> bool {
+        let fresh = self.seen.insert(id);
+        let Some(store) = &self.seen_store else {
+            return fresh;
+        };
+        store.insert(id, unix_time()).unwrap_or_else(|e| {
+            warn!("Failed to record message id {}: {e:?}", b64_encode(id));
+            fresh
+        })
+    }
+
+    fn is_expired(&self, msg: &TransportMessage) -> bool {
+        msg.is_expired(unix_time(), self.config.clock_skew.as_secs())
     }
 }
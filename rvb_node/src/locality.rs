@@ -0,0 +1,84 @@
+//! Regions and zones of other nodes, learned from their gossip, so writes can
+//! be passed on to nodes spread over regions. A node's label for itself is
+//! taken as it is; what a node says of others only fills in nodes nothing
+//! was heard from yet, and is replaced once they gossip their own.
+
+use crate::addresses::MAX_KNOWN_NODES;
+use rvb_common::protocol::Locality;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Localities of other nodes, by key.
+#[derive(Default)]
+pub struct LocalityBook {
+    /// Each with whether the node gave it itself.
+    localities: Mutex<HashMap<Vec<u8>, (Locality, bool)>>,
+}
+
+impl LocalityBook {
+    /// Localities known of other nodes.
+    #[must_use]
+    pub fn localities(&self) -> HashMap<Vec<u8>, Locality> {
+        self.localities
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, (locality, _))| (key.clone(), locality.clone()))
+            .collect()
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &[u8]) -> Option<Locality> {
+        self.localities
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(locality, _)| locality.clone())
+    }
+
+    /// Records `locality` for `key`, as given by the node itself if `own`.
+    /// Returns whether it was taken.
+    pub(crate) fn learn(&self, key: &[u8], locality: Locality, own: bool) -> bool {
+        let mut localities = self.localities.lock().unwrap();
+        match localities.get(key) {
+            Some((known, _)) if *known == locality => false,
+            Some((_, true)) if !own => false,
+            Some(_) => {
+                localities.insert(key.to_vec(), (locality, own));
+                true
+            }
+            None if localities.len() >= MAX_KNOWN_NODES => false,
+            None => {
+                localities.insert(key.to_vec(), (locality, own));
+                true
+            }
+        }
+    }
+}
+
+/// Picks up to `copies` of `peers` in each region with `pick`, each peer
+/// given with its region if known. The node picking is in `own` region and
+/// holds a copy itself, so one fewer is picked there. Peers of no known
+/// region are taken as one more region.
+pub fn per_region<T>(
+    peers: Vec<(Option<String>, T)>,
+    own: Option<&str>,
+    copies: usize,
+    pick: impl Fn(Vec<T>, usize) -> Vec<T>,
+) -> Vec<T> {
+    let mut regions: HashMap<Option<String>, Vec<T>> = HashMap::new();
+    for (region, peer) in peers {
+        regions.entry(region).or_default().push(peer);
+    }
+    regions
+        .into_iter()
+        .flat_map(|(region, peers)| {
+            let copies = if region.is_some() && region.as_deref() == own {
+                copies.saturating_sub(1)
+            } else {
+                copies
+            };
+            pick(peers, copies)
+        })
+        .collect()
+}
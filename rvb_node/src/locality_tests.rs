@@ -0,0 +1,43 @@
+use crate::locality::{LocalityBook, per_region};
+use rvb_common::protocol::Locality;
+
+fn locality(region: &str) -> Locality {
+    Locality {
+        region: region.into(),
+        zone: None,
+    }
+}
+
+#[test]
+fn test_own_localities_win() {
+    let book = LocalityBook::default();
+    assert!(book.learn(&[1], locality("eu"), false));
+    assert!(book.learn(&[1], locality("us"), true));
+    // Others cannot relabel a node that gave its own locality
+    assert!(!book.learn(&[1], locality("ap"), false));
+    assert!(!book.learn(&[1], locality("us"), true));
+    assert!(book.learn(&[1], locality("ap"), true));
+    assert_eq!(book.get(&[1]), Some(locality("ap")));
+    assert_eq!(book.localities().len(), 1);
+}
+
+#[test]
+fn test_copies_per_region() {
+    let peers = vec![
+        (Some("eu".to_string()), 1),
+        (Some("eu".to_string()), 2),
+        (Some("eu".to_string()), 3),
+        (Some("us".to_string()), 4),
+        (Some("us".to_string()), 5),
+        (None, 6),
+    ];
+    let take = |peers: Vec<i32>, count| peers.into_iter().take(count).collect();
+
+    let mut picked = per_region(peers.clone(), Some("eu"), 2, take);
+    picked.sort_unstable();
+    assert_eq!(picked, [1, 4, 5, 6]);
+
+    let mut picked = per_region(peers, None, 1, take);
+    picked.sort_unstable();
+    assert_eq!(picked, [1, 4, 6]);
+}
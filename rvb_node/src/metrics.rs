@@ -0,0 +1,147 @@
+use rvb_common::contract::ExecutionReport;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Refused actions kept per contract by [`Diagnostics`]; older ones are
+/// dropped first.
+const DIAGNOSTICS_PER_CONTRACT: usize = 64;
+
+/// Contract calls made on this node since it started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ContractUsage {
+    pub calls: u64,
+    /// Fuel spent by the calls, 0 for runtimes without metering.
+    pub fuel: u64,
+    /// Largest linear memory any call reached, in bytes.
+    pub peak_memory: u64,
+    /// Wall-clock time spent in the calls.
+    pub time: Duration,
+}
+
+/// Per contract usage, kept in memory. Totals that survive restarts are kept
+/// by [`crate::storage::FuelLedger`] when fuel accounting is enabled.
+#[derive(Default)]
+pub struct UsageMetrics {
+    contracts: Mutex<HashMap<Vec<u8>, ContractUsage>>,
+}
+
+impl UsageMetrics {
+    pub fn record(&self, contract: &[u8], report: &ExecutionReport) {
+        let mut contracts = self.contracts.lock().unwrap();
+        let usage = contracts.entry(contract.to_vec()).or_default();
+        usage.calls += 1;
+        usage.fuel = usage.fuel.saturating_add(report.fuel);
+        usage.peak_memory = usage.peak_memory.max(report.peak_memory);
+        usage.time += report.duration;
+    }
+
+    #[must_use]
+    pub fn get(&self, contract: &[u8]) -> ContractUsage {
+        self.contracts
+            .lock()
+            .unwrap()
+            .get(contract)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every contract called so far, most expensive first.
+    #[must_use]
+    pub fn by_fuel(&self) -> Vec<(Vec<u8>, ContractUsage)> {
+        let mut usage = self
+            .contracts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, usage)| (id.clone(), *usage))
+            .collect::<Vec<_>>();
+        usage.sort_by_key(|(_, usage)| Reverse(usage.fuel));
+        usage
+    }
+}
+
+/// A bound of [`crate::Limits`] that messages or calls can be rejected for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    MessageSize,
+    BatchMessages,
+    ValueSize,
+    NamespaceKeys,
+    InflightExecutions,
+}
+
+/// Rejections per limit since the node started.
+#[derive(Default)]
+pub struct Rejections {
+    counts: Mutex<HashMap<Limit, u64>>,
+}
+
+impl Rejections {
+    pub fn record(&self, limit: Limit) {
+        *self.counts.lock().unwrap().entry(limit).or_default() += 1;
+    }
+
+    /// Counts a rejection for `limit` and returns the error reporting it.
+    pub fn reject(&self, limit: Limit) -> crate::NodeError {
+        self.record(limit);
+        crate::NodeError::LimitExceeded(limit)
+    }
+
+    #[must_use]
+    pub fn get(&self, limit: Limit) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(&limit)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// An action returned by a contract call that the node refused to store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionRejection {
+    /// Kind of the action, such as `insert`.
+    pub action: &'static str,
+    pub namespace: String,
+    pub contract_space: String,
+    /// Key the action wrote to, empty for actions without one.
+    pub key: String,
+    /// The limit the action went over, if that is why it was refused.
+    pub limit: Option<Limit>,
+    /// The error the action was refused with.
+    pub reason: String,
+    /// When it was refused, in seconds since the Unix epoch.
+    pub rejected_at: u64,
+}
+
+/// The last actions refused per contract, so authors can find out why their
+/// writes never showed up. Kept in memory only.
+#[derive(Default)]
+pub struct Diagnostics {
+    contracts: Mutex<HashMap<Vec<u8>, VecDeque<ActionRejection>>>,
+}
+
+impl Diagnostics {
+    pub fn record(&self, contract: &[u8], rejection: ActionRejection) {
+        let mut contracts = self.contracts.lock().unwrap();
+        let rejections = contracts.entry(contract.to_vec()).or_default();
+        if rejections.len() >= DIAGNOSTICS_PER_CONTRACT {
+            rejections.pop_front();
+        }
+        rejections.push_back(rejection);
+    }
+
+    /// Actions of `contract` refused so far, oldest first.
+    #[must_use]
+    pub fn get(&self, contract: &[u8]) -> Vec<ActionRejection> {
+        self.contracts
+            .lock()
+            .unwrap()
+            .get(contract)
+            .map(|rejections| rejections.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,91 @@
+//! Guardrails for a namespace set in the node's config rather than written
+//! into a contract: who may insert, how large values may be and how often a
+//! key may be written. Inserts are checked against them before their contract
+//! runs, so breaking one costs no contract call.
+
+use rvb_common::protocol::Location;
+use rvb_common::schema::DbValue;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window [`NamespacePolicy::max_writes_per_key`] counts writes in.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Keys counted at once before those whose window ended are dropped.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Rules inserts into a namespace must pass. Rules left unset are not
+/// checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamespacePolicy {
+    /// Inserts each key may take per minute.
+    pub max_writes_per_key: Option<u32>,
+    /// Bytes an inserted value may take once encoded.
+    pub max_value_size: Option<usize>,
+    /// Public keys that may insert; any key if `None`.
+    pub allowed_signers: Option<HashSet<Vec<u8>>>,
+}
+
+/// The rule of a [`NamespacePolicy`] an insert broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyRule {
+    WriteRate,
+    ValueSize,
+    Signer,
+}
+
+/// Checks inserts against the policies of their namespaces.
+pub struct PolicyEngine {
+    policies: HashMap<String, NamespacePolicy>,
+    /// Writes per key in its current window, and when the window started.
+    writes: Mutex<HashMap<Location, (Instant, u32)>>,
+}
+
+impl PolicyEngine {
+    #[must_use]
+    pub fn new(policies: HashMap<String, NamespacePolicy>) -> Self {
+        Self {
+            policies,
+            writes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks an insert of `value` at `location` signed by `signer`. Inserts
+    /// that pass count towards the key's write rate.
+    pub fn check(
+        &self,
+        location: &Location,
+        value: &DbValue,
+        signer: &[u8],
+    ) -> Result<(), PolicyRule> {
+        let Some(policy) = self.policies.get(&location.namespace) else {
+            return Ok(());
+        };
+        if let Some(signers) = &policy.allowed_signers
+            && !signers.contains(signer)
+        {
+            return Err(PolicyRule::Signer);
+        }
+        if let Some(max) = policy.max_value_size
+            && rmp_serde::to_vec(value).unwrap().len() > max
+        {
+            return Err(PolicyRule::ValueSize);
+        }
+        if let Some(max) = policy.max_writes_per_key {
+            let now = Instant::now();
+            let mut writes = self.writes.lock().unwrap();
+            if writes.len() >= PRUNE_THRESHOLD {
+                writes.retain(|_, (started, _)| now.duration_since(*started) < RATE_WINDOW);
+            }
+            let (started, count) = writes.entry(location.clone()).or_insert((now, 0));
+            if now.duration_since(*started) >= RATE_WINDOW {
+                (*started, *count) = (now, 0);
+            }
+            if *count >= max {
+                return Err(PolicyRule::WriteRate);
+            }
+            *count += 1;
+        }
+        Ok(())
+    }
+}
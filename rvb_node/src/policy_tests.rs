@@ -0,0 +1,57 @@
+use crate::policy::*;
+use rvb_common::protocol::Location;
+use rvb_common::schema::DbValue;
+use std::collections::{HashMap, HashSet};
+
+fn location(namespace: &str, key: &str) -> Location {
+    Location {
+        namespace: namespace.into(),
+        contract_space: "space".into(),
+        contract: vec![1],
+        key: key.into(),
+    }
+}
+
+#[test]
+fn test_policies() {
+    let engine = PolicyEngine::new(HashMap::from([(
+        "ns".to_string(),
+        NamespacePolicy {
+            max_writes_per_key: Some(2),
+            max_value_size: Some(32),
+            allowed_signers: Some(HashSet::from([b"alice".to_vec()])),
+        },
+    )]));
+    let small = DbValue::Number(1);
+
+    assert_eq!(
+        engine.check(&location("ns", "a"), &small, b"mallory"),
+        Err(PolicyRule::Signer)
+    );
+    assert_eq!(
+        engine.check(
+            &location("ns", "a"),
+            &DbValue::String("x".repeat(64)),
+            b"alice"
+        ),
+        Err(PolicyRule::ValueSize)
+    );
+    // Refused inserts do not count towards the rate
+    for _ in 0..2 {
+        assert_eq!(engine.check(&location("ns", "a"), &small, b"alice"), Ok(()));
+    }
+    assert_eq!(
+        engine.check(&location("ns", "a"), &small, b"alice"),
+        Err(PolicyRule::WriteRate)
+    );
+    assert_eq!(engine.check(&location("ns", "b"), &small, b"alice"), Ok(()));
+
+    // Namespaces without a policy take anything
+    let large = DbValue::String("x".repeat(64));
+    for _ in 0..3 {
+        assert_eq!(
+            engine.check(&location("other", "a"), &large, b"mallory"),
+            Ok(())
+        );
+    }
+}
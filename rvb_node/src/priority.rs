@@ -0,0 +1,83 @@
+//! Queues incoming batches are sorted into, so a flood of inserts cannot hold
+//! back the handshakes and pings that keep peers connected. Each batch is
+//! classified by its reading task and processed from the queue of its
+//! priority; queues of a higher priority are always emptied first.
+
+use rvb_common::protocol::Message;
+
+/// How urgently a batch is processed, most urgent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Handshakes, capabilities and keepalives. Delaying them gets peers
+    /// dropped as unresponsive, so they are dispatched apart from the other
+    /// queues and never wait behind a busy worker.
+    Handshake,
+    /// Contract deployments, subscriptions, group keys and other messages
+    /// changing how the node handles later data.
+    Control,
+    /// Reads and writes sent by the peer itself, and their replies.
+    Data,
+    /// Writes and events the peer passes on for other senders, as when
+    /// replicating a namespace.
+    Bulk,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Handshake,
+        Priority::Control,
+        Priority::Data,
+        Priority::Bulk,
+    ];
+
+    /// Priority of `message`. `relayed` is set if the batch carrying it was
+    /// signed by a key other than the peer's own.
+    #[must_use]
+    pub fn of(message: &Message, relayed: bool) -> Self {
+        match message {
+            Message::Hello { .. }
+            | Message::WhoAreYou { .. }
+            | Message::ItsMe { .. }
+            | Message::Welcome { .. }
+            | Message::Capabilities { .. }
+            | Message::Ping { .. }
+            | Message::Pong { .. } => Priority::Handshake,
+            Message::Insert { .. } | Message::Delete { .. } | Message::Event { .. } if relayed => {
+                Priority::Bulk
+            }
+            Message::Insert { .. }
+            | Message::Delete { .. }
+            | Message::Get { .. }
+            | Message::GetResult { .. }
+            | Message::ExecutionReport { .. }
+            | Message::SearchTags { .. }
+            | Message::SearchResult { .. }
+            | Message::Event { .. }
+            | Message::Forward { .. } => Priority::Data,
+            Message::DeployContract { .. }
+            | Message::UpgradeContract { .. }
+            | Message::PatchContract { .. }
+            | Message::Gossip { .. }
+            | Message::Subscribe { .. }
+            | Message::Unsubscribe { .. }
+            | Message::ShareGroupKey { .. }
+            | Message::GetGroupKey { .. }
+            | Message::GroupKey { .. }
+            | Message::SetWriters { .. }
+            | Message::SetAdmins { .. }
+            | Message::AdminSetWriters { .. }
+            | Message::Replicate { .. } => Priority::Control,
+        }
+    }
+
+    /// Priority of a batch: that of its most urgent message, so a `Pong`
+    /// batched with inserts is not held back by them.
+    #[must_use]
+    pub fn of_batch(messages: &[Message], relayed: bool) -> Self {
+        messages
+            .iter()
+            .map(|message| Self::of(message, relayed))
+            .min()
+            .unwrap_or(Priority::Bulk)
+    }
+}
@@ -0,0 +1,53 @@
+use crate::priority::Priority;
+use rvb_common::protocol::{Location, Message};
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+fn insert() -> Message {
+    Message::Insert {
+        location: Location {
+            namespace: "ns".into(),
+            contract_space: "space".into(),
+            contract: vec![1],
+            key: "key".into(),
+        },
+        incoming_data: DbValue::None,
+        metadata: HashMap::new(),
+        state: 0,
+    }
+}
+
+#[test]
+fn test_priorities() {
+    assert_eq!(
+        Priority::of(&Message::Ping { nonce: 1 }, false),
+        Priority::Handshake
+    );
+    assert_eq!(
+        Priority::of(&Message::Pong { nonce: 1 }, true),
+        Priority::Handshake
+    );
+    assert_eq!(
+        Priority::of(
+            &Message::Replicate {
+                namespaces: vec!["ns".into()]
+            },
+            false
+        ),
+        Priority::Control
+    );
+    assert_eq!(Priority::of(&insert(), false), Priority::Data);
+    assert_eq!(Priority::of(&insert(), true), Priority::Bulk);
+}
+
+#[test]
+fn test_batch_priority() {
+    let inserts = vec![insert(); 16];
+    assert_eq!(Priority::of_batch(&inserts, true), Priority::Bulk);
+
+    let mut batch = inserts.clone();
+    batch.push(Message::Pong { nonce: 1 });
+    assert_eq!(Priority::of_batch(&batch, true), Priority::Handshake);
+
+    assert_eq!(Priority::of_batch(&[], false), Priority::Bulk);
+}
@@ -0,0 +1,95 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+
+/// Bits kept per id; with [`HASHES`] probes a full filter mistakes about
+/// 0.0067% of new ids for seen ones, so about 0.013% across both generations.
+const BITS_PER_ID: usize = 20;
+const HASHES: u64 = 14;
+
+/// Ids of the messages a node has seen, kept in a rotating pair of Bloom
+/// filters. Once the current filter holds its capacity it replaces the
+/// previous one and a new filter is started, so memory stays bounded while
+/// every id is remembered for at least `capacity` further messages.
+///
+/// Hashes are keyed per node, so ids picked to collide on one node do not
+/// collide on the others.
+pub struct SeenFilter {
+    hasher: (RandomState, RandomState),
+    capacity: usize,
+    generations: Mutex<Generations>,
+}
+
+struct Generations {
+    current: Bloom,
+    previous: Bloom,
+}
+
+struct Bloom {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl Bloom {
+    fn new(capacity: usize) -> Self {
+        let words = (capacity.max(1) * BITS_PER_ID).div_ceil(64);
+        Self {
+            bits: vec![0; words],
+            len: 0,
+        }
+    }
+
+    fn contains(&self, hashes: (u64, u64)) -> bool {
+        positions(self.bits.len(), hashes).all(|(word, mask)| self.bits[word] & mask != 0)
+    }
+
+    fn insert(&mut self, hashes: (u64, u64)) {
+        for (word, mask) in positions(self.bits.len(), hashes) {
+            self.bits[word] |= mask;
+        }
+        self.len += 1;
+    }
+}
+
+/// Words and bits of a filter of `words` words probed for an id with the
+/// given pair of hashes.
+fn positions(words: usize, (h1, h2): (u64, u64)) -> impl Iterator<Item = (usize, u64)> {
+    let size = words as u64 * 64;
+    (0..HASHES).map(move |i| {
+        let bit = h1.wrapping_add(i.wrapping_mul(h2)) % size;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    })
+}
+
+impl SeenFilter {
+    /// Filter remembering at least the last `capacity` ids.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hasher: (RandomState::new(), RandomState::new()),
+            capacity,
+            generations: Mutex::new(Generations {
+                current: Bloom::new(capacity),
+                previous: Bloom::new(capacity),
+            }),
+        }
+    }
+
+    /// Records `id`, returning `false` if it was, most likely, seen before.
+    /// A small fraction of new ids is mistaken for seen ones; ids are never
+    /// mistaken for new ones while they are remembered.
+    pub fn insert(&self, id: &[u8]) -> bool {
+        let hashes = (self.hasher.0.hash_one(id), self.hasher.1.hash_one(id) | 1);
+        let mut generations = self.generations.lock().unwrap();
+        if generations.current.contains(hashes) || generations.previous.contains(hashes) {
+            return false;
+        }
+
+        if generations.current.len >= self.capacity {
+            let fresh = Bloom::new(self.capacity);
+            generations.previous = std::mem::replace(&mut generations.current, fresh);
+        }
+        generations.current.insert(hashes);
+        true
+    }
+}
@@ -0,0 +1,165 @@
+use crate::NodeError;
+use rvb_common::crypto::hash;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// A message the node applied, as kept by [`AuditLog`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position in the log, from 0.
+    pub sequence: u64,
+    /// Id of the transport message.
+    pub message_id: Vec<u8>,
+    /// Public key that signed the message.
+    pub signed_by: Vec<u8>,
+    pub namespace: String,
+    /// What the message did, such as `insert space/key`.
+    pub action: String,
+    /// State of the written value once the message was applied, for inserts.
+    pub state: Option<u64>,
+    /// Hash of the previous record, zeroes for the first one.
+    pub previous: [u8; 32],
+    /// blake3 hash of every other field of the record.
+    pub hash: [u8; 32],
+}
+
+impl AuditRecord {
+    fn digest(&self) -> [u8; 32] {
+        let fields = (
+            self.sequence,
+            &self.message_id,
+            &self.signed_by,
+            &self.namespace,
+            &self.action,
+            self.state,
+            self.previous,
+        );
+        hash::blake3(&rmp_serde::to_vec(&fields).unwrap())
+    }
+}
+
+/// Append-only log of applied messages in the `audit` tree, keyed by
+/// big-endian sequence number. Each record holds the hash of the one before
+/// it, so editing or removing a record breaks the chain from there on.
+pub struct AuditLog {
+    db: sled::Db,
+    append: Mutex<()>,
+}
+
+impl AuditLog {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self {
+            db,
+            append: Mutex::new(()),
+        }
+    }
+
+    fn tree(&self) -> Result<sled::Tree, NodeError> {
+        self.db.open_tree(b"audit").map_err(NodeError::StorageError)
+    }
+
+    /// Adds a record after the last one and returns it.
+    pub fn append(
+        &self,
+        message_id: &[u8],
+        signed_by: &[u8],
+        namespace: &str,
+        action: String,
+        state: Option<u64>,
+    ) -> Result<AuditRecord, NodeError> {
+        let _guard = self.append.lock().unwrap();
+        let tree = self.tree()?;
+        let last = tree
+            .last()
+            .map_err(NodeError::StorageError)?
+            .map(|(_, raw)| rmp_serde::from_slice::<AuditRecord>(&raw))
+            .transpose()
+            .map_err(NodeError::SchemaError)?;
+
+        let mut record = AuditRecord {
+            sequence: last.as_ref().map_or(0, |last| last.sequence + 1),
+            message_id: message_id.to_vec(),
+            signed_by: signed_by.to_vec(),
+            namespace: namespace.to_string(),
+            action,
+            state,
+            previous: last.map_or([0; 32], |last| last.hash),
+            hash: [0; 32],
+        };
+        record.hash = record.digest();
+
+        tree.insert(
+            record.sequence.to_be_bytes(),
+            rmp_serde::to_vec(&record).unwrap(),
+        )
+        .map_err(NodeError::StorageError)?;
+        Ok(record)
+    }
+
+    /// Up to `limit` records, starting at sequence number `from`.
+    pub fn records(&self, from: u64, limit: usize) -> Result<Vec<AuditRecord>, NodeError> {
+        self.tree()?
+            .range(from.to_be_bytes()..)
+            .take(limit)
+            .map(|item| {
+                let (_, raw) = item.map_err(NodeError::StorageError)?;
+                rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)
+            })
+            .collect()
+    }
+
+    /// Walks the chain from the oldest record kept and returns how many
+    /// records it holds, or [`NodeError::AuditChainBroken`] with the sequence
+    /// number of the first record that does not match its hash or its
+    /// predecessor. Once the log was trimmed, records removed from its start
+    /// go unnoticed.
+    pub fn verify(&self) -> Result<u64, NodeError> {
+        let mut expected = None;
+        let mut count = 0;
+
+        for item in self.tree()?.iter() {
+            let (key, raw) = item.map_err(NodeError::StorageError)?;
+            let record: AuditRecord =
+                rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)?;
+            let (sequence, previous) = expected.unwrap_or_else(|| {
+                let previous = if record.sequence == 0 {
+                    [0; 32]
+                } else {
+                    record.previous
+                };
+                (record.sequence, previous)
+            });
+            if *key != record.sequence.to_be_bytes()
+                || record.sequence != sequence
+                || record.previous != previous
+                || record.hash != record.digest()
+            {
+                return Err(NodeError::AuditChainBroken(sequence));
+            }
+            expected = Some((sequence + 1, record.hash));
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Removes the oldest records, keeping the last `keep`, and returns how
+    /// many were removed. Sequence numbers carry on from the last record.
+    pub fn trim(&self, keep: u64) -> Result<u64, NodeError> {
+        let _guard = self.append.lock().unwrap();
+        let tree = self.tree()?;
+        let Some((_, raw)) = tree.last().map_err(NodeError::StorageError)? else {
+            return Ok(0);
+        };
+        let last: AuditRecord = rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)?;
+        let first_kept = (last.sequence + 1).saturating_sub(keep);
+
+        let mut trimmed = 0;
+        for key in tree.range(..first_kept.to_be_bytes()).keys() {
+            tree.remove(key.map_err(NodeError::StorageError)?)
+                .map_err(NodeError::StorageError)?;
+            trimmed += 1;
+        }
+        Ok(trimmed)
+    }
+}
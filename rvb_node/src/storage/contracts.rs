@@ -0,0 +1,228 @@
+use crate::NodeError;
+use rvb_common::crypto::ThresholdKey;
+use rvb_common::protocol::ContractInfo;
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+/// Deployed contracts, keyed by contract id: bytecode in `contracts`, deploy
+/// params in `contract_params` and everything else known about the contract
+/// in `contract_info`. `contract_spaces` records which contract spaces hold
+/// data written through each contract, so upgrades know what to migrate.
+/// `namespace_writers` holds the keys allowed to insert into namespaces whose
+/// writers are limited, and `namespace_admins` the threshold keys of
+/// namespaces handed to several operators.
+#[derive(Clone)]
+pub struct ContractStore {
+    db: sled::Db,
+}
+
+impl ContractStore {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    fn tree(&self, name: &[u8]) -> Result<sled::Tree, NodeError> {
+        self.db.open_tree(name).map_err(NodeError::StorageError)
+    }
+
+    pub fn bytecode(&self, id: &[u8]) -> Result<Option<Vec<u8>>, NodeError> {
+        Ok(self
+            .tree(b"contracts")?
+            .get(id)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| raw.to_vec()))
+    }
+
+    /// Parameters the contract was deployed with.
+    pub fn params(&self, id: &[u8]) -> Result<HashMap<String, DbValue>, NodeError> {
+        let raw = self
+            .tree(b"contract_params")?
+            .get(id)
+            .map_err(NodeError::StorageError)?;
+
+        match raw {
+            Some(raw) => rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    pub fn info(&self, id: &[u8]) -> Result<Option<ContractInfo>, NodeError> {
+        self.tree(b"contract_info")?
+            .get(id)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    pub fn insert(
+        &self,
+        info: &ContractInfo,
+        bytecode: &[u8],
+        params: &HashMap<String, DbValue>,
+    ) -> Result<(), NodeError> {
+        self.tree(b"contracts")?
+            .insert(&info.id, bytecode)
+            .map_err(NodeError::StorageError)?;
+        self.tree(b"contract_params")?
+            .insert(&info.id, rmp_serde::to_vec(params).unwrap())
+            .map_err(NodeError::StorageError)?;
+        self.tree(b"contract_info")?
+            .insert(&info.id, rmp_serde::to_vec(info).unwrap())
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    fn space_prefix(id: &[u8]) -> Vec<u8> {
+        let mut prefix = (id.len() as u32).to_be_bytes().to_vec();
+        prefix.extend_from_slice(id);
+        prefix
+    }
+
+    /// Records that the contract wrote to `contract_space` in `namespace`.
+    pub fn add_space(
+        &self,
+        id: &[u8],
+        namespace: &str,
+        contract_space: &str,
+    ) -> Result<(), NodeError> {
+        let mut key = Self::space_prefix(id);
+        key.extend_from_slice(format!("{namespace}\0{contract_space}").as_bytes());
+
+        self.tree(b"contract_spaces")?
+            .insert(key, &[])
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    pub fn has_space(
+        &self,
+        id: &[u8],
+        namespace: &str,
+        contract_space: &str,
+    ) -> Result<bool, NodeError> {
+        let mut key = Self::space_prefix(id);
+        key.extend_from_slice(format!("{namespace}\0{contract_space}").as_bytes());
+
+        self.tree(b"contract_spaces")?
+            .contains_key(key)
+            .map_err(NodeError::StorageError)
+    }
+
+    /// Namespace and contract space pairs the contract has written to.
+    pub fn spaces(&self, id: &[u8]) -> Result<Vec<(String, String)>, NodeError> {
+        let prefix = Self::space_prefix(id);
+        let mut spaces = Vec::new();
+
+        for entry in self.tree(b"contract_spaces")?.scan_prefix(&prefix) {
+            let (key, _) = entry.map_err(NodeError::StorageError)?;
+            let name = String::from_utf8_lossy(&key[prefix.len()..]);
+            if let Some((namespace, contract_space)) = name.split_once('\0') {
+                spaces.push((namespace.to_string(), contract_space.to_string()));
+            }
+        }
+
+        Ok(spaces)
+    }
+
+    /// Whether `key` deployed any contract in `namespace`.
+    pub fn is_deployer(&self, namespace: &str, key: &[u8]) -> Result<bool, NodeError> {
+        Ok(self
+            .search(namespace, &[])?
+            .iter()
+            .any(|info| info.deployed_by == key))
+    }
+
+    /// Whether `key` may deploy into `namespace`. Deploying the first contract
+    /// claims a namespace, after which only keys that deployed into it may
+    /// add more, since deployers control who may write to it.
+    pub fn may_deploy(&self, namespace: &str, key: &[u8]) -> Result<bool, NodeError> {
+        let deployed = self.search(namespace, &[])?;
+        Ok(deployed.is_empty() || deployed.iter().any(|info| info.deployed_by == key))
+    }
+
+    /// Keys allowed to insert into `namespace`, or `None` if anyone may.
+    pub fn writers(&self, namespace: &str) -> Result<Option<Vec<Vec<u8>>>, NodeError> {
+        self.tree(b"namespace_writers")?
+            .get(namespace)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    pub fn set_writers(
+        &self,
+        namespace: &str,
+        writers: Option<&[Vec<u8>]>,
+    ) -> Result<(), NodeError> {
+        let tree = self.tree(b"namespace_writers")?;
+        match writers {
+            Some(writers) => tree.insert(namespace, rmp_serde::to_vec(writers).unwrap()),
+            None => tree.remove(namespace),
+        }
+        .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    /// Threshold key of the admins of `namespace`, with the sequence number of
+    /// the last change they agreed on, or `None` if it has no admins.
+    pub fn admins(&self, namespace: &str) -> Result<Option<(ThresholdKey, u64)>, NodeError> {
+        self.tree(b"namespace_admins")?
+            .get(namespace)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    pub fn set_admins(
+        &self,
+        namespace: &str,
+        admins: &ThresholdKey,
+        sequence: u64,
+    ) -> Result<(), NodeError> {
+        self.tree(b"namespace_admins")?
+            .insert(namespace, rmp_serde::to_vec(&(admins, sequence)).unwrap())
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    /// Records `sequence` as the last change the admins of `namespace`
+    /// agreed on, returning `false` if another was recorded since `last`.
+    pub fn advance_admins(
+        &self,
+        namespace: &str,
+        admins: &ThresholdKey,
+        last: u64,
+        sequence: u64,
+    ) -> Result<bool, NodeError> {
+        let swapped = self
+            .tree(b"namespace_admins")?
+            .compare_and_swap(
+                namespace,
+                Some(rmp_serde::to_vec(&(admins, last)).unwrap()),
+                Some(rmp_serde::to_vec(&(admins, sequence)).unwrap()),
+            )
+            .map_err(NodeError::StorageError)?;
+        Ok(swapped.is_ok())
+    }
+
+    /// Contracts deployed in `namespace` that carry every tag in `query`.
+    pub fn search(
+        &self,
+        namespace: &str,
+        query: &[String],
+    ) -> Result<Vec<ContractInfo>, NodeError> {
+        let mut found = Vec::new();
+
+        for entry in self.tree(b"contract_info")?.iter() {
+            let (_, raw) = entry.map_err(NodeError::StorageError)?;
+            let info: ContractInfo = rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)?;
+
+            if info.namespace == namespace && query.iter().all(|tag| info.tags.contains(tag)) {
+                found.push(info);
+            }
+        }
+
+        Ok(found)
+    }
+}
@@ -0,0 +1,81 @@
+use super::DataStore;
+use crate::NodeError;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// One stored value, as a line of the JSON dumps written by [`export`] and
+/// read by [`import`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub namespace: String,
+    pub contract_space: String,
+    pub key: String,
+    pub state: u64,
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug)]
+pub enum DumpError {
+    IoError(std::io::Error),
+    /// A line of the dump is not a valid record; lines count from 1.
+    InvalidRecord {
+        line: usize,
+        error: serde_json::Error,
+    },
+    StorageError(NodeError),
+}
+
+/// Writes every value stored in `namespace` as one JSON record per line,
+/// ordered by contract space and key, and returns how many were written.
+pub fn export(
+    store: &DataStore,
+    namespace: &str,
+    mut writer: impl Write,
+) -> Result<usize, DumpError> {
+    let entries = store.entries(namespace).map_err(DumpError::StorageError)?;
+    let count = entries.len();
+
+    for entry in entries {
+        let record = Record {
+            namespace: namespace.to_string(),
+            contract_space: entry.contract_space,
+            key: entry.key,
+            state: entry.state,
+            value: entry.value.into(),
+        };
+        serde_json::to_writer(&mut writer, &record).expect("Failed to encode record");
+        writer.write_all(b"\n").map_err(DumpError::IoError)?;
+    }
+    writer.flush().map_err(DumpError::IoError)?;
+    Ok(count)
+}
+
+/// Merges the records of a dump into `store` with the regular conflict
+/// resolution, so a record only replaces values at older states. Contracts
+/// are not consulted. Blank lines are skipped. Returns how many records were
+/// read.
+pub fn import(store: &DataStore, reader: impl BufRead) -> Result<usize, DumpError> {
+    let mut count = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(DumpError::IoError)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|error| DumpError::InvalidRecord {
+                line: index + 1,
+                error,
+            })?;
+        store
+            .insert(
+                &record.namespace,
+                &record.contract_space,
+                &record.key,
+                record.value.into(),
+                record.state,
+            )
+            .map_err(DumpError::StorageError)?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,57 @@
+use crate::NodeError;
+
+/// Fuel spent by contracts, as big-endian `u64` totals: `fuel_deployers` is
+/// keyed by the public key of the contracts' deployer and `fuel_namespaces`
+/// by the namespace the calls ran in.
+#[derive(Clone)]
+pub struct FuelLedger {
+    db: sled::Db,
+}
+
+fn add(tree: &sled::Tree, key: &[u8], fuel: u64) -> Result<(), NodeError> {
+    tree.update_and_fetch(key, |old| {
+        let total = old.map_or(0, decode).saturating_add(fuel);
+        Some(total.to_be_bytes().to_vec())
+    })
+    .map_err(NodeError::StorageError)?;
+    Ok(())
+}
+
+fn decode(raw: &[u8]) -> u64 {
+    raw.try_into().map_or(0, u64::from_be_bytes)
+}
+
+impl FuelLedger {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    fn tree(&self, name: &[u8]) -> Result<sled::Tree, NodeError> {
+        self.db.open_tree(name).map_err(NodeError::StorageError)
+    }
+
+    /// Adds `fuel` to the totals of `deployer` and `namespace`.
+    pub fn charge(&self, deployer: &[u8], namespace: &str, fuel: u64) -> Result<(), NodeError> {
+        add(&self.tree(b"fuel_deployers")?, deployer, fuel)?;
+        add(&self.tree(b"fuel_namespaces")?, namespace.as_bytes(), fuel)
+    }
+
+    /// Fuel spent by every contract `deployer` deployed.
+    pub fn deployer(&self, deployer: &[u8]) -> Result<u64, NodeError> {
+        Ok(self
+            .tree(b"fuel_deployers")?
+            .get(deployer)
+            .map_err(NodeError::StorageError)?
+            .map_or(0, |raw| decode(&raw)))
+    }
+
+    /// Fuel spent by calls running in `namespace`.
+    pub fn namespace(&self, namespace: &str) -> Result<u64, NodeError> {
+        Ok(self
+            .tree(b"fuel_namespaces")?
+            .get(namespace)
+            .map_err(NodeError::StorageError)?
+            .map_or(0, |raw| decode(&raw)))
+    }
+}
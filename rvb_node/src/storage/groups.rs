@@ -0,0 +1,64 @@
+use crate::NodeError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The encryption group of a private namespace, as kept by [`GroupStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    /// Public key that shared the first epoch.
+    pub owner: Vec<u8>,
+    pub epoch: u64,
+    /// Group key of `epoch` wrapped to each member, by member public key.
+    pub keys: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Encryption groups of private namespaces in the `groups` tree, keyed by
+/// namespace. Only wrapped keys are kept, so the node cannot read the values
+/// stored in these namespaces.
+#[derive(Clone)]
+pub struct GroupStore {
+    db: sled::Db,
+}
+
+impl GroupStore {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    fn tree(&self) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(b"groups")
+            .map_err(NodeError::StorageError)
+    }
+
+    pub fn get(&self, namespace: &str) -> Result<Option<Group>, NodeError> {
+        self.tree()?
+            .get(namespace)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    /// Starts `epoch` of the group of `namespace`, shared by `shared_by`.
+    /// Returns `false` if the group is already at `epoch` or later.
+    pub fn share(
+        &self,
+        namespace: &str,
+        shared_by: &[u8],
+        epoch: u64,
+        keys: HashMap<Vec<u8>, Vec<u8>>,
+    ) -> Result<bool, NodeError> {
+        let owner = match self.get(namespace)? {
+            Some(group) if group.owner != shared_by => return Err(NodeError::NotGroupOwner),
+            Some(group) if group.epoch >= epoch => return Ok(false),
+            _ => shared_by.to_vec(),
+        };
+
+        let group = Group { owner, epoch, keys };
+        self.tree()?
+            .insert(namespace, rmp_serde::to_vec(&group).unwrap())
+            .map_err(NodeError::StorageError)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::NodeError;
+use rvb_common::protocol::TransportMessage;
+use serde::{Deserialize, Serialize};
+
+/// An accepted write that may not have been applied yet, as kept by
+/// [`Journal`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingWrite {
+    /// Position in the journal, in the order writes were accepted.
+    pub sequence: u64,
+    /// The batch the write came in.
+    pub transport: TransportMessage,
+    /// Position of the write's message in `transport`.
+    pub index: usize,
+}
+
+/// Write-ahead journal of accepted inserts and deletions in the `journal` tree, keyed by
+/// big-endian sequence number. A write is recorded before its contract runs
+/// and removed once its actions are stored, so writes cut short by a crash
+/// can be applied again on the next start.
+#[derive(Clone)]
+pub struct Journal {
+    db: sled::Db,
+}
+
+impl Journal {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    fn tree(&self) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(b"journal")
+            .map_err(NodeError::StorageError)
+    }
+
+    /// Records the write of message `index` of `transport` and returns its
+    /// sequence number.
+    pub fn record(&self, transport: &TransportMessage, index: usize) -> Result<u64, NodeError> {
+        let sequence = self.db.generate_id().map_err(NodeError::StorageError)?;
+        let write = PendingWrite {
+            sequence,
+            transport: transport.clone(),
+            index,
+        };
+        self.tree()?
+            .insert(sequence.to_be_bytes(), rmp_serde::to_vec(&write).unwrap())
+            .map_err(NodeError::StorageError)?;
+        Ok(sequence)
+    }
+
+    /// Removes a write once it has been applied.
+    pub fn complete(&self, sequence: u64) -> Result<(), NodeError> {
+        self.tree()?
+            .remove(sequence.to_be_bytes())
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    /// Writes recorded but not completed, oldest first.
+    pub fn pending(&self) -> Result<Vec<PendingWrite>, NodeError> {
+        self.tree()?
+            .iter()
+            .values()
+            .map(|raw| {
+                let raw = raw.map_err(NodeError::StorageError)?;
+                rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)
+            })
+            .collect()
+    }
+}
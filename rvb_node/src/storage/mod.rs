@@ -0,0 +1,698 @@
+use crate::metrics::{Limit, Rejections};
+use crate::{Limits, NodeError};
+use log::debug;
+use rvb_common::contract::{ArtifactCache, ContractHost, ExecutionReport};
+use rvb_common::crypto::hash::{HashAlgorithm, Hasher};
+use rvb_common::protocol::TransportMessage;
+use rvb_common::schema::{DbValue, PatchOp, merge};
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod audit;
+mod contracts;
+pub mod dump;
+mod fuel;
+mod groups;
+mod journal;
+mod seen;
+
+pub use audit::{AuditLog, AuditRecord};
+pub use contracts::ContractStore;
+pub use fuel::FuelLedger;
+pub use groups::{Group, GroupStore};
+pub use journal::{Journal, PendingWrite};
+pub use seen::{SeenStats, SeenStore};
+
+/// Replicated data, kept in sled as one pair of trees per namespace and
+/// contract space: `data` maps keys to msgpack encoded values and `state` maps
+/// them to their state counters as big-endian `u64`s. Values written with
+/// [`DataStore::insert_latest`] also have the signing time of the message that
+/// wrote them in a `stamp` tree, and deleted keys leave a tombstone in a
+/// `tombstone` tree: the state they were deleted at and when, in seconds since
+/// the Unix epoch, as two big-endian `u64`s. Contracts' private state
+/// lives in the `private` tree, keyed by contract id and key. The number of
+/// keys in each namespace is kept in the `key_counts` tree, counted on first
+/// use for namespaces written before it existed.
+#[derive(Clone)]
+pub struct DataStore {
+    db: sled::Db,
+    limits: Limits,
+    rejections: Arc<Rejections>,
+}
+
+/// A stored value with its state counter, as listed by [`DataStore::entries`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub contract_space: String,
+    pub key: String,
+    pub value: DbValue,
+    pub state: u64,
+}
+
+fn tree_name(kind: &str, namespace: &str, contract_space: &str) -> Vec<u8> {
+    format!("{kind}\0{namespace}\0{contract_space}").into_bytes()
+}
+
+impl DataStore {
+    #[must_use]
+    pub fn new(db: sled::Db) -> Self {
+        Self {
+            db,
+            limits: Limits::UNLIMITED,
+            rejections: Arc::default(),
+        }
+    }
+
+    /// Refuses writes of values larger than `limits.max_value_size` and new
+    /// keys beyond `limits.max_keys_per_namespace`, counting each refusal in
+    /// `rejections`.
+    #[must_use]
+    pub fn with_limits(mut self, limits: Limits, rejections: Arc<Rejections>) -> Self {
+        self.limits = limits;
+        self.rejections = rejections;
+        self
+    }
+
+    fn data(&self, namespace: &str, contract_space: &str) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(tree_name("data", namespace, contract_space))
+            .map_err(NodeError::StorageError)
+    }
+
+    fn states(&self, namespace: &str, contract_space: &str) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(tree_name("state", namespace, contract_space))
+            .map_err(NodeError::StorageError)
+    }
+
+    fn stamps(&self, namespace: &str, contract_space: &str) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(tree_name("stamp", namespace, contract_space))
+            .map_err(NodeError::StorageError)
+    }
+
+    fn tombstones(&self, namespace: &str, contract_space: &str) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(tree_name("tombstone", namespace, contract_space))
+            .map_err(NodeError::StorageError)
+    }
+
+    /// State `key` had when it was deleted, while its tombstone is kept.
+    pub fn tombstone(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+    ) -> Result<Option<u64>, NodeError> {
+        let raw = self
+            .tombstones(namespace, contract_space)?
+            .get(key)
+            .map_err(NodeError::StorageError)?;
+
+        Ok(raw
+            .and_then(|raw| raw.get(..8).and_then(|state| state.try_into().ok()))
+            .map(u64::from_be_bytes))
+    }
+
+    /// Whether a write of `key` at `state` is no newer than its deletion, and
+    /// would bring back the deleted value. Deletions win ties with writes, as
+    /// a write at the same state may reach a node before or after them.
+    fn buried(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        state: u64,
+    ) -> Result<bool, NodeError> {
+        let buried = self
+            .tombstone(namespace, contract_space, key)?
+            .is_some_and(|deleted| state <= deleted);
+        if buried {
+            debug!("Dropping write of {key} from before its deletion");
+        }
+        Ok(buried)
+    }
+
+    /// Removes the tombstones of keys deleted before `before`, in seconds
+    /// since the Unix epoch, and returns how many. Writes from before a
+    /// deletion are taken again once its tombstone is gone, so tombstones
+    /// should outlive the time writes take to reach every node.
+    pub fn prune_tombstones(&self, before: u64) -> Result<usize, NodeError> {
+        let mut pruned = 0;
+        for name in self.db.tree_names() {
+            if !name.starts_with(b"tombstone\0") {
+                continue;
+            }
+            let tree = self.db.open_tree(name).map_err(NodeError::StorageError)?;
+            for item in tree.iter() {
+                let (key, raw) = item.map_err(NodeError::StorageError)?;
+                let deleted_at = raw
+                    .get(8..)
+                    .and_then(|time| time.try_into().ok())
+                    .map_or(0, u64::from_be_bytes);
+                if deleted_at >= before {
+                    continue;
+                }
+                // Left alone if the key was deleted again in the meantime
+                let swapped = tree
+                    .compare_and_swap(&key, Some(&raw), None::<&[u8]>)
+                    .map_err(NodeError::StorageError)?;
+                if swapped.is_ok() {
+                    pruned += 1;
+                }
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Writes pending changes to disk and returns the size of the database
+    /// files. Space of removed data is reused by sled for later writes rather
+    /// than given back, so the files do not shrink.
+    pub fn flush(&self) -> Result<u64, NodeError> {
+        self.db.flush().map_err(NodeError::StorageError)?;
+        self.db.size_on_disk().map_err(NodeError::StorageError)
+    }
+
+    /// Signing time of the message that wrote the value of `key` through
+    /// [`DataStore::insert_latest`], 0 for values written otherwise.
+    pub fn stamp(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+    ) -> Result<u64, NodeError> {
+        let raw = self
+            .stamps(namespace, contract_space)?
+            .get(key)
+            .map_err(NodeError::StorageError)?;
+
+        Ok(raw
+            .and_then(|raw| raw.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(0))
+    }
+
+    pub fn get(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+    ) -> Result<Option<DbValue>, NodeError> {
+        self.data(namespace, contract_space)?
+            .get(key)
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    fn private_key(contract: &[u8], key: &str) -> Vec<u8> {
+        let mut private_key = (contract.len() as u32).to_be_bytes().to_vec();
+        private_key.extend_from_slice(contract);
+        private_key.extend_from_slice(key.as_bytes());
+        private_key
+    }
+
+    /// Value of `key` in the private state of `contract`.
+    pub fn private_get(&self, contract: &[u8], key: &str) -> Result<Option<DbValue>, NodeError> {
+        self.db
+            .open_tree("private")
+            .map_err(NodeError::StorageError)?
+            .get(Self::private_key(contract, key))
+            .map_err(NodeError::StorageError)?
+            .map(|raw| rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError))
+            .transpose()
+    }
+
+    pub fn private_set(
+        &self,
+        contract: &[u8],
+        key: &str,
+        value: &DbValue,
+    ) -> Result<(), NodeError> {
+        self.db
+            .open_tree("private")
+            .map_err(NodeError::StorageError)?
+            .insert(
+                Self::private_key(contract, key),
+                rmp_serde::to_vec(value).unwrap(),
+            )
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    /// Keys stored in a contract space.
+    pub fn keys(&self, namespace: &str, contract_space: &str) -> Result<Vec<String>, NodeError> {
+        self.data(namespace, contract_space)?
+            .iter()
+            .keys()
+            .map(|key| {
+                key.map(|key| String::from_utf8_lossy(&key).into_owned())
+                    .map_err(NodeError::StorageError)
+            })
+            .collect()
+    }
+
+    /// Contract spaces of `namespace` that have a data tree.
+    pub fn spaces(&self, namespace: &str) -> Result<Vec<String>, NodeError> {
+        let prefix = tree_name("data", namespace, "");
+        let mut spaces: Vec<String> = self
+            .db
+            .tree_names()
+            .into_iter()
+            .filter_map(|name| {
+                name.strip_prefix(prefix.as_slice())
+                    .map(|space| String::from_utf8_lossy(space).into_owned())
+            })
+            .collect();
+        spaces.sort();
+        Ok(spaces)
+    }
+
+    /// Every value stored in `namespace`, ordered by contract space and key.
+    pub fn entries(&self, namespace: &str) -> Result<Vec<Entry>, NodeError> {
+        let mut entries = Vec::new();
+        for contract_space in self.spaces(namespace)? {
+            for item in self.data(namespace, &contract_space)?.iter() {
+                let (key, raw) = item.map_err(NodeError::StorageError)?;
+                let key = String::from_utf8_lossy(&key).into_owned();
+                entries.push(Entry {
+                    value: rmp_serde::from_slice(&raw).map_err(NodeError::SchemaError)?,
+                    state: self.state(namespace, &contract_space, &key)?,
+                    contract_space: contract_space.clone(),
+                    key,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn state(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+    ) -> Result<u64, NodeError> {
+        let raw = self
+            .states(namespace, contract_space)?
+            .get(key)
+            .map_err(NodeError::StorageError)?;
+
+        Ok(raw
+            .and_then(|raw| raw.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(0))
+    }
+
+    /// Number of keys stored across the contract spaces of `namespace`.
+    pub fn key_count(&self, namespace: &str) -> Result<u64, NodeError> {
+        let counted = self
+            .key_counts()?
+            .get(namespace)
+            .map_err(NodeError::StorageError)?
+            .and_then(|raw| raw.as_ref().try_into().ok())
+            .map(u64::from_be_bytes);
+        match counted {
+            Some(count) => Ok(count),
+            None => self.count_keys(namespace),
+        }
+    }
+
+    fn key_counts(&self) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree("key_counts")
+            .map_err(NodeError::StorageError)
+    }
+
+    fn count_keys(&self, namespace: &str) -> Result<u64, NodeError> {
+        let mut count = 0;
+        for contract_space in self.spaces(namespace)? {
+            count += self.data(namespace, &contract_space)?.len() as u64;
+        }
+        Ok(count)
+    }
+
+    /// Counts a new key in `namespace`, unless it already holds `max` keys.
+    fn add_key(&self, namespace: &str, max: u64) -> Result<(), NodeError> {
+        let initial = self.key_count(namespace)?;
+        let mut full = false;
+        self.key_counts()?
+            .fetch_and_update(namespace, |raw| {
+                let count = raw
+                    .and_then(|raw| raw.try_into().ok())
+                    .map_or(initial, u64::from_be_bytes);
+                full = count >= max;
+                Some(
+                    (if full { count } else { count + 1 })
+                        .to_be_bytes()
+                        .to_vec(),
+                )
+            })
+            .map_err(NodeError::StorageError)?;
+        if full {
+            return Err(self.rejections.reject(Limit::NamespaceKeys));
+        }
+        Ok(())
+    }
+
+    /// Uncounts a key removed from `namespace`. Namespaces not counted yet are
+    /// left alone, as counting them later will not see the key.
+    fn remove_key(&self, namespace: &str) -> Result<(), NodeError> {
+        self.key_counts()?
+            .fetch_and_update(namespace, |raw| {
+                raw.and_then(|raw| raw.try_into().ok()).map(|count| {
+                    u64::from_be_bytes(count)
+                        .saturating_sub(1)
+                        .to_be_bytes()
+                        .to_vec()
+                })
+            })
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        value: &DbValue,
+        state: u64,
+    ) -> Result<(), NodeError> {
+        let raw = rmp_serde::to_vec(value).unwrap();
+        if raw.len() > self.limits.max_value_size {
+            return Err(self.rejections.reject(Limit::ValueSize));
+        }
+
+        let data = self.data(namespace, contract_space)?;
+        let added = !data.contains_key(key).map_err(NodeError::StorageError)?;
+        if added {
+            self.add_key(namespace, self.limits.max_keys_per_namespace)?;
+        }
+        let replaced = data.insert(key, raw).map_err(NodeError::StorageError)?;
+        // Another write added the key first and counted it already
+        if added && replaced.is_some() {
+            self.remove_key(namespace)?;
+        }
+        self.states(namespace, contract_space)?
+            .insert(key, &state.to_be_bytes())
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+
+    /// Merges `value` into the stored one using the regular conflict resolution.
+    pub fn insert(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        value: DbValue,
+        state: u64,
+    ) -> Result<(), NodeError> {
+        if self.buried(namespace, contract_space, key, state)? {
+            return Ok(());
+        }
+        let current_state = self.state(namespace, contract_space, key)?;
+        self.merge_write(
+            namespace,
+            contract_space,
+            key,
+            value,
+            (current_state, state),
+            state,
+        )
+    }
+
+    /// Like [`DataStore::insert`], except that a conflict between values at
+    /// the same state goes to the one signed last, at `signed_at`, as if it
+    /// had the higher state. Values signed at the same time fall back to the
+    /// regular resolution.
+    pub fn insert_latest(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        value: DbValue,
+        state: u64,
+        signed_at: u64,
+    ) -> Result<(), NodeError> {
+        if self.buried(namespace, contract_space, key, state)? {
+            return Ok(());
+        }
+        let current_state = self.state(namespace, contract_space, key)?;
+        let current_stamp = self.stamp(namespace, contract_space, key)?;
+        let states = match (current_state.cmp(&state), current_stamp.cmp(&signed_at)) {
+            (cmp::Ordering::Equal, cmp::Ordering::Less) => (0, 1),
+            (cmp::Ordering::Equal, cmp::Ordering::Greater) => (1, 0),
+            _ => (current_state, state),
+        };
+
+        self.merge_write(namespace, contract_space, key, value, states, state)?;
+        if (state, signed_at) > (current_state, current_stamp) {
+            self.stamps(namespace, contract_space)?
+                .insert(key, &signed_at.to_be_bytes())
+                .map_err(NodeError::StorageError)?;
+        }
+        Ok(())
+    }
+
+    /// Merges `value` into the stored value of `key`, resolving conflicts as
+    /// if the two were at the `(stored, incoming)` states given, and stores
+    /// the result at the higher of its actual state and `state`.
+    fn merge_write(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        value: DbValue,
+        (target_state, from_state): (u64, u64),
+        state: u64,
+    ) -> Result<(), NodeError> {
+        let current_state = self.state(namespace, contract_space, key)?;
+
+        let mut target = HashMap::new();
+        if let Some(current) = self.get(namespace, contract_space, key)? {
+            target.insert(key.to_string(), Box::new(current));
+        }
+
+        merge(
+            &mut target,
+            &HashMap::from([(key.to_string(), Box::new(value))]),
+            &HashMap::from([(key.to_string(), target_state)]),
+            &HashMap::from([(key.to_string(), from_state)]),
+        );
+
+        match target.remove(key) {
+            Some(merged) => self.write(
+                namespace,
+                contract_space,
+                key,
+                &merged,
+                current_state.max(state),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies `ops` to the stored value of `key` as of `state`. Patches older
+    /// than the stored value are dropped, as they were made to a value that
+    /// has since been replaced, and applying them would leave nodes that saw
+    /// the two writes in different orders with different values.
+    pub fn patch(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        ops: &[PatchOp],
+        state: u64,
+    ) -> Result<(), NodeError> {
+        if self.buried(namespace, contract_space, key, state)? {
+            return Ok(());
+        }
+        if state < self.state(namespace, contract_space, key)? {
+            debug!("Dropping patch of {key} older than its stored value");
+            return Ok(());
+        }
+        let mut value = self
+            .get(namespace, contract_space, key)?
+            .unwrap_or(DbValue::None);
+
+        value.apply_patch(ops);
+
+        self.write(namespace, contract_space, key, &value, state)
+    }
+
+    /// Removes `key` as of `state`, leaving a tombstone so writes up to that
+    /// state cannot bring it back. A value stored at a higher state was written
+    /// after the deletion and stays, though the tombstone is still kept, so
+    /// nodes taking the two in either order end up with the same value.
+    pub fn delete(
+        &self,
+        namespace: &str,
+        contract_space: &str,
+        key: &str,
+        state: u64,
+    ) -> Result<(), NodeError> {
+        let current_state = self.state(namespace, contract_space, key)?;
+        let buried = self
+            .tombstone(namespace, contract_space, key)?
+            .map_or(state, |deleted| deleted.max(state));
+        let mut tombstone = buried.to_be_bytes().to_vec();
+        tombstone.extend_from_slice(&crate::unix_time().to_be_bytes());
+        self.tombstones(namespace, contract_space)?
+            .insert(key, tombstone)
+            .map_err(NodeError::StorageError)?;
+        if current_state > state {
+            debug!("Keeping {key}, written after its deletion");
+            return Ok(());
+        }
+        let removed = self
+            .data(namespace, contract_space)?
+            .remove(key)
+            .map_err(NodeError::StorageError)?;
+        if removed.is_some() {
+            self.remove_key(namespace)?;
+        }
+        self.states(namespace, contract_space)?
+            .remove(key)
+            .map_err(NodeError::StorageError)?;
+        self.stamps(namespace, contract_space)?
+            .remove(key)
+            .map_err(NodeError::StorageError)?;
+        Ok(())
+    }
+}
+
+/// Compiled contract artifacts kept in the node database, so contract runtimes
+/// can skip compilation after a restart.
+pub struct SledArtifactCache {
+    tree: sled::Tree,
+}
+
+impl SledArtifactCache {
+    pub fn new(db: &sled::Db) -> Result<Self, NodeError> {
+        Ok(Self {
+            tree: db
+                .open_tree(b"compiled_contracts")
+                .map_err(NodeError::StorageError)?,
+        })
+    }
+}
+
+impl ArtifactCache for SledArtifactCache {
+    fn load(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree
+            .get(key)
+            .inspect_err(|e| debug!("Failed to read compiled contract: {e:?}"))
+            .ok()
+            .flatten()
+            .map(|artifact| artifact.to_vec())
+    }
+
+    fn store(&self, key: &[u8], artifact: &[u8]) {
+        if let Err(e) = self.tree.insert(key, artifact) {
+            debug!("Failed to store compiled contract: {e:?}");
+        }
+    }
+}
+
+/// Host services for a contract executing on behalf of a single message.
+/// Private state written by the contract is held back until [`Self::commit`].
+pub struct MessageHost {
+    store: DataStore,
+    contract: Vec<u8>,
+    seed: [u8; 32],
+    pending: Mutex<HashMap<String, DbValue>>,
+    fuel: AtomicU64,
+    peak_memory: AtomicU64,
+}
+
+impl MessageHost {
+    #[must_use]
+    pub fn new(store: DataStore, contract: &[u8], message: &TransportMessage) -> Self {
+        let seed = Hasher::new(HashAlgorithm::Blake3)
+            .update(&message.id)
+            .update(&message.signature.data)
+            .finalize();
+
+        Self::seeded(store, contract, seed)
+    }
+
+    /// Host for calls not caused by a single message, such as migrations.
+    /// `seed` must be the same on every node running the call.
+    #[must_use]
+    pub fn seeded(store: DataStore, contract: &[u8], seed: [u8; 32]) -> Self {
+        Self {
+            store,
+            contract: contract.to_vec(),
+            seed,
+            pending: Mutex::new(HashMap::new()),
+            fuel: AtomicU64::new(0),
+            peak_memory: AtomicU64::new(0),
+        }
+    }
+
+    /// Stores the private state the contract wrote. Call only once the call
+    /// has succeeded.
+    pub fn commit(&self) -> Result<(), NodeError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for (key, value) in pending {
+            self.store.private_set(&self.contract, &key, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Resources the runtime reported for calls made with this host, given
+    /// how long they took.
+    #[must_use]
+    pub fn report(&self, duration: Duration) -> ExecutionReport {
+        ExecutionReport {
+            fuel: self.fuel.load(Ordering::Relaxed),
+            peak_memory: self.peak_memory.load(Ordering::Relaxed),
+            duration,
+        }
+    }
+}
+
+impl ContractHost for MessageHost {
+    fn get(&self, namespace: &str, contract_space: &str, key: &str) -> Option<DbValue> {
+        self.store
+            .get(namespace, contract_space, key)
+            .inspect_err(|e| debug!("Contract read of {key} failed: {e:?}"))
+            .ok()
+            .flatten()
+    }
+
+    fn random_seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    fn state_get(&self, key: &str) -> Option<DbValue> {
+        if let Some(value) = self.pending.lock().unwrap().get(key) {
+            return Some(value.clone());
+        }
+
+        self.store
+            .private_get(&self.contract, key)
+            .inspect_err(|e| debug!("Contract state read of {key} failed: {e:?}"))
+            .ok()
+            .flatten()
+    }
+
+    fn state_set(&self, key: &str, value: DbValue) {
+        self.pending.lock().unwrap().insert(key.to_string(), value);
+    }
+
+    fn record_fuel(&self, fuel: u64) {
+        self.fuel.fetch_add(fuel, Ordering::Relaxed);
+    }
+
+    fn record_memory(&self, bytes: u64) {
+        self.peak_memory.fetch_max(bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests;
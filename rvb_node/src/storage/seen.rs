@@ -0,0 +1,123 @@
+use crate::NodeError;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Buckets a retention period is split into. Ids are dropped a whole bucket
+/// at a time, so they are kept for up to one bucket longer than asked.
+const BUCKETS: u64 = 16;
+
+/// How many ids [`SeenStore`] holds, and how long ago the oldest was seen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeenStats {
+    pub ids: usize,
+    /// Age of the oldest bucket still kept, `None` if there is none.
+    pub oldest: Option<Duration>,
+}
+
+/// Ids of the messages a node has seen, kept on disk so duplicates, and
+/// replays of old messages, are still dropped after a restart. Time is split
+/// into buckets: the `seen` tree maps each id to the big-endian bucket it was
+/// seen in, and the `seen_buckets` tree holds the same ids keyed by bucket
+/// then id, so buckets past the retention are dropped in key order.
+pub struct SeenStore {
+    db: sled::Db,
+    /// Length of a bucket, in seconds.
+    bucket: u64,
+    /// Buckets kept besides the current one.
+    kept: u64,
+    /// Bucket ids were last pruned in.
+    pruned: AtomicU64,
+}
+
+impl SeenStore {
+    /// Store keeping ids for at least `retention`.
+    #[must_use]
+    pub fn new(db: sled::Db, retention: Duration) -> Self {
+        let bucket = (retention.as_secs() / BUCKETS).max(1);
+        Self {
+            db,
+            bucket,
+            kept: retention.as_secs().div_ceil(bucket),
+            pruned: AtomicU64::new(0),
+        }
+    }
+
+    fn ids(&self) -> Result<sled::Tree, NodeError> {
+        self.db.open_tree(b"seen").map_err(NodeError::StorageError)
+    }
+
+    fn buckets(&self) -> Result<sled::Tree, NodeError> {
+        self.db
+            .open_tree(b"seen_buckets")
+            .map_err(NodeError::StorageError)
+    }
+
+    /// Oldest bucket kept while `current` is the current one.
+    fn oldest_kept(&self, current: u64) -> u64 {
+        current.saturating_sub(self.kept)
+    }
+
+    /// Records `id` as seen at `now`, in seconds since the Unix epoch,
+    /// returning `false` if it was seen within the retention. Buckets that
+    /// fell out of it are dropped first, once per bucket.
+    pub fn insert(&self, id: &[u8], now: u64) -> Result<bool, NodeError> {
+        let bucket = now / self.bucket;
+        if self.pruned.swap(bucket, Ordering::Relaxed) != bucket {
+            self.prune(now)?;
+        }
+
+        let (ids, buckets) = (self.ids()?, self.buckets()?);
+        if let Some(previous) = ids.get(id).map_err(NodeError::StorageError)? {
+            let previous = u64::from_be_bytes(previous.as_ref().try_into().unwrap_or_default());
+            if previous >= self.oldest_kept(bucket) {
+                return Ok(false);
+            }
+            buckets
+                .remove(bucket_key(previous, id))
+                .map_err(NodeError::StorageError)?;
+        }
+        ids.insert(id, &bucket.to_be_bytes())
+            .map_err(NodeError::StorageError)?;
+        buckets
+            .insert(bucket_key(bucket, id), &[])
+            .map_err(NodeError::StorageError)?;
+        Ok(true)
+    }
+
+    /// Drops the ids seen before the retention as of `now`, returning how
+    /// many there were.
+    pub fn prune(&self, now: u64) -> Result<usize, NodeError> {
+        let (ids, buckets) = (self.ids()?, self.buckets()?);
+        let oldest = self.oldest_kept(now / self.bucket);
+        let mut pruned = 0;
+        for entry in buckets.range(..oldest.to_be_bytes()) {
+            let (key, _) = entry.map_err(NodeError::StorageError)?;
+            ids.remove(&key[8..]).map_err(NodeError::StorageError)?;
+            buckets.remove(key).map_err(NodeError::StorageError)?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// Size and age of the store as of `now`.
+    pub fn stats(&self, now: u64) -> Result<SeenStats, NodeError> {
+        let oldest = self
+            .buckets()?
+            .first()
+            .map_err(NodeError::StorageError)?
+            .and_then(|(key, _)| key[..8].try_into().ok())
+            .map(|bucket| {
+                Duration::from_secs(now.saturating_sub(u64::from_be_bytes(bucket) * self.bucket))
+            });
+        Ok(SeenStats {
+            ids: self.ids()?.len(),
+            oldest,
+        })
+    }
+}
+
+fn bucket_key(bucket: u64, id: &[u8]) -> Vec<u8> {
+    let mut key = bucket.to_be_bytes().to_vec();
+    key.extend_from_slice(id);
+    key
+}
@@ -0,0 +1,667 @@
+use super::*;
+use crate::NodeConfig;
+use crate::testing::{location, node_with, process, wait_until};
+use rvb_common::crypto::KeyPair;
+use rvb_common::protocol::{ContractInfo, Message};
+
+fn store() -> DataStore {
+    DataStore::new(sled::Config::new().temporary(true).open().unwrap())
+}
+
+#[test]
+fn test_insert_and_get() {
+    let store = store();
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 1)
+        .unwrap();
+
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Number(1))
+    );
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 1);
+}
+
+#[test]
+fn test_get_missing() {
+    let store = store();
+    assert_eq!(store.get("ns", "space", "key").unwrap(), None);
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 0);
+}
+
+#[test]
+fn test_insert_keeps_newer_state() {
+    let store = store();
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 5)
+        .unwrap();
+    store
+        .insert("ns", "space", "key", DbValue::Number(2), 3)
+        .unwrap();
+
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Number(1))
+    );
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 5);
+}
+
+#[test]
+fn test_scopes_are_isolated() {
+    let store = store();
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 1)
+        .unwrap();
+
+    assert_eq!(store.get("ns", "other", "key").unwrap(), None);
+    assert_eq!(store.get("other", "space", "key").unwrap(), None);
+}
+
+#[test]
+fn test_entries() {
+    let store = store();
+    store
+        .insert("ns", "b", "key", DbValue::Number(2), 4)
+        .unwrap();
+    store
+        .insert("ns", "a", "key", DbValue::Number(1), 1)
+        .unwrap();
+    store
+        .insert("other", "a", "key", DbValue::Number(3), 1)
+        .unwrap();
+
+    assert_eq!(store.spaces("ns").unwrap(), vec!["a", "b"]);
+    assert_eq!(
+        store.entries("ns").unwrap(),
+        vec![
+            Entry {
+                contract_space: "a".into(),
+                key: "key".into(),
+                value: DbValue::Number(1),
+                state: 1,
+            },
+            Entry {
+                contract_space: "b".into(),
+                key: "key".into(),
+                value: DbValue::Number(2),
+                state: 4,
+            },
+        ]
+    );
+    assert!(store.entries("missing").unwrap().is_empty());
+}
+
+#[test]
+fn test_patch() {
+    let store = store();
+    store
+        .patch(
+            "ns",
+            "space",
+            "key",
+            &[PatchOp::Set {
+                path: vec!["a".into()],
+                value: DbValue::Boolean(true),
+            }],
+            2,
+        )
+        .unwrap();
+
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Object(HashMap::from([(
+            "a".into(),
+            Box::new(DbValue::Boolean(true))
+        )])))
+    );
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 2);
+}
+
+#[test]
+fn test_patch_order_independence() {
+    let ops = [PatchOp::Set {
+        path: vec!["a".into()],
+        value: DbValue::Boolean(true),
+    }];
+
+    let patched_first = store();
+    patched_first.patch("ns", "space", "key", &ops, 2).unwrap();
+    patched_first
+        .insert("ns", "space", "key", DbValue::Number(1), 3)
+        .unwrap();
+
+    let inserted_first = store();
+    inserted_first
+        .insert("ns", "space", "key", DbValue::Number(1), 3)
+        .unwrap();
+    inserted_first.patch("ns", "space", "key", &ops, 2).unwrap();
+
+    for store in [patched_first, inserted_first] {
+        assert_eq!(
+            store.get("ns", "space", "key").unwrap(),
+            Some(DbValue::Number(1))
+        );
+        assert_eq!(store.state("ns", "space", "key").unwrap(), 3);
+    }
+}
+
+#[test]
+fn test_delete() {
+    let store = store();
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 1)
+        .unwrap();
+    store.delete("ns", "space", "key", 1).unwrap();
+
+    assert_eq!(store.get("ns", "space", "key").unwrap(), None);
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 0);
+}
+
+fn contract(id: u8, namespace: &str, tags: &[&str]) -> ContractInfo {
+    ContractInfo {
+        id: vec![id],
+        namespace: namespace.into(),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        deployed_by: vec![1, 2, 3],
+        metadata: None,
+    }
+}
+
+#[test]
+fn test_contract_registry() {
+    let contracts = ContractStore::new(sled::Config::new().temporary(true).open().unwrap());
+    let params = HashMap::from([("owner".to_string(), DbValue::Number(1))]);
+
+    contracts
+        .insert(&contract(1, "ns", &["kv", "public"]), b"one", &params)
+        .unwrap();
+    contracts
+        .insert(&contract(2, "ns", &["kv"]), b"two", &HashMap::new())
+        .unwrap();
+    contracts
+        .insert(
+            &contract(3, "other", &["kv", "public"]),
+            b"three",
+            &HashMap::new(),
+        )
+        .unwrap();
+
+    assert_eq!(contracts.bytecode(&[1]).unwrap(), Some(b"one".to_vec()));
+    assert_eq!(contracts.params(&[1]).unwrap(), params);
+    assert_eq!(
+        contracts.info(&[2]).unwrap(),
+        Some(contract(2, "ns", &["kv"]))
+    );
+    assert_eq!(contracts.info(&[4]).unwrap(), None);
+
+    let ids = |found: Vec<ContractInfo>| found.into_iter().map(|c| c.id).collect::<Vec<_>>();
+    assert_eq!(
+        ids(contracts.search("ns", &["kv".into()]).unwrap()),
+        vec![vec![1], vec![2]]
+    );
+    assert_eq!(
+        ids(contracts.search("ns", &["public".into()]).unwrap()),
+        vec![vec![1]]
+    );
+}
+
+#[test]
+fn test_contract_spaces() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let contracts = ContractStore::new(db.clone());
+    let store = DataStore::new(db);
+
+    contracts.add_space(&[1], "ns", "a").unwrap();
+    contracts.add_space(&[1], "ns", "a").unwrap();
+    contracts.add_space(&[1], "other", "b").unwrap();
+    contracts.add_space(&[1, 2], "ns", "c").unwrap();
+
+    assert_eq!(
+        contracts.spaces(&[1]).unwrap(),
+        vec![("ns".into(), "a".into()), ("other".into(), "b".into())]
+    );
+    assert_eq!(contracts.spaces(&[3]).unwrap(), vec![]);
+
+    store.insert("ns", "a", "x", DbValue::Number(1), 1).unwrap();
+    store.insert("ns", "a", "y", DbValue::Number(2), 1).unwrap();
+    assert_eq!(store.keys("ns", "a").unwrap(), vec!["x", "y"]);
+}
+
+#[test]
+fn test_private_state() {
+    let store = store();
+    store
+        .private_set(&[1], "hits", &DbValue::Number(1))
+        .unwrap();
+
+    assert_eq!(
+        store.private_get(&[1], "hits").unwrap(),
+        Some(DbValue::Number(1))
+    );
+    assert_eq!(store.private_get(&[2], "hits").unwrap(), None);
+    // Private state is not user data
+    assert_eq!(store.keys("ns", "space").unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn test_fuel_ledger() {
+    let ledger = FuelLedger::new(sled::Config::new().temporary(true).open().unwrap());
+    ledger.charge(&[1], "ns", 10).unwrap();
+    ledger.charge(&[1], "other", 5).unwrap();
+    ledger.charge(&[2], "ns", 1).unwrap();
+
+    assert_eq!(ledger.deployer(&[1]).unwrap(), 15);
+    assert_eq!(ledger.deployer(&[2]).unwrap(), 1);
+    assert_eq!(ledger.deployer(&[3]).unwrap(), 0);
+    assert_eq!(ledger.namespace("ns").unwrap(), 11);
+    assert_eq!(ledger.namespace("other").unwrap(), 5);
+}
+
+#[test]
+fn test_dump_round_trip() {
+    let source = store();
+    let object = DbValue::Object(HashMap::from([(
+        "stars".into(),
+        Box::new(DbValue::Number(3)),
+    )]));
+    source.insert("ns", "b", "key", object.clone(), 7).unwrap();
+    source
+        .insert("ns", "a", "key", DbValue::String("x".into()), 1)
+        .unwrap();
+    source
+        .insert("other", "a", "key", DbValue::Number(1), 1)
+        .unwrap();
+
+    let mut dump = Vec::new();
+    assert_eq!(dump::export(&source, "ns", &mut dump).unwrap(), 2);
+    let text = String::from_utf8(dump.clone()).unwrap();
+    assert_eq!(
+        text.lines().next().unwrap(),
+        r#"{"namespace":"ns","contract_space":"a","key":"key","state":1,"value":"x"}"#
+    );
+
+    let target = store();
+    // Newer than the dump, so the import leaves it alone.
+    target
+        .insert("ns", "a", "key", DbValue::Number(9), 5)
+        .unwrap();
+    assert_eq!(dump::import(&target, dump.as_slice()).unwrap(), 2);
+
+    assert_eq!(
+        target.get("ns", "a", "key").unwrap(),
+        Some(DbValue::Number(9))
+    );
+    assert_eq!(target.get("ns", "b", "key").unwrap(), Some(object));
+    assert_eq!(target.state("ns", "b", "key").unwrap(), 7);
+    assert!(target.entries("other").unwrap().is_empty());
+}
+
+#[test]
+fn test_import_invalid_record() {
+    let dump = b"\n{\"namespace\":\"ns\",\"contract_space\":\"a\",\"key\":\"k\",\"state\":1,\"value\":1}\n{oops}\n";
+    match dump::import(&store(), dump.as_slice()) {
+        Err(dump::DumpError::InvalidRecord { line, .. }) => assert_eq!(line, 3),
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn test_audit_log_chain() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let log = AuditLog::new(db.clone());
+    let first = log
+        .append(&[1], &[2], "ns", "deploy a".into(), None)
+        .unwrap();
+    let second = log
+        .append(&[3], &[2], "ns", "insert space/key".into(), Some(4))
+        .unwrap();
+
+    assert_eq!((first.sequence, second.sequence), (0, 1));
+    assert_eq!(first.previous, [0; 32]);
+    assert_eq!(second.previous, first.hash);
+    assert_eq!(
+        log.records(0, 10).unwrap(),
+        vec![first.clone(), second.clone()]
+    );
+    assert_eq!(log.records(1, 10).unwrap(), vec![second.clone()]);
+    assert_eq!(log.verify().unwrap(), 2);
+
+    // Rewriting a record breaks the chain from there on.
+    let tampered = AuditRecord {
+        state: Some(5),
+        ..second
+    };
+    db.open_tree(b"audit")
+        .unwrap()
+        .insert(1u64.to_be_bytes(), rmp_serde::to_vec(&tampered).unwrap())
+        .unwrap();
+    assert!(matches!(log.verify(), Err(NodeError::AuditChainBroken(1))));
+}
+
+fn limited_store(limits: Limits) -> (DataStore, Arc<Rejections>) {
+    let rejections = Arc::new(Rejections::default());
+    let store = store().with_limits(limits, rejections.clone());
+    (store, rejections)
+}
+
+#[test]
+fn test_value_size_limit() {
+    let (store, rejections) = limited_store(Limits {
+        max_value_size: 64,
+        ..Limits::default()
+    });
+    store
+        .insert("ns", "space", "key", DbValue::String("short".into()), 1)
+        .unwrap();
+    let long = DbValue::String("x".repeat(64));
+    assert!(matches!(
+        store.insert("ns", "space", "key", long, 2),
+        Err(NodeError::LimitExceeded(Limit::ValueSize))
+    ));
+
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::String("short".into()))
+    );
+    assert_eq!(rejections.get(Limit::ValueSize), 1);
+}
+
+#[test]
+fn test_namespace_key_limit() {
+    let (store, rejections) = limited_store(Limits {
+        max_keys_per_namespace: 2,
+        ..Limits::default()
+    });
+    store
+        .insert("ns", "a", "one", DbValue::Number(1), 1)
+        .unwrap();
+    store
+        .insert("ns", "b", "two", DbValue::Number(2), 1)
+        .unwrap();
+    // Existing keys can still be written, and other namespaces are counted
+    // on their own
+    store
+        .insert("ns", "a", "one", DbValue::Number(3), 2)
+        .unwrap();
+    store
+        .insert("other", "a", "one", DbValue::Number(1), 1)
+        .unwrap();
+    assert!(matches!(
+        store.insert("ns", "a", "three", DbValue::Number(3), 1),
+        Err(NodeError::LimitExceeded(Limit::NamespaceKeys))
+    ));
+    assert_eq!(rejections.get(Limit::NamespaceKeys), 1);
+    assert_eq!(store.key_count("ns").unwrap(), 2);
+
+    store.delete("ns", "b", "two", 1).unwrap();
+    store
+        .insert("ns", "a", "three", DbValue::Number(3), 1)
+        .unwrap();
+    assert_eq!(store.key_count("ns").unwrap(), 2);
+}
+
+#[test]
+fn test_key_count_of_existing_namespace() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let store = DataStore::new(db.clone());
+    store
+        .insert("ns", "a", "one", DbValue::Number(1), 1)
+        .unwrap();
+    store
+        .insert("ns", "b", "two", DbValue::Number(2), 1)
+        .unwrap();
+    db.drop_tree("key_counts").unwrap();
+
+    let store = DataStore::new(db);
+    assert_eq!(store.key_count("ns").unwrap(), 2);
+    store
+        .insert("ns", "a", "three", DbValue::Number(3), 1)
+        .unwrap();
+    assert_eq!(store.key_count("ns").unwrap(), 3);
+}
+
+#[test]
+fn test_journal() {
+    let journal = Journal::new(sled::Config::new().temporary(true).open().unwrap());
+    let message = Message::SearchTags {
+        namespace: "ns".into(),
+        query: Vec::new(),
+    };
+    let transport = message.sign(&mut KeyPair::generate(), "test".into());
+    let first = journal.record(&transport, 0).unwrap();
+    let second = journal.record(&transport, 1).unwrap();
+
+    let pending = journal.pending().unwrap();
+    assert_eq!(
+        pending
+            .iter()
+            .map(|write| (write.sequence, write.index))
+            .collect::<Vec<_>>(),
+        [(first, 0), (second, 1)]
+    );
+    assert_eq!(pending[0].transport.id, transport.id);
+
+    journal.complete(first).unwrap();
+    let pending = journal.pending().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].sequence, second);
+}
+
+#[test]
+fn test_group_store() {
+    let groups = GroupStore::new(sled::Config::new().temporary(true).open().unwrap());
+    let keys = |member: &[u8]| HashMap::from([(member.to_vec(), b"wrapped".to_vec())]);
+    assert!(groups.share("ns", b"owner", 1, keys(b"a")).unwrap());
+
+    // Older epochs are ignored and only the owner may start new ones
+    assert!(!groups.share("ns", b"owner", 1, keys(b"b")).unwrap());
+    assert!(matches!(
+        groups.share("ns", b"other", 2, keys(b"b")),
+        Err(NodeError::NotGroupOwner)
+    ));
+    assert!(groups.share("ns", b"owner", 2, keys(b"b")).unwrap());
+
+    let group = groups.get("ns").unwrap().unwrap();
+    assert_eq!((group.owner.as_slice(), group.epoch), (&b"owner"[..], 2));
+    assert_eq!(group.keys, keys(b"b"));
+    assert_eq!(groups.get("other").unwrap(), None);
+}
+
+#[test]
+fn test_insert_latest() {
+    let store = store();
+    let text = |text: &str| DbValue::String(text.into());
+    let insert = |value: &str, state, signed_at| {
+        store
+            .insert_latest("ns", "space", "key", text(value), state, signed_at)
+            .unwrap();
+    };
+
+    // At the same state the later signature wins, whatever the content
+    insert("a", 1, 10);
+    insert("z", 1, 5);
+    assert_eq!(store.get("ns", "space", "key").unwrap(), Some(text("a")));
+    insert("0", 1, 20);
+    assert_eq!(store.get("ns", "space", "key").unwrap(), Some(text("0")));
+    assert_eq!(store.stamp("ns", "space", "key").unwrap(), 20);
+
+    // A higher state still wins over a later signature
+    insert("old", 2, 1);
+    insert("new", 1, 30);
+    assert_eq!(store.get("ns", "space", "key").unwrap(), Some(text("old")));
+    assert_eq!(store.state("ns", "space", "key").unwrap(), 2);
+    assert_eq!(store.stamp("ns", "space", "key").unwrap(), 1);
+}
+
+#[test]
+fn test_namespace_writers() {
+    let contracts = ContractStore::new(sled::Config::new().temporary(true).open().unwrap());
+    contracts
+        .insert(&contract(1, "ns", &[]), b"one", &HashMap::new())
+        .unwrap();
+
+    assert!(contracts.is_deployer("ns", &[1, 2, 3]).unwrap());
+    assert!(!contracts.is_deployer("ns", &[4]).unwrap());
+    assert!(!contracts.is_deployer("other", &[1, 2, 3]).unwrap());
+    assert!(contracts.may_deploy("ns", &[1, 2, 3]).unwrap());
+    assert!(!contracts.may_deploy("ns", &[4]).unwrap());
+    assert!(contracts.may_deploy("other", &[4]).unwrap());
+
+    assert_eq!(contracts.writers("ns").unwrap(), None);
+    contracts.set_writers("ns", Some(&[vec![4]])).unwrap();
+    assert_eq!(contracts.writers("ns").unwrap(), Some(vec![vec![4]]));
+    contracts.set_writers("ns", None).unwrap();
+    assert_eq!(contracts.writers("ns").unwrap(), None);
+}
+
+#[test]
+fn test_tombstones() {
+    let store = store();
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 2)
+        .unwrap();
+    store.delete("ns", "space", "key", 2).unwrap();
+    assert_eq!(store.tombstone("ns", "space", "key").unwrap(), Some(2));
+
+    // Writes up to the deletion do not bring the value back
+    for state in [1, 2] {
+        store
+            .insert("ns", "space", "key", DbValue::Number(1), state)
+            .unwrap();
+        assert_eq!(store.get("ns", "space", "key").unwrap(), None);
+    }
+    store
+        .insert("ns", "space", "key", DbValue::Number(3), 3)
+        .unwrap();
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Number(3))
+    );
+
+    // A value written after a deletion outlives it, whichever comes first
+    store.delete("ns", "space", "key", 2).unwrap();
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Number(3))
+    );
+    store.delete("ns", "space", "key", 4).unwrap();
+    store
+        .insert("ns", "space", "key", DbValue::Number(5), 5)
+        .unwrap();
+    store.delete("ns", "space", "key", 4).unwrap();
+    assert_eq!(store.tombstone("ns", "space", "key").unwrap(), Some(4));
+    assert_eq!(
+        store.get("ns", "space", "key").unwrap(),
+        Some(DbValue::Number(5))
+    );
+
+    // Only tombstones older than the cut-off are pruned
+    assert_eq!(store.prune_tombstones(0).unwrap(), 0);
+    assert_eq!(store.prune_tombstones(u64::MAX).unwrap(), 1);
+    assert_eq!(store.tombstone("ns", "space", "key").unwrap(), None);
+    assert!(store.flush().unwrap() > 0);
+}
+
+#[test]
+fn test_tombstone_at_state_zero() {
+    let store = store();
+    store.delete("ns", "space", "key", 0).unwrap();
+    assert_eq!(store.tombstone("ns", "space", "key").unwrap(), Some(0));
+
+    store
+        .insert("ns", "space", "key", DbValue::Number(1), 0)
+        .unwrap();
+    assert_eq!(store.get("ns", "space", "key").unwrap(), None);
+}
+
+#[test]
+fn test_audit_log_trim() {
+    let log = AuditLog::new(sled::Config::new().temporary(true).open().unwrap());
+    assert_eq!(log.trim(2).unwrap(), 0);
+    for i in 0..5 {
+        log.append(&[i], &[2], "ns", "insert space/key".into(), Some(1))
+            .unwrap();
+    }
+
+    assert_eq!(log.trim(2).unwrap(), 3);
+    assert_eq!(log.trim(2).unwrap(), 0);
+    assert_eq!(log.verify().unwrap(), 2);
+    let next = log
+        .append(&[5], &[2], "ns", "insert space/key".into(), Some(1))
+        .unwrap();
+    assert_eq!(next.sequence, 5);
+    assert_eq!(
+        log.records(0, 10)
+            .unwrap()
+            .iter()
+            .map(|record| record.sequence)
+            .collect::<Vec<_>>(),
+        vec![3, 4, 5]
+    );
+}
+
+#[test]
+fn test_seen_ids() {
+    let seen = SeenStore::new(
+        sled::Config::new().temporary(true).open().unwrap(),
+        Duration::from_secs(160),
+    );
+    assert!(seen.insert(&[1], 1000).unwrap());
+    assert!(!seen.insert(&[1], 1050).unwrap());
+    assert!(seen.insert(&[2], 1100).unwrap());
+    assert_eq!(
+        seen.stats(1100).unwrap(),
+        SeenStats {
+            ids: 2,
+            oldest: Some(Duration::from_secs(100)),
+        }
+    );
+
+    // Whole buckets are dropped once they fall out of the retention
+    assert_eq!(seen.prune(1150).unwrap(), 0);
+    assert_eq!(seen.prune(1200).unwrap(), 1);
+    assert!(!seen.insert(&[2], 1200).unwrap());
+    assert!(seen.insert(&[1], 1200).unwrap());
+    assert_eq!(seen.stats(1200).unwrap().ids, 2);
+}
+
+#[tokio::test]
+async fn test_journaled_writes_are_replayed() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let node = Arc::new(node_with(
+        KeyPair::generate(),
+        NodeConfig::default(),
+        db.clone(),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+
+    // A write accepted right before a crash, left in the journal
+    let insert = Message::Insert {
+        location: location(&contract, "key"),
+        incoming_data: DbValue::Number(1),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    let transport = insert.sign(&mut KeyPair::generate(), "test".into());
+    let journal = Journal::new(db);
+    journal.record(&transport, 0).unwrap();
+
+    let task = process(&node);
+    wait_until("the write to be replayed", || {
+        !node.entries("ns").unwrap().is_empty()
+    })
+    .await;
+
+    assert_eq!(node.entries("ns").unwrap()[0].value, DbValue::Number(1));
+    assert!(journal.pending().unwrap().is_empty());
+    task.abort();
+}
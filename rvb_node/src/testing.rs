@@ -0,0 +1,122 @@
+//! Helpers for tests of nodes outside a cluster, talking to them through
+//! in-memory peers.
+
+use crate::{Node, NodeConfig};
+use rvb_common::crypto::KeyPair;
+use rvb_common::protocol::{Location, Message, TransportMessage, challenge_payload};
+use rvb_common::transport::{Server, TransportError, TransportPeer};
+use rvb_contract::accept::AcceptContractCompiler;
+use rvb_transport::memory::MemoryPeer;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How long tests wait for a node to answer.
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server for nodes that only get peers through [`Node::connect_peer`].
+pub(crate) struct NoServer;
+
+#[async_trait::async_trait]
+impl Server for NoServer {
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        std::future::pending().await
+    }
+}
+
+/// Node with a new key and temporary storage, accepting every contract call.
+pub(crate) fn node(config: NodeConfig) -> Node {
+    node_with(
+        KeyPair::generate(),
+        config,
+        sled::Config::new().temporary(true).open().unwrap(),
+    )
+}
+
+/// Like [`node`], as `keypair` and keeping its data in `db`.
+pub(crate) fn node_with(keypair: KeyPair, config: NodeConfig, db: sled::Db) -> Node {
+    Node::new(
+        keypair,
+        config,
+        db,
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    )
+}
+
+/// Processes the messages of `node` until the task is aborted.
+pub(crate) fn process(node: &Arc<Node>) -> JoinHandle<()> {
+    let node = node.clone();
+    tokio::spawn(async move { node.process().await })
+}
+
+/// Connects a new in-memory peer to `node` and returns the test's end of it.
+pub(crate) async fn connect(node: &Node) -> MemoryPeer {
+    let (peer, node_side) = rvb_transport::memory::pair();
+    node.connect_peer(Box::new(node_side)).await;
+    peer
+}
+
+pub(crate) fn location(contract: &[u8], key: &str) -> Location {
+    Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: contract.to_vec(),
+        key: key.into(),
+    }
+}
+
+pub(crate) async fn send(peer: &dyn TransportPeer, msg: &TransportMessage) {
+    peer.send(rmp_serde::to_vec(msg).unwrap().into())
+        .await
+        .unwrap();
+}
+
+/// Waits until `condition` holds.
+pub(crate) async fn wait_until(what: &str, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for {what}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Next batch the node on the other end of `peer` sends, past the `Hello` it
+/// opens every connection with.
+pub(crate) async fn next_batch(peer: &dyn TransportPeer) -> Vec<Message> {
+    loop {
+        let raw = tokio::time::timeout(TIMEOUT, peer.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let msg: TransportMessage = rmp_serde::from_slice(&raw).unwrap();
+        let messages: Vec<Message> = msg.try_into().unwrap();
+        if !matches!(messages[..], [Message::Hello { .. }]) {
+            return messages;
+        }
+    }
+}
+
+/// Takes `peer` through the handshake with the node on its other end as
+/// `keypair`, after which the node takes its inserts and reads.
+pub(crate) async fn introduce(peer: &dyn TransportPeer, keypair: &mut KeyPair) {
+    let hello = Message::Hello {
+        public_key: keypair.export_public(),
+        session_key: Vec::new(),
+        certificates: Vec::new(),
+    };
+    send(peer, &hello.sign(keypair, "test".into())).await;
+    loop {
+        match next_batch(peer).await.as_slice() {
+            [Message::WhoAreYou { data, .. }] => {
+                let answer = Message::ItsMe {
+                    signature: keypair.sign(&challenge_payload(data)),
+                    data: data.clone(),
+                };
+                send(peer, &answer.sign(keypair, "test".into())).await;
+            }
+            [Message::Welcome { .. }] => return,
+            _ => {}
+        }
+    }
+}
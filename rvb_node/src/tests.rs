@@ -0,0 +1,432 @@
+use crate::metrics::Limit;
+use crate::testing::{
+    TIMEOUT, connect, introduce, location, next_batch, node, node_with, process, send, wait_until,
+};
+use crate::{Limits, Node, NodeConfig, NodeError, RelayConfig};
+use rvb_common::crypto::{KeyPair, hash};
+use rvb_common::protocol::{
+    Capability, ContractEvent, Message, TransportMessage, challenge_payload,
+};
+use rvb_common::schema::DbValue;
+use rvb_common::transport::TransportPeer;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+fn search(namespace: &str) -> Message {
+    Message::SearchTags {
+        namespace: namespace.into(),
+        query: Vec::new(),
+    }
+}
+
+/// Namespace of the single `SearchResult` in the next batch from `peer`.
+async fn search_reply(peer: &dyn TransportPeer) -> String {
+    match &next_batch(peer).await[..] {
+        [Message::SearchResult { namespace, .. }] => namespace.clone(),
+        other => panic!("unexpected reply {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_replies_are_batched() {
+    let node = Arc::new(node(NodeConfig {
+        batch_window: Some(Duration::from_millis(200)),
+        max_batch: 16,
+        ..NodeConfig::default()
+    }));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    let mut keypair = KeyPair::generate();
+    for _ in 0..3 {
+        send(&peer, &search("ns").sign(&mut keypair, "test".into())).await;
+    }
+
+    let messages = next_batch(&peer).await;
+    assert_eq!(messages.len(), 3);
+    assert!(
+        messages
+            .iter()
+            .all(|message| matches!(message, Message::SearchResult { .. }))
+    );
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_duplicates_are_dropped() {
+    let node = Arc::new(node(NodeConfig::default()));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    let mut keypair = KeyPair::generate();
+    let first = search("first").sign(&mut keypair, "test".into());
+    let second = search("second").sign(&mut keypair, "test".into());
+    for msg in [&first, &first, &second] {
+        send(&peer, msg).await;
+    }
+
+    assert_eq!(search_reply(&peer).await, "first");
+    assert_eq!(search_reply(&peer).await, "second");
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_seen_ids_survive_restarts() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let start = || async {
+        let node = Arc::new(node_with(
+            KeyPair::generate(),
+            NodeConfig {
+                seen_retention: Some(Duration::from_secs(3600)),
+                ..NodeConfig::default()
+            },
+            db.clone(),
+        ));
+        let peer = connect(&node).await;
+        let task = process(&node);
+        (node, peer, task)
+    };
+
+    let mut keypair = KeyPair::generate();
+    let first = search("first").sign(&mut keypair, "test".into());
+    let second = search("second").sign(&mut keypair, "test".into());
+
+    let (node, peer, task) = start().await;
+    send(&peer, &first).await;
+    assert_eq!(search_reply(&peer).await, "first");
+    task.abort();
+    drop(node);
+
+    // The restarted node still knows the first message
+    let (node, peer, task) = start().await;
+    for msg in [&first, &second] {
+        send(&peer, msg).await;
+    }
+    assert_eq!(search_reply(&peer).await, "second");
+    assert_eq!(node.seen_stats().unwrap().unwrap().ids, 2);
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_seen_store_overrules_filter_false_positives() {
+    let node = Arc::new(node(NodeConfig {
+        seen_retention: Some(Duration::from_secs(3600)),
+        ..NodeConfig::default()
+    }));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    let mut keypair = KeyPair::generate();
+    let first = search("first").sign(&mut keypair, "test".into());
+    let second = search("second").sign(&mut keypair, "test".into());
+    // The filter takes the first message for one it has seen
+    node.seen.insert(&first.id);
+    for msg in [&first, &first, &second] {
+        send(&peer, msg).await;
+    }
+
+    assert_eq!(search_reply(&peer).await, "first");
+    assert_eq!(search_reply(&peer).await, "second");
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_expired_messages_are_dropped() {
+    let node = Arc::new(node(NodeConfig::default()));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    let mut keypair = KeyPair::generate();
+    let expired =
+        TransportMessage::sign_expiring(&[search("expired")], &mut keypair, "test".into(), 1);
+    let fresh = TransportMessage::sign(&[search("fresh")], &mut keypair, "test".into());
+    for msg in [expired, fresh] {
+        send(&peer, &msg).await;
+    }
+
+    assert_eq!(search_reply(&peer).await, "fresh");
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_reads_need_a_handshake() {
+    let node = Arc::new(node(NodeConfig::default()));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let task = process(&node);
+    let get = || Message::Get {
+        location: location(&contract, "key"),
+        select: Vec::new(),
+    };
+
+    // Signing the challenge with another key than the one introduced gets
+    // no welcome, and reads stay refused
+    let peer = connect(&node).await;
+    let mut keypair = KeyPair::generate();
+    let hello = Message::Hello {
+        public_key: keypair.export_public(),
+        session_key: Vec::new(),
+        certificates: Vec::new(),
+    };
+    send(&peer, &hello.sign(&mut keypair, "test".into())).await;
+    let challenge = next_batch(&peer).await;
+    let [Message::WhoAreYou { data, .. }] = challenge.as_slice() else {
+        panic!("expected WhoAreYou");
+    };
+    let forged = Message::ItsMe {
+        signature: KeyPair::generate().sign(&challenge_payload(data)),
+        data: data.clone(),
+    };
+    for msg in [forged, get()] {
+        send(&peer, &msg.sign(&mut keypair, "test".into())).await;
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), next_batch(&peer))
+            .await
+            .is_err()
+    );
+    assert!(!node.has_welcomed(&keypair.export_public()).await);
+
+    let peer = connect(&node).await;
+    let mut keypair = KeyPair::generate();
+    introduce(&peer, &mut keypair).await;
+    assert!(node.has_welcomed(&keypair.export_public()).await);
+    send(&peer, &get().sign(&mut keypair, "test".into())).await;
+    assert!(matches!(
+        next_batch(&peer).await.as_slice(),
+        [Message::GetResult { value: None, .. }]
+    ));
+    task.abort();
+}
+
+/// Nodes `[relay, a, b]` where `a` and `b` are only connected to `relay`.
+async fn relayed_nodes(quota: u64) -> (Vec<Arc<Node>>, Vec<JoinHandle<()>>) {
+    let relay = RelayConfig {
+        quota,
+        window: Duration::from_secs(60),
+    };
+    let nodes: Vec<_> = [Some(relay), None, None]
+        .into_iter()
+        .map(|relay| {
+            Arc::new(node(NodeConfig {
+                relay,
+                ..NodeConfig::default()
+            }))
+        })
+        .collect();
+    let tasks = nodes.iter().map(process).collect();
+
+    for leaf in &nodes[1..] {
+        let (relay_side, leaf_side) = rvb_transport::memory::pair();
+        nodes[0].connect_peer(Box::new(relay_side)).await;
+        leaf.connect_peer(Box::new(leaf_side)).await;
+    }
+    (nodes, tasks)
+}
+
+fn relayed_event(topic: &str) -> Message {
+    Message::Event {
+        event: ContractEvent {
+            namespace: "ns".into(),
+            contract_space: "space".into(),
+            topic: topic.into(),
+            payload: DbValue::None,
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_messages_are_relayed() {
+    let (nodes, tasks) = relayed_nodes(1 << 20).await;
+    let mut events = nodes[2].watch_events();
+
+    // The leaves learn about the relay, and it about them, as messages arrive
+    let received = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let _ = nodes[1]
+                .send_to(nodes[2].identity(), &[relayed_event("relayed")])
+                .await;
+            if let Ok(Ok(event)) =
+                tokio::time::timeout(Duration::from_millis(50), events.recv()).await
+            {
+                return event;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received.topic, "relayed");
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_relay_quota() {
+    let (nodes, tasks) = relayed_nodes(0).await;
+    let mut events = nodes[2].watch_events();
+
+    let deadline = Instant::now() + Duration::from_millis(500);
+    while Instant::now() < deadline {
+        let _ = nodes[1]
+            .send_to(nodes[2].identity(), &[relayed_event("relayed")])
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(events.try_recv().is_err());
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_limits_are_enforced() {
+    let node = Arc::new(node(NodeConfig {
+        limits: Limits {
+            max_message_size: 1024,
+            max_batch_messages: 2,
+            ..Limits::default()
+        },
+        ..NodeConfig::default()
+    }));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    let mut keypair = KeyPair::generate();
+    let oversized = search(&"x".repeat(2048)).sign(&mut keypair, "test".into());
+    let batch = TransportMessage::sign(&vec![search("batch"); 3], &mut keypair, "test".into());
+    let allowed = search("allowed").sign(&mut keypair, "test".into());
+    for msg in [oversized, batch, allowed] {
+        send(&peer, &msg).await;
+    }
+
+    // Messages are handled in order, so the others were refused by the time
+    // the last one is answered
+    assert_eq!(search_reply(&peer).await, "allowed");
+    assert_eq!(node.rejections(Limit::MessageSize), 1);
+    assert_eq!(node.rejections(Limit::BatchMessages), 1);
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_peers_known_only_by_proven_keys() {
+    let node = Arc::new(node(NodeConfig::default()));
+    let peer = connect(&node).await;
+    let task = process(&node);
+
+    // Messages another node signed, replayed before the handshake, neither
+    // make the connection that node nor a relay
+    let mut victim = KeyPair::generate();
+    let replayed = [
+        Message::Capabilities {
+            capabilities: vec![Capability::Relay],
+        },
+        Message::Gossip {
+            peers: HashMap::new(),
+            localities: HashMap::new(),
+        },
+    ];
+    for message in replayed {
+        send(&peer, &message.sign(&mut victim, "test".into())).await;
+    }
+    let mut own = KeyPair::generate();
+    introduce(&peer, &mut own).await;
+    assert!(node.has_peer(&own.export_public()).await);
+    assert!(!node.has_peer(&victim.export_public()).await);
+    assert!(matches!(
+        node.send_to(&victim.export_public(), &[Message::Ping { nonce: 1 }])
+            .await,
+        Err(NodeError::UnreachablePeer)
+    ));
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_deployments_check_contract_ids() {
+    let node = Arc::new(node(NodeConfig::default()));
+    let task = process(&node);
+    let peer = connect(&node).await;
+
+    let mut keypair = KeyPair::generate();
+    let id = rvb_common::crypto::contract_id(b"contract", &keypair.export_public()).to_vec();
+    let deploy = |payload: &[u8]| Message::DeployContract {
+        contract_payload: payload.to_vec(),
+        namespace: "ns".into(),
+        params: HashMap::new(),
+        tags: Vec::new(),
+        id: Some(id.clone()),
+    };
+    // Bytecode altered after the id was worked out is refused
+    for payload in [&b"tampered"[..], b"contract"] {
+        send(&peer, &deploy(payload).sign(&mut keypair, "test".into())).await;
+    }
+
+    wait_until("the deployment", || {
+        node.contract_info(&id).unwrap().is_some()
+    })
+    .await;
+    assert_eq!(node.search_contracts("ns", &[]).unwrap().len(), 1);
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_contract_patches() {
+    let node = node(NodeConfig::default());
+    let deployer = b"deployer".to_vec();
+    let v1 = b"contract version 1 with a long shared body".to_vec();
+    let v2 = b"contract version 2 with a long shared body, extended".to_vec();
+    let id = node
+        .deploy_contract(
+            v1.clone(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            deployer.clone(),
+        )
+        .await
+        .unwrap();
+
+    let patch = rvb_common::delta::diff(&v1, &v2).unwrap();
+    let (base, target) = (hash::blake3(&v1), hash::blake3(&v2));
+    assert!(matches!(
+        node.patch_contract(&id, &target, &patch, &target, &deployer)
+            .await,
+        Err(NodeError::PatchMismatch)
+    ));
+    assert!(matches!(
+        node.patch_contract(&id, &base, &patch, &base, &deployer)
+            .await,
+        Err(NodeError::PatchMismatch)
+    ));
+    assert!(matches!(
+        node.patch_contract(&id, &base, &patch, &target, b"someone else")
+            .await,
+        Err(NodeError::NotDeployer)
+    ));
+    node.patch_contract(&id, &base, &patch, &target, &deployer)
+        .await
+        .unwrap();
+
+    // Later patches go against the new bytecode
+    assert!(matches!(
+        node.patch_contract(&id, &base, &patch, &target, &deployer)
+            .await,
+        Err(NodeError::PatchMismatch)
+    ));
+    let patch = rvb_common::delta::diff(&v2, &v1).unwrap();
+    node.patch_contract(&id, &target, &patch, &base, &deployer)
+        .await
+        .unwrap();
+}
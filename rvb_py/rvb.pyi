@@ -0,0 +1,43 @@
+from typing import Any, AsyncIterator, Optional
+
+Value = Any
+
+class ReverbError(Exception): ...
+
+class Location:
+    namespace: str
+    contract_space: str
+    contract: bytes
+    key: str
+    def __init__(self, namespace: str, contract_space: str, contract: bytes, key: str) -> None: ...
+
+class Subscription(AsyncIterator[dict[str, Any]]):
+    def __aiter__(self) -> "Subscription": ...
+    async def __anext__(self) -> dict[str, Any]: ...
+
+class Client:
+    @staticmethod
+    async def connect(addr: str, key: Optional[str] = None, timeout: Optional[float] = None) -> "Client": ...
+    @property
+    def identity(self) -> bytes: ...
+    async def insert(
+        self,
+        location: Location,
+        value: Value,
+        metadata: Optional[dict[str, Value]] = None,
+        state: int = 1,
+    ) -> None: ...
+    async def get(self, location: Location, select: Optional[list[list[str]]] = None) -> Value: ...
+    async def deploy_contract(
+        self,
+        contract_payload: bytes,
+        namespace: str,
+        params: Optional[dict[str, Value]] = None,
+        tags: Optional[list[str]] = None,
+    ) -> bytes: ...
+    async def subscribe(
+        self, namespace: str, topic: Optional[str] = None, filter: Optional[str] = None
+    ) -> Subscription: ...
+    async def unsubscribe(self, namespace: str, topic: Optional[str] = None) -> None: ...
+
+def generate_key() -> str: ...
@@ -0,0 +1,94 @@
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyInt, PyList, PyString, PyTuple};
+use rvb_common::protocol::ContractEvent;
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+/// Converts a Python value made of `None`, booleans, integers, strings,
+/// lists, tuples and dicts with string keys. Floats are rejected rather than
+/// truncated, as values only hold integers.
+pub fn to_value(obj: &Bound<'_, PyAny>) -> PyResult<DbValue> {
+    if obj.is_none() {
+        return Ok(DbValue::None);
+    }
+    // Booleans are integers in Python, so they are checked first.
+    if let Ok(boolean) = obj.downcast::<PyBool>() {
+        return Ok(DbValue::Boolean(boolean.is_true()));
+    }
+    if let Ok(int) = obj.downcast::<PyInt>() {
+        return Ok(DbValue::Number(int.extract()?));
+    }
+    if let Ok(string) = obj.downcast::<PyString>() {
+        return Ok(DbValue::String(string.to_str()?.to_owned()));
+    }
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        return to_map(dict).map(|map| {
+            DbValue::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key.into(), Box::new(value)))
+                    .collect(),
+            )
+        });
+    }
+    if obj.downcast::<PyList>().is_ok() || obj.downcast::<PyTuple>().is_ok() {
+        return obj
+            .try_iter()?
+            .map(|item| to_value(&item?).map(Box::new))
+            .collect::<PyResult<_>>()
+            .map(DbValue::Array);
+    }
+    Err(PyTypeError::new_err(format!(
+        "cannot store a value of type {}",
+        obj.get_type().name()?
+    )))
+}
+
+/// Converts a dict with string keys, such as insert metadata or contract
+/// params.
+pub fn to_map(dict: &Bound<'_, PyDict>) -> PyResult<HashMap<String, DbValue>> {
+    dict.iter()
+        .map(|(key, value)| {
+            let key = key
+                .downcast::<PyString>()
+                .map_err(|_| PyTypeError::new_err("dict keys must be strings"))?
+                .to_str()?
+                .to_owned();
+            Ok((key, to_value(&value)?))
+        })
+        .collect()
+}
+
+pub fn to_py(py: Python<'_>, value: DbValue) -> PyResult<PyObject> {
+    Ok(match value {
+        DbValue::None => py.None(),
+        DbValue::Boolean(boolean) => boolean.into_pyobject(py)?.to_owned().into_any().unbind(),
+        DbValue::Number(number) => number.into_pyobject(py)?.into_any().unbind(),
+        DbValue::String(string) => string.into_pyobject(py)?.into_any().unbind(),
+        DbValue::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key.as_str(), to_py(py, *value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        DbValue::Array(values) => {
+            let values = values
+                .into_iter()
+                .map(|value| to_py(py, *value))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, values)?.into_any().unbind()
+        }
+    })
+}
+
+/// An event as a dict with `namespace`, `contract_space`, `topic` and
+/// `payload`.
+pub fn event_to_py(py: Python<'_>, event: ContractEvent) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("namespace", event.namespace)?;
+    dict.set_item("contract_space", event.contract_space)?;
+    dict.set_item("topic", event.topic)?;
+    dict.set_item("payload", to_py(py, event.payload)?)?;
+    Ok(dict.into_any().unbind())
+}
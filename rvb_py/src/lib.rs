@@ -0,0 +1,304 @@
+//! Python bindings for [`rvb_client`], built into the `rvb` module with
+//! maturin. Requests are coroutines run on a tokio runtime owned by the
+//! module, values are converted from and to plain Python objects, and
+//! subscriptions are async iterators:
+//!
+//! ```python
+//! client = await rvb.Client.connect("127.0.0.1:7070")
+//! await client.insert(location, {"name": "reverb", "stars": 3})
+//! async for event in await client.subscribe("ns"):
+//!     print(event["topic"], event["payload"])
+//! ```
+
+use pyo3::create_exception;
+use pyo3::exceptions::{
+    PyConnectionError, PyException, PyStopAsyncIteration, PyTimeoutError, PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pyo3_async_runtimes::tokio::future_into_py;
+use rvb_client::ClientError;
+use rvb_common::crypto::KeyPair;
+use rvb_common::schema::Filter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub mod convert;
+
+use convert::{event_to_py, to_map, to_py, to_value};
+
+#[cfg(test)]
+mod tests;
+
+create_exception!(
+    rvb,
+    ReverbError,
+    PyException,
+    "Request rejected by the client."
+);
+
+fn client_error(e: ClientError) -> PyErr {
+    match e {
+        ClientError::Timeout => PyTimeoutError::new_err("the node did not reply"),
+        ClientError::Closed | ClientError::TransportError(_) => {
+            PyConnectionError::new_err("the connection to the node is closed")
+        }
+        e => ReverbError::new_err(format!("{e:?}")),
+    }
+}
+
+/// Where a value lives: the contract governing it, its namespace and
+/// contract space, and its key.
+#[pyclass(module = "rvb", get_all, set_all)]
+#[derive(Clone)]
+pub struct Location {
+    pub namespace: String,
+    pub contract_space: String,
+    pub contract: Vec<u8>,
+    pub key: String,
+}
+
+#[pymethods]
+impl Location {
+    #[new]
+    fn new(namespace: String, contract_space: String, contract: Vec<u8>, key: String) -> Self {
+        Self {
+            namespace,
+            contract_space,
+            contract,
+            key,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Location({:?}, {:?}, {:?}, {:?})",
+            self.namespace,
+            self.contract_space,
+            rvb_common::crypto::b64_encode(&self.contract),
+            self.key
+        )
+    }
+}
+
+impl From<Location> for rvb_common::protocol::Location {
+    fn from(location: Location) -> Self {
+        Self {
+            namespace: location.namespace,
+            contract_space: location.contract_space,
+            contract: location.contract,
+            key: location.key,
+        }
+    }
+}
+
+/// A connection to a node; see `rvb_client::Client`.
+#[pyclass(module = "rvb")]
+pub struct Client {
+    inner: Arc<rvb_client::Client>,
+}
+
+#[pymethods]
+impl Client {
+    /// Connects to the node at `addr` over TCP, signing with the armored
+    /// private key `key`, or with a new key if there is none. `timeout` is how
+    /// many seconds requests wait for their reply.
+    #[staticmethod]
+    #[pyo3(signature = (addr, key=None, timeout=None))]
+    fn connect(
+        py: Python<'_>,
+        addr: String,
+        key: Option<String>,
+        timeout: Option<f64>,
+    ) -> PyResult<Bound<'_, PyAny>> {
+        let keypair = match key {
+            Some(key) => KeyPair::import_armored(&key)
+                .map_err(|e| PyValueError::new_err(format!("invalid key: {e:?}")))?,
+            None => KeyPair::generate(),
+        };
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(format!("invalid timeout: {e}")))?;
+
+        future_into_py(py, async move {
+            let mut client = rvb_client::Client::connect(&addr, keypair)
+                .await
+                .map_err(client_error)?;
+            if let Some(timeout) = timeout {
+                client = client.with_timeout(timeout);
+            }
+            Ok(Client {
+                inner: Arc::new(client),
+            })
+        })
+    }
+
+    /// Public key of the client.
+    #[getter]
+    fn identity<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.inner.identity())
+    }
+
+    /// Inserts `value` at `location`. The node applies it asynchronously, at
+    /// `state` or later.
+    #[pyo3(signature = (location, value, metadata=None, state=1))]
+    fn insert<'py>(
+        &self,
+        py: Python<'py>,
+        location: Location,
+        value: &Bound<'py, PyAny>,
+        metadata: Option<&Bound<'py, PyDict>>,
+        state: u64,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let value = to_value(value)?;
+        let metadata = metadata.map(to_map).transpose()?.unwrap_or_default();
+        let client = self.inner.clone();
+
+        future_into_py(py, async move {
+            client
+                .insert(location.into(), value, metadata, state)
+                .await
+                .map_err(client_error)
+        })
+    }
+
+    /// Reads the value at `location`, narrowed to the `select`ed field paths,
+    /// or all of it if there are none. `None` if there is no value.
+    #[pyo3(signature = (location, select=None))]
+    fn get<'py>(
+        &self,
+        py: Python<'py>,
+        location: Location,
+        select: Option<Vec<Vec<String>>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+
+        future_into_py(py, async move {
+            let value = client
+                .get(location.into(), select.unwrap_or_default())
+                .await
+                .map_err(client_error)?;
+            Python::with_gil(|py| match value {
+                Some(value) => to_py(py, value),
+                None => Ok(py.None()),
+            })
+        })
+    }
+
+    /// Deploys a contract and returns its id.
+    #[pyo3(signature = (contract_payload, namespace, params=None, tags=None))]
+    fn deploy_contract<'py>(
+        &self,
+        py: Python<'py>,
+        contract_payload: Vec<u8>,
+        namespace: String,
+        params: Option<&Bound<'py, PyDict>>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let params = params.map(to_map).transpose()?.unwrap_or_default();
+        let client = self.inner.clone();
+
+        future_into_py(py, async move {
+            let id = client
+                .deploy_contract(
+                    contract_payload,
+                    namespace,
+                    params,
+                    tags.unwrap_or_default(),
+                )
+                .await
+                .map_err(client_error)?;
+            Python::with_gil(|py| Ok(PyBytes::new(py, &id).unbind()))
+        })
+    }
+
+    /// Asks the node to forward events emitted in `namespace`, on `topic` or
+    /// on all topics, and returns an async iterator over them. With `filter`,
+    /// an expression such as `status == "open"`, only events whose payload
+    /// matches it are sent.
+    #[pyo3(signature = (namespace, topic=None, filter=None))]
+    fn subscribe<'py>(
+        &self,
+        py: Python<'py>,
+        namespace: String,
+        topic: Option<String>,
+        filter: Option<&str>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+        let filter = filter
+            .map(Filter::parse)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        future_into_py(py, async move {
+            let subscription = client
+                .subscribe(namespace, topic, filter)
+                .await
+                .map_err(client_error)?;
+            Ok(Subscription {
+                inner: Arc::new(Mutex::new(subscription)),
+            })
+        })
+    }
+
+    #[pyo3(signature = (namespace, topic=None))]
+    fn unsubscribe<'py>(
+        &self,
+        py: Python<'py>,
+        namespace: String,
+        topic: Option<String>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+
+        future_into_py(py, async move {
+            client
+                .unsubscribe(namespace, topic)
+                .await
+                .map_err(client_error)
+        })
+    }
+}
+
+/// Events from one `Client.subscribe` call, as dicts. Iteration stops once
+/// the connection is closed.
+#[pyclass(module = "rvb")]
+pub struct Subscription {
+    inner: Arc<Mutex<rvb_client::Subscription>>,
+}
+
+#[pymethods]
+impl Subscription {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+
+        future_into_py(py, async move {
+            match inner.lock().await.next().await {
+                Some(event) => Python::with_gil(|py| event_to_py(py, event)),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })
+    }
+}
+
+/// A new armored private key, to connect with the same identity later.
+#[pyfunction]
+fn generate_key() -> String {
+    KeyPair::generate().armor_private()
+}
+
+#[pymodule]
+#[pyo3(name = "rvb")]
+fn rvb_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Client>()?;
+    m.add_class::<Location>()?;
+    m.add_class::<Subscription>()?;
+    m.add_function(wrap_pyfunction!(generate_key, m)?)?;
+    m.add("ReverbError", m.py().get_type::<ReverbError>())?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use super::*;
+use pyo3::exceptions::PyTypeError;
+use pyo3::ffi::c_str;
+use rvb_common::protocol::ContractEvent;
+use rvb_common::schema::DbValue;
+use std::collections::HashMap;
+
+fn eval<'py>(py: Python<'py>, code: &std::ffi::CStr) -> Bound<'py, PyAny> {
+    py.eval(code, None, None).unwrap()
+}
+
+#[test]
+fn test_to_value() {
+    Python::with_gil(|py| {
+        let value = to_value(&eval(
+            py,
+            c_str!("{'name': 'reverb', 'stars': 3, 'tags': ('a', None), 'ok': True}"),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            value,
+            DbValue::Object(HashMap::from([
+                ("name".into(), Box::new(DbValue::String("reverb".into()))),
+                ("stars".into(), Box::new(DbValue::Number(3))),
+                (
+                    "tags".into(),
+                    Box::new(DbValue::Array(vec![
+                        Box::new(DbValue::String("a".into())),
+                        Box::new(DbValue::None),
+                    ]))
+                ),
+                ("ok".into(), Box::new(DbValue::Boolean(true))),
+            ]))
+        );
+    });
+}
+
+#[test]
+fn test_to_value_rejects_unsupported_types() {
+    Python::with_gil(|py| {
+        for code in [c_str!("1.5"), c_str!("{1: 'a'}"), c_str!("b'raw'")] {
+            let e = to_value(&eval(py, code)).unwrap_err();
+            assert!(e.is_instance_of::<PyTypeError>(py), "{code:?}: {e}");
+        }
+    });
+}
+
+#[test]
+fn test_to_py_round_trip() {
+    Python::with_gil(|py| {
+        let original = eval(py, c_str!("{'a': [1, 'two', False, None], 'b': {}}"));
+        let value = to_value(&original).unwrap();
+        let converted = to_py(py, value).unwrap();
+        assert!(converted.bind(py).eq(&original).unwrap());
+
+        let big = to_py(py, DbValue::Number(i128::MAX)).unwrap();
+        assert_eq!(big.extract::<i128>(py).unwrap(), i128::MAX);
+    });
+}
+
+#[test]
+fn test_event_to_py() {
+    Python::with_gil(|py| {
+        let event = event_to_py(
+            py,
+            ContractEvent {
+                namespace: "ns".into(),
+                contract_space: "space".into(),
+                topic: "starred".into(),
+                payload: DbValue::Number(3),
+            },
+        )
+        .unwrap();
+
+        let expected = eval(
+            py,
+            c_str!(
+                "{'namespace': 'ns', 'contract_space': 'space', 'topic': 'starred', 'payload': 3}"
+            ),
+        );
+        assert!(event.bind(py).eq(&expected).unwrap());
+    });
+}
+
+#[test]
+fn test_client_errors() {
+    Python::with_gil(|py| {
+        assert!(client_error(ClientError::Timeout).is_instance_of::<PyTimeoutError>(py));
+        assert!(client_error(ClientError::Closed).is_instance_of::<PyConnectionError>(py));
+        assert!(
+            client_error(ClientError::SigningError(
+                rvb_common::crypto::CryptoError::InvalidKey
+            ))
+            .is_instance_of::<ReverbError>(py)
+        );
+    });
+}
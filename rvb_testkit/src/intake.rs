@@ -0,0 +1,319 @@
+//! A harness for fuzzing the path a node takes in messages from its peers:
+//! decoding, checking signatures, then dispatching. [`IntakeHarness`] sends
+//! inputs to a node over a transport picked by the caller. After each input
+//! it checks that the node still answers a ping. If [`TrackingAllocator`] is
+//! installed, it also checks that the heap stayed within a budget.
+//!
+//! [`Mutator`] makes the inputs: random bytes, and signed batches that are
+//! cut short, have bits flipped, or carry msgpack headers with huge lengths.
+
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rvb_common::crypto::KeyPair;
+use rvb_common::protocol::{Message, TransportMessage};
+use rvb_common::transport::{TransportError, TransportPeer};
+use rvb_contract::accept::AcceptContractCompiler;
+use rvb_node::{Node, NodeConfig};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::{DEFAULT_TIMEOUT, NoServer};
+
+/// Heap the node may grow by over a run before [`IntakeHarness::check`]
+/// fails, unless set with [`IntakeHarness::with_memory_budget`].
+const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Global allocator counting live heap bytes, so [`IntakeHarness`] can check
+/// that inputs do not make the node's memory grow without bound. Install it
+/// in the fuzz target with `#[global_allocator]`. Without it, memory is not
+/// checked.
+pub struct TrackingAllocator;
+
+impl TrackingAllocator {
+    /// Bytes allocated and not freed yet, 0 if the allocator is not installed.
+    #[must_use]
+    pub fn live() -> usize {
+        LIVE_BYTES.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            LIVE_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        }
+        new
+    }
+}
+
+/// How an input broke the node.
+#[derive(Debug)]
+pub enum IntakeFailure {
+    /// The node stopped processing messages, most likely by panicking.
+    Stopped(String),
+    /// The node did not answer a ping within the harness's timeout.
+    Unresponsive,
+    /// The heap grew by more than the budget since the harness started.
+    MemoryGrew {
+        grown: usize,
+        budget: usize,
+    },
+    Transport(TransportError),
+}
+
+/// A node fed inputs through one end of a connection, checking after each
+/// that the node still works.
+pub struct IntakeHarness {
+    node: Arc<Node>,
+    peer: Box<dyn TransportPeer>,
+    keypair: KeyPair,
+    task: JoinHandle<()>,
+    timeout: Duration,
+    memory_budget: usize,
+    /// Live heap when the harness started.
+    baseline: usize,
+}
+
+impl IntakeHarness {
+    /// A node with the default limits, fed through an in-memory link.
+    pub async fn new() -> Self {
+        let (ours, node_side) = rvb_transport::memory::pair();
+        Self::with_transport(Box::new(ours), Box::new(node_side)).await
+    }
+
+    /// A node fed through `ours`, where `node_side` is the other end of the
+    /// same connection, as made by the transport under test.
+    pub async fn with_transport(
+        ours: Box<dyn TransportPeer>,
+        node_side: Box<dyn TransportPeer>,
+    ) -> Self {
+        let node = Arc::new(Node::new(
+            KeyPair::generate(),
+            NodeConfig::default(),
+            sled::Config::new().temporary(true).open().unwrap(),
+            Box::new(AcceptContractCompiler),
+            Box::new(NoServer),
+        ));
+        let task = {
+            let node = node.clone();
+            tokio::spawn(async move { node.process().await })
+        };
+        node.connect_peer(node_side).await;
+
+        Self {
+            node,
+            peer: ours,
+            keypair: KeyPair::generate(),
+            task,
+            timeout: DEFAULT_TIMEOUT,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            baseline: TrackingAllocator::live(),
+        }
+    }
+
+    /// Sets how long the node has to answer a ping.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how much the heap may grow over the run.
+    #[must_use]
+    pub fn with_memory_budget(mut self, budget: usize) -> Self {
+        self.memory_budget = budget;
+        self
+    }
+
+    #[must_use]
+    pub fn node(&self) -> &Arc<Node> {
+        &self.node
+    }
+
+    /// Sends `input` to the node as it is, then checks the node.
+    pub async fn feed(&mut self, input: &[u8]) -> Result<(), IntakeFailure> {
+        self.peer
+            .send(Bytes::copy_from_slice(input))
+            .await
+            .map_err(IntakeFailure::Transport)?;
+        self.check().await
+    }
+
+    /// Feeds `rounds` inputs made by a [`Mutator`] seeded with `seed`,
+    /// returning the first one that broke the node.
+    pub async fn run(&mut self, seed: u64, rounds: usize) -> Result<(), (Vec<u8>, IntakeFailure)> {
+        let mut mutator = Mutator::new(seed);
+        for _ in 0..rounds {
+            let input = mutator.next_input();
+            if let Err(failure) = self.feed(&input).await {
+                return Err((input, failure));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the node is still running, answers a ping and, with
+    /// [`TrackingAllocator`] installed, kept within the memory budget.
+    pub async fn check(&mut self) -> Result<(), IntakeFailure> {
+        if self.task.is_finished() {
+            let reason = match (&mut self.task).await {
+                Err(e) => format!("{e}"),
+                Ok(()) => "the node stopped processing messages".into(),
+            };
+            return Err(IntakeFailure::Stopped(reason));
+        }
+
+        let nonce = rand::random();
+        let ping = Message::Ping { nonce }.sign(&mut self.keypair, "fuzz".into());
+        self.peer
+            .send(rmp_serde::to_vec(&ping).unwrap().into())
+            .await
+            .map_err(IntakeFailure::Transport)?;
+        tokio::time::timeout(self.timeout, self.pong(nonce))
+            .await
+            .map_err(|_| IntakeFailure::Unresponsive)??;
+
+        let grown = TrackingAllocator::live().saturating_sub(self.baseline);
+        if grown > self.memory_budget {
+            return Err(IntakeFailure::MemoryGrew {
+                grown,
+                budget: self.memory_budget,
+            });
+        }
+        Ok(())
+    }
+
+    /// Waits for the `Pong` answering `nonce`, skipping other replies.
+    async fn pong(&self, nonce: u64) -> Result<(), IntakeFailure> {
+        loop {
+            let raw = self.peer.recv().await.map_err(IntakeFailure::Transport)?;
+            let Ok(msg) = rmp_serde::from_slice::<TransportMessage>(&raw) else {
+                continue;
+            };
+            let messages: Vec<Message> = msg.try_into().unwrap_or_default();
+            if messages
+                .iter()
+                .any(|message| matches!(message, Message::Pong { nonce: n } if *n == nonce))
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for IntakeHarness {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Seeded source of inputs for [`IntakeHarness`]. The same seed gives the
+/// same inputs, apart from the signatures and ids of signed batches.
+pub struct Mutator {
+    rng: StdRng,
+    keypair: KeyPair,
+}
+
+/// Msgpack headers announcing far more elements or bytes than follow.
+const HUGE_HEADERS: [&[u8]; 4] = [
+    &[0xdc, 0xff, 0xff],
+    &[0xdd, 0xff, 0xff, 0xff, 0xff],
+    &[0xdf, 0xff, 0xff, 0xff, 0xff],
+    &[0xc6, 0xff, 0xff, 0xff, 0xff],
+];
+
+impl Mutator {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            keypair: KeyPair::generate(),
+        }
+    }
+
+    /// Random bytes, up to `max_len` of them.
+    pub fn arbitrary(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.rng.gen_range(0..=max_len);
+        (0..len).map(|_| self.rng.r#gen()).collect()
+    }
+
+    /// A correctly signed batch of a few harmless messages.
+    pub fn signed_batch(&mut self) -> Vec<u8> {
+        let messages: Vec<_> = (0..self.rng.gen_range(1..4))
+            .map(|_| match self.rng.gen_range(0..3) {
+                0 => Message::Ping {
+                    nonce: self.rng.r#gen(),
+                },
+                1 => Message::SearchTags {
+                    namespace: "fuzz".into(),
+                    query: Vec::new(),
+                },
+                _ => Message::Unsubscribe {
+                    namespace: "fuzz".into(),
+                    topic: None,
+                },
+            })
+            .collect();
+        let msg = TransportMessage::sign(&messages, &mut self.keypair, "fuzz".into());
+        rmp_serde::to_vec(&msg).unwrap()
+    }
+
+    /// `input` damaged in one of several ways.
+    pub fn mutate(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = input.to_vec();
+        if output.is_empty() {
+            return self.arbitrary(64);
+        }
+        let at = self.rng.gen_range(0..output.len());
+        match self.rng.gen_range(0..5) {
+            0 => output.truncate(at),
+            1 => output[at] ^= 1 << self.rng.gen_range(0..8),
+            2 => {
+                let byte = self.rng.r#gen();
+                output.insert(at, byte);
+            }
+            3 => {
+                output.remove(at);
+            }
+            _ => {
+                let header = HUGE_HEADERS.choose(&mut self.rng).unwrap();
+                output.splice(at..at, header.iter().copied());
+            }
+        }
+        output
+    }
+
+    /// The next input: random bytes, a valid batch, or a damaged one.
+    pub fn next_input(&mut self) -> Vec<u8> {
+        match self.rng.gen_range(0..4) {
+            0 => self.arbitrary(256),
+            1 => self.signed_batch(),
+            _ => {
+                let batch = self.signed_batch();
+                self.mutate(&batch)
+            }
+        }
+    }
+}
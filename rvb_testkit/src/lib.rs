@@ -0,0 +1,330 @@
+//! In-process clusters of nodes for tests. A [`Cluster`] runs every node in
+//! the test's runtime, connects each pair of nodes through in-memory links and
+//! lets the test partition the network, slow links down and drop messages,
+//! then wait for the nodes to agree with [`Cluster::eventually_converged`].
+//!
+//! Nodes do not forward the messages they apply to their peers yet, so data
+//! only converges when every node is given the same writes; events already
+//! travel between nodes through [`Cluster::subscribe`].
+//!
+//! [`IntakeHarness`] feeds a single node malformed input through a transport
+//! of the caller's choosing, for fuzzing transports against the node.
+
+use rvb_client::{Client, ClientError};
+use rvb_common::contract::ContractCompiler;
+use rvb_common::crypto::KeyPair;
+use rvb_common::schema::{DbValue, Filter};
+use rvb_common::transport::{Server, TransportError, TransportPeer};
+use rvb_contract::accept::AcceptContractCompiler;
+use rvb_node::storage::Entry;
+use rvb_node::{Node, NodeConfig, NodeError};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+mod intake;
+mod network;
+
+pub use intake::{IntakeFailure, IntakeHarness, Mutator, TrackingAllocator};
+pub use network::LinkConditions;
+use network::{LinkPeer, Network};
+
+#[cfg(test)]
+mod tests;
+
+/// How long assertions wait for a condition by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Cluster {
+    nodes: Vec<Arc<Node>>,
+    /// Remote node of each of a node's peers, in the order they were added.
+    links: Vec<Vec<usize>>,
+    network: Arc<Network>,
+    tasks: Vec<JoinHandle<()>>,
+    timeout: Duration,
+}
+
+/// Server for nodes that only get peers through [`Node::connect_peer`].
+struct NoServer;
+
+#[async_trait::async_trait]
+impl Server for NoServer {
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        std::future::pending().await
+    }
+}
+
+impl Cluster {
+    /// Starts `size` nodes accepting every contract call, with losses drawn
+    /// from seed 0.
+    pub async fn new(size: usize) -> Self {
+        Self::with_seed(size, 0).await
+    }
+
+    /// Starts `size` nodes accepting every contract call, with losses drawn
+    /// from `seed`.
+    pub async fn with_seed(size: usize, seed: u64) -> Self {
+        Self::with_compiler(size, seed, || Box::new(AcceptContractCompiler)).await
+    }
+
+    /// Starts `size` nodes, each compiling contracts with a compiler made by
+    /// `compiler`, and connects every pair of them.
+    pub async fn with_compiler(
+        size: usize,
+        seed: u64,
+        compiler: impl Fn() -> Box<dyn ContractCompiler>,
+    ) -> Self {
+        let network = Arc::new(Network::new(seed));
+        let nodes: Vec<_> = (0..size)
+            .map(|_| {
+                Arc::new(Node::new(
+                    KeyPair::generate(),
+                    NodeConfig::default(),
+                    sled::Config::new().temporary(true).open().unwrap(),
+                    compiler(),
+                    Box::new(NoServer),
+                ))
+            })
+            .collect();
+        let tasks = nodes
+            .iter()
+            .map(|node| {
+                let node = node.clone();
+                tokio::spawn(async move { node.process().await })
+            })
+            .collect();
+
+        let mut links = vec![Vec::new(); size];
+        for a in 0..size {
+            for b in a + 1..size {
+                let (a_side, b_side) = rvb_transport::memory::pair();
+                for (from, to, inner) in [(a, b, a_side), (b, a, b_side)] {
+                    network.set_link(from, to, LinkConditions::default());
+                    nodes[from]
+                        .connect_peer(Box::new(LinkPeer {
+                            inner,
+                            from,
+                            to,
+                            network: network.clone(),
+                        }))
+                        .await;
+                    links[from].push(to);
+                }
+            }
+        }
+
+        let cluster = Self {
+            nodes,
+            links,
+            network,
+            tasks,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        for node in &cluster.nodes {
+            while node.peers.read().await.len() < size.saturating_sub(1) {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        cluster
+    }
+
+    /// Sets how long assertions wait before failing.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    #[must_use]
+    pub fn node(&self, index: usize) -> &Arc<Node> {
+        &self.nodes[index]
+    }
+
+    /// Connects a client with a new key to node `index`. Clients talk to their
+    /// node directly, whatever the network conditions.
+    pub async fn client(&self, index: usize) -> Result<Client, ClientError> {
+        let (client, node_side) = rvb_transport::memory::pair();
+        self.nodes[index].connect_peer(Box::new(node_side)).await;
+        Client::handshake(Box::new(client), KeyPair::generate(), &[]).await
+    }
+
+    /// Deploys a contract on every node and returns its id.
+    pub async fn deploy(
+        &self,
+        bytecode: &[u8],
+        namespace: &str,
+        params: HashMap<String, DbValue>,
+    ) -> Result<Vec<u8>, NodeError> {
+        let mut id = Vec::new();
+        for node in &self.nodes {
+            id = node
+                .deploy_contract(
+                    bytecode.to_vec(),
+                    namespace.to_string(),
+                    params.clone(),
+                    Vec::new(),
+                    Vec::new(),
+                )
+                .await?;
+        }
+        Ok(id)
+    }
+
+    /// Makes node `from` ask node `to` to forward the events emitted in
+    /// `namespace`. The request travels over the link between them.
+    pub async fn subscribe(
+        &self,
+        from: usize,
+        to: usize,
+        namespace: &str,
+    ) -> Result<(), NodeError> {
+        self.subscribe_filtered(from, to, namespace, None).await
+    }
+
+    /// Like [`Cluster::subscribe`], forwarding only the events whose payload
+    /// matches `filter`.
+    pub async fn subscribe_where(
+        &self,
+        from: usize,
+        to: usize,
+        namespace: &str,
+        filter: Filter,
+    ) -> Result<(), NodeError> {
+        self.subscribe_filtered(from, to, namespace, Some(filter))
+            .await
+    }
+
+    async fn subscribe_filtered(
+        &self,
+        from: usize,
+        to: usize,
+        namespace: &str,
+        filter: Option<Filter>,
+    ) -> Result<(), NodeError> {
+        let index = self.links[from]
+            .iter()
+            .position(|&remote| remote == to)
+            .expect("Nodes are connected to every other node");
+        let node = &self.nodes[from];
+        let peer = node.peers.read().await[index].clone();
+        node.subscribe_events(&peer, namespace.to_string(), None, filter)
+            .await
+    }
+
+    #[must_use]
+    pub fn link(&self, from: usize, to: usize) -> LinkConditions {
+        self.network.link(from, to)
+    }
+
+    /// Sets the conditions of the link from node `from` to node `to`.
+    pub fn set_link(&self, from: usize, to: usize, conditions: LinkConditions) {
+        self.network.set_link(from, to, conditions);
+    }
+
+    /// Drops every message between a node of `a` and a node of `b`, both ways.
+    pub fn partition(&self, a: &[usize], b: &[usize]) {
+        for &x in a {
+            for &y in b {
+                for (from, to) in [(x, y), (y, x)] {
+                    let mut link = self.link(from, to);
+                    link.partitioned = true;
+                    self.set_link(from, to, link);
+                }
+            }
+        }
+    }
+
+    /// Cuts node `index` off from every other node.
+    pub fn isolate(&self, index: usize) {
+        let others: Vec<_> = (0..self.len()).filter(|&i| i != index).collect();
+        self.partition(&[index], &others);
+    }
+
+    /// Lifts every partition, keeping latencies and losses.
+    pub fn heal(&self) {
+        self.network.update(|link| link.partitioned = false);
+    }
+
+    /// Delays every message between nodes by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.network.update(|link| link.latency = latency);
+    }
+
+    /// Drops messages between nodes with probability `loss`.
+    pub fn set_loss(&self, loss: f64) {
+        self.network.update(|link| link.loss = loss);
+    }
+
+    /// Whether every node stores the same values, at the same states, in
+    /// `namespace`.
+    pub fn converged(&self, namespace: &str) -> Result<bool, NodeError> {
+        let expected = self.nodes[0].entries(namespace)?;
+        for node in &self.nodes[1..] {
+            if node.entries(namespace)? != expected {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Waits until every node stores the same values in `namespace`. Nodes
+    /// that have applied nothing yet agree too, so wait for the writes to land
+    /// on some node first.
+    ///
+    /// # Panics
+    ///
+    /// If the nodes still disagree after the cluster's timeout, listing what
+    /// each of them stores.
+    pub async fn eventually_converged(&self, namespace: &str) {
+        let deadline = Instant::now() + self.timeout;
+        while !self
+            .converged(namespace)
+            .expect("Failed to read node storage")
+        {
+            if Instant::now() >= deadline {
+                let entries: Vec<Vec<Entry>> = self
+                    .nodes
+                    .iter()
+                    .map(|node| node.entries(namespace).unwrap())
+                    .collect();
+                panic!("Nodes did not converge in namespace {namespace}: {entries:#?}");
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until `condition` holds.
+    ///
+    /// # Panics
+    ///
+    /// If it still does not after the cluster's timeout, naming `what` was
+    /// waited for.
+    pub async fn eventually(&self, what: &str, mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + self.timeout;
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for {what}");
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
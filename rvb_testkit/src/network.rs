@@ -0,0 +1,95 @@
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rvb_common::transport::{TransportError, TransportPeer};
+use rvb_transport::memory::MemoryPeer;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Conditions on the link carrying messages from one node to another. Links
+/// are directional: the way back has conditions of its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkConditions {
+    /// Drop every message.
+    pub partitioned: bool,
+    /// Delay before a message is delivered.
+    pub latency: Duration,
+    /// Probability, between 0 and 1, of dropping a message.
+    pub loss: f64,
+}
+
+/// Conditions of every link between the nodes of a cluster. Losses are drawn
+/// from a generator seeded by the cluster, so a test that sends the same
+/// messages in the same order loses the same ones on every run.
+pub(crate) struct Network {
+    links: Mutex<HashMap<(usize, usize), LinkConditions>>,
+    rng: Mutex<StdRng>,
+}
+
+impl Network {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            links: Mutex::new(HashMap::new()),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub(crate) fn link(&self, from: usize, to: usize) -> LinkConditions {
+        self.links
+            .lock()
+            .unwrap()
+            .get(&(from, to))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_link(&self, from: usize, to: usize, conditions: LinkConditions) {
+        self.links.lock().unwrap().insert((from, to), conditions);
+    }
+
+    pub(crate) fn update(&self, mut f: impl FnMut(&mut LinkConditions)) {
+        self.links.lock().unwrap().values_mut().for_each(&mut f);
+    }
+
+    /// Delay after which a message sent from `from` to `to` arrives, or `None`
+    /// if it is dropped.
+    pub(crate) fn route(&self, from: usize, to: usize) -> Option<Duration> {
+        let link = self.link(from, to);
+        if link.partitioned || (link.loss > 0.0 && self.rng.lock().unwrap().gen_bool(link.loss)) {
+            return None;
+        }
+        Some(link.latency)
+    }
+}
+
+/// End of an in-memory connection held by node `from`, sending to node `to`
+/// under the conditions the network sets for that link.
+pub(crate) struct LinkPeer {
+    pub(crate) inner: MemoryPeer,
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    pub(crate) network: Arc<Network>,
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for LinkPeer {
+    async fn bye(self) -> Result<(), TransportError> {
+        self.inner.bye().await
+    }
+
+    /// Dropped messages are reported as sent, like a packet lost on the way.
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        let Some(latency) = self.network.route(self.from, self.to) else {
+            return Ok(());
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        self.inner.send(msg).await
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.inner.recv().await
+    }
+}
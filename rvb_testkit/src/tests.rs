@@ -0,0 +1,1322 @@
+use super::*;
+use rvb_common::contract::{Contract, ContractContext, ContractError, ContractHost};
+use rvb_common::crypto::{GroupKey, PublicKey, ThresholdKey, ThresholdSignature};
+use rvb_common::protocol::{
+    Capability, ContractEvent, Locality, Location, Message, TransportMessage, admin_payload,
+    challenge_payload,
+};
+use rvb_common::schema::DataAction;
+use rvb_node::metrics::Limit;
+use rvb_node::{Fanout, Limits, StateProofs};
+use std::collections::HashSet;
+use tokio::sync::broadcast;
+
+/// Contract accepting every call and emitting an event for each, carrying
+/// the inserted value if there is one.
+struct EmitContract;
+
+impl Contract for EmitContract {
+    fn execute(
+        &mut self,
+        ctx: ContractContext,
+        _host: Arc<dyn ContractHost>,
+    ) -> Result<Vec<DataAction>, ContractError> {
+        let payload = match &ctx.action {
+            DataAction::Insert { incoming_data, .. } => incoming_data.clone(),
+            _ => DbValue::None,
+        };
+        Ok(vec![
+            ctx.action,
+            DataAction::Emit {
+                topic: "inserted".into(),
+                payload,
+            },
+        ])
+    }
+}
+
+struct EmitContractCompiler;
+
+impl ContractCompiler for EmitContractCompiler {
+    fn create_contract(&self, _bytecode: &[u8]) -> Result<Box<dyn Contract>, ContractError> {
+        Ok(Box::new(EmitContract))
+    }
+}
+
+fn location(contract: &[u8], key: &str) -> Location {
+    Location {
+        namespace: "ns".into(),
+        contract_space: "space".into(),
+        contract: contract.to_vec(),
+        key: key.into(),
+    }
+}
+
+async fn insert(client: &Client, contract: &[u8], key: &str, value: i128) {
+    client
+        .insert(
+            location(contract, key),
+            DbValue::Number(value),
+            HashMap::new(),
+            1,
+        )
+        .await
+        .unwrap();
+}
+
+/// Waits until `condition` holds, for tests on nodes outside a [`Cluster`].
+async fn wait_until(what: &str, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + DEFAULT_TIMEOUT;
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for {what}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Next batch the node on the other end of `peer` sends, past the `Hello` it
+/// opens every connection with.
+async fn next_batch(peer: &dyn TransportPeer) -> Vec<Message> {
+    loop {
+        let raw = tokio::time::timeout(DEFAULT_TIMEOUT, peer.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let msg: TransportMessage = rmp_serde::from_slice(&raw).unwrap();
+        let messages: Vec<Message> = msg.try_into().unwrap();
+        if !matches!(messages[..], [Message::Hello { .. }]) {
+            return messages;
+        }
+    }
+}
+
+/// Takes `peer` through the handshake with the node on its other end as
+/// `keypair`, after which the node takes its inserts and reads.
+async fn introduce(peer: &dyn TransportPeer, keypair: &mut KeyPair) {
+    let hello = Message::Hello {
+        public_key: keypair.export_public(),
+        session_key: Vec::new(),
+        certificates: Vec::new(),
+    };
+    let msg = hello.sign(keypair, "test".into());
+    peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+        .await
+        .unwrap();
+    loop {
+        match next_batch(peer).await.as_slice() {
+            [Message::WhoAreYou { data, .. }] => {
+                let answer = Message::ItsMe {
+                    signature: keypair.sign(&challenge_payload(data)),
+                    data: data.clone(),
+                };
+                let msg = answer.sign(keypair, "test".into());
+                peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+                    .await
+                    .unwrap();
+            }
+            [Message::Welcome { .. }] => return,
+            _ => {}
+        }
+    }
+}
+
+/// Challenges the node on the other end of `peer` to prove its key, as the
+/// peer `keypair`, and welcomes it.
+async fn welcome(peer: &dyn TransportPeer, keypair: &mut KeyPair) {
+    let data = b"challenge".to_vec();
+    let challenge = Message::WhoAreYou {
+        data: data.clone(),
+        public_key: keypair.export_public(),
+        session_key: Vec::new(),
+        certificates: Vec::new(),
+    };
+    let msg = challenge.sign(keypair, "test".into());
+    peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+        .await
+        .unwrap();
+    assert!(matches!(
+        next_batch(peer).await.as_slice(),
+        [Message::ItsMe { data: signed, .. }] if signed == &data
+    ));
+    let welcome = Message::Welcome {
+        dht_ip: String::new(),
+        dht_port: 0,
+        signature: keypair.sign(&challenge_payload(&data)),
+    };
+    let msg = welcome.sign(keypair, "test".into());
+    peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+        .await
+        .unwrap();
+}
+
+async fn next_event(
+    events: &mut broadcast::Receiver<ContractEvent>,
+    timeout: Duration,
+) -> Option<ContractEvent> {
+    tokio::time::timeout(timeout, events.recv())
+        .await
+        .ok()
+        .map(Result::unwrap)
+}
+
+#[tokio::test]
+async fn test_converged_with_the_same_writes() {
+    let cluster = Cluster::new(3).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+
+    for index in 0..cluster.len() {
+        let client = cluster.client(index).await.unwrap();
+        insert(&client, &contract, "a", 1).await;
+        insert(&client, &contract, "b", 2).await;
+    }
+
+    let node = cluster.node(2).clone();
+    cluster
+        .eventually("the inserts to be applied", || {
+            node.entries("ns").unwrap().len() == 2
+        })
+        .await;
+    cluster.eventually_converged("ns").await;
+}
+
+#[tokio::test]
+async fn test_diverged_with_different_writes() {
+    let cluster = Cluster::new(2).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+
+    let client = cluster.client(0).await.unwrap();
+    insert(&client, &contract, "a", 1).await;
+
+    let node = cluster.node(0).clone();
+    cluster
+        .eventually("the insert to be applied", || {
+            !node.entries("ns").unwrap().is_empty()
+        })
+        .await;
+    assert!(!cluster.converged("ns").unwrap());
+}
+
+#[tokio::test]
+async fn test_deletions_converge_in_any_order() {
+    let cluster = Cluster::new(2).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+    let write = async |client: &Client, key, state: u64| {
+        client
+            .insert(
+                location(&contract, key),
+                DbValue::Number(state.into()),
+                HashMap::new(),
+                state,
+            )
+            .await
+            .unwrap();
+    };
+    let delete = async |client: &Client, key| {
+        client.delete(location(&contract, key), 2).await.unwrap();
+    };
+
+    // `a` is written before its deletion and `b` after, each node taking the
+    // two in a different order
+    let client = cluster.client(0).await.unwrap();
+    write(&client, "a", 1).await;
+    delete(&client, "a").await;
+    write(&client, "b", 3).await;
+    delete(&client, "b").await;
+    let client = cluster.client(1).await.unwrap();
+    delete(&client, "a").await;
+    write(&client, "a", 1).await;
+    delete(&client, "b").await;
+    write(&client, "b", 3).await;
+
+    for index in 0..cluster.len() {
+        let node = cluster.node(index).clone();
+        cluster
+            .eventually("the writes to be applied", || {
+                node.entries("ns").unwrap().len() == 1
+            })
+            .await;
+    }
+    cluster.eventually_converged("ns").await;
+    let entries = cluster.node(0).entries("ns").unwrap();
+    assert_eq!(
+        (entries[0].key.as_str(), &entries[0].value),
+        ("b", &DbValue::Number(3))
+    );
+}
+
+#[tokio::test]
+#[should_panic(expected = "Nodes did not converge")]
+async fn test_eventually_converged_times_out() {
+    let cluster = Cluster::new(2)
+        .await
+        .with_timeout(Duration::from_millis(100));
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+
+    let client = cluster.client(1).await.unwrap();
+    insert(&client, &contract, "a", 1).await;
+    let node = cluster.node(1).clone();
+    cluster
+        .eventually("the insert to be applied", || {
+            !node.entries("ns").unwrap().is_empty()
+        })
+        .await;
+    cluster.eventually_converged("ns").await;
+}
+
+#[tokio::test]
+async fn test_partition_blocks_events() {
+    let cluster = Cluster::with_compiler(2, 0, || Box::new(EmitContractCompiler)).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+    let client = cluster.client(0).await.unwrap();
+    let mut events = cluster.node(1).watch_events();
+
+    cluster.subscribe(1, 0, "ns").await.unwrap();
+    // The subscription and the insert reach node 0 through different peers.
+    let mut received = false;
+    for _ in 0..50 {
+        insert(&client, &contract, "a", 1).await;
+        if next_event(&mut events, Duration::from_millis(100))
+            .await
+            .is_some()
+        {
+            received = true;
+            break;
+        }
+    }
+    assert!(received);
+    while next_event(&mut events, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    cluster.partition(&[0], &[1]);
+    insert(&client, &contract, "a", 1).await;
+    assert!(
+        next_event(&mut events, Duration::from_millis(200))
+            .await
+            .is_none()
+    );
+
+    cluster.heal();
+    insert(&client, &contract, "a", 1).await;
+    assert!(
+        next_event(&mut events, Duration::from_secs(5))
+            .await
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn test_filtered_subscription() {
+    let cluster = Cluster::with_compiler(2, 0, || Box::new(EmitContractCompiler)).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+    let client = cluster.client(0).await.unwrap();
+    let mut events = cluster.node(1).watch_events();
+    let insert_votes = |key: &'static str, votes: i128| {
+        let value = DbValue::Object(HashMap::from([(
+            "votes".into(),
+            Box::new(DbValue::Number(votes)),
+        )]));
+        client.insert(location(&contract, key), value, HashMap::new(), 1)
+    };
+
+    cluster
+        .subscribe_where(1, 0, "ns", Filter::parse("votes > 10").unwrap())
+        .await
+        .unwrap();
+    // The subscription and the insert reach node 0 through different peers.
+    let mut received = false;
+    for _ in 0..50 {
+        insert_votes("probe", 100).await.unwrap();
+        if next_event(&mut events, Duration::from_millis(100))
+            .await
+            .is_some()
+        {
+            received = true;
+            break;
+        }
+    }
+    assert!(received);
+    while next_event(&mut events, Duration::from_millis(100))
+        .await
+        .is_some()
+    {}
+
+    insert_votes("a", 3).await.unwrap();
+    insert_votes("b", 30).await.unwrap();
+    let event = next_event(&mut events, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(
+        event.payload.get_path(&["votes".into()]),
+        Some(&DbValue::Number(30))
+    );
+    assert!(
+        next_event(&mut events, Duration::from_millis(200))
+            .await
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_latency_delays_events() {
+    let cluster = Cluster::with_compiler(2, 0, || Box::new(EmitContractCompiler)).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+    let client = cluster.client(0).await.unwrap();
+    let mut events = cluster.node(1).watch_events();
+
+    cluster.set_latency(Duration::from_millis(200));
+    cluster.subscribe(1, 0, "ns").await.unwrap();
+    // Wait out the subscription's own delay.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let sent = Instant::now();
+    insert(&client, &contract, "a", 1).await;
+    assert!(
+        next_event(&mut events, Duration::from_secs(5))
+            .await
+            .is_some()
+    );
+    assert!(sent.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_link_conditions() {
+    let cluster = Cluster::new(3).await;
+    cluster.set_latency(Duration::from_millis(5));
+    cluster.isolate(1);
+
+    assert!(cluster.link(0, 1).partitioned);
+    assert!(cluster.link(1, 2).partitioned);
+    assert!(!cluster.link(0, 2).partitioned);
+
+    cluster.heal();
+    assert_eq!(
+        cluster.link(1, 0),
+        LinkConditions {
+            partitioned: false,
+            latency: Duration::from_millis(5),
+            loss: 0.0,
+        }
+    );
+}
+
+#[test]
+fn test_loss_is_seeded() {
+    let routes = |seed| {
+        let network = Network::new(seed);
+        network.set_link(
+            0,
+            1,
+            LinkConditions {
+                loss: 0.5,
+                ..LinkConditions::default()
+            },
+        );
+        (0..64)
+            .map(|_| network.route(0, 1).is_some())
+            .collect::<Vec<_>>()
+    };
+
+    let delivered = routes(7);
+    assert_eq!(delivered, routes(7));
+    assert!(delivered.contains(&true));
+    assert!(delivered.contains(&false));
+}
+
+#[tokio::test]
+async fn test_private_namespace() {
+    let cluster = Cluster::new(1).await;
+    let owner = cluster.client(0).await.unwrap();
+    let member = cluster.client(0).await.unwrap();
+    let outsider = cluster.client(0).await.unwrap();
+    let contract = cluster
+        .node(0)
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            owner.identity().to_vec(),
+        )
+        .await
+        .unwrap();
+
+    // Only deployers of the namespace's contracts may make it private
+    let stolen = GroupKey::generate(1);
+    outsider
+        .share_group_key(
+            "ns".into(),
+            &stolen,
+            &[PublicKey::import(outsider.identity()).unwrap()],
+        )
+        .await
+        .unwrap();
+    assert!(outsider.group_key("ns".into()).await.unwrap().is_none());
+
+    let key = GroupKey::generate(1);
+    let members =
+        [owner.identity(), member.identity()].map(|identity| PublicKey::import(identity).unwrap());
+    owner
+        .share_group_key("ns".into(), &key, &members)
+        .await
+        .unwrap();
+    // Answered once the group is stored, as both go through the same node
+    assert!(owner.group_key("ns".into()).await.unwrap().is_some());
+    assert!(outsider.group_key("ns".into()).await.unwrap().is_none());
+
+    let member_key = member.group_key("ns".into()).await.unwrap().unwrap();
+    let secret = DbValue::String("secret".into());
+    // Refused, even though its newer state would otherwise win
+    member
+        .insert(location(&contract, "a"), secret.clone(), HashMap::new(), 2)
+        .await
+        .unwrap();
+    member
+        .insert(
+            location(&contract, "a"),
+            member_key.seal_value(&secret).unwrap(),
+            HashMap::new(),
+            1,
+        )
+        .await
+        .unwrap();
+
+    let node = cluster.node(0).clone();
+    cluster
+        .eventually("the sealed insert to be applied", || {
+            !node.entries("ns").unwrap().is_empty()
+        })
+        .await;
+    let stored = node.entries("ns").unwrap().remove(0);
+    assert_eq!(stored.state, 1);
+    assert_ne!(stored.value, secret);
+    assert_eq!(key.open_value(&stored.value).unwrap(), secret);
+}
+
+#[tokio::test]
+async fn test_namespace_writers() {
+    let cluster = Cluster::new(1).await;
+    let node = cluster.node(0).clone();
+    let deployer = cluster.client(0).await.unwrap();
+    let writer = cluster.client(0).await.unwrap();
+    let outsider = cluster.client(0).await.unwrap();
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            deployer.identity().to_vec(),
+        )
+        .await
+        .unwrap();
+
+    // Others cannot make themselves deployers of the namespace to lock its
+    // writers out
+    assert!(matches!(
+        node.deploy_contract(
+            b"takeover".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            outsider.identity().to_vec(),
+        )
+        .await,
+        Err(NodeError::NotDeployer)
+    ));
+    outsider
+        .deploy_contract(
+            b"takeover".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    // Only deployers of the namespace's contracts may limit its writers
+    outsider
+        .set_writers("ns".into(), Some(vec![outsider.identity().to_vec()]))
+        .await
+        .unwrap();
+    deployer
+        .set_writers("ns".into(), Some(vec![writer.identity().to_vec()]))
+        .await
+        .unwrap();
+    let expected = Some(vec![writer.identity().to_vec()]);
+    cluster
+        .eventually("the writers to be set", || {
+            node.writers("ns").unwrap() == expected
+        })
+        .await;
+
+    insert(&outsider, &contract, "outsider", 1).await;
+    insert(&writer, &contract, "writer", 2).await;
+    insert(&deployer, &contract, "deployer", 3).await;
+    cluster
+        .eventually("the allowed inserts to be applied", || {
+            node.entries("ns").unwrap().len() == 2
+        })
+        .await;
+    let mut keys: Vec<_> = node
+        .entries("ns")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.key)
+        .collect();
+    keys.sort();
+    assert_eq!(keys, vec!["deployer", "writer"]);
+    assert_eq!(node.search_contracts("ns", &[]).unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_namespace_admins() {
+    let cluster = Cluster::new(1).await;
+    let node = cluster.node(0).clone();
+    let deployer = cluster.client(0).await.unwrap();
+    node.deploy_contract(
+        b"contract".to_vec(),
+        "ns".into(),
+        HashMap::new(),
+        Vec::new(),
+        deployer.identity().to_vec(),
+    )
+    .await
+    .unwrap();
+    let members: Vec<_> = (0..3).map(|_| KeyPair::generate()).collect();
+    let admins =
+        ThresholdKey::new(2, members.iter().map(KeyPair::export_public).collect()).unwrap();
+    let writers = |key: &[u8]| Some(vec![key.to_vec()]);
+    let agreed = |writers: &Option<Vec<Vec<u8>>>, sequence, signers: &[usize]| {
+        let payload = admin_payload("ns", writers.as_deref(), sequence);
+        let mut signature = ThresholdSignature::default();
+        for &i in signers {
+            signature.add(i, members[i].sign(&payload));
+        }
+        signature
+    };
+
+    deployer.set_admins("ns".into(), &admins).await.unwrap();
+    // Single deployers no longer set the writers once admins are
+    deployer
+        .set_writers("ns".into(), writers(b"deployer"))
+        .await
+        .unwrap();
+    // A change one signature short of the threshold is refused, leaving its
+    // sequence number to the agreed change that follows
+    deployer
+        .admin_set_writers(
+            "ns".into(),
+            writers(b"one"),
+            1,
+            agreed(&writers(b"one"), 1, &[0]),
+        )
+        .await
+        .unwrap();
+    deployer
+        .admin_set_writers(
+            "ns".into(),
+            writers(b"two"),
+            1,
+            agreed(&writers(b"two"), 1, &[0, 2]),
+        )
+        .await
+        .unwrap();
+    cluster
+        .eventually("the agreed writers to be set", || {
+            node.writers("ns").unwrap() == writers(b"two")
+        })
+        .await;
+
+    let signature = agreed(&writers(b"three"), 2, &[1]);
+    assert!(matches!(
+        node.admin_set_writers("ns", writers(b"three"), 2, signature),
+        Err(NodeError::NotAuthorized)
+    ));
+    // Agreed changes cannot be replayed, nor signatures reused for others
+    let signature = agreed(&writers(b"two"), 1, &[0, 2]);
+    assert!(matches!(
+        node.admin_set_writers("ns", writers(b"two"), 1, signature.clone()),
+        Err(NodeError::NotAuthorized)
+    ));
+    assert!(matches!(
+        node.admin_set_writers("ns", writers(b"three"), 2, signature),
+        Err(NodeError::NotAuthorized)
+    ));
+    node.admin_set_writers("ns", None, 2, agreed(&None, 2, &[1, 2]))
+        .unwrap();
+    assert_eq!(node.writers("ns").unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_observer_takes_only_passed_on_writes() {
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig {
+            observer: true,
+            ..NodeConfig::default()
+        },
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let (peer, node_side) = rvb_transport::memory::pair();
+    node.connect_peer(Box::new(node_side)).await;
+    let task = {
+        let node = node.clone();
+        tokio::spawn(async move { node.process().await })
+    };
+
+    let mut client = KeyPair::generate();
+    let mut mirror = KeyPair::generate();
+    // The observer says what it is once welcomed
+    welcome(&peer, &mut mirror).await;
+    let messages = next_batch(&peer).await;
+    assert!(matches!(
+        messages.as_slice(),
+        [Message::Capabilities { capabilities }] if capabilities == &[Capability::Observer]
+    ));
+
+    let insert = |value| Message::Insert {
+        location: location(&contract, "key"),
+        incoming_data: DbValue::Number(value),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    // Writes are refused before the peer said who it is, and after that
+    // those of the peer itself; the client's write passed on by the peer is
+    // stored
+    let early = insert(3).sign(&mut client, "test".into());
+    peer.send(rmp_serde::to_vec(&early).unwrap().into())
+        .await
+        .unwrap();
+    introduce(&peer, &mut mirror).await;
+    let sent = [
+        insert(3).sign(&mut mirror, "test".into()),
+        insert(2).sign(&mut client, "test".into()),
+    ];
+    for msg in sent {
+        peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+            .await
+            .unwrap();
+    }
+
+    wait_until("the write", || !node.entries("ns").unwrap().is_empty()).await;
+    assert_eq!(
+        node.entries("ns").unwrap().remove(0).value,
+        DbValue::Number(2)
+    );
+
+    // Nor does the observer send writes of its own
+    assert!(matches!(
+        node.send_to(&[1, 2, 3], &[insert(1)]).await,
+        Err(NodeError::ReadOnly)
+    ));
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_light_node_holds_only_its_namespaces() {
+    let nodes: Vec<_> = [None, Some(HashSet::from(["ns".to_string()]))]
+        .into_iter()
+        .map(|namespaces| {
+            Arc::new(Node::new(
+                KeyPair::generate(),
+                NodeConfig {
+                    namespaces,
+                    ..NodeConfig::default()
+                },
+                sled::Config::new().temporary(true).open().unwrap(),
+                Box::new(AcceptContractCompiler),
+                Box::new(NoServer),
+            ))
+        })
+        .collect();
+    let mut contract = Vec::new();
+    for node in &nodes {
+        contract = node
+            .deploy_contract(
+                b"contract".to_vec(),
+                "ns".into(),
+                HashMap::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+    }
+    let tasks: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            let node = node.clone();
+            tokio::spawn(async move { node.process().await })
+        })
+        .collect();
+    let (full_side, light_side) = rvb_transport::memory::pair();
+    nodes[0].connect_peer(Box::new(full_side)).await;
+    nodes[1].connect_peer(Box::new(light_side)).await;
+    let (client, node_side) = rvb_transport::memory::pair();
+    nodes[0].connect_peer(Box::new(node_side)).await;
+
+    let mut keypair = KeyPair::generate();
+    introduce(&client, &mut keypair).await;
+    let insert = |namespace: &str, key: &str, value| Message::Insert {
+        location: Location {
+            namespace: namespace.into(),
+            ..location(&contract, key)
+        },
+        incoming_data: DbValue::Number(value),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    // Inserts sent before the light node's request arrived are not passed
+    // on, so keep writing until one is
+    let mut value = 0;
+    while nodes[1].entries("ns").unwrap().is_empty() {
+        value += 1;
+        let msg = TransportMessage::sign(&[insert("ns", "a", value)], &mut keypair, "test".into());
+        client
+            .send(rmp_serde::to_vec(&msg).unwrap().into())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(value < 100, "The light node got no inserts");
+    }
+
+    let batch = TransportMessage::sign(
+        &[insert("ns", "b", 1), insert("other", "b", 1)],
+        &mut keypair,
+        "test".into(),
+    );
+    client
+        .send(rmp_serde::to_vec(&batch).unwrap().into())
+        .await
+        .unwrap();
+    wait_until("the batch to be passed on", || {
+        nodes[1].entries("ns").unwrap().len() == 2
+    })
+    .await;
+    wait_until("the full node to store both", || {
+        !nodes[0].entries("other").unwrap().is_empty()
+    })
+    .await;
+    assert!(nodes[1].entries("other").unwrap().is_empty());
+    assert!(!nodes[1].holds("other"));
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_regional_fanout() {
+    // A full node in eu keeping two copies per region, and light nodes in
+    // eu, eu and us
+    let regions = [
+        ("eu", None),
+        ("eu", Some("ns")),
+        ("eu", Some("ns")),
+        ("us", Some("ns")),
+    ];
+    let nodes: Vec<_> = regions
+        .into_iter()
+        .map(|(region, namespace)| {
+            Arc::new(Node::new(
+                KeyPair::generate(),
+                NodeConfig {
+                    namespaces: namespace.map(|ns: &str| HashSet::from([ns.to_string()])),
+                    fanout: Fanout::Regional { copies: 2 },
+                    locality: Some(Locality {
+                        region: region.into(),
+                        zone: None,
+                    }),
+                    ..NodeConfig::default()
+                },
+                sled::Config::new().temporary(true).open().unwrap(),
+                Box::new(AcceptContractCompiler),
+                Box::new(NoServer),
+            ))
+        })
+        .collect();
+    let mut contract = Vec::new();
+    for node in &nodes {
+        contract = node
+            .deploy_contract(
+                b"contract".to_vec(),
+                "ns".into(),
+                HashMap::new(),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+    }
+    let tasks: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            let node = node.clone();
+            tokio::spawn(async move { node.process().await })
+        })
+        .collect();
+    for light in &nodes[1..] {
+        let (full_side, light_side) = rvb_transport::memory::pair();
+        nodes[0].connect_peer(Box::new(full_side)).await;
+        light.connect_peer(Box::new(light_side)).await;
+    }
+    wait_until("the light nodes to gossip their regions", || {
+        nodes[0].localities().len() == 3
+    })
+    .await;
+
+    let (client, node_side) = rvb_transport::memory::pair();
+    nodes[0].connect_peer(Box::new(node_side)).await;
+    let mut keypair = KeyPair::generate();
+    introduce(&client, &mut keypair).await;
+    let insert = Message::Insert {
+        location: location(&contract, "a"),
+        incoming_data: DbValue::Number(1),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    let msg = TransportMessage::sign(&[insert], &mut keypair, "test".into());
+    client
+        .send(rmp_serde::to_vec(&msg).unwrap().into())
+        .await
+        .unwrap();
+
+    // The full node holds a copy in eu, so it passes on one more there, and
+    // both copies for us go to the only node there
+    let copies = || {
+        nodes[1..]
+            .iter()
+            .map(|node| node.entries("ns").unwrap().len())
+            .collect::<Vec<_>>()
+    };
+    wait_until("the insert to be passed on", || {
+        let copies = copies();
+        copies[0] + copies[1] == 1 && copies[2] == 1
+    })
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(copies().iter().sum::<usize>(), 2);
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+/// The node's end of a connection, keeping every frame sent or received.
+struct Tap {
+    peer: rvb_transport::memory::MemoryPeer,
+    frames: Arc<std::sync::Mutex<Vec<bytes::Bytes>>>,
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for Tap {
+    async fn bye(self) -> Result<(), TransportError> {
+        self.peer.bye().await
+    }
+
+    async fn send(&self, msg: bytes::Bytes) -> Result<(), TransportError> {
+        self.frames.lock().unwrap().push(msg.clone());
+        self.peer.send(msg).await
+    }
+
+    async fn recv(&self) -> Result<bytes::Bytes, TransportError> {
+        let msg = self.peer.recv().await?;
+        self.frames.lock().unwrap().push(msg.clone());
+        Ok(msg)
+    }
+}
+
+#[tokio::test]
+async fn test_traffic_is_encrypted_after_welcome() {
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig::default(),
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let task = {
+        let node = node.clone();
+        tokio::spawn(async move { node.process().await })
+    };
+    let (client_side, node_side) = rvb_transport::memory::pair();
+    let frames = Arc::new(std::sync::Mutex::new(Vec::new()));
+    node.connect_peer(Box::new(Tap {
+        peer: node_side,
+        frames: frames.clone(),
+    }))
+    .await;
+    let client = Client::handshake(Box::new(client_side), KeyPair::generate(), &[])
+        .await
+        .unwrap();
+    let handshake = frames.lock().unwrap().len();
+
+    let secret = "a secret nobody on the way may read";
+    client
+        .insert(
+            location(&contract, "key"),
+            DbValue::String(secret.into()),
+            HashMap::new(),
+            1,
+        )
+        .await
+        .unwrap();
+    wait_until("the insert", || node.entries("ns").unwrap().len() == 1).await;
+    assert_eq!(
+        client
+            .get(location(&contract, "key"), Vec::new())
+            .await
+            .unwrap(),
+        Some(DbValue::String(secret.into()))
+    );
+
+    let frames = frames.lock().unwrap();
+    // The handshake itself is readable, everything after it is not
+    assert!(
+        frames[..handshake]
+            .iter()
+            .all(|frame| rmp_serde::from_slice::<TransportMessage>(frame).is_ok())
+    );
+    assert!(frames.len() >= handshake + 2);
+    for frame in &frames[handshake..] {
+        assert!(rmp_serde::from_slice::<TransportMessage>(frame).is_err());
+        assert!(!frame.windows(secret.len()).any(|w| w == secret.as_bytes()));
+    }
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_proven_reads() {
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig {
+            state_proofs: StateProofs::Anchored,
+            ..NodeConfig::default()
+        },
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let task = {
+        let node = node.clone();
+        tokio::spawn(async move { node.process().await })
+    };
+    let (client_side, node_side) = rvb_transport::memory::pair();
+    node.connect_peer(Box::new(node_side)).await;
+    let client = Client::handshake(Box::new(client_side), KeyPair::generate(), &[])
+        .await
+        .unwrap();
+
+    for (value, key) in ["a", "b", "c"].into_iter().enumerate() {
+        insert(&client, &contract, key, value as i128).await;
+    }
+    wait_until("the inserts", || node.entries("ns").unwrap().len() == 3).await;
+
+    let (value, proof) = client.get_proven(location(&contract, "b")).await.unwrap();
+    assert_eq!(value, Some(DbValue::Number(1)));
+    assert_eq!(proof.signed_by, node.identity());
+    assert_eq!(proof.state, 1);
+    assert_eq!(proof.anchor.unwrap().path.len(), 2);
+
+    // Keys that are not stored are proven absent, without an anchor
+    let (value, proof) = client.get_proven(location(&contract, "d")).await.unwrap();
+    assert_eq!((value, proof.anchor), (None, None));
+
+    // Nodes not proving their reads send no proof
+    let cluster = Cluster::new(1).await;
+    let contract = cluster
+        .deploy(b"contract", "ns", HashMap::new())
+        .await
+        .unwrap();
+    let client = cluster.client(0).await.unwrap();
+    assert!(matches!(
+        client.get_proven(location(&contract, "a")).await,
+        Err(ClientError::InvalidProof(None))
+    ));
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_latency_fanout() {
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig {
+            fanout: Fanout::Latency {
+                peers: 2,
+                ping_interval: Duration::from_millis(50),
+            },
+            ..NodeConfig::default()
+        },
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(NoServer),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let task = tokio::spawn({
+        let node = node.clone();
+        async move { node.process().await }
+    });
+
+    // Peers asking for the namespace, answering pings after a delay of their
+    // own and counting the inserts passed on to them
+    let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let mut peers = Vec::new();
+    for delay in 0..4 {
+        let (peer, node_side) = rvb_transport::memory::pair();
+        node.connect_peer(Box::new(node_side)).await;
+        let mut keypair = KeyPair::generate();
+        let replicate = Message::Replicate {
+            namespaces: vec!["ns".into()],
+        };
+        let msg = replicate.sign(&mut keypair, "test".into());
+        peer.send(rmp_serde::to_vec(&msg).unwrap().into())
+            .await
+            .unwrap();
+
+        let received = received.clone();
+        peers.push(tokio::spawn(async move {
+            while let Ok(raw) = peer.recv().await {
+                let msg: TransportMessage = rmp_serde::from_slice(&raw).unwrap();
+                let messages: Vec<Message> = msg.try_into().unwrap();
+                for message in messages {
+                    match message {
+                        Message::Ping { nonce } => {
+                            tokio::time::sleep(Duration::from_millis(delay * 5)).await;
+                            let pong = Message::Pong { nonce }.sign(&mut keypair, "test".into());
+                            peer.send(rmp_serde::to_vec(&pong).unwrap().into())
+                                .await
+                                .unwrap();
+                        }
+                        Message::Insert { .. } => {
+                            received.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }));
+    }
+
+    let deadline = Instant::now() + DEFAULT_TIMEOUT;
+    loop {
+        let measured = node
+            .peers
+            .read()
+            .await
+            .iter()
+            .filter(|peer| peer.rtt().is_some())
+            .count();
+        if measured == 4 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "Timed out waiting for pings");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (client, node_side) = rvb_transport::memory::pair();
+    node.connect_peer(Box::new(node_side)).await;
+    let mut keypair = KeyPair::generate();
+    introduce(&client, &mut keypair).await;
+    let insert = Message::Insert {
+        location: location(&contract, "key"),
+        incoming_data: DbValue::Number(1),
+        metadata: HashMap::new(),
+        state: 1,
+    };
+    let msg = insert.sign(&mut keypair, "test".into());
+    client
+        .send(rmp_serde::to_vec(&msg).unwrap().into())
+        .await
+        .unwrap();
+    wait_until("the insert", || !node.entries("ns").unwrap().is_empty()).await;
+    // Only two of the four peers asking get it
+    wait_until("the insert to be passed on", || {
+        received.load(std::sync::atomic::Ordering::SeqCst) >= 2
+    })
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+    task.abort();
+    for peer in peers {
+        peer.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_intake_survives_malformed_input() {
+    let mut harness = IntakeHarness::new().await;
+    if let Err((input, failure)) = harness.run(7, 100).await {
+        panic!("input {input:?} broke the node: {failure:?}");
+    }
+
+    // Oversized input is refused before it is decoded
+    harness
+        .feed(&vec![0xdd; Limits::default().max_message_size + 1])
+        .await
+        .unwrap();
+    assert_eq!(harness.node().rejections(Limit::MessageSize), 1);
+}
+
+#[tokio::test]
+async fn test_addresses_learned_from_inbound_peers() {
+    use rvb_common::transport::Client as _;
+    use rvb_node::addresses::DiscoveryConfig;
+    use rvb_transport::tcp::{TcpClient, TcpServer};
+
+    async fn start() -> (Arc<Node>, u16) {
+        let server = TcpServer::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        let node = Arc::new(Node::new(
+            KeyPair::generate(),
+            NodeConfig {
+                discovery: Some(DiscoveryConfig {
+                    advertise: Some(format!("0.0.0.0:{port}")),
+                    client: Arc::new(TcpClient),
+                    probe_timeout: DEFAULT_TIMEOUT,
+                }),
+                ..NodeConfig::default()
+            },
+            sled::Config::new().temporary(true).open().unwrap(),
+            Box::new(AcceptContractCompiler),
+            Box::new(server),
+        ));
+        let task = node.clone();
+        tokio::spawn(async move { tokio::join!(task.process(), task.receive_peers()) });
+        (node, port)
+    }
+    async fn dial(from: &Node, port: u16) {
+        let peer = TcpClient
+            .connect(&format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        from.connect_peer(peer).await;
+    }
+
+    let (a, a_port) = start().await;
+    let (b, b_port) = start().await;
+    let (c, _) = start().await;
+
+    // b fills in the host a left out with the one a connected from
+    dial(&a, b_port).await;
+    let a_addr = vec![format!("127.0.0.1:{a_port}")];
+    wait_until("b to confirm a", || {
+        b.addresses().get(a.identity()) == Some(&a_addr)
+    })
+    .await;
+
+    // c hears of a from b, but cannot fill in the host of b's own address
+    // over a connection it opened itself
+    dial(&c, b_port).await;
+    wait_until("c to confirm a", || {
+        c.addresses().get(a.identity()) == Some(&a_addr)
+    })
+    .await;
+    assert!(!c.addresses().contains_key(b.identity()));
+    assert!(!a.addresses().contains_key(a.identity()));
+}
+
+#[tokio::test]
+async fn test_node_over_quic() {
+    use rvb_common::transport::Client as _;
+    use rvb_transport::quic::{QuicClient, QuicServer};
+
+    let server = QuicServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("127.0.0.1:{}", server.local_addr().unwrap().port());
+    let node = Arc::new(Node::new(
+        KeyPair::generate(),
+        NodeConfig::default(),
+        sled::Config::new().temporary(true).open().unwrap(),
+        Box::new(AcceptContractCompiler),
+        Box::new(server),
+    ));
+    let contract = node
+        .deploy_contract(
+            b"contract".to_vec(),
+            "ns".into(),
+            HashMap::new(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let task = node.clone();
+    let task = tokio::spawn(async move { tokio::join!(task.process(), task.receive_peers()) });
+
+    let peer = QuicClient.connect(&addr).await.unwrap();
+    let client = Client::handshake(peer, KeyPair::generate(), &[])
+        .await
+        .unwrap();
+    insert(&client, &contract, "a", 1).await;
+    assert_eq!(
+        client
+            .get(location(&contract, "a"), Vec::new())
+            .await
+            .unwrap(),
+        Some(DbValue::Number(1))
+    );
+
+    task.abort();
+}
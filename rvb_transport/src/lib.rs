@@ -1,2 +1,10 @@
+#[cfg(feature = "memory")]
+pub mod memory;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(all(test, feature = "quic"))]
+mod quic_tests;
 #[cfg(feature = "tcp")]
 pub mod tcp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
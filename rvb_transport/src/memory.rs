@@ -0,0 +1,53 @@
+use bytes::Bytes;
+use rvb_common::transport::{TransportError, TransportPeer};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// One end of an in-process connection made by [`pair`].
+pub struct MemoryPeer {
+    tx: Sender<Bytes>,
+    rx: Mutex<Receiver<Bytes>>,
+}
+
+/// Two connected peers: what one sends, the other receives. Useful for
+/// attaching services to a node in the same process, and for tests.
+#[must_use]
+pub fn pair() -> (MemoryPeer, MemoryPeer) {
+    let (a_tx, a_rx) = channel(CHANNEL_CAPACITY);
+    let (b_tx, b_rx) = channel(CHANNEL_CAPACITY);
+    (
+        MemoryPeer {
+            tx: a_tx,
+            rx: Mutex::new(b_rx),
+        },
+        MemoryPeer {
+            tx: b_tx,
+            rx: Mutex::new(a_rx),
+        },
+    )
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for MemoryPeer {
+    async fn bye(self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        self.tx
+            .send(msg)
+            .await
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+}
@@ -0,0 +1,413 @@
+//! Peers over QUIC. Each connection carries any number of channels, each a
+//! QUIC stream with messages framed by their length as over TCP. A channel
+//! keeps its messages in order, while a message held up on one, such as a
+//! large upload or one waiting on a lost packet, does not hold up the others.
+//!
+//! The [`TransportPeer`] methods of [`QuicPeer`] use the channel its
+//! connection opens with; [`QuicPeer::open_channel`] and
+//! [`QuicPeer::accept_channel`] add more.
+//!
+//! Servers present a self-signed certificate and clients accept any, so TLS
+//! here authenticates no one: a QUIC connection only keeps what is sent over
+//! it from being read or changed on the way. Who is on the other end is
+//! established by the reverb handshake alone, in which nodes and clients
+//! prove their keys, and nothing should be trusted of a [`QuicPeer`] before
+//! it. In particular, its [`PeerMetadata::remote_addr`] is only where the
+//! connection came from.
+
+use bytes::{Bytes, BytesMut};
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use log::debug;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{
+    ClientConfig, Connection, ConnectionError, Endpoint, RecvStream, SendStream, ServerConfig,
+    TransportConfig,
+};
+use rustls::DigitallySignedStruct;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rvb_common::transport::{Client, PeerMetadata, Server, TransportError, TransportPeer};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+/// Name servers present their certificate for, and clients ask for.
+const SERVER_NAME: &str = "reverb";
+/// How often an idle connection is pinged, so it outlives the idle timeout.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+/// How long an incoming connection has to finish its handshake and open its
+/// first channel.
+pub const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections set up and waiting to be accepted.
+const PENDING_PEERS: usize = 64;
+
+fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> TransportError {
+    TransportError::IO(std::io::Error::other(e))
+}
+
+fn connection_error(e: ConnectionError) -> TransportError {
+    match e {
+        ConnectionError::ApplicationClosed(_)
+        | ConnectionError::ConnectionClosed(_)
+        | ConnectionError::LocallyClosed
+        | ConnectionError::Reset
+        | ConnectionError::TimedOut => TransportError::ConnectionClosed,
+        e => io_error(e),
+    }
+}
+
+/// Errors of a channel's streams, which quinn gives as [`std::io::Error`]s of
+/// kind [`NotConnected`](std::io::ErrorKind::NotConnected) once the
+/// connection is gone.
+fn stream_error(e: std::io::Error) -> TransportError {
+    if e.kind() == std::io::ErrorKind::NotConnected {
+        TransportError::ConnectionClosed
+    } else {
+        TransportError::IO(e)
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE));
+    Arc::new(config)
+}
+
+/// One ordered stream of messages within a QUIC connection.
+pub struct QuicChannel {
+    send: Mutex<FramedWrite<SendStream, LengthDelimitedCodec>>,
+    recv: Mutex<FramedRead<RecvStream, LengthDelimitedCodec>>,
+    metadata: PeerMetadata,
+}
+
+impl QuicChannel {
+    /// Opens a channel, sending the empty message that makes it known to the
+    /// other end.
+    async fn open(connection: &Connection, metadata: PeerMetadata) -> Result<Self, TransportError> {
+        let (send, recv) = connection.open_bi().await.map_err(connection_error)?;
+        let channel = Self::new(send, recv, metadata);
+        channel.send(Bytes::new()).await?;
+        Ok(channel)
+    }
+
+    /// Accepts a channel the other end opened, past its empty first message.
+    async fn accept(
+        connection: &Connection,
+        metadata: PeerMetadata,
+    ) -> Result<Self, TransportError> {
+        let (send, recv) = connection.accept_bi().await.map_err(connection_error)?;
+        let channel = Self::new(send, recv, metadata);
+        if !channel.recv().await?.is_empty() {
+            return Err(io_error(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "channel opened with a message",
+            )));
+        }
+        Ok(channel)
+    }
+
+    fn new(send: SendStream, recv: RecvStream, metadata: PeerMetadata) -> Self {
+        Self {
+            send: Mutex::new(FramedWrite::new(send, LengthDelimitedCodec::new())),
+            recv: Mutex::new(FramedRead::new(recv, LengthDelimitedCodec::new())),
+            metadata,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for QuicChannel {
+    /// Ends the channel, leaving the connection and its other channels open.
+    async fn bye(self) -> Result<(), TransportError> {
+        self.send
+            .into_inner()
+            .get_mut()
+            .finish()
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        self.send.lock().await.send(msg).await.map_err(stream_error)
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.recv
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(TransportError::ConnectionClosed)?
+            .map_err(stream_error)
+            .map(BytesMut::freeze)
+    }
+
+    fn metadata(&self) -> PeerMetadata {
+        self.metadata
+    }
+}
+
+/// A peer over a QUIC connection; see [`crate::quic`].
+pub struct QuicPeer {
+    connection: Connection,
+    channel: QuicChannel,
+    /// Kept by the end that connected, whose endpoint serves only this
+    /// connection.
+    _endpoint: Option<Endpoint>,
+}
+
+impl QuicPeer {
+    /// Another channel to the peer, which it takes with
+    /// [`QuicPeer::accept_channel`].
+    pub async fn open_channel(&self) -> Result<QuicChannel, TransportError> {
+        QuicChannel::open(&self.connection, self.channel.metadata).await
+    }
+
+    /// Waits for the peer to open another channel.
+    pub async fn accept_channel(&self) -> Result<QuicChannel, TransportError> {
+        QuicChannel::accept(&self.connection, self.channel.metadata).await
+    }
+}
+
+#[async_trait::async_trait]
+impl TransportPeer for QuicPeer {
+    async fn bye(self) -> Result<(), TransportError> {
+        self.connection.close(0u32.into(), b"bye");
+        Ok(())
+    }
+
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        self.channel.send(msg).await
+    }
+
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        self.channel.recv().await
+    }
+
+    fn metadata(&self) -> PeerMetadata {
+        self.channel.metadata
+    }
+}
+
+/// Accepts any certificate, though still checks that the server holds its
+/// key. This does not authenticate the server, as anyone can make up a
+/// certificate; see [`crate::quic`].
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Opens QUIC connections to `host:port` addresses, each from its own UDP
+/// socket.
+#[derive(Default)]
+pub struct QuicClient;
+
+impl QuicClient {
+    pub(crate) fn config() -> Result<ClientConfig, TransportError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(io_error)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+            .with_no_client_auth();
+        let crypto = QuicClientConfig::try_from(crypto).map_err(io_error)?;
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config());
+        Ok(config)
+    }
+
+    /// Connects to `addr`, as [`Client::connect`] does, keeping the peer's
+    /// channels within reach.
+    pub async fn open(&self, addr: &str) -> Result<QuicPeer, TransportError> {
+        let remote = tokio::net::lookup_host(addr)
+            .await
+            .map_err(TransportError::IO)?
+            .next()
+            .ok_or(TransportError::IO(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{addr} resolves to no address"),
+            )))?;
+        let local: SocketAddr = if remote.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+
+        let mut endpoint = Endpoint::client(local).map_err(TransportError::IO)?;
+        endpoint.set_default_client_config(Self::config()?);
+        let connection = endpoint
+            .connect(remote, SERVER_NAME)
+            .map_err(io_error)?
+            .await
+            .map_err(connection_error)?;
+        let metadata = PeerMetadata {
+            remote_addr: Some(connection.remote_address()),
+            inbound: false,
+        };
+        let channel = QuicChannel::open(&connection, metadata).await?;
+        Ok(QuicPeer {
+            connection,
+            channel,
+            _endpoint: Some(endpoint),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for QuicClient {
+    async fn connect(&self, addr: &str) -> Result<Box<dyn TransportPeer>, TransportError> {
+        Ok(Box::new(self.open(addr).await?))
+    }
+}
+
+/// Accepts QUIC connections on a bound UDP address, presenting a certificate
+/// made up when it was bound.
+///
+/// Connections are set up in tasks of their own, each given
+/// [`SETUP_TIMEOUT`] to finish its TLS handshake and open its first channel,
+/// so a connection failing or stalling there holds up no other. Those that
+/// fail are logged and dropped rather than returned from
+/// [`Server::accept`], which would end a node's accept loop.
+pub struct QuicServer {
+    endpoint: Endpoint,
+    peers: Mutex<mpsc::Receiver<QuicPeer>>,
+    accepting: JoinHandle<()>,
+}
+
+impl QuicServer {
+    pub async fn bind(addr: &str) -> Result<Self, TransportError> {
+        let addr = tokio::net::lookup_host(addr)
+            .await
+            .map_err(TransportError::IO)?
+            .next()
+            .ok_or(TransportError::IO(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{addr} resolves to no address"),
+            )))?;
+        let certified =
+            rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()]).map_err(io_error)?;
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let mut config = ServerConfig::with_single_cert(vec![certified.cert.into()], key.into())
+            .map_err(io_error)?;
+        config.transport_config(transport_config());
+
+        let endpoint = Endpoint::server(config, addr).map_err(TransportError::IO)?;
+        let (tx, rx) = mpsc::channel(PENDING_PEERS);
+        let accepting = tokio::spawn(accept_connections(endpoint.clone(), tx));
+        Ok(Self {
+            endpoint,
+            peers: Mutex::new(rx),
+            accepting,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        self.endpoint.local_addr().map_err(TransportError::IO)
+    }
+
+    /// Next connection set up, as [`Server::accept`] gives it, keeping the
+    /// peer's channels within reach. Fails once the endpoint is closed.
+    pub async fn accept_peer(&self) -> Result<QuicPeer, TransportError> {
+        self.peers
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::ConnectionClosed)
+    }
+}
+
+impl Drop for QuicServer {
+    fn drop(&mut self) {
+        self.accepting.abort();
+        self.endpoint.close(0u32.into(), b"bye");
+    }
+}
+
+/// Sets up connections coming in to `endpoint`, each in a task of its own,
+/// passing those that open their first channel in time on to `peers`.
+async fn accept_connections(endpoint: Endpoint, peers: mpsc::Sender<QuicPeer>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let peers = peers.clone();
+        tokio::spawn(async move {
+            let remote = incoming.remote_address();
+            let setup = async {
+                let connection = incoming.await.map_err(connection_error)?;
+                let metadata = PeerMetadata {
+                    remote_addr: Some(connection.remote_address()),
+                    inbound: true,
+                };
+                let channel = QuicChannel::accept(&connection, metadata).await?;
+                Ok::<_, TransportError>(QuicPeer {
+                    connection,
+                    channel,
+                    _endpoint: None,
+                })
+            };
+            match tokio::time::timeout(SETUP_TIMEOUT, setup).await {
+                Ok(Ok(peer)) => {
+                    if peers.send(peer).await.is_err() {
+                        debug!("Dropped a QUIC connection from {remote}, the server is gone");
+                    }
+                }
+                Ok(Err(e)) => debug!("Dropped a QUIC connection from {remote}: {e:?}"),
+                Err(_) => debug!("Dropped a QUIC connection from {remote} not set up in time"),
+            }
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Server for QuicServer {
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        Ok(Some(Box::new(self.accept_peer().await?)))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::quic::{QuicClient, QuicServer};
+use quinn::Endpoint;
+use rvb_common::transport::{Client, Server, TransportError, TransportPeer};
+use std::time::Duration;
+
+async fn server() -> (QuicServer, String) {
+    let server = QuicServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("127.0.0.1:{}", server.local_addr().unwrap().port());
+    (server, addr)
+}
+
+#[tokio::test]
+async fn test_messages_both_ways() {
+    let (server, addr) = server().await;
+    let dialer = QuicClient.open(&addr).await.unwrap();
+    let accepted = server.accept().await.unwrap().unwrap();
+    assert!(!dialer.metadata().inbound);
+    assert!(accepted.metadata().inbound);
+    assert_eq!(
+        accepted.metadata().remote_addr.map(|addr| addr.ip()),
+        Some([127, 0, 0, 1].into())
+    );
+
+    dialer.send(b"ping".to_vec().into()).await.unwrap();
+    assert_eq!(&accepted.recv().await.unwrap()[..], b"ping");
+    accepted.send(b"pong".to_vec().into()).await.unwrap();
+    assert_eq!(&dialer.recv().await.unwrap()[..], b"pong");
+
+    dialer.bye().await.unwrap();
+    assert!(matches!(
+        accepted.recv().await,
+        Err(TransportError::ConnectionClosed)
+    ));
+}
+
+#[tokio::test]
+async fn test_channels_do_not_block_each_other() {
+    let (server, addr) = server().await;
+    let dialer = QuicClient.open(&addr).await.unwrap();
+    let accepted = server.accept_peer().await.unwrap();
+
+    // A channel is read while others hold messages nobody reads yet
+    dialer.send(vec![0; 1 << 20].into()).await.unwrap();
+    let bulk = dialer.open_channel().await.unwrap();
+    bulk.send(vec![1; 1 << 20].into()).await.unwrap();
+    let urgent = dialer.open_channel().await.unwrap();
+    urgent.send(b"urgent".to_vec().into()).await.unwrap();
+
+    let _bulk = accepted.accept_channel().await.unwrap();
+    let urgent = accepted.accept_channel().await.unwrap();
+    assert_eq!(&urgent.recv().await.unwrap()[..], b"urgent");
+    assert_eq!(accepted.recv().await.unwrap().len(), 1 << 20);
+
+    // Ending a channel leaves the others open
+    urgent.bye().await.unwrap();
+    dialer.send(b"still open".to_vec().into()).await.unwrap();
+    assert_eq!(&accepted.recv().await.unwrap()[..], b"still open");
+}
+
+#[tokio::test]
+async fn test_stalled_connections_do_not_block_accept() {
+    let (server, addr) = server().await;
+
+    // A connection that never opens a channel
+    let mut endpoint = Endpoint::client(([127, 0, 0, 1], 0).into()).unwrap();
+    endpoint.set_default_client_config(QuicClient::config().unwrap());
+    let _stalled = endpoint
+        .connect(addr.parse().unwrap(), "reverb")
+        .unwrap()
+        .await
+        .unwrap();
+    // One that is not QUIC at all
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(&[0xff; 1200], &addr).unwrap();
+
+    let dialer = QuicClient.connect(&addr).await.unwrap();
+    let accepted = tokio::time::timeout(Duration::from_secs(5), server.accept())
+        .await
+        .expect("accepting waited on a stalled connection")
+        .unwrap()
+        .unwrap();
+    dialer.send(b"hello".to_vec().into()).await.unwrap();
+    assert_eq!(&accepted.recv().await.unwrap()[..], b"hello");
+}
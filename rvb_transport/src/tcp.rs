@@ -1,19 +1,50 @@
+use bytes::{Bytes, BytesMut};
 use futures::sink::SinkExt;
-use rvb_common::transport::{TransportError, TransportPeer};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use futures::stream::{SplitSink, SplitStream, StreamExt};
+use rvb_common::transport::{Client, PeerMetadata, Server, TransportError, TransportPeer};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, RwLock};
-use tokio_stream::StreamExt;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+type FramedStream = Framed<TcpStream, LengthDelimitedCodec>;
+
+/// A peer over TCP, with each message framed by its length. Sending and
+/// receiving lock separate halves of the stream, so a peer can send while
+/// another task waits for its next message.
 pub struct TcpPeer {
-    stream: Mutex<Framed<TcpStream, LengthDelimitedCodec>>,
+    sink: Mutex<SplitSink<FramedStream, Bytes>>,
+    stream: Mutex<SplitStream<FramedStream>>,
     shutdown: RwLock<bool>,
+    metadata: PeerMetadata,
 }
 
 impl TcpPeer {
+    #[must_use]
+    pub fn new(stream: TcpStream) -> Self {
+        let metadata = PeerMetadata {
+            remote_addr: stream.peer_addr().ok(),
+            inbound: false,
+        };
+        let (sink, stream) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        Self {
+            sink: Mutex::new(sink),
+            stream: Mutex::new(stream),
+            shutdown: RwLock::new(false),
+            metadata,
+        }
+    }
+
+    /// A connection accepted from `stream`, opened by the other end.
+    #[must_use]
+    pub fn accepted(stream: TcpStream) -> Self {
+        let mut peer = Self::new(stream);
+        peer.metadata.inbound = true;
+        peer
+    }
+
     pub async fn is_open(&self) -> bool {
-        *self.shutdown.read().await
+        !*self.shutdown.read().await
     }
 
     pub async fn must_be_open(&self) -> Result<(), TransportError> {
@@ -28,37 +59,77 @@ impl TcpPeer {
 impl TransportPeer for TcpPeer {
     async fn bye(self) -> Result<(), TransportError> {
         self.must_be_open().await?;
-        
-        self.stream
+        *self.shutdown.write().await = true;
+
+        self.sink
             .lock()
             .await
-            .get_mut()
-            .shutdown()
+            .close()
             .await
             .map_err(TransportError::IO)
     }
 
-    async fn send(&self, msg: Vec<u8>) -> Result<(), TransportError> {
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
         self.must_be_open().await?;
-        
-        self.stream
+
+        self.sink
             .lock()
             .await
-            .send(msg.into())
+            .send(msg)
             .await
             .map_err(TransportError::IO)
     }
 
-    async fn recv(&self) -> Result<Vec<u8>, TransportError> {
+    async fn recv(&self) -> Result<Bytes, TransportError> {
         self.must_be_open().await?;
-        
+
         self.stream
             .lock()
             .await
             .next()
             .await
-            .ok_or(TransportError::Runtime)?
+            .ok_or(TransportError::ConnectionClosed)?
             .map_err(TransportError::IO)
-            .map(Into::into)
+            .map(BytesMut::freeze)
+    }
+
+    fn metadata(&self) -> PeerMetadata {
+        self.metadata
+    }
+}
+
+/// Opens TCP connections to `host:port` addresses.
+#[derive(Default)]
+pub struct TcpClient;
+
+#[async_trait::async_trait]
+impl Client for TcpClient {
+    async fn connect(&self, addr: &str) -> Result<Box<dyn TransportPeer>, TransportError> {
+        let stream = TcpStream::connect(addr).await.map_err(TransportError::IO)?;
+        Ok(Box::new(TcpPeer::new(stream)))
+    }
+}
+
+/// Accepts TCP connections on a bound address.
+pub struct TcpServer {
+    listener: TcpListener,
+}
+
+impl TcpServer {
+    pub async fn bind(addr: &str) -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(addr).await.map_err(TransportError::IO)?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        self.listener.local_addr().map_err(TransportError::IO)
+    }
+}
+
+#[async_trait::async_trait]
+impl Server for TcpServer {
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        let (stream, _) = self.listener.accept().await.map_err(TransportError::IO)?;
+        Ok(Some(Box::new(TcpPeer::accepted(stream))))
     }
 }
@@ -0,0 +1,133 @@
+use bytes::Bytes;
+use futures::sink::SinkExt;
+use futures::stream::{SplitSink, SplitStream, StreamExt};
+use rvb_common::transport::{Client, PeerMetadata, Server, TransportError, TransportPeer};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{WebSocketStream, accept_async, connect_async};
+
+fn io_error(e: tungstenite::Error) -> TransportError {
+    match e {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            TransportError::ConnectionClosed
+        }
+        tungstenite::Error::Io(e) => TransportError::IO(e),
+        e => TransportError::IO(std::io::Error::other(e)),
+    }
+}
+
+/// A peer over WebSocket, with each message in one binary frame. This is the
+/// transport browsers can open to a node.
+pub struct WebSocketPeer<S> {
+    sink: Mutex<SplitSink<WebSocketStream<S>, Message>>,
+    stream: Mutex<SplitStream<WebSocketStream<S>>>,
+    metadata: PeerMetadata,
+}
+
+impl<S> WebSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    #[must_use]
+    pub fn new(stream: WebSocketStream<S>) -> Self {
+        let (sink, stream) = stream.split();
+        Self {
+            sink: Mutex::new(sink),
+            stream: Mutex::new(stream),
+            metadata: PeerMetadata::default(),
+        }
+    }
+
+    /// Sets what [`TransportPeer::metadata`] reports, which the stream itself
+    /// does not know.
+    #[must_use]
+    pub fn with_metadata(mut self, metadata: PeerMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> TransportPeer for WebSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    async fn bye(self) -> Result<(), TransportError> {
+        self.sink.lock().await.close().await.map_err(io_error)
+    }
+
+    async fn send(&self, msg: Bytes) -> Result<(), TransportError> {
+        self.sink
+            .lock()
+            .await
+            .send(Message::Binary(msg))
+            .await
+            .map_err(io_error)
+    }
+
+    /// Skips control and text frames; pings are answered by the stream itself.
+    async fn recv(&self) -> Result<Bytes, TransportError> {
+        let mut stream = self.stream.lock().await;
+        loop {
+            match stream.next().await {
+                Some(Ok(Message::Binary(data))) => return Ok(data),
+                Some(Ok(Message::Close(_))) | None => return Err(TransportError::ConnectionClosed),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(io_error(e)),
+            }
+        }
+    }
+
+    fn metadata(&self) -> PeerMetadata {
+        self.metadata
+    }
+}
+
+/// Opens WebSocket connections to `ws://` URLs.
+#[derive(Default)]
+pub struct WebSocketClient;
+
+#[async_trait::async_trait]
+impl Client for WebSocketClient {
+    async fn connect(&self, addr: &str) -> Result<Box<dyn TransportPeer>, TransportError> {
+        let (stream, _) = connect_async(addr).await.map_err(io_error)?;
+        Ok(Box::new(WebSocketPeer::new(stream)))
+    }
+}
+
+/// Accepts WebSocket connections on a bound address.
+pub struct WebSocketServer {
+    listener: TcpListener,
+}
+
+impl WebSocketServer {
+    pub async fn bind(addr: &str) -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(addr).await.map_err(TransportError::IO)?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, TransportError> {
+        self.listener.local_addr().map_err(TransportError::IO)
+    }
+}
+
+#[async_trait::async_trait]
+impl Server for WebSocketServer {
+    /// `None` if the connection failed the WebSocket handshake.
+    async fn accept(&self) -> Result<Option<Box<dyn TransportPeer>>, TransportError> {
+        let (stream, remote_addr) = self.listener.accept().await.map_err(TransportError::IO)?;
+        let metadata = PeerMetadata {
+            remote_addr: Some(remote_addr),
+            inbound: true,
+        };
+        match accept_async::<TcpStream>(stream).await {
+            Ok(stream) => Ok(Some(Box::new(
+                WebSocketPeer::new(stream).with_metadata(metadata),
+            ))),
+            Err(_) => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use rvb_common::crypto::b64_decode;
+use rvb_common::protocol::Locality;
+use rvb_common::transport::Client;
+use rvb_node::addresses::DiscoveryConfig;
+use rvb_node::policy::NamespacePolicy;
+use rvb_node::{
+    Fanout, Limits, MaintenanceConfig, MergePolicy, NodeConfig, RelayConfig, StateProofs,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Contents of the `rvbd.toml` file a node is started from.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Directory of the sled database.
+    #[serde(default = "default_storage")]
+    pub storage: PathBuf,
+    /// File holding the node's armored private key, created on first start.
+    #[serde(default = "default_key")]
+    pub key: PathBuf,
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// Address to serve the gRPC interface on; disabled if unset.
+    #[serde(default)]
+    pub grpc: Option<String>,
+    /// Address to serve the gRPC admin interface on, such as the audit log;
+    /// disabled if unset. It is not authenticated, so keep it private.
+    #[serde(default)]
+    pub admin: Option<String>,
+    /// Address to accept peers on over WebSocket, for browser clients;
+    /// disabled if unset.
+    #[serde(default)]
+    pub websocket: Option<String>,
+    /// WebSocket gateway pushing events to browsers; disabled if unset.
+    pub gateway: Option<GatewaySection>,
+    /// Nodes to connect to on startup, by address, or `dns:<name>` to look
+    /// them up in the TXT and SRV records of `name`.
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Seconds between lookups of the `dns:` entries of `peers`.
+    #[serde(default = "default_seed_refresh")]
+    pub seed_refresh_secs: u64,
+    /// Log filter, in `RUST_LOG` syntax. `RUST_LOG` takes precedence.
+    #[serde(default = "default_log")]
+    pub log: String,
+    #[serde(default)]
+    pub node: NodeSection,
+    #[serde(default)]
+    pub runtime: RuntimeSection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewaySection {
+    pub listen: String,
+    /// Armored public keys allowed to connect; any key if unset.
+    pub allowed_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeSection {
+    /// Message ids remembered to drop duplicates.
+    pub seen_messages: usize,
+    /// Seconds message ids are also kept on disk, so duplicates are still
+    /// dropped after a restart; in memory only if unset.
+    pub seen_retention_secs: Option<u64>,
+    pub fuel_accounting: bool,
+    pub namespace_fuel_budget: Option<u64>,
+    pub execution_reports: bool,
+    pub workers: usize,
+    /// Record every applied message in the audit log.
+    pub audit: bool,
+    /// Milliseconds replies and events to a peer wait to be batched with
+    /// others; sent at once if unset.
+    pub batch_window_ms: Option<u64>,
+    pub max_batch: usize,
+    /// Seconds the clocks of nodes may be apart when checking whether a
+    /// message expired.
+    pub clock_skew_secs: u64,
+    /// Relay messages between peers that cannot reach each other.
+    pub relay: Option<RelaySection>,
+    pub limits: LimitsSection,
+    /// Conflict resolution per namespace, for namespaces not resolved by
+    /// content. Must match on every node holding the namespace.
+    pub merge: HashMap<String, MergeKind>,
+    /// Rules inserts must pass before their contract runs, per namespace.
+    pub policies: HashMap<String, PolicySection>,
+    /// Only mirror data passed on by other nodes, refusing writes from
+    /// clients, as for analytics replicas.
+    pub observer: bool,
+    /// Namespaces to hold, as a light node asking its peers for the writes in
+    /// them; every namespace if unset.
+    pub namespaces: Option<HashSet<String>>,
+    /// Whether reads of stored values come with a signed proof.
+    pub proofs: ProofKind,
+    /// Pass inserts on to a few peers picked by latency or spread over
+    /// regions rather than to every peer asking for them.
+    pub fanout: Option<FanoutSection>,
+    /// Periodic clean-up of the database; never run if unset.
+    pub maintenance: Option<MaintenanceSection>,
+    /// Learn the addresses of nodes connecting to this one and gossip them
+    /// to peers; disabled if unset.
+    pub discovery: Option<DiscoverySection>,
+    /// Region the node runs in, gossiped to peers; none if unset.
+    pub region: Option<String>,
+    /// Zone within the region.
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeKind {
+    Content,
+    /// The write signed last wins.
+    Latest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofKind {
+    Off,
+    Signed,
+    /// Signed and placed in the Merkle tree of the contract space.
+    Anchored,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicySection {
+    pub max_writes_per_minute: Option<u32>,
+    pub max_value_bytes: Option<usize>,
+    /// Base64 public keys that may insert; any key if unset.
+    pub allowed_signers: Option<Vec<String>>,
+}
+
+impl PolicySection {
+    pub fn policy(&self) -> Result<NamespacePolicy, String> {
+        let allowed_signers = self
+            .allowed_signers
+            .as_ref()
+            .map(|keys| {
+                keys.iter()
+                    .map(|key| b64_decode(key).map_err(|_| format!("invalid signer key {key}")))
+                    .collect()
+            })
+            .transpose()?;
+        Ok(NamespacePolicy {
+            max_writes_per_key: self.max_writes_per_minute,
+            max_value_size: self.max_value_bytes,
+            allowed_signers,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelaySection {
+    /// Bytes each peer may have relayed per window.
+    pub quota_bytes: u64,
+    #[serde(default = "default_relay_window")]
+    pub window_secs: u64,
+}
+
+impl RelaySection {
+    #[must_use]
+    pub fn config(&self) -> RelayConfig {
+        RelayConfig {
+            quota: self.quota_bytes,
+            window: Duration::from_secs(self.window_secs),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanoutSection {
+    /// Peers each insert is passed on to.
+    pub peers: Option<usize>,
+    /// Peers each insert is passed on to in each region, counting this node
+    /// in its own; instead of `peers`.
+    pub per_region: Option<usize>,
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceSection {
+    pub interval_secs: u64,
+    /// How long to remember deleted keys, so older writes arriving late do
+    /// not bring them back.
+    pub tombstone_ttl_secs: u64,
+    /// Newest audit records to keep; all if unset.
+    pub audit_records: Option<u64>,
+}
+
+impl MaintenanceSection {
+    #[must_use]
+    pub fn config(&self) -> MaintenanceConfig {
+        MaintenanceConfig {
+            interval: Duration::from_secs(self.interval_secs),
+            tombstone_ttl: Duration::from_secs(self.tombstone_ttl_secs),
+            audit_retention: self.audit_records,
+        }
+    }
+}
+
+impl Default for MaintenanceSection {
+    fn default() -> Self {
+        Self {
+            interval_secs: 3600,
+            tombstone_ttl_secs: 7 * 24 * 3600,
+            audit_records: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoverySection {
+    /// Address peers are told to dial this node at; `listen` if unset. An
+    /// unspecified host is filled in by each peer with the one it sees.
+    pub advertise: Option<String>,
+    pub probe_timeout_secs: u64,
+}
+
+impl DiscoverySection {
+    /// Config probing learned addresses with `client`, advertising `listen`
+    /// unless another address is set.
+    #[must_use]
+    pub fn config(&self, listen: &str, client: Arc<dyn Client>) -> DiscoveryConfig {
+        DiscoveryConfig {
+            advertise: Some(self.advertise.clone().unwrap_or_else(|| listen.to_string())),
+            client,
+            probe_timeout: Duration::from_secs(self.probe_timeout_secs),
+        }
+    }
+}
+
+impl Default for DiscoverySection {
+    fn default() -> Self {
+        Self {
+            advertise: None,
+            probe_timeout_secs: 5,
+        }
+    }
+}
+
+/// Bounds on what peers can make the node decode, store and run.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub max_message_bytes: usize,
+    pub max_batch_messages: usize,
+    /// Bytes a stored value may take once encoded.
+    pub max_value_bytes: usize,
+    pub max_keys_per_namespace: u64,
+    /// Contract calls running at once; further ones are refused.
+    pub max_inflight_executions: usize,
+}
+
+impl LimitsSection {
+    #[must_use]
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_message_size: self.max_message_bytes,
+            max_batch_messages: self.max_batch_messages,
+            max_value_size: self.max_value_bytes,
+            max_keys_per_namespace: self.max_keys_per_namespace,
+            max_inflight_executions: self.max_inflight_executions,
+        }
+    }
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_message_bytes: limits.max_message_size,
+            max_batch_messages: limits.max_batch_messages,
+            max_value_bytes: limits.max_value_size,
+            max_keys_per_namespace: limits.max_keys_per_namespace,
+            max_inflight_executions: limits.max_inflight_executions,
+        }
+    }
+}
+
+impl NodeSection {
+    #[must_use]
+    pub fn seen_retention(&self) -> Option<Duration> {
+        self.seen_retention_secs.map(Duration::from_secs)
+    }
+
+    #[must_use]
+    pub fn batch_window(&self) -> Option<Duration> {
+        self.batch_window_ms.map(Duration::from_millis)
+    }
+
+    #[must_use]
+    pub fn clock_skew(&self) -> Duration {
+        Duration::from_secs(self.clock_skew_secs)
+    }
+
+    #[must_use]
+    pub fn merge_policies(&self) -> HashMap<String, MergePolicy> {
+        self.merge
+            .iter()
+            .map(|(namespace, kind)| {
+                let policy = match kind {
+                    MergeKind::Content => MergePolicy::Content,
+                    MergeKind::Latest => MergePolicy::LatestSigned,
+                };
+                (namespace.clone(), policy)
+            })
+            .collect()
+    }
+
+    pub fn namespace_policies(&self) -> Result<HashMap<String, NamespacePolicy>, String> {
+        self.policies
+            .iter()
+            .map(|(namespace, section)| Ok((namespace.clone(), section.policy()?)))
+            .collect()
+    }
+
+    pub fn fanout(&self) -> Result<Fanout, String> {
+        let Some(section) = &self.fanout else {
+            return Ok(Fanout::All);
+        };
+        match (section.peers, section.per_region) {
+            (Some(peers), None) => Ok(Fanout::Latency {
+                peers,
+                ping_interval: Duration::from_millis(section.ping_interval_ms),
+            }),
+            (None, Some(copies)) => Ok(Fanout::Regional { copies }),
+            _ => Err("[node.fanout] needs either peers or per_region".into()),
+        }
+    }
+
+    pub fn locality(&self) -> Result<Option<Locality>, String> {
+        match (&self.region, &self.zone) {
+            (Some(region), zone) => Ok(Some(Locality {
+                region: region.clone(),
+                zone: zone.clone(),
+            })),
+            (None, Some(_)) => Err("node zone set without a region".into()),
+            (None, None) => Ok(None),
+        }
+    }
+
+    #[must_use]
+    pub fn state_proofs(&self) -> StateProofs {
+        match self.proofs {
+            ProofKind::Off => StateProofs::Off,
+            ProofKind::Signed => StateProofs::Signed,
+            ProofKind::Anchored => StateProofs::Anchored,
+        }
+    }
+}
+
+impl Default for NodeSection {
+    fn default() -> Self {
+        let config = NodeConfig::default();
+        Self {
+            seen_messages: config.seen_messages,
+            seen_retention_secs: None,
+            fuel_accounting: config.fuel_accounting,
+            namespace_fuel_budget: None,
+            execution_reports: config.execution_reports,
+            workers: config.workers,
+            audit: config.audit,
+            batch_window_ms: None,
+            max_batch: config.max_batch,
+            clock_skew_secs: config.clock_skew.as_secs(),
+            relay: None,
+            limits: LimitsSection::default(),
+            merge: HashMap::new(),
+            policies: HashMap::new(),
+            observer: false,
+            namespaces: None,
+            proofs: ProofKind::Off,
+            fanout: None,
+            maintenance: None,
+            discovery: None,
+            region: None,
+            zone: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    Wasmtime,
+    #[cfg(feature = "rhai")]
+    Rhai,
+}
+
+/// Contract runtime. The limits apply to wasmtime; Rhai scripts are bounded
+/// by their own operation count.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeSection {
+    pub kind: RuntimeKind,
+    pub deadline_ms: u64,
+    pub fuel: Option<u64>,
+    pub interpreted: bool,
+    /// Directory caching compiled contracts across restarts.
+    pub cache: Option<PathBuf>,
+}
+
+impl Default for RuntimeSection {
+    fn default() -> Self {
+        Self {
+            kind: RuntimeKind::Wasmtime,
+            deadline_ms: 1000,
+            fuel: None,
+            interpreted: false,
+            cache: None,
+        }
+    }
+}
+
+impl RuntimeSection {
+    #[must_use]
+    pub fn deadline(&self) -> Duration {
+        Duration::from_millis(self.deadline_ms)
+    }
+}
+
+fn default_storage() -> PathBuf {
+    "rvbd.db".into()
+}
+
+fn default_key() -> PathBuf {
+    "rvbd.key".into()
+}
+
+fn default_listen() -> String {
+    "0.0.0.0:7070".into()
+}
+
+fn default_log() -> String {
+    "info".into()
+}
+
+fn default_relay_window() -> u64 {
+    60
+}
+
+fn default_ping_interval() -> u64 {
+    5000
+}
+
+fn default_seed_refresh() -> u64 {
+    300
+}
+
+impl Config {
+    #[must_use]
+    pub fn seed_refresh(&self) -> Duration {
+        Duration::from_secs(self.seed_refresh_secs)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("invalid config {}: {e}", path.display()))
+    }
+}
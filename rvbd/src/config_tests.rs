@@ -0,0 +1,196 @@
+use crate::config::*;
+use rvb_node::policy::NamespacePolicy;
+use rvb_node::{Fanout, MergePolicy, StateProofs};
+use rvb_transport::tcp::TcpClient;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_empty_config_uses_defaults() {
+    let config = Config::parse("").unwrap();
+    assert_eq!(config.listen, "0.0.0.0:7070");
+    assert_eq!(config.node.workers, 4);
+    assert_eq!(config.runtime.kind, RuntimeKind::Wasmtime);
+    assert!(config.peers.is_empty());
+    assert!(config.grpc.is_none());
+    assert!(config.admin.is_none());
+    assert!(!config.node.audit);
+    assert_eq!(config.node.batch_window(), None);
+    assert_eq!(config.node.seen_retention(), None);
+    assert!(config.websocket.is_none());
+    assert_eq!(config.seed_refresh(), Duration::from_secs(300));
+    assert_eq!(config.node.fanout().unwrap(), Fanout::All);
+    assert_eq!(config.node.locality().unwrap(), None);
+}
+
+#[test]
+fn test_full_config() {
+    let config = Config::parse(
+        r#"
+        storage = "/var/lib/rvbd"
+        key = "/etc/rvbd/node.key"
+        listen = "127.0.0.1:9000"
+        grpc = "127.0.0.1:9001"
+        admin = "127.0.0.1:9004"
+        websocket = "127.0.0.1:9003"
+        peers = ["10.0.0.2:7070", "dns:seeds.example.com"]
+        seed_refresh_secs = 60
+        log = "debug"
+
+        [gateway]
+        listen = "127.0.0.1:9002"
+        allowed_keys = ["AQID"]
+
+        [node]
+        workers = 8
+        namespace_fuel_budget = 1000000
+        audit = true
+        seen_retention_secs = 86400
+        batch_window_ms = 5
+        clock_skew_secs = 10
+        observer = true
+        namespaces = ["docs"]
+        proofs = "anchored"
+
+        [node.relay]
+        quota_bytes = 1048576
+
+        [node.limits]
+        max_value_bytes = 65536
+        max_inflight_executions = 16
+
+        [node.merge]
+        docs = "latest"
+
+        [node.policies.docs]
+        max_writes_per_minute = 10
+        allowed_signers = ["AQID"]
+
+        [node.maintenance]
+        audit_records = 100000
+
+        [node.fanout]
+        peers = 3
+
+        [node.discovery]
+        probe_timeout_secs = 2
+
+        [runtime]
+        deadline_ms = 250
+        interpreted = true
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.storage, PathBuf::from("/var/lib/rvbd"));
+    assert_eq!(config.grpc.as_deref(), Some("127.0.0.1:9001"));
+    assert_eq!(config.websocket.as_deref(), Some("127.0.0.1:9003"));
+    assert_eq!(config.admin.as_deref(), Some("127.0.0.1:9004"));
+    assert_eq!(config.peers, ["10.0.0.2:7070", "dns:seeds.example.com"]);
+    assert_eq!(config.seed_refresh(), Duration::from_secs(60));
+    let gateway = config.gateway.unwrap();
+    assert_eq!(gateway.listen, "127.0.0.1:9002");
+    assert_eq!(gateway.allowed_keys.unwrap(), ["AQID"]);
+    assert_eq!(config.node.workers, 8);
+    assert_eq!(config.node.namespace_fuel_budget, Some(1_000_000));
+    assert_eq!(config.node.seen_messages, 100_000);
+    assert_eq!(
+        config.node.seen_retention(),
+        Some(Duration::from_secs(86_400))
+    );
+    assert!(config.node.audit);
+    assert_eq!(config.node.batch_window(), Some(Duration::from_millis(5)));
+    assert_eq!(config.node.max_batch, 64);
+    assert_eq!(config.node.clock_skew(), Duration::from_secs(10));
+    assert!(config.node.observer);
+    assert_eq!(
+        config.node.namespaces,
+        Some(HashSet::from(["docs".to_string()]))
+    );
+    let relay = config.node.relay.as_ref().unwrap().config();
+    assert_eq!(relay.quota, 1_048_576);
+    assert_eq!(relay.window, Duration::from_secs(60));
+    let limits = config.node.limits.limits();
+    assert_eq!(limits.max_value_size, 65_536);
+    assert_eq!(limits.max_inflight_executions, 16);
+    assert_eq!(limits.max_batch_messages, 1024);
+    assert_eq!(
+        config.node.merge_policies(),
+        HashMap::from([("docs".to_string(), MergePolicy::LatestSigned)])
+    );
+    assert_eq!(config.node.state_proofs(), StateProofs::Anchored);
+    assert_eq!(
+        config.node.namespace_policies().unwrap()["docs"],
+        NamespacePolicy {
+            max_writes_per_key: Some(10),
+            max_value_size: None,
+            allowed_signers: Some(HashSet::from([vec![1, 2, 3]])),
+        }
+    );
+    assert_eq!(
+        config.node.fanout().unwrap(),
+        Fanout::Latency {
+            peers: 3,
+            ping_interval: Duration::from_secs(5),
+        }
+    );
+    let maintenance = config.node.maintenance.as_ref().unwrap().config();
+    assert_eq!(maintenance.interval, Duration::from_secs(3600));
+    assert_eq!(maintenance.audit_retention, Some(100_000));
+    let discovery = config
+        .node
+        .discovery
+        .as_ref()
+        .unwrap()
+        .config("0.0.0.0:7700", Arc::new(TcpClient));
+    assert_eq!(discovery.advertise.as_deref(), Some("0.0.0.0:7700"));
+    assert_eq!(discovery.probe_timeout, Duration::from_secs(2));
+    assert_eq!(config.runtime.deadline(), Duration::from_millis(250));
+    assert!(config.runtime.interpreted);
+}
+
+#[test]
+fn test_unknown_keys_are_rejected() {
+    assert!(Config::parse("listn = \"127.0.0.1:9000\"").is_err());
+    assert!(Config::parse("[runtime]\nkind = \"jvm\"").is_err());
+}
+
+#[test]
+fn test_regional_fanout() {
+    let config = Config::parse(
+        r#"
+        [node]
+        region = "eu-west"
+        zone = "b"
+
+        [node.fanout]
+        per_region = 2
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.node.fanout().unwrap(),
+        Fanout::Regional { copies: 2 }
+    );
+    let locality = config.node.locality().unwrap().unwrap();
+    assert_eq!(
+        (locality.region.as_str(), locality.zone.as_deref()),
+        ("eu-west", Some("b"))
+    );
+
+    for invalid in [
+        "[node.fanout]\npeers = 3\nper_region = 2",
+        "[node.fanout]\nping_interval_ms = 10",
+    ] {
+        assert!(Config::parse(invalid).unwrap().node.fanout().is_err());
+    }
+    assert!(
+        Config::parse("[node]\nzone = \"b\"")
+            .unwrap()
+            .node
+            .locality()
+            .is_err()
+    );
+}
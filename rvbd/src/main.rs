@@ -0,0 +1,301 @@
+//! `rvbd`, a reverb node configured from a TOML file.
+//!
+//! ```text
+//! rvbd [config]    # defaults to rvbd.toml
+//! ```
+//!
+//! The node stops on SIGTERM or Ctrl-C, flushing its storage before exiting.
+
+use config::{Config, RuntimeKind};
+use rvb_common::contract::ContractCompiler;
+use rvb_common::crypto::{KeyPair, b64_decode, b64_encode};
+use rvb_common::transport::{Client as _, Server as _};
+use rvb_contract::cache::DirArtifactCache;
+use rvb_contract::wasmtime::{WasmtimeConfig, WasmtimeContractCompiler};
+use rvb_gateway::Gateway;
+use rvb_grpc::{AdminService, ReverbService};
+use rvb_node::{Node, NodeConfig};
+use rvb_transport::tcp::{TcpClient, TcpServer};
+use rvb_transport::websocket::WebSocketServer;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+mod config;
+#[cfg(test)]
+mod config_tests;
+mod seeds;
+#[cfg(test)]
+mod seeds_tests;
+
+const DEFAULT_CONFIG: &str = "rvbd.toml";
+
+/// Reads the node key from `path`, generating and saving one if the file does
+/// not exist yet.
+fn load_key(path: &Path) -> Result<KeyPair, String> {
+    match std::fs::read_to_string(path) {
+        Ok(armored) => KeyPair::import_armored(armored.trim())
+            .map_err(|e| format!("invalid key in {}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = KeyPair::generate();
+            std::fs::write(path, keypair.armor_private())
+                .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+            info!("Generated a new node key in {}", path.display());
+            Ok(keypair)
+        }
+        Err(e) => Err(format!("failed to read {}: {e}", path.display())),
+    }
+}
+
+fn compiler(config: &config::RuntimeSection) -> Result<Box<dyn ContractCompiler>, String> {
+    match config.kind {
+        RuntimeKind::Wasmtime => {
+            let mut compiler = WasmtimeContractCompiler::new(WasmtimeConfig {
+                deadline: config.deadline(),
+                fuel: config.fuel,
+                interpreted: config.interpreted,
+                ..WasmtimeConfig::default()
+            })
+            .map_err(|e| format!("failed to start wasmtime: {e:?}"))?;
+            if let Some(dir) = &config.cache {
+                let cache = DirArtifactCache::new(dir)
+                    .map_err(|e| format!("failed to open contract cache: {e:?}"))?;
+                compiler = compiler.with_cache(Arc::new(cache));
+            }
+            Ok(Box::new(compiler))
+        }
+        #[cfg(feature = "rhai")]
+        RuntimeKind::Rhai => Ok(Box::new(rvb_contract::rhai::RhaiContractCompiler::new(
+            rvb_contract::rhai::RhaiConfig::default(),
+        ))),
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+async fn run(config: Config) -> Result<(), String> {
+    let keypair = load_key(&config.key)?;
+    let storage = sled::open(&config.storage)
+        .map_err(|e| format!("failed to open {}: {e}", config.storage.display()))?;
+    let server = TcpServer::bind(&config.listen)
+        .await
+        .map_err(|e| format!("failed to listen on {}: {e:?}", config.listen))?;
+
+    let node = Arc::new(Node::new(
+        keypair.clone(),
+        NodeConfig {
+            seen_messages: config.node.seen_messages,
+            seen_retention: config.node.seen_retention(),
+            fuel_accounting: config.node.fuel_accounting,
+            namespace_fuel_budget: config.node.namespace_fuel_budget,
+            execution_reports: config.node.execution_reports,
+            workers: config.node.workers,
+            audit: config.node.audit,
+            batch_window: config.node.batch_window(),
+            max_batch: config.node.max_batch,
+            clock_skew: config.node.clock_skew(),
+            relay: config.node.relay.as_ref().map(config::RelaySection::config),
+            limits: config.node.limits.limits(),
+            merge_policies: config.node.merge_policies(),
+            namespace_policies: config.node.namespace_policies()?,
+            observer: config.node.observer,
+            namespaces: config.node.namespaces.clone(),
+            state_proofs: config.node.state_proofs(),
+            fanout: config.node.fanout()?,
+            maintenance: config
+                .node
+                .maintenance
+                .as_ref()
+                .map(config::MaintenanceSection::config),
+            discovery: config
+                .node
+                .discovery
+                .as_ref()
+                .map(|section| section.config(&config.listen, Arc::new(TcpClient))),
+            locality: config.node.locality()?,
+            ..NodeConfig::default()
+        },
+        storage.clone(),
+        compiler(&config.runtime)?,
+        Box::new(server),
+    ));
+    info!(
+        identity = %b64_encode(node.identity()),
+        listen = %config.listen,
+        "Node started"
+    );
+
+    let (addrs, seed_names) = seeds::split(&config.peers);
+    for addr in &addrs {
+        match TcpClient.connect(addr).await {
+            Ok(peer) => node.connect_peer(peer).await,
+            Err(e) => warn!("Failed to connect to {addr}: {e:?}"),
+        }
+    }
+    let seeding: Pin<Box<dyn Future<Output = ()>>> = if seed_names.is_empty() {
+        Box::pin(std::future::pending())
+    } else {
+        Box::pin(seeds::run(node.clone(), seed_names, config.seed_refresh()))
+    };
+
+    let grpc: Pin<Box<dyn Future<Output = ()>>> = match &config.grpc {
+        Some(addr) => {
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|e| format!("invalid gRPC address {addr}: {e}"))?;
+            let node = node.clone();
+            Box::pin(async move {
+                // The handshake needs the node to be processing messages
+                let service = match ReverbService::attach(&node, keypair).await {
+                    Ok(service) => service,
+                    Err(e) => {
+                        error!("Failed to attach the gRPC service: {e:?}");
+                        return;
+                    }
+                };
+                info!(%addr, "Serving gRPC");
+                let served = tonic::transport::Server::builder()
+                    .add_service(service.into_server())
+                    .serve(addr)
+                    .await;
+                if let Err(e) = served {
+                    error!("gRPC server failed: {e}");
+                }
+            })
+        }
+        None => Box::pin(std::future::pending()),
+    };
+
+    let admin: Pin<Box<dyn Future<Output = ()>>> = match &config.admin {
+        Some(addr) => {
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|e| format!("invalid admin address {addr}: {e}"))?;
+            let service = AdminService::new(node.clone());
+            info!(%addr, "Serving the admin interface");
+            Box::pin(async move {
+                let served = tonic::transport::Server::builder()
+                    .add_service(service.into_server())
+                    .serve(addr)
+                    .await;
+                if let Err(e) = served {
+                    error!("Admin server failed: {e}");
+                }
+            })
+        }
+        None => Box::pin(std::future::pending()),
+    };
+
+    let websocket: Pin<Box<dyn Future<Output = ()>>> = match &config.websocket {
+        Some(addr) => {
+            let server = WebSocketServer::bind(addr)
+                .await
+                .map_err(|e| format!("failed to listen on {addr}: {e:?}"))?;
+            info!(listen = %addr, "Accepting peers over WebSocket");
+            let node = node.clone();
+            Box::pin(async move {
+                loop {
+                    match server.accept().await {
+                        Ok(Some(peer)) => node.connect_peer(peer).await,
+                        Ok(None) => {}
+                        Err(e) => {
+                            error!("WebSocket server failed: {e:?}");
+                            break;
+                        }
+                    }
+                }
+            })
+        }
+        None => Box::pin(std::future::pending()),
+    };
+
+    let gateway: Pin<Box<dyn Future<Output = ()>>> = match &config.gateway {
+        Some(section) => {
+            let allowed_keys = section
+                .allowed_keys
+                .as_ref()
+                .map(|keys| {
+                    keys.iter()
+                        .map(|key| {
+                            b64_decode(key).map_err(|_| format!("invalid gateway key {key}"))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?;
+            let listener = tokio::net::TcpListener::bind(&section.listen)
+                .await
+                .map_err(|e| format!("failed to listen on {}: {e}", section.listen))?;
+            info!(listen = %section.listen, "Serving the WebSocket gateway");
+            let gateway = Arc::new(Gateway::new(node.clone(), allowed_keys));
+            Box::pin(async move {
+                if let Err(e) = gateway.serve(listener).await {
+                    error!("WebSocket gateway failed: {e}");
+                }
+            })
+        }
+        None => Box::pin(std::future::pending()),
+    };
+
+    tokio::select! {
+        () = node.receive_peers() => warn!("Server stopped accepting peers"),
+        () = node.process() => warn!("Node stopped processing messages"),
+        () = grpc => warn!("gRPC server stopped"),
+        () = admin => warn!("Admin server stopped"),
+        () = websocket => warn!("WebSocket server stopped"),
+        () = gateway => warn!("WebSocket gateway stopped"),
+        () = seeding => warn!("Seed lookups stopped"),
+        () = shutdown_signal() => info!("Shutting down"),
+    }
+
+    storage
+        .flush_async()
+        .await
+        .map_err(|e| format!("failed to flush storage: {e}"))?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let path = std::env::args_os()
+        .nth(1)
+        .map_or_else(|| PathBuf::from(DEFAULT_CONFIG), PathBuf::from);
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("rvbd: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log)),
+        )
+        .init();
+
+    match run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Bootstrap peers listed in DNS. A `dns:<name>` entry in `peers` is looked up
+//! for TXT records of the form `addr=<host:port> key=<base64 public key>`,
+//! with the key optional, and for SRV records, which carry no key. Seeds are
+//! looked up again every refresh, so the bootstrap set can change without
+//! touching the nodes' configs.
+
+use hickory_resolver::TokioAsyncResolver;
+use rvb_common::crypto::b64_decode;
+use rvb_common::transport::Client as _;
+use rvb_node::Node;
+use rvb_transport::tcp::TcpClient;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+const DNS_PREFIX: &str = "dns:";
+
+/// A peer found in DNS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seed {
+    pub addr: String,
+    /// Public key the peer is expected to have, if the record names one.
+    pub key: Option<Vec<u8>>,
+}
+
+/// Splits `peers` into addresses to connect to and names to look seeds up
+/// under.
+#[must_use]
+pub fn split(peers: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut addrs = Vec::new();
+    let mut names = Vec::new();
+    for peer in peers {
+        match peer.strip_prefix(DNS_PREFIX) {
+            Some(name) => names.push(name.to_string()),
+            None => addrs.push(peer.clone()),
+        }
+    }
+    (addrs, names)
+}
+
+/// Reads a seed from the text of a TXT record, or `None` if it is not one.
+#[must_use]
+pub fn parse_txt(text: &str) -> Option<Seed> {
+    let mut addr = None;
+    let mut key = None;
+    for field in text.split_whitespace() {
+        match field.split_once('=')? {
+            ("addr", value) => addr = Some(value.to_string()),
+            ("key", value) => key = Some(b64_decode(value).ok()?),
+            _ => {}
+        }
+    }
+    Some(Seed { addr: addr?, key })
+}
+
+async fn resolve(resolver: &TokioAsyncResolver, name: &str) -> Result<Vec<Seed>, String> {
+    let txt = resolver.txt_lookup(name).await;
+    let srv = resolver.srv_lookup(name).await;
+    if let (Err(txt), Err(_)) = (&txt, &srv) {
+        return Err(txt.to_string());
+    }
+
+    let mut seeds = Vec::new();
+    for record in txt.iter().flat_map(|lookup| lookup.iter()) {
+        let text: String = record
+            .txt_data()
+            .iter()
+            .map(|part| String::from_utf8_lossy(part))
+            .collect();
+        match parse_txt(&text) {
+            Some(seed) => seeds.push(seed),
+            None => debug!("Ignoring TXT record {text:?} of {name}"),
+        }
+    }
+    for record in srv.iter().flat_map(|lookup| lookup.iter()) {
+        let host = record.target().to_utf8();
+        seeds.push(Seed {
+            addr: format!("{}:{}", host.trim_end_matches('.'), record.port()),
+            key: None,
+        });
+    }
+    Ok(seeds)
+}
+
+/// Looks up the seeds under `names` every `refresh` and connects to them.
+/// Seeds with a key are connected to whenever the node has no peer with that
+/// key; seeds without one once per address.
+pub async fn run(node: Arc<Node>, names: Vec<String>, refresh: Duration) {
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            error!("Failed to read the system DNS config: {e}");
+            return std::future::pending().await;
+        }
+    };
+    let mut dialed = HashSet::new();
+    let mut interval = tokio::time::interval(refresh);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        for name in &names {
+            let seeds = match resolve(&resolver, name).await {
+                Ok(seeds) => seeds,
+                Err(e) => {
+                    warn!("Failed to look up seeds of {name}: {e}");
+                    continue;
+                }
+            };
+            for seed in seeds {
+                let connected = match &seed.key {
+                    Some(key) => node.has_peer(key).await,
+                    None => !dialed.insert(seed.addr.clone()),
+                };
+                if connected {
+                    continue;
+                }
+                match TcpClient.connect(&seed.addr).await {
+                    Ok(peer) => node.connect_peer(peer).await,
+                    Err(e) => warn!("Failed to connect to seed {}: {e:?}", seed.addr),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::seeds::*;
+
+#[test]
+fn test_split() {
+    let peers = ["10.0.0.2:7070", "dns:seeds.example.com"].map(String::from);
+    assert_eq!(
+        split(&peers),
+        (
+            vec!["10.0.0.2:7070".to_string()],
+            vec!["seeds.example.com".to_string()]
+        )
+    );
+}
+
+#[test]
+fn test_parse_txt() {
+    assert_eq!(
+        parse_txt("addr=10.0.0.2:7070 key=AQID"),
+        Some(Seed {
+            addr: "10.0.0.2:7070".into(),
+            key: Some(vec![1, 2, 3]),
+        })
+    );
+    assert_eq!(
+        parse_txt("addr=node.example.com:7070"),
+        Some(Seed {
+            addr: "node.example.com:7070".into(),
+            key: None,
+        })
+    );
+    assert_eq!(parse_txt("v=spf1 -all"), None);
+    assert_eq!(parse_txt("addr=10.0.0.2:7070 key=!"), None);
+}